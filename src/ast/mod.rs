@@ -30,6 +30,7 @@ pub mod structure {
             Rule::int => "int literal",
             Rule::literal => "literal value",
             Rule::identifier => "identifier",
+            Rule::qualified_identifier => "qualified identifier",
 
             Rule::vp_var => "variable",
             Rule::build_array => "array data",
@@ -66,20 +67,25 @@ pub mod structure {
             Rule::if_statement => "if statement",
             Rule::no_unroll_keyword => "no_unroll (keyword)",
             Rule::for_loop_statement => "for loop",
+            Rule::every_statement => "every block",
 
             Rule::raw_string => "string segment",
             Rule::escape_sequence => "string escape sequence",
             Rule::string => "string literal",
 
+            Rule::specializable_keyword => "specializable (keyword)",
             Rule::input_variable_statement => "input declaration",
             Rule::output_variable_statement => "output declaration",
             Rule::static_variable_statement => "static variable declaration",
+            Rule::entry_point_statement => "entry point definition",
             Rule::assign_statement => "assignment statement",
             Rule::macro_call_statement => "macro call as statement",
             Rule::var_dec_statement => "variable declaration as statement",
             Rule::return_statement => "return statement",
             Rule::assert_statement => "assert statement",
             Rule::include_statement => "include statement",
+            Rule::import_names => "list of names to import",
+            Rule::import_statement => "import statement",
             Rule::statement => "statement",
 
             Rule::code_block => "code block",
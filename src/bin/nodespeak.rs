@@ -7,11 +7,15 @@ use std::process;
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 3 {
-        eprintln!("Usage: nodespeak [compile|interpret|[phase]] [path to file]");
+        eprintln!(
+            "Usage: nodespeak [compile|interpret|[phase]] [path to file] [-I[search path]]... \
+             [--emit=trivial]"
+        );
         eprintln!("compile: compiles the specified file and outputs the result.");
         eprintln!("interpret: interprets the specified file using the built-in resolver.");
         eprintln!("[phase]: runs compilation of the file up until [phase] of compilation.");
         eprintln!("    phases: parse, structure, resolve, trivialize, specialize");
+        eprintln!("--emit=trivial: also prints the trivial IR of the file in its textual format.");
         process::exit(64);
     }
 
@@ -26,14 +30,45 @@ fn main() {
         process::exit(74);
     }
 
+    let mut loader = nodespeak::FileSystemLoader::new();
+    let mut emit_trivial = false;
     for arg_index in 3..args.len() {
         let source_name = &args[arg_index];
+        if let Some(emit) = source_name.strip_prefix("--emit=") {
+            if emit != "trivial" {
+                eprintln!("Invalid value for --emit, expected trivial.");
+                process::exit(64);
+            }
+            emit_trivial = true;
+            continue;
+        }
+        if source_name.starts_with("-I") {
+            loader.add_search_path(&source_name[2..]);
+            continue;
+        }
         if let Err(err) = compiler.add_source_from_file(source_name.to_owned()) {
             eprintln!("Could not read from {}:", source_name);
             eprintln!("{:?}", err);
             process::exit(74);
         }
     }
+    compiler.set_source_loader(Box::new(loader));
+
+    #[cfg(not(feature = "no-trivial"))]
+    if emit_trivial {
+        match compiler.compile_to_trivial(main_source_name) {
+            Result::Ok(program) => print!("{}", nodespeak::trivial::text::print(&program)),
+            Result::Err(err) => {
+                eprintln!("{}", err);
+                process::exit(101);
+            }
+        }
+    }
+    #[cfg(feature = "no-trivial")]
+    if emit_trivial {
+        eprintln!("--emit=trivial is not available because the trivial phase is disabled.");
+        process::exit(64);
+    }
 
     println!("\nStarting...");
     match args[1].as_ref() {
@@ -76,11 +111,35 @@ fn main() {
                 process::exit(101);
             }
         },
+        #[cfg(not(feature = "no-c"))]
+        "c" => match compiler.compile_to_c(main_source_name, "ns") {
+            Result::Ok(program) => println!("{}", program),
+            Result::Err(err) => {
+                eprintln!("{}", err);
+                process::exit(101);
+            }
+        },
+        #[cfg(not(feature = "no-wasm"))]
+        "wasm" => match compiler.compile_to_wasm(main_source_name) {
+            Result::Ok(program) => println!("{}", program.borrow_text()),
+            Result::Err(err) => {
+                eprintln!("{}", err);
+                process::exit(101);
+            }
+        },
+        #[cfg(feature = "cranelift")]
+        "cranelift" => match compiler.compile_with_cranelift(main_source_name) {
+            Result::Ok(program) => println!("{:?}", program),
+            Result::Err(err) => {
+                eprintln!("{}", err);
+                process::exit(101);
+            }
+        },
         _ => {
             eprintln!("Invalid mode '{}', expected compile or a phase.", args[1]);
             eprintln!("compile: compiles the specified file and outputs the result.");
             eprintln!("[phase]: runs compilation of the file up until [phase] of compilation.");
-            eprintln!("    phases: ast, vague, resolved, trivial, llvmir");
+            eprintln!("    phases: ast, vague, resolved, trivial, llvmir, c, wasm");
             process::exit(64);
         }
     }
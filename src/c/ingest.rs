@@ -0,0 +1,616 @@
+use crate::shared::ProxyMode;
+use crate::trivial::structure as i;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+fn base_type_name(typ: &i::DataType) -> &'static str {
+    match typ {
+        // Bools take up a full byte which is either 0 or 1, the same as in the LLVM version.
+        i::DataType::B1 => "uint8_t",
+        i::DataType::I32 => "int32_t",
+        i::DataType::F32 => "float",
+        i::DataType::Array(_, etype) => base_type_name(etype),
+    }
+}
+
+/// Declares a variable or struct member named `name` which has the type `typ`.
+fn declaration(typ: &i::DataType, name: &str) -> String {
+    let mut result = format!("{} {}", base_type_name(typ), name);
+    for len in typ.collect_dimensions() {
+        write!(result, "[{}]", len).unwrap();
+    }
+    result
+}
+
+fn int_literal(value: i64) -> String {
+    let value = value as i32;
+    if value == i32::MIN {
+        // -2147483648 would be parsed as negating a literal which is too big for an int.
+        "INT32_MIN".to_owned()
+    } else if value < 0 {
+        format!("({})", value)
+    } else {
+        format!("{}", value)
+    }
+}
+
+fn float_literal(value: f64) -> String {
+    let value = value as f32;
+    if value.is_nan() {
+        "NAN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 {
+            "INFINITY"
+        } else {
+            "(-INFINITY)"
+        }
+        .to_owned()
+    } else if value.is_sign_negative() {
+        format!("({:?}f)", value)
+    } else {
+        format!("{:?}f", value)
+    }
+}
+
+fn literal(data: &i::KnownData) -> String {
+    match data {
+        i::KnownData::Bool(value) => if *value { "1" } else { "0" }.to_owned(),
+        i::KnownData::Int(value) => int_literal(*value),
+        i::KnownData::Float(value) => float_literal(*value),
+        i::KnownData::Array(items) => {
+            let items: Vec<_> = items.iter().map(literal).collect();
+            format!("{{{}}}", items.join(", "))
+        }
+    }
+}
+
+fn string_literal(text: &str) -> String {
+    let mut result = "\"".to_owned();
+    for byte in text.bytes() {
+        match byte {
+            b'"' => result.push_str("\\\""),
+            b'\\' => result.push_str("\\\\"),
+            // Avoids accidentally creating trigraphs.
+            b'?' => result.push_str("\\?"),
+            b'\n' => result.push_str("\\n"),
+            0x20..=0x7E => result.push(byte as char),
+            _ => write!(result, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn comparison(condition: &i::Condition, a: &str, b: &str, float: bool) -> String {
+    match condition {
+        i::Condition::LessThan => format!("{} < {}", a, b),
+        i::Condition::GreaterThan => format!("{} > {}", a, b),
+        i::Condition::LessThanOrEqual => format!("{} <= {}", a, b),
+        i::Condition::GreaterThanOrEqual => format!("{} >= {}", a, b),
+        i::Condition::Equal => format!("{} == {}", a, b),
+        // The LLVM version uses an ordered comparison, which is false if either side is NaN.
+        i::Condition::NotEqual if float => format!("{0} < {1} || {0} > {1}", a, b),
+        i::Condition::NotEqual => format!("{} != {}", a, b),
+    }
+}
+
+fn unary_expression(op: &i::UnaryOperator, a: &str) -> String {
+    match op {
+        i::UnaryOperator::BNot => format!("~{}", a),
+        i::UnaryOperator::FAbs => format!("fabsf({})", a),
+        i::UnaryOperator::FCeil => format!("ceilf({})", a),
+        i::UnaryOperator::FCos => format!("cosf({})", a),
+        i::UnaryOperator::FExp => format!("expf({})", a),
+        i::UnaryOperator::FExp2 => format!("exp2f({})", a),
+        i::UnaryOperator::FFloor => format!("floorf({})", a),
+        i::UnaryOperator::FLog => format!("logf({})", a),
+        i::UnaryOperator::FLog10 => format!("log10f({})", a),
+        i::UnaryOperator::FLog2 => format!("log2f({})", a),
+        i::UnaryOperator::FSin => format!("sinf({})", a),
+        i::UnaryOperator::FSqrt => format!("sqrtf({})", a),
+        i::UnaryOperator::FTrunc => format!("truncf({})", a),
+        i::UnaryOperator::IAbs => format!("{0} < 0 ? (int32_t)(0u - (uint32_t){0}) : {0}", a),
+        // Computed as 0 - a like the LLVM version, which gives 0 instead of -0 when a is 0.
+        i::UnaryOperator::NegF => format!("(0.0f - {})", a),
+        // Integer arithmetic is done on unsigned values so that it wraps instead of being
+        // undefined when it overflows, like it does in the LLVM version.
+        i::UnaryOperator::NegI => format!("(int32_t)(0u - (uint32_t){})", a),
+        i::UnaryOperator::Not => format!("!{}", a),
+        i::UnaryOperator::Ftoi => format!("(int32_t){}", a),
+        i::UnaryOperator::Itof => format!("(float){}", a),
+    }
+}
+
+fn binary_expression(op: &i::BinaryOperator, a: &str, b: &str) -> String {
+    match op {
+        i::BinaryOperator::AddI => format!("(int32_t)((uint32_t){} + (uint32_t){})", a, b),
+        i::BinaryOperator::SubI => format!("(int32_t)((uint32_t){} - (uint32_t){})", a, b),
+        i::BinaryOperator::MulI => format!("(int32_t)((uint32_t){} * (uint32_t){})", a, b),
+        i::BinaryOperator::DivI => format!("{} / {}", a, b),
+        i::BinaryOperator::ModI => format!("{} % {}", a, b),
+        i::BinaryOperator::AddF => format!("{} + {}", a, b),
+        i::BinaryOperator::SubF => format!("{} - {}", a, b),
+        i::BinaryOperator::MulF => format!("{} * {}", a, b),
+        i::BinaryOperator::DivF => format!("{} / {}", a, b),
+        i::BinaryOperator::ModF => format!("fmodf({}, {})", a, b),
+        i::BinaryOperator::PowF => format!("powf({}, {})", a, b),
+        i::BinaryOperator::BAnd | i::BinaryOperator::And => format!("{} & {}", a, b),
+        i::BinaryOperator::BOr | i::BinaryOperator::Or => format!("{} | {}", a, b),
+        i::BinaryOperator::BXor | i::BinaryOperator::Xor => format!("{} ^ {}", a, b),
+        i::BinaryOperator::LeftShift => format!("(int32_t)((uint32_t){} << {})", a, b),
+        i::BinaryOperator::RightShift => format!("(int32_t)((uint32_t){} >> {})", a, b),
+        i::BinaryOperator::CompI(condition) => comparison(condition, a, b, false),
+        i::BinaryOperator::CompF(condition) => comparison(condition, a, b, true),
+    }
+}
+
+struct Converter<'a> {
+    source: &'a i::Program,
+    prefix: &'a str,
+
+    /// The name of the constant holding each array literal, keyed by its initializer.
+    array_literals: HashMap<String, String>,
+    array_literal_definitions: String,
+
+    /// How each variable is accessed in the function currently being written.
+    variable_expressions: HashMap<i::VariableId, String>,
+    code: String,
+    indent: usize,
+}
+
+impl<'a> Converter<'a> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.code.push_str("    ");
+        }
+        self.code.push_str(text);
+        self.code.push('\n');
+    }
+
+    fn array_literal_name(&mut self, data: &i::KnownData) -> String {
+        let initializer = literal(data);
+        if let Some(name) = self.array_literals.get(&initializer) {
+            return name.clone();
+        }
+        let name = format!("{}_literal_{}", self.prefix, self.array_literals.len());
+        writeln!(
+            self.array_literal_definitions,
+            "static const {} = {};",
+            declaration(&data.get_type(), &name),
+            initializer
+        )
+        .unwrap();
+        self.array_literals.insert(initializer, name.clone());
+        name
+    }
+
+    fn variable_expression(&self, id: &i::VariableId) -> String {
+        self.variable_expressions
+            .get(id)
+            .expect("A variable was not given an expression.")
+            .clone()
+    }
+
+    /// Returns an expression for the element of `value` at `indexes`, which should contain one
+    /// index for every dimension of the value before its proxy is applied.
+    fn value_expression(&mut self, value: &i::Value, indexes: &[String]) -> String {
+        debug_assert!(value.dimensions.len() == indexes.len());
+        let mut result = match &value.base {
+            i::ValueBase::Variable(id) => self.variable_expression(id),
+            i::ValueBase::Literal(data) => {
+                if let i::KnownData::Array(..) = data {
+                    self.array_literal_name(data)
+                } else {
+                    literal(data)
+                }
+            }
+        };
+        for ((_, mode), index) in value.dimensions.iter().zip(indexes.iter()) {
+            match mode {
+                ProxyMode::Keep => write!(result, "[{}]", index).unwrap(),
+                ProxyMode::Collapse => result.push_str("[0]"),
+                ProxyMode::Discard => (),
+            }
+        }
+        result
+    }
+
+    /// Used for arguments of extern functions which are passed by pointer.
+    fn pointer_expression(&self, value: &i::Value, is_const: bool) -> String {
+        if let i::ValueBase::Variable(id) = &value.base {
+            let typ = self.source[*id].borrow_type();
+            format!(
+                "({}{} *)&{}",
+                if is_const { "const " } else { "" },
+                base_type_name(typ),
+                self.variable_expression(id)
+            )
+        } else {
+            unreachable!("Only variables can be passed by pointer.")
+        }
+    }
+
+    /// Opens a loop for every dimension, returning the names of the variables holding the index
+    /// along each dimension.
+    fn open_loops(&mut self, dimensions: &[(usize, ProxyMode)]) -> Vec<String> {
+        let mut indexes = Vec::new();
+        for (len, _) in dimensions {
+            let index = format!("i{}", indexes.len());
+            self.line(&format!(
+                "for (int32_t {0} = 0; {0} < {1}; {0}++) {{",
+                index, len
+            ));
+            self.indent += 1;
+            indexes.push(index);
+        }
+        indexes
+    }
+
+    fn close_loops(&mut self, indexes: Vec<String>) {
+        for _ in indexes {
+            self.indent -= 1;
+            self.line("}");
+        }
+    }
+
+    fn convert_move(&mut self, from: &i::Value, to: &i::Value) {
+        let indexes = self.open_loops(&to.dimensions);
+        let from = self.value_expression(from, &indexes);
+        let to = self.value_expression(to, &indexes);
+        self.line(&format!("{} = {};", to, from));
+        self.close_loops(indexes);
+    }
+
+    fn convert_load(&mut self, from: &i::Value, from_indexes: &[i::Value], to: &i::Value) {
+        let mut all_indexes: Vec<_> = from_indexes
+            .iter()
+            .map(|index| self.value_expression(index, &[]))
+            .collect();
+        let indexes = self.open_loops(&to.dimensions);
+        all_indexes.extend(indexes.iter().cloned());
+        let from = self.value_expression(from, &all_indexes);
+        let to = self.value_expression(to, &indexes);
+        self.line(&format!("{} = {};", to, from));
+        self.close_loops(indexes);
+    }
+
+    fn convert_store(&mut self, from: &i::Value, to: &i::Value, to_indexes: &[i::Value]) {
+        let mut all_indexes: Vec<_> = to_indexes
+            .iter()
+            .map(|index| self.value_expression(index, &[]))
+            .collect();
+        let indexes = self.open_loops(&from.dimensions);
+        all_indexes.extend(indexes.iter().cloned());
+        let from = self.value_expression(from, &indexes);
+        let to = self.value_expression(to, &all_indexes);
+        self.line(&format!("{} = {};", to, from));
+        self.close_loops(indexes);
+    }
+
+    fn convert_unary_expression(&mut self, op: &i::UnaryOperator, a: &i::Value, x: &i::Value) {
+        let indexes = self.open_loops(&x.dimensions);
+        let a = self.value_expression(a, &indexes);
+        let x = self.value_expression(x, &indexes);
+        self.line(&format!("{} = {};", x, unary_expression(op, &a)));
+        self.close_loops(indexes);
+    }
+
+    fn convert_binary_expression(
+        &mut self,
+        op: &i::BinaryOperator,
+        a: &i::Value,
+        b: &i::Value,
+        x: &i::Value,
+    ) {
+        let indexes = self.open_loops(&x.dimensions);
+        let a = self.value_expression(a, &indexes);
+        let b = self.value_expression(b, &indexes);
+        let x = self.value_expression(x, &indexes);
+        self.line(&format!("{} = {};", x, binary_expression(op, &a, &b)));
+        self.close_loops(indexes);
+    }
+
+    fn convert_select(&mut self, condition: &i::Value, a: &i::Value, b: &i::Value, x: &i::Value) {
+        let indexes = self.open_loops(&x.dimensions);
+        let condition = self.value_expression(condition, &indexes);
+        let a = self.value_expression(a, &indexes);
+        let b = self.value_expression(b, &indexes);
+        let x = self.value_expression(x, &indexes);
+        self.line(&format!("{} = {} ? {} : {};", x, condition, a, b));
+        self.close_loops(indexes);
+    }
+
+    fn convert_call_extern(&mut self, function: usize, inputs: &[i::Value], outputs: &[i::Value]) {
+        let mut args = Vec::new();
+        for input in inputs {
+            if !input.dimensions.is_empty() {
+                args.push(self.pointer_expression(input, true));
+            } else {
+                args.push(self.value_expression(input, &[]));
+            }
+        }
+        for output in outputs {
+            args.push(self.pointer_expression(output, false));
+        }
+        let name = self.source.borrow_extern_functions()[function].borrow_name();
+        self.line(&format!("{}({});", name, args.join(", ")));
+    }
+
+    fn convert_instruction(&mut self, instruction: &i::Instruction) {
+        match instruction {
+            i::Instruction::Abort(error_code) => self.line(&format!("return {};", error_code)),
+            // There is no portable way to tell a C compiler to assume something.
+            i::Instruction::Assume(..) => (),
+            i::Instruction::CallExtern {
+                function,
+                inputs,
+                outputs,
+            } => self.convert_call_extern(*function, inputs, outputs),
+            i::Instruction::BinaryOperation { op, a, b, x } => {
+                self.convert_binary_expression(op, a, b, x)
+            }
+            i::Instruction::UnaryOperation { op, a, x } => self.convert_unary_expression(op, a, x),
+            i::Instruction::Select { condition, a, b, x } => {
+                self.convert_select(condition, a, b, x)
+            }
+            i::Instruction::Move { from, to } => self.convert_move(from, to),
+            i::Instruction::Label(id) => self.line(&format!("{:?}:;", id)),
+            i::Instruction::Branch {
+                condition,
+                true_target,
+                false_target,
+            } => {
+                let condition = self.value_expression(condition, &[]);
+                self.line(&format!(
+                    "if ({}) goto {:?}; else goto {:?};",
+                    condition, true_target, false_target
+                ));
+            }
+            i::Instruction::Jump { label } => self.line(&format!("goto {:?};", label)),
+            i::Instruction::Store {
+                from,
+                to,
+                to_indexes,
+            } => self.convert_store(from, to, to_indexes),
+            i::Instruction::Load {
+                from,
+                from_indexes,
+                to,
+            } => self.convert_load(from, from_indexes, to),
+        }
+    }
+
+    /// Writes a function returning an error code. Variables in the input, output, and static
+    /// structs are accessed through the parameters `in`, `out`, and `st`.
+    fn convert_body(
+        &mut self,
+        signature: &str,
+        body: i::CodeBody,
+        instructions: &[i::Instruction],
+    ) {
+        self.variable_expressions.clear();
+        self.line(&format!("uint32_t {} {{", signature));
+        self.indent += 1;
+
+        let source = self.source;
+        let input_location = body.get_input_location();
+        let output_location = body.get_output_location();
+        let local_location = body.get_local_location();
+        for var_id in source.iterate_all_variables() {
+            let location = source[var_id].get_location();
+            let name = format!("{:?}", var_id);
+            let expression = if Some(location) == input_location {
+                format!("in->{}", name)
+            } else if Some(location) == output_location {
+                format!("out->{}", name)
+            } else if location == i::StorageLocation::Static {
+                format!("st->{}", name)
+            } else if location == local_location {
+                self.line(&format!(
+                    "{};",
+                    declaration(source[var_id].borrow_type(), &name)
+                ));
+                name
+            } else {
+                continue;
+            };
+            self.variable_expressions.insert(var_id, expression);
+        }
+
+        for instruction in instructions {
+            self.convert_instruction(instruction);
+        }
+        self.line("return 0;");
+        self.indent -= 1;
+        self.line("}");
+        self.line("");
+    }
+
+    fn convert(&mut self) {
+        let source = self.source;
+        let prefix = self.prefix;
+        self.convert_body(
+            &format!("{0}_static_init({0}_static_t *st)", prefix),
+            i::CodeBody::StaticInit,
+            &source.borrow_static_init_instructions()[..],
+        );
+        self.convert_body(
+            &format!(
+                "{0}_main({0}_input_t *in, {0}_static_t *st, {0}_output_t *out)",
+                prefix
+            ),
+            i::CodeBody::Main,
+            &source.borrow_instructions()[..],
+        );
+        for (index, entry_point) in source.borrow_entry_points().iter().enumerate() {
+            self.convert_body(
+                &format!(
+                    "{0}_entry_{1}({0}_{1}_input_t *in, {0}_static_t *st, {0}_{1}_output_t *out)",
+                    prefix,
+                    entry_point.borrow_name()
+                ),
+                i::CodeBody::EntryPoint(index),
+                &entry_point.borrow_instructions()[..],
+            );
+        }
+    }
+}
+
+/// Defines a struct containing every variable stored in `location`, in the order they were
+/// declared.
+fn struct_definition(source: &i::Program, location: i::StorageLocation, name: &str) -> String {
+    let mut result = "typedef struct {\n".to_owned();
+    let mut empty = true;
+    for var_id in source.iterate_all_variables() {
+        if source[var_id].get_location() != location {
+            continue;
+        }
+        empty = false;
+        let member = declaration(source[var_id].borrow_type(), &format!("{:?}", var_id));
+        write!(result, "    {};", member).unwrap();
+        let original_name = source
+            .borrow_static_var_name(var_id)
+            .or_else(|| source.borrow_specializable_input_name(var_id));
+        if let Some(original_name) = original_name {
+            write!(result, " /* {} */", original_name).unwrap();
+        }
+        result.push('\n');
+    }
+    if empty {
+        // C does not allow empty structs.
+        result.push_str("    uint8_t unused;\n");
+    }
+    writeln!(result, "}} {};", name).unwrap();
+    result
+}
+
+/// Declares a function matching the calling convention described by `vague::ExternFunction`.
+fn extern_declaration(function: &i::ExternFunction) -> String {
+    let mut params = Vec::new();
+    for typ in function.borrow_inputs() {
+        params.push(match typ {
+            i::DataType::Array(..) => format!("const {} *", base_type_name(typ)),
+            _ => base_type_name(typ).to_owned(),
+        });
+    }
+    for typ in function.borrow_outputs() {
+        params.push(format!("{} *", base_type_name(typ)));
+    }
+    if params.is_empty() {
+        params.push("void".to_owned());
+    }
+    format!(
+        "extern void {}({});\n",
+        function.borrow_name(),
+        params.join(", ")
+    )
+}
+
+/// Converts the program to a self-contained C99 file, prefixing every type and function it
+/// defines with `prefix`. The file contains:
+/// - `<prefix>_input_t`, `<prefix>_output_t`, and `<prefix>_static_t`, which have the same layout
+///   as the data used by the LLVM version of the program, except that empty structs contain a
+///   single unused byte.
+/// - `<prefix>_static_init` and `<prefix>_main`, which return 0 on success or an error code.
+/// - `<prefix>_entry_<name>` for each entry point, taking `<prefix>_<name>_input_t` and
+///   `<prefix>_<name>_output_t`.
+/// - `<prefix>_describe_error`, which returns a description of an error code.
+///
+/// Extern functions are declared with the name they were added with and must be provided when
+/// linking.
+pub fn ingest(source: &i::Program, prefix: &str) -> String {
+    let mut converter = Converter {
+        source,
+        prefix,
+
+        array_literals: HashMap::new(),
+        array_literal_definitions: String::new(),
+
+        variable_expressions: HashMap::new(),
+        code: String::new(),
+        indent: 0,
+    };
+    converter.convert();
+
+    let mut result = String::new();
+    result.push_str("/* Generated by nodespeak. */\n\n");
+    result.push_str("#include <math.h>\n");
+    result.push_str("#include <stdint.h>\n\n");
+
+    // The input and output data are packed so that their layout can be more easily predicted by
+    // the host program, the same as in the LLVM version.
+    result.push_str("#pragma pack(push, 1)\n");
+    let input_name = format!("{}_input_t", prefix);
+    result.push_str(&struct_definition(
+        source,
+        i::StorageLocation::Input,
+        &input_name,
+    ));
+    let output_name = format!("{}_output_t", prefix);
+    result.push_str(&struct_definition(
+        source,
+        i::StorageLocation::Output,
+        &output_name,
+    ));
+    for (index, entry_point) in source.borrow_entry_points().iter().enumerate() {
+        let name = entry_point.borrow_name();
+        let input_name = format!("{}_{}_input_t", prefix, name);
+        let input_location = i::StorageLocation::EntryInput(index);
+        result.push_str(&struct_definition(source, input_location, &input_name));
+        let output_name = format!("{}_{}_output_t", prefix, name);
+        let output_location = i::StorageLocation::EntryOutput(index);
+        result.push_str(&struct_definition(source, output_location, &output_name));
+    }
+    result.push_str("#pragma pack(pop)\n");
+    let static_name = format!("{}_static_t", prefix);
+    result.push_str(&struct_definition(
+        source,
+        i::StorageLocation::Static,
+        &static_name,
+    ));
+    result.push('\n');
+
+    for function in source.borrow_extern_functions() {
+        result.push_str(&extern_declaration(function));
+    }
+    if !source.borrow_extern_functions().is_empty() {
+        result.push('\n');
+    }
+
+    if !converter.array_literal_definitions.is_empty() {
+        result.push_str(&converter.array_literal_definitions);
+        result.push('\n');
+    }
+
+    writeln!(
+        result,
+        "static const char *const {}_error_descriptions[] = {{",
+        prefix
+    )
+    .unwrap();
+    result.push_str("    \"Success\",\n");
+    for error in source.borrow_errors() {
+        let description = format!("{:?}: {}", error.get_kind(), error.borrow_message());
+        writeln!(result, "    {},", string_literal(&description)).unwrap();
+    }
+    result.push_str("};\n\n");
+    writeln!(
+        result,
+        "const char *{}_describe_error(uint32_t code) {{",
+        prefix
+    )
+    .unwrap();
+    writeln!(
+        result,
+        "    if (code > {}) {{",
+        source.borrow_errors().len()
+    )
+    .unwrap();
+    result.push_str("        return \"Unknown error\";\n");
+    result.push_str("    }\n");
+    writeln!(result, "    return {}_error_descriptions[code];", prefix).unwrap();
+    result.push_str("}\n\n");
+
+    result.push_str(&converter.code);
+    result
+}
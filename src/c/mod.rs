@@ -0,0 +1,32 @@
+mod ingest;
+
+pub use ingest::ingest;
+
+use crate::high_level::backend::Backend;
+
+/// Produces a self-contained C99 file, see `ingest` for details.
+#[derive(Clone, Debug)]
+pub struct CBackend {
+    prefix: String,
+}
+
+impl CBackend {
+    /// Every type and function in the generated file will start with `prefix`.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_owned(),
+        }
+    }
+}
+
+impl Backend for CBackend {
+    type Output = String;
+
+    fn borrow_name(&self) -> &str {
+        "c"
+    }
+
+    fn compile(&mut self, source: &crate::trivial::structure::Program) -> Result<String, String> {
+        Ok(ingest(source, &self.prefix))
+    }
+}
@@ -0,0 +1,884 @@
+use super::structure as o;
+use crate::shared::{self, ProxyMode};
+use crate::trivial::structure as i;
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, Signature, StackSlotData,
+    StackSlotKind, Type, Value,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
+use std::collections::{HashMap, HashSet};
+
+extern "C" fn sin_f32(x: f32) -> f32 {
+    x.sin()
+}
+
+extern "C" fn cos_f32(x: f32) -> f32 {
+    x.cos()
+}
+
+extern "C" fn exp_f32(x: f32) -> f32 {
+    x.exp()
+}
+
+extern "C" fn exp2_f32(x: f32) -> f32 {
+    x.exp2()
+}
+
+extern "C" fn log_f32(x: f32) -> f32 {
+    x.ln()
+}
+
+extern "C" fn log10_f32(x: f32) -> f32 {
+    x.log10()
+}
+
+extern "C" fn log2_f32(x: f32) -> f32 {
+    x.log2()
+}
+
+extern "C" fn pow_f32(x: f32, y: f32) -> f32 {
+    x.powf(y)
+}
+
+extern "C" fn fmod_f32(x: f32, y: f32) -> f32 {
+    x % y
+}
+
+/// Math functions which Cranelift has no instruction for, along with how many arguments they
+/// take. They are implemented in Rust and linked into the module.
+fn math_functions() -> Vec<(&'static str, *const u8, usize)> {
+    vec![
+        ("math.sin", sin_f32 as *const u8, 1),
+        ("math.cos", cos_f32 as *const u8, 1),
+        ("math.exp", exp_f32 as *const u8, 1),
+        ("math.exp2", exp2_f32 as *const u8, 1),
+        ("math.log", log_f32 as *const u8, 1),
+        ("math.log10", log10_f32 as *const u8, 1),
+        ("math.log2", log2_f32 as *const u8, 1),
+        ("math.pow", pow_f32 as *const u8, 2),
+        ("math.fmod", fmod_f32 as *const u8, 2),
+    ]
+}
+
+fn type_size(typ: &i::DataType) -> usize {
+    match typ {
+        i::DataType::B1 => 1,
+        i::DataType::I32 | i::DataType::F32 => 4,
+        i::DataType::Array(len, etype) => len * type_size(etype),
+    }
+}
+
+fn type_alignment(typ: &i::DataType) -> usize {
+    match typ {
+        i::DataType::B1 => 1,
+        i::DataType::I32 | i::DataType::F32 => 4,
+        i::DataType::Array(_, etype) => type_alignment(etype),
+    }
+}
+
+fn base_type(typ: &i::DataType) -> &i::DataType {
+    match typ {
+        i::DataType::Array(_, etype) => base_type(etype),
+        _ => typ,
+    }
+}
+
+/// Bools are stored as a full byte which is either 0 or 1, the same as in the LLVM version.
+fn clif_type(typ: &i::DataType) -> Type {
+    match base_type(typ) {
+        i::DataType::B1 => types::I8,
+        i::DataType::I32 => types::I32,
+        _ => types::F32,
+    }
+}
+
+fn align(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+/// Assigns an offset to every variable stored in `location`, laid out the same way LLVM lays out
+/// packed or regular structs. Returns the size of the whole struct.
+fn layout_struct(
+    source: &i::Program,
+    location: i::StorageLocation,
+    packed: bool,
+    offsets: &mut HashMap<i::VariableId, usize>,
+) -> usize {
+    let mut size = 0;
+    let mut max_alignment = 1;
+    for var_id in source.iterate_all_variables() {
+        let typ = source[var_id].borrow_type();
+        if source[var_id].get_location() != location {
+            continue;
+        }
+        if !packed {
+            size = align(size, type_alignment(typ));
+            max_alignment = max_alignment.max(type_alignment(typ));
+        }
+        offsets.insert(var_id, size);
+        size += type_size(typ);
+    }
+    if !packed {
+        size = align(size, max_alignment);
+    }
+    size
+}
+
+#[derive(Clone, Copy)]
+enum Storage {
+    Variable(Variable),
+    /// Memory at a constant offset from an address.
+    Memory(Value, usize),
+}
+
+enum Index<'v> {
+    Static(usize),
+    Dynamic(&'v i::Value),
+}
+
+fn static_indexes<'v>(coord: &[usize]) -> Vec<Index<'v>> {
+    coord.iter().map(|index| Index::Static(*index)).collect()
+}
+
+fn dynamic_index(value: &i::Value) -> Index<'_> {
+    match &value.base {
+        i::ValueBase::Literal(i::KnownData::Int(index)) => Index::Static(*index as usize),
+        _ => Index::Dynamic(value),
+    }
+}
+
+fn apply_proxy<'v>(value: &i::Value, indexes: Vec<Index<'v>>) -> Vec<Index<'v>> {
+    debug_assert!(value.dimensions.len() == indexes.len());
+    let mut result = Vec::new();
+    for ((_, mode), index) in value.dimensions.iter().zip(indexes) {
+        match mode {
+            ProxyMode::Keep => result.push(index),
+            ProxyMode::Collapse => result.push(Index::Static(0)),
+            ProxyMode::Discard => (),
+        }
+    }
+    result
+}
+
+fn coordinates(value: &i::Value) -> shared::NDIndexIter {
+    shared::NDIndexIter::new(value.dimensions.iter().map(|(len, _)| *len).collect())
+}
+
+/// Things which stay the same for every function in the module.
+struct ModuleInfo<'a> {
+    source: &'a i::Program,
+    pointer_type: Type,
+    /// The offset of every variable in the input, output, and static data.
+    struct_offsets: HashMap<i::VariableId, usize>,
+    /// Variables which are passed to extern functions by pointer, so they have to be in memory.
+    addressed: HashSet<i::VariableId>,
+    math_functions: HashMap<&'static str, FuncId>,
+    extern_functions: Vec<FuncId>,
+    /// Array literals which are indexed at runtime, keyed by their contents.
+    literal_data: HashMap<String, DataId>,
+}
+
+struct Converter<'a, 'f> {
+    info: &'a ModuleInfo<'a>,
+    module: &'a mut JITModule,
+    builder: FunctionBuilder<'f>,
+    storage: HashMap<i::VariableId, Storage>,
+    labels: HashMap<i::LabelId, Block>,
+    function_refs: HashMap<FuncId, FuncRef>,
+    /// The address of each array literal in `ModuleInfo::literal_data`.
+    literal_addresses: HashMap<String, Value>,
+}
+
+impl<'a, 'f> Converter<'a, 'f> {
+    fn function_ref(&mut self, id: FuncId) -> FuncRef {
+        if let Some(function_ref) = self.function_refs.get(&id) {
+            return *function_ref;
+        }
+        let function_ref = self.module.declare_func_in_func(id, self.builder.func);
+        self.function_refs.insert(id, function_ref);
+        function_ref
+    }
+
+    fn call_math(&mut self, name: &str, args: &[Value]) -> Value {
+        let function_ref = self.function_ref(self.info.math_functions[name]);
+        let call = self.builder.ins().call(function_ref, args);
+        self.builder.inst_results(call)[0]
+    }
+
+    /// Returns the address of an element of a variable along with a constant offset which should
+    /// be added to it.
+    fn build_address(
+        &mut self,
+        base: Value,
+        offset: usize,
+        typ: &i::DataType,
+        indexes: &[Index],
+    ) -> (Value, usize) {
+        let mut address = base;
+        let mut offset = offset;
+        let mut typ = typ;
+        for index in indexes {
+            let etype = if let i::DataType::Array(_, etype) = typ {
+                etype
+            } else {
+                unreachable!("Illegal indexes should be caught earlier.")
+            };
+            let stride = type_size(etype);
+            match index {
+                Index::Static(index) => offset += index * stride,
+                Index::Dynamic(value) => {
+                    let index = self.load_value(value, Vec::new());
+                    let index = self.builder.ins().uextend(self.info.pointer_type, index);
+                    let index = self.builder.ins().imul_imm(index, stride as i64);
+                    address = self.builder.ins().iadd(address, index);
+                }
+            }
+            typ = etype;
+        }
+        (address, offset)
+    }
+
+    fn constant(&mut self, data: &i::KnownData) -> Value {
+        match data {
+            i::KnownData::Bool(value) => self.builder.ins().iconst(types::I8, *value as i64),
+            i::KnownData::Int(value) => self.builder.ins().iconst(types::I32, *value),
+            i::KnownData::Float(value) => self.builder.ins().f32const(*value as f32),
+            i::KnownData::Array(..) => unreachable!("Arrays cannot be used as a single value."),
+        }
+    }
+
+    /// Loads the element of `value` at `indexes`, which should contain one index for every
+    /// dimension of the value before its proxy is applied.
+    fn load_value(&mut self, value: &i::Value, indexes: Vec<Index>) -> Value {
+        let indexes = apply_proxy(value, indexes);
+        let source = self.info.source;
+        match &value.base {
+            i::ValueBase::Variable(id) => {
+                let typ = source[*id].borrow_type();
+                match self.storage[id] {
+                    Storage::Variable(variable) => {
+                        assert!(indexes.is_empty(), "Cannot index scalar data.");
+                        self.builder.use_var(variable)
+                    }
+                    Storage::Memory(base, offset) => {
+                        let (address, offset) = self.build_address(base, offset, typ, &indexes);
+                        let flags = MemFlags::new().with_notrap();
+                        let ctype = clif_type(typ);
+                        self.builder
+                            .ins()
+                            .load(ctype, flags, address, offset as i32)
+                    }
+                }
+            }
+            i::ValueBase::Literal(data) => {
+                let mut element = data;
+                for index in &indexes {
+                    match (index, element) {
+                        (Index::Static(index), i::KnownData::Array(items)) => {
+                            element = &items[*index];
+                        }
+                        _ => break,
+                    }
+                }
+                if let i::KnownData::Array(..) = element {
+                    let base = self.literal_addresses[&format!("{:?}", data)];
+                    let typ = data.get_type();
+                    let (address, offset) = self.build_address(base, 0, &typ, &indexes);
+                    let flags = MemFlags::trusted().with_readonly();
+                    let ctype = clif_type(&typ);
+                    self.builder
+                        .ins()
+                        .load(ctype, flags, address, offset as i32)
+                } else {
+                    self.constant(element)
+                }
+            }
+        }
+    }
+
+    fn store_value(&mut self, to: &i::Value, indexes: Vec<Index>, content: Value) {
+        let indexes = apply_proxy(to, indexes);
+        let source = self.info.source;
+        match &to.base {
+            i::ValueBase::Variable(id) => match self.storage[id] {
+                Storage::Variable(variable) => {
+                    assert!(indexes.is_empty(), "Cannot index scalar data.");
+                    self.builder.def_var(variable, content);
+                }
+                Storage::Memory(base, offset) => {
+                    let typ = source[*id].borrow_type();
+                    let (address, offset) = self.build_address(base, offset, typ, &indexes);
+                    let flags = MemFlags::new().with_notrap();
+                    self.builder
+                        .ins()
+                        .store(flags, content, address, offset as i32);
+                }
+            },
+            i::ValueBase::Literal(..) => panic!("Cannot store to a constant."),
+        }
+    }
+
+    /// Returns the address of a variable which is passed by pointer.
+    fn variable_pointer(&mut self, value: &i::Value) -> Value {
+        if let i::ValueBase::Variable(id) = &value.base {
+            if let Storage::Memory(base, offset) = self.storage[id] {
+                self.builder.ins().iadd_imm(base, offset as i64)
+            } else {
+                unreachable!("Variables passed by pointer should be stored in memory.")
+            }
+        } else {
+            unreachable!("Only variables can be passed by pointer.")
+        }
+    }
+
+    fn build_unary_op(&mut self, op: &i::UnaryOperator, a: Value) -> Value {
+        let ins = self.builder.ins();
+        match op {
+            i::UnaryOperator::BNot => ins.bnot(a),
+            i::UnaryOperator::FAbs => ins.fabs(a),
+            i::UnaryOperator::FCeil => ins.ceil(a),
+            i::UnaryOperator::FFloor => ins.floor(a),
+            i::UnaryOperator::FTrunc => ins.trunc(a),
+            i::UnaryOperator::FSqrt => ins.sqrt(a),
+            i::UnaryOperator::IAbs => ins.iabs(a),
+            // Computed as 0 - a like the LLVM version, which gives 0 instead of -0 when a is 0.
+            i::UnaryOperator::NegF => {
+                let zero = ins.f32const(0.0);
+                self.builder.ins().fsub(zero, a)
+            }
+            i::UnaryOperator::NegI => ins.ineg(a),
+            i::UnaryOperator::Not => ins.bxor_imm(a, 1),
+            // The saturating version is used because the LLVM version does not trap either.
+            i::UnaryOperator::Ftoi => ins.fcvt_to_sint_sat(types::I32, a),
+            i::UnaryOperator::Itof => ins.fcvt_from_sint(types::F32, a),
+            i::UnaryOperator::FSin => self.call_math("math.sin", &[a]),
+            i::UnaryOperator::FCos => self.call_math("math.cos", &[a]),
+            i::UnaryOperator::FExp => self.call_math("math.exp", &[a]),
+            i::UnaryOperator::FExp2 => self.call_math("math.exp2", &[a]),
+            i::UnaryOperator::FLog => self.call_math("math.log", &[a]),
+            i::UnaryOperator::FLog10 => self.call_math("math.log10", &[a]),
+            i::UnaryOperator::FLog2 => self.call_math("math.log2", &[a]),
+        }
+    }
+
+    fn build_binary_op(&mut self, op: &i::BinaryOperator, a: Value, b: Value) -> Value {
+        let ins = self.builder.ins();
+        match op {
+            i::BinaryOperator::AddI => ins.iadd(a, b),
+            i::BinaryOperator::SubI => ins.isub(a, b),
+            i::BinaryOperator::MulI => ins.imul(a, b),
+            i::BinaryOperator::DivI => ins.sdiv(a, b),
+            i::BinaryOperator::ModI => ins.srem(a, b),
+            i::BinaryOperator::AddF => ins.fadd(a, b),
+            i::BinaryOperator::SubF => ins.fsub(a, b),
+            i::BinaryOperator::MulF => ins.fmul(a, b),
+            i::BinaryOperator::DivF => ins.fdiv(a, b),
+            i::BinaryOperator::ModF => self.call_math("math.fmod", &[a, b]),
+            i::BinaryOperator::PowF => self.call_math("math.pow", &[a, b]),
+            i::BinaryOperator::BAnd | i::BinaryOperator::And => ins.band(a, b),
+            i::BinaryOperator::BOr | i::BinaryOperator::Or => ins.bor(a, b),
+            i::BinaryOperator::BXor | i::BinaryOperator::Xor => ins.bxor(a, b),
+            i::BinaryOperator::LeftShift => ins.ishl(a, b),
+            i::BinaryOperator::RightShift => ins.ushr(a, b),
+            i::BinaryOperator::CompI(condition) => {
+                let condition = match condition {
+                    i::Condition::Equal => IntCC::Equal,
+                    i::Condition::NotEqual => IntCC::NotEqual,
+                    i::Condition::GreaterThan => IntCC::SignedGreaterThan,
+                    i::Condition::GreaterThanOrEqual => IntCC::SignedGreaterThanOrEqual,
+                    i::Condition::LessThan => IntCC::SignedLessThan,
+                    i::Condition::LessThanOrEqual => IntCC::SignedLessThanOrEqual,
+                };
+                ins.icmp(condition, a, b)
+            }
+            i::BinaryOperator::CompF(condition) => {
+                // Ordered comparisons, the same as the LLVM version.
+                let condition = match condition {
+                    i::Condition::Equal => FloatCC::Equal,
+                    i::Condition::NotEqual => FloatCC::OrderedNotEqual,
+                    i::Condition::GreaterThan => FloatCC::GreaterThan,
+                    i::Condition::GreaterThanOrEqual => FloatCC::GreaterThanOrEqual,
+                    i::Condition::LessThan => FloatCC::LessThan,
+                    i::Condition::LessThanOrEqual => FloatCC::LessThanOrEqual,
+                };
+                ins.fcmp(condition, a, b)
+            }
+        }
+    }
+
+    fn convert_unary_expression(&mut self, op: &i::UnaryOperator, a: &i::Value, x: &i::Value) {
+        for coord in coordinates(x) {
+            let ar = self.load_value(a, static_indexes(&coord));
+            let result = self.build_unary_op(op, ar);
+            self.store_value(x, static_indexes(&coord), result);
+        }
+    }
+
+    fn convert_binary_expression(
+        &mut self,
+        op: &i::BinaryOperator,
+        a: &i::Value,
+        b: &i::Value,
+        x: &i::Value,
+    ) {
+        for coord in coordinates(x) {
+            let ar = self.load_value(a, static_indexes(&coord));
+            let br = self.load_value(b, static_indexes(&coord));
+            let result = self.build_binary_op(op, ar, br);
+            self.store_value(x, static_indexes(&coord), result);
+        }
+    }
+
+    fn convert_select(&mut self, condition: &i::Value, a: &i::Value, b: &i::Value, x: &i::Value) {
+        for coord in coordinates(x) {
+            let cr = self.load_value(condition, static_indexes(&coord));
+            let ar = self.load_value(a, static_indexes(&coord));
+            let br = self.load_value(b, static_indexes(&coord));
+            let result = self.builder.ins().select(cr, ar, br);
+            self.store_value(x, static_indexes(&coord), result);
+        }
+    }
+
+    fn convert_move(&mut self, from: &i::Value, to: &i::Value) {
+        for coord in coordinates(to) {
+            let content = self.load_value(from, static_indexes(&coord));
+            self.store_value(to, static_indexes(&coord), content);
+        }
+    }
+
+    fn convert_load(&mut self, from: &i::Value, from_indexes: &[i::Value], to: &i::Value) {
+        for coord in coordinates(to) {
+            let mut indexes: Vec<_> = from_indexes.iter().map(dynamic_index).collect();
+            indexes.append(&mut static_indexes(&coord));
+            let content = self.load_value(from, indexes);
+            self.store_value(to, static_indexes(&coord), content);
+        }
+    }
+
+    fn convert_store(&mut self, from: &i::Value, to: &i::Value, to_indexes: &[i::Value]) {
+        for coord in coordinates(from) {
+            let content = self.load_value(from, static_indexes(&coord));
+            let mut indexes: Vec<_> = to_indexes.iter().map(dynamic_index).collect();
+            indexes.append(&mut static_indexes(&coord));
+            self.store_value(to, indexes, content);
+        }
+    }
+
+    fn convert_call_extern(&mut self, function: usize, inputs: &[i::Value], outputs: &[i::Value]) {
+        let mut args = Vec::new();
+        for input in inputs {
+            if input.dimensions.is_empty() {
+                args.push(self.load_value(input, Vec::new()));
+            } else {
+                args.push(self.variable_pointer(input));
+            }
+        }
+        for output in outputs {
+            args.push(self.variable_pointer(output));
+        }
+        let function_ref = self.function_ref(self.info.extern_functions[function]);
+        self.builder.ins().call(function_ref, &args[..]);
+    }
+
+    /// Code after a jump cannot be reached until the next label, but Cranelift still needs a
+    /// block to put it in.
+    fn start_unreachable_block(&mut self) {
+        let block = self.builder.create_block();
+        self.builder.switch_to_block(block);
+    }
+
+    fn convert_instruction(&mut self, instruction: &i::Instruction) {
+        match instruction {
+            i::Instruction::Abort(error_code) => {
+                let code = self.builder.ins().iconst(types::I32, *error_code as i64);
+                self.builder.ins().return_(&[code]);
+                self.start_unreachable_block();
+            }
+            // Cranelift has nothing equivalent to an assume instruction.
+            i::Instruction::Assume(..) => (),
+            i::Instruction::CallExtern {
+                function,
+                inputs,
+                outputs,
+            } => self.convert_call_extern(*function, inputs, outputs),
+            i::Instruction::BinaryOperation { op, a, b, x } => {
+                self.convert_binary_expression(op, a, b, x)
+            }
+            i::Instruction::UnaryOperation { op, a, x } => self.convert_unary_expression(op, a, x),
+            i::Instruction::Select { condition, a, b, x } => {
+                self.convert_select(condition, a, b, x)
+            }
+            i::Instruction::Move { from, to } => self.convert_move(from, to),
+            i::Instruction::Label(id) => {
+                let block = self.labels[id];
+                self.builder.ins().jump(block, &[]);
+                self.builder.switch_to_block(block);
+            }
+            i::Instruction::Branch {
+                condition,
+                true_target,
+                false_target,
+            } => {
+                let condition = self.load_value(condition, Vec::new());
+                let (true_block, false_block) =
+                    (self.labels[true_target], self.labels[false_target]);
+                self.builder
+                    .ins()
+                    .brif(condition, true_block, &[], false_block, &[]);
+                self.start_unreachable_block();
+            }
+            i::Instruction::Jump { label } => {
+                let block = self.labels[label];
+                self.builder.ins().jump(block, &[]);
+                self.start_unreachable_block();
+            }
+            i::Instruction::Store {
+                from,
+                to,
+                to_indexes,
+            } => self.convert_store(from, to, to_indexes),
+            i::Instruction::Load {
+                from,
+                from_indexes,
+                to,
+            } => self.convert_load(from, from_indexes, to),
+        }
+    }
+
+    /// Decides where each variable used by the body is stored. `params` are the input, static,
+    /// and output pointers. This also computes the addresses of array literals, so it should be
+    /// called at the start of the entry block.
+    fn assign_storage(&mut self, body: i::CodeBody, params: &[Value]) {
+        let source = self.info.source;
+        let (input_param, static_param, output_param) = if body == i::CodeBody::StaticInit {
+            (None, params[0], None)
+        } else {
+            (Some(params[0]), params[1], Some(params[2]))
+        };
+        let input_location = body.get_input_location();
+        let output_location = body.get_output_location();
+        let local_location = body.get_local_location();
+        for var_id in source.iterate_all_variables() {
+            let typ = source[var_id].borrow_type();
+            let location = source[var_id].get_location();
+            let storage = if Some(location) == input_location {
+                Storage::Memory(input_param.unwrap(), self.info.struct_offsets[&var_id])
+            } else if Some(location) == output_location {
+                Storage::Memory(output_param.unwrap(), self.info.struct_offsets[&var_id])
+            } else if location == i::StorageLocation::Static {
+                Storage::Memory(static_param, self.info.struct_offsets[&var_id])
+            } else if location == local_location {
+                let is_array = matches!(typ, i::DataType::Array(..));
+                if is_array || self.info.addressed.contains(&var_id) {
+                    let size = type_size(typ) as u32;
+                    let align_shift = type_alignment(typ).trailing_zeros() as u8;
+                    let slot_data =
+                        StackSlotData::new(StackSlotKind::ExplicitSlot, size, align_shift);
+                    let slot = self.builder.create_sized_stack_slot(slot_data);
+                    let address = self
+                        .builder
+                        .ins()
+                        .stack_addr(self.info.pointer_type, slot, 0);
+                    Storage::Memory(address, 0)
+                } else {
+                    let variable = Variable::from_u32(self.storage.len() as u32);
+                    let ctype = clif_type(typ);
+                    self.builder.declare_var(variable, ctype);
+                    // Cranelift requires variables to be defined before they are used, even if
+                    // the value is never actually read.
+                    let zero = if ctype == types::F32 {
+                        self.builder.ins().f32const(0.0)
+                    } else {
+                        self.builder.ins().iconst(ctype, 0)
+                    };
+                    self.builder.def_var(variable, zero);
+                    Storage::Variable(variable)
+                }
+            } else {
+                continue;
+            };
+            self.storage.insert(var_id, storage);
+        }
+        for (key, id) in self.info.literal_data.iter() {
+            let global = self.module.declare_data_in_func(*id, self.builder.func);
+            let pointer_type = self.info.pointer_type;
+            let address = self.builder.ins().symbol_value(pointer_type, global);
+            self.literal_addresses.insert(key.clone(), address);
+        }
+    }
+
+    fn convert_body(mut self, body: i::CodeBody, instructions: &[i::Instruction]) {
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let params = self.builder.block_params(entry).to_owned();
+        self.assign_storage(body, &params);
+        for instruction in instructions {
+            if let i::Instruction::Label(id) = instruction {
+                let block = self.builder.create_block();
+                self.labels.insert(*id, block);
+            }
+        }
+        for instruction in instructions {
+            self.convert_instruction(instruction);
+        }
+        let success = self.builder.ins().iconst(types::I32, 0);
+        self.builder.ins().return_(&[success]);
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+}
+
+/// Finds variables which have to be stored in memory because they are passed by pointer, and
+/// array literals which are indexed at runtime.
+fn prepare(source: &i::Program) -> (HashSet<i::VariableId>, Vec<&i::KnownData>) {
+    let mut addressed = HashSet::new();
+    let mut literals = Vec::new();
+    let mut bodies = vec![
+        source.borrow_static_init_instructions(),
+        source.borrow_instructions(),
+    ];
+    for entry_point in source.borrow_entry_points() {
+        bodies.push(entry_point.borrow_instructions());
+    }
+    for instruction in bodies.into_iter().flatten() {
+        match instruction {
+            i::Instruction::CallExtern {
+                inputs, outputs, ..
+            } => {
+                for value in inputs.iter().chain(outputs.iter()) {
+                    if let i::ValueBase::Variable(id) = &value.base {
+                        addressed.insert(*id);
+                    }
+                }
+            }
+            i::Instruction::Load { from, .. } => {
+                if let i::ValueBase::Literal(data @ i::KnownData::Array(..)) = &from.base {
+                    literals.push(data);
+                }
+            }
+            _ => (),
+        }
+    }
+    (addressed, literals)
+}
+
+fn body_signature(module: &JITModule, num_params: usize) -> Signature {
+    let pointer_type = module.target_config().pointer_type();
+    let mut signature = module.make_signature();
+    for _ in 0..num_params {
+        signature.params.push(AbiParam::new(pointer_type));
+    }
+    signature.returns.push(AbiParam::new(types::I32));
+    signature
+}
+
+/// Declares a function matching the calling convention described by `vague::ExternFunction`.
+fn extern_signature(module: &JITModule, function: &i::ExternFunction) -> Signature {
+    let pointer_type = module.target_config().pointer_type();
+    let mut signature = module.make_signature();
+    for typ in function.borrow_inputs() {
+        signature.params.push(match typ {
+            i::DataType::Array(..) => AbiParam::new(pointer_type),
+            // Hosts expect bools to be passed as a full byte which is either 0 or 1.
+            i::DataType::B1 => AbiParam::new(types::I8).uext(),
+            _ => AbiParam::new(clif_type(typ)),
+        });
+    }
+    for _ in function.borrow_outputs() {
+        signature.params.push(AbiParam::new(pointer_type));
+    }
+    signature
+}
+
+fn create_module(source: &i::Program) -> JITModule {
+    let mut flag_builder = settings::builder();
+    flag_builder.set("opt_level", "speed").unwrap();
+    let isa = cranelift_native::builder()
+        .expect("The host is not supported by Cranelift.")
+        .finish(settings::Flags::new(flag_builder))
+        .unwrap();
+    let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+    for (name, address, _) in math_functions() {
+        builder.symbol(name, address);
+    }
+    for function in source.borrow_extern_functions() {
+        let name = format!("extern.{}", function.borrow_name());
+        builder.symbol(name, function.get_address() as *const u8);
+    }
+    JITModule::new(builder)
+}
+
+/// Compiles the program with Cranelift. The resulting program uses the same data layout as the
+/// LLVM version.
+pub fn ingest(source: &i::Program) -> o::Program {
+    let mut module = create_module(source);
+
+    let mut struct_offsets = HashMap::new();
+    let input_size = layout_struct(source, i::StorageLocation::Input, true, &mut struct_offsets);
+    let output_size = layout_struct(
+        source,
+        i::StorageLocation::Output,
+        true,
+        &mut struct_offsets,
+    );
+    let static_size = layout_struct(
+        source,
+        i::StorageLocation::Static,
+        false,
+        &mut struct_offsets,
+    );
+    let mut entry_sizes = Vec::new();
+    for index in 0..source.borrow_entry_points().len() {
+        let input = i::StorageLocation::EntryInput(index);
+        let output = i::StorageLocation::EntryOutput(index);
+        entry_sizes.push((
+            layout_struct(source, input, true, &mut struct_offsets),
+            layout_struct(source, output, true, &mut struct_offsets),
+        ));
+    }
+    let mut static_vars = Vec::new();
+    let mut static_keys = Vec::new();
+    let mut static_types = Vec::new();
+    for var_id in source.iterate_all_variables() {
+        if source[var_id].get_location() == i::StorageLocation::Static {
+            let typ = source[var_id].borrow_type();
+            if let Some(name) = source.borrow_static_var_name(var_id) {
+                static_vars.push((struct_offsets[&var_id], name.clone(), typ.clone()));
+            }
+            if let Some(key) = source.borrow_static_var_key(var_id) {
+                static_keys.push((struct_offsets[&var_id], key.clone(), typ.clone()));
+            }
+            static_types.push(typ);
+        }
+    }
+    let static_description = format!("{:?}", static_types);
+
+    let mut math_function_ids = HashMap::new();
+    for (name, _, num_args) in math_functions() {
+        let mut signature = module.make_signature();
+        for _ in 0..num_args {
+            signature.params.push(AbiParam::new(types::F32));
+        }
+        signature.returns.push(AbiParam::new(types::F32));
+        let id = module
+            .declare_function(name, Linkage::Import, &signature)
+            .unwrap();
+        math_function_ids.insert(name, id);
+    }
+    let mut extern_functions = Vec::new();
+    for function in source.borrow_extern_functions() {
+        let signature = extern_signature(&module, function);
+        let name = format!("extern.{}", function.borrow_name());
+        let id = module
+            .declare_function(&name, Linkage::Import, &signature)
+            .unwrap();
+        extern_functions.push(id);
+    }
+
+    let (addressed, literals) = prepare(source);
+    let mut literal_data = HashMap::new();
+    for literal in literals {
+        let key = format!("{:?}", literal);
+        if literal_data.contains_key(&key) {
+            continue;
+        }
+        let id = module.declare_anonymous_data(false, false).unwrap();
+        let mut description = DataDescription::new();
+        description.define(literal.arbitrary_len_binary_data().into_boxed_slice());
+        description.set_align(4);
+        module.define_data(id, &description).unwrap();
+        literal_data.insert(key, id);
+    }
+
+    let info = ModuleInfo {
+        source,
+        pointer_type: module.target_config().pointer_type(),
+        struct_offsets,
+        addressed,
+        math_functions: math_function_ids,
+        extern_functions,
+        literal_data,
+    };
+
+    let mut bodies = vec![
+        (
+            "static_init".to_owned(),
+            i::CodeBody::StaticInit,
+            source.borrow_static_init_instructions(),
+        ),
+        (
+            "main".to_owned(),
+            i::CodeBody::Main,
+            source.borrow_instructions(),
+        ),
+    ];
+    for (index, entry_point) in source.borrow_entry_points().iter().enumerate() {
+        bodies.push((
+            format!("entry.{}", entry_point.borrow_name()),
+            i::CodeBody::EntryPoint(index),
+            entry_point.borrow_instructions(),
+        ));
+    }
+    let mut context = Context::new();
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut ir = String::new();
+    let mut function_ids = Vec::new();
+    for (name, body, instructions) in bodies {
+        let num_params = if body == i::CodeBody::StaticInit {
+            1
+        } else {
+            3
+        };
+        context.func.signature = body_signature(&module, num_params);
+        let id = module
+            .declare_function(&name, Linkage::Local, &context.func.signature)
+            .unwrap();
+        let converter = Converter {
+            info: &info,
+            module: &mut module,
+            builder: FunctionBuilder::new(&mut context.func, &mut builder_context),
+            storage: HashMap::new(),
+            labels: HashMap::new(),
+            function_refs: HashMap::new(),
+            literal_addresses: HashMap::new(),
+        };
+        converter.convert_body(body, instructions);
+        ir.push_str(&format!("; {}\n{}\n", name, context.func.display()));
+        module
+            .define_function(id, &mut context)
+            .expect("Cranelift rejected generated code.");
+        module.clear_context(&mut context);
+        function_ids.push(id);
+    }
+    module.finalize_definitions().unwrap();
+
+    let functions: Vec<_> = function_ids
+        .iter()
+        .map(|id| module.get_finalized_function(*id))
+        .collect();
+    let mut program = o::Program::new(
+        module,
+        ir,
+        (functions[1], functions[0]),
+        (input_size, output_size, static_size),
+        static_description,
+        (static_vars, static_keys),
+        source.borrow_errors().clone(),
+    );
+    for (index, entry_point) in source.borrow_entry_points().iter().enumerate() {
+        let (input_size, output_size) = entry_sizes[index];
+        program.add_entry_point(
+            entry_point.borrow_name().clone(),
+            functions[index + 2],
+            input_size,
+            output_size,
+        );
+    }
+    program
+}
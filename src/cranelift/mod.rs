@@ -0,0 +1,26 @@
+mod ingest;
+pub mod structure;
+
+pub use ingest::ingest;
+
+use crate::high_level::backend::Backend;
+
+/// Compiles programs to native code with Cranelift, which is much faster than LLVM but produces
+/// less optimized code.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CraneliftBackend;
+
+impl Backend for CraneliftBackend {
+    type Output = structure::Program;
+
+    fn borrow_name(&self) -> &str {
+        "clif"
+    }
+
+    fn compile(
+        &mut self,
+        source: &crate::trivial::structure::Program,
+    ) -> Result<Self::Output, String> {
+        Ok(ingest(source))
+    }
+}
@@ -0,0 +1,352 @@
+use crate::high_level::backend::CompiledProgram;
+use crate::high_level::problem::RuntimeError;
+use crate::high_level::scratch::Scratch;
+use crate::high_level::static_data::{
+    StaticData, StaticDataOwner, StaticLayout, StaticMigration, StaticVariable,
+};
+use cranelift_jit::JITModule;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+use std::sync::Arc;
+
+type MainFunction = extern "C" fn(*mut u8, *mut u8, *mut u8) -> u32;
+type StaticInitFunction = extern "C" fn(*mut u8) -> u32;
+
+/// The offset of each variable of a particular kind in the static data, along with the name or
+/// key of the variable and its type.
+type StaticElements = Vec<(usize, String, crate::trivial::structure::DataType)>;
+
+struct EntryFunction {
+    function: MainFunction,
+    in_size: usize,
+    out_size: usize,
+}
+
+/// A program compiled by Cranelift. This has the same interface and data layout as the LLVM
+/// version, but is compiled much faster at the cost of less optimized code.
+pub struct Program {
+    module: Option<JITModule>,
+    /// The Cranelift IR of every function, kept for debugging.
+    ir: String,
+    function: MainFunction,
+    static_init: StaticInitFunction,
+    in_size: usize,
+    out_size: usize,
+    static_layout: Arc<StaticLayout>,
+    errors: Vec<RuntimeError>,
+    entry_points: HashMap<String, EntryFunction>,
+}
+
+impl Debug for Program {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        writeln!(formatter, "error codes:")?;
+        for error in self.errors.iter() {
+            writeln!(
+                formatter,
+                "  {}: {:?} {}",
+                error.get_code(),
+                error.get_kind(),
+                error.borrow_message()
+            )?;
+        }
+        write!(formatter, "Cranelift IR Code:\n{}", self.ir)
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Nothing can call the compiled functions anymore since they are only reachable
+            // through this program.
+            unsafe {
+                module.free_memory();
+            }
+        }
+    }
+}
+
+impl StaticDataOwner for Program {
+    fn borrow_static_layout(&self) -> &Arc<StaticLayout> {
+        &self.static_layout
+    }
+}
+
+impl CompiledProgram for Program {
+    fn borrow_errors(&self) -> &Vec<RuntimeError> {
+        Program::borrow_errors(self)
+    }
+
+    fn has_entry_point(&self, name: &str) -> bool {
+        Program::has_entry_point(self, name)
+    }
+
+    /// Cranelift programs always keep their local variables on the stack.
+    fn scratch_size(&self) -> usize {
+        0
+    }
+
+    unsafe fn reinit_static_data(&self, data: &mut StaticData) -> Result<(), RuntimeError> {
+        Program::reinit_static_data(self, data)
+    }
+
+    unsafe fn execute_raw(
+        &self,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+        static_data: &mut StaticData,
+        _scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError> {
+        Program::execute_raw(self, input_data, output_data, static_data)
+    }
+
+    unsafe fn execute_entry_raw(
+        &self,
+        name: &str,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+        static_data: &mut StaticData,
+        _scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError> {
+        Program::execute_entry_raw(self, name, input_data, output_data, static_data)
+    }
+}
+
+impl Program {
+    /// `main` and `static_init` must be the addresses of finalized functions in `module`, which
+    /// the program takes ownership of. `static_vars` lists the offset, name, and type of each
+    /// named variable in the static data, and `static_keys` does the same for each variable which
+    /// has a key.
+    pub(super) fn new(
+        module: JITModule,
+        ir: String,
+        (main, static_init): (*const u8, *const u8),
+        (in_size, out_size, static_size): (usize, usize, usize),
+        static_description: String,
+        (static_vars, static_keys): (StaticElements, StaticElements),
+        errors: Vec<RuntimeError>,
+    ) -> Self {
+        let locate = |elements: StaticElements| {
+            let mut variables = HashMap::new();
+            for (offset, name, data_type) in elements {
+                variables.insert(name, StaticVariable::new(offset, data_type));
+            }
+            variables
+        };
+        let static_layout = StaticLayout::new(
+            static_description.as_bytes(),
+            static_size,
+            locate(static_vars),
+            locate(static_keys),
+        );
+        Self {
+            module: Some(module),
+            ir,
+            function: unsafe { mem::transmute::<*const u8, MainFunction>(main) },
+            static_init: unsafe { mem::transmute::<*const u8, StaticInitFunction>(static_init) },
+            in_size,
+            out_size,
+            static_layout: Arc::new(static_layout),
+            errors,
+            entry_points: HashMap::new(),
+        }
+    }
+
+    /// Makes a finalized function in the module callable through `execute_entry`. The function
+    /// must have the same signature as the main function.
+    pub(super) fn add_entry_point(
+        &mut self,
+        name: String,
+        function: *const u8,
+        in_size: usize,
+        out_size: usize,
+    ) {
+        self.entry_points.insert(
+            name,
+            EntryFunction {
+                function: unsafe { mem::transmute::<*const u8, MainFunction>(function) },
+                in_size,
+                out_size,
+            },
+        );
+    }
+
+    fn assert_static_layout(&self, data: &StaticData) {
+        self.static_layout.assert_matches(data);
+    }
+
+    fn assert_size(&self, in_size: usize, out_size: usize, static_data: &StaticData) {
+        assert!(
+            self.in_size == in_size,
+            "Expected {}, got {}.",
+            self.in_size,
+            in_size
+        );
+        assert!(
+            self.out_size == out_size,
+            "Expected {}, got {}.",
+            self.out_size,
+            out_size
+        );
+        self.assert_static_layout(static_data);
+    }
+
+    /// Returns every variable exported from a static block, indexed by the name it was exported
+    /// as.
+    pub fn borrow_static_variables(&self) -> &HashMap<String, StaticVariable> {
+        self.static_layout.borrow_variables()
+    }
+
+    pub fn has_entry_point(&self, name: &str) -> bool {
+        self.entry_points.contains_key(name)
+    }
+
+    fn borrow_entry_point(&self, name: &str) -> &EntryFunction {
+        match self.entry_points.get(name) {
+            Some(entry_point) => entry_point,
+            None => panic!("The program has no entry point named {}.", name),
+        }
+    }
+
+    pub fn borrow_errors(&self) -> &Vec<RuntimeError> {
+        &self.errors
+    }
+
+    fn parse_error_code(&self, error_code: u32) -> Result<(), RuntimeError> {
+        if error_code == 0 {
+            return Ok(());
+        }
+        // Error codes start at 1, 0 is reserved for success.
+        match self.errors.get(error_code as usize - 1) {
+            Some(error) => Err(error.clone()),
+            None => Err(RuntimeError::unknown(error_code)),
+        }
+    }
+
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    pub unsafe fn create_static_data(&self) -> Result<StaticData, RuntimeError> {
+        let mut data = StaticData::new(&self.static_layout);
+        let error_code = (self.static_init)(data.as_mut_ptr());
+        self.parse_error_code(error_code)?;
+        Ok(data)
+    }
+
+    /// See the LLVM version of this function.
+    ///
+    /// # Safety
+    /// This runs the new program's static init code, the same as `create_static_data`.
+    pub unsafe fn migrate_static_data(
+        &self,
+        old_program: &Program,
+        old_data: &StaticData,
+    ) -> Result<(StaticData, StaticMigration), RuntimeError> {
+        old_program.assert_static_layout(old_data);
+        let mut data = self.create_static_data()?;
+        let migration = data.copy_matching_variables(old_data);
+        Ok((data, migration))
+    }
+
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    pub unsafe fn reinit_static_data(&self, data: &mut StaticData) -> Result<(), RuntimeError> {
+        self.assert_static_layout(data);
+        let error_code = (self.static_init)(data.as_mut_ptr());
+        self.parse_error_code(error_code)
+    }
+
+    /// # Safety
+    /// `T` and `U` must have the same layout as the input and output structs. Only their sizes
+    /// are checked.
+    pub unsafe fn execute_data<T: Sized, U: Sized>(
+        &self,
+        input_data: &mut T,
+        output_data: &mut U,
+        static_data: &mut StaticData,
+    ) -> Result<(), RuntimeError> {
+        self.assert_size(mem::size_of::<T>(), mem::size_of::<U>(), static_data);
+        let error_code = (self.function)(
+            input_data as *mut T as *mut u8,
+            static_data.as_mut_ptr(),
+            output_data as *mut U as *mut u8,
+        );
+        self.parse_error_code(error_code)
+    }
+
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    pub unsafe fn execute_raw(
+        &self,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+        static_data: &mut StaticData,
+    ) -> Result<(), RuntimeError> {
+        self.assert_size(input_data.len(), output_data.len(), static_data);
+        let error_code = (self.function)(
+            input_data.as_mut_ptr(),
+            static_data.as_mut_ptr(),
+            output_data.as_mut_ptr(),
+        );
+        self.parse_error_code(error_code)
+    }
+
+    /// Runs the entry point with the given name. Panics if there is no such entry point.
+    ///
+    /// # Safety
+    /// `T` and `U` must have the same layout as the entry point's input and output structs. Only
+    /// their sizes are checked.
+    pub unsafe fn execute_entry<T: Sized, U: Sized>(
+        &self,
+        name: &str,
+        input_data: &mut T,
+        output_data: &mut U,
+        static_data: &mut StaticData,
+    ) -> Result<(), RuntimeError> {
+        let entry_point = self.borrow_entry_point(name);
+        assert_entry_size(entry_point, mem::size_of::<T>(), mem::size_of::<U>());
+        self.assert_static_layout(static_data);
+        let error_code = (entry_point.function)(
+            input_data as *mut T as *mut u8,
+            static_data.as_mut_ptr(),
+            output_data as *mut U as *mut u8,
+        );
+        self.parse_error_code(error_code)
+    }
+
+    /// Like `execute_entry`, but with the input and output structs given as bytes.
+    ///
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    pub unsafe fn execute_entry_raw(
+        &self,
+        name: &str,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+        static_data: &mut StaticData,
+    ) -> Result<(), RuntimeError> {
+        let entry_point = self.borrow_entry_point(name);
+        assert_entry_size(entry_point, input_data.len(), output_data.len());
+        self.assert_static_layout(static_data);
+        let error_code = (entry_point.function)(
+            input_data.as_mut_ptr(),
+            static_data.as_mut_ptr(),
+            output_data.as_mut_ptr(),
+        );
+        self.parse_error_code(error_code)
+    }
+}
+
+fn assert_entry_size(entry_point: &EntryFunction, in_size: usize, out_size: usize) {
+    assert!(
+        entry_point.in_size == in_size,
+        "Expected {}, got {}.",
+        entry_point.in_size,
+        in_size
+    );
+    assert!(
+        entry_point.out_size == out_size,
+        "Expected {}, got {}.",
+        entry_point.out_size,
+        out_size
+    );
+}
@@ -0,0 +1,130 @@
+use crate::high_level::problem::RuntimeError;
+use crate::high_level::scratch::Scratch;
+use crate::high_level::static_data::{StaticData, StaticDataOwner, StaticVariable};
+use crate::trivial::passes::Pass;
+use crate::trivial::structure as i;
+use std::collections::HashMap;
+use std::mem;
+use std::slice;
+
+/// Turns a trivial program into something which can be run or saved, such as a JIT-compiled
+/// program or source code for another compiler. Use `Compiler::compile_with` to compile a source
+/// file with a particular backend.
+pub trait Backend {
+    /// What the backend produces from a program.
+    type Output;
+
+    /// A short name for the backend, used to label its performance counter.
+    fn borrow_name(&self) -> &str;
+
+    /// The optimizations which are run on the trivial program before it is given to this backend,
+    /// unless they were turned on or off with `Compiler::set_pass_enabled`.
+    fn default_passes(&self) -> &'static [Pass] {
+        Pass::ALL
+    }
+
+    fn compile(&mut self, source: &i::Program) -> Result<Self::Output, String>;
+}
+
+/// A program which has been compiled to code that can be run in the current process. The data
+/// passed to it uses the layout described in `llvmir::ingest`, which every backend implementing
+/// this trait should follow.
+pub trait CompiledProgram: StaticDataOwner {
+    fn borrow_errors(&self) -> &Vec<RuntimeError>;
+
+    fn has_entry_point(&self, name: &str) -> bool;
+
+    /// How many bytes of scratch memory must be passed when running the program. This is zero
+    /// unless the program was compiled to keep large temporary values out of the stack.
+    fn scratch_size(&self) -> usize;
+
+    /// Runs the static init code of the program on `data`, which must have been created for this
+    /// program.
+    ///
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    unsafe fn reinit_static_data(&self, data: &mut StaticData) -> Result<(), RuntimeError>;
+
+    /// Runs the main body of the program with the input and output structs given as bytes.
+    ///
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    unsafe fn execute_raw(
+        &self,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+        static_data: &mut StaticData,
+        scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError>;
+
+    /// Runs the entry point with the given name. Panics if there is no such entry point.
+    ///
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    unsafe fn execute_entry_raw(
+        &self,
+        name: &str,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+        static_data: &mut StaticData,
+        scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError>;
+
+    /// Returns every variable exported from a static block, indexed by the name it was exported
+    /// as.
+    fn borrow_static_variables(&self) -> &HashMap<String, StaticVariable> {
+        self.borrow_static_layout().borrow_variables()
+    }
+
+    /// Creates scratch memory which is big enough to run this program with.
+    fn create_scratch(&self) -> Scratch {
+        Scratch::new(self.scratch_size())
+    }
+
+    /// # Safety
+    /// This runs the program's static init code, see `reinit_static_data`.
+    unsafe fn create_static_data(&self) -> Result<StaticData, RuntimeError> {
+        let mut data = StaticData::new(self.borrow_static_layout());
+        self.reinit_static_data(&mut data)?;
+        Ok(data)
+    }
+
+    /// # Safety
+    /// `T` and `U` must have the same layout as the input and output structs. Only their sizes
+    /// are checked.
+    unsafe fn execute_data<T: Sized, U: Sized>(
+        &self,
+        input_data: &mut T,
+        output_data: &mut U,
+        static_data: &mut StaticData,
+        scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError>
+    where
+        Self: Sized,
+    {
+        let (input_data, output_data) = (as_bytes(input_data), as_bytes(output_data));
+        self.execute_raw(input_data, output_data, static_data, scratch)
+    }
+
+    /// # Safety
+    /// `T` and `U` must have the same layout as the entry point's input and output structs. Only
+    /// their sizes are checked.
+    unsafe fn execute_entry<T: Sized, U: Sized>(
+        &self,
+        name: &str,
+        input_data: &mut T,
+        output_data: &mut U,
+        static_data: &mut StaticData,
+        scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError>
+    where
+        Self: Sized,
+    {
+        let (input_data, output_data) = (as_bytes(input_data), as_bytes(output_data));
+        self.execute_entry_raw(name, input_data, output_data, static_data, scratch)
+    }
+}
+
+unsafe fn as_bytes<T: Sized>(data: &mut T) -> &mut [u8] {
+    slice::from_raw_parts_mut(data as *mut T as *mut u8, mem::size_of::<T>())
+}
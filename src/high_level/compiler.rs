@@ -1,6 +1,13 @@
-use crate::high_level::problem::CompileProblem;
+#[cfg(not(feature = "no-trivial"))]
+use crate::high_level::backend::Backend;
+use crate::high_level::problem::{CompileProblem, RuntimeError};
+use crate::high_level::source_loader::{normalize_path, FileSystemLoader, SourceLoader};
+#[cfg(not(feature = "no-llvmir"))]
+use crate::high_level::specializing::SpecializingProgram;
 use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
+#[cfg(not(feature = "no-llvmir"))]
+use std::rc::Rc;
 use std::time::Instant;
 
 pub const FAKE_BUILTIN_SOURCE: &str = r#"
@@ -11,6 +18,21 @@ DATA_TYPE FLOAT;
 DATA_TYPE VOID;
 "#;
 
+/// The standard library, which is embedded in the compiler so that any program can include it
+/// without the files being present on disk.
+pub const STD_SOURCES: &[(&str, &str)] = &[
+    ("std/filters.ns", include_str!("../../std/filters.ns")),
+    (
+        "std/interpolation.ns",
+        include_str!("../../std/interpolation.ns"),
+    ),
+    ("std/math.ns", include_str!("../../std/math.ns")),
+    (
+        "std/oscillators.ns",
+        include_str!("../../std/oscillators.ns"),
+    ),
+];
+
 #[derive(Default)]
 pub struct PerformanceCounter {
     pub time: u128,
@@ -33,7 +55,22 @@ pub struct PerformanceCounters {
     vague: PerformanceCounter,
     resolved: PerformanceCounter,
     trivial: PerformanceCounter,
-    llvmir: PerformanceCounter,
+    /// One counter for each backend that has been used, labeled with its name.
+    backends: Vec<(String, PerformanceCounter)>,
+}
+
+impl PerformanceCounters {
+    #[cfg(not(feature = "no-trivial"))]
+    fn backend_counter(&mut self, name: &str) -> &mut PerformanceCounter {
+        let index = match self.backends.iter().position(|(other, _)| other == name) {
+            Some(index) => index,
+            None => {
+                self.backends.push((name.to_owned(), Default::default()));
+                self.backends.len() - 1
+            }
+        };
+        &mut self.backends[index].1
+    }
 }
 
 impl Display for PerformanceCounters {
@@ -42,14 +79,31 @@ impl Display for PerformanceCounters {
         writeln!(formatter, "     ast: {}", self.ast)?;
         writeln!(formatter, "   vague: {}", self.vague)?;
         writeln!(formatter, "resolved: {}", self.resolved)?;
-        writeln!(formatter, " trivial: {}", self.trivial)?;
-        write!(formatter, "  llvmir: {}", self.llvmir)
+        write!(formatter, " trivial: {}", self.trivial)?;
+        for (name, counter) in &self.backends {
+            write!(formatter, "\n{:>8}: {}", name, counter)?;
+        }
+        Ok(())
     }
 }
 
+/// Controls what code is generated for `assert` statements whose conditions cannot be checked at
+/// compile time. Asserts which are known to fail at compile time are always reported as errors.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AssertMode {
+    /// Check the condition at runtime, aborting the program if it is false.
+    Keep,
+    /// Remove the assert entirely. The condition is not evaluated.
+    Elide,
+    /// Do not check the condition, but let the optimizer assume that it is always true. If the
+    /// condition is ever false, the behavior of the program is undefined.
+    Assume,
+}
+
 pub struct SourceSet {
     sources: Vec<(String, String)>,
     source_indices: HashMap<String, usize>,
+    loader: Box<dyn SourceLoader>,
 }
 
 impl SourceSet {
@@ -57,19 +111,24 @@ impl SourceSet {
         let mut new = Self {
             sources: Vec::new(),
             source_indices: HashMap::new(),
+            loader: Box::new(FileSystemLoader::new()),
         };
         new.add_source(
             "(internal code) builtins".to_owned(),
             FAKE_BUILTIN_SOURCE.to_owned(),
         );
+        for (name, content) in STD_SOURCES {
+            new.add_source((*name).to_owned(), (*content).to_owned());
+        }
         new
     }
 
     pub fn add_source(&mut self, name: String, content: String) {
-        if let Some(existing_index) = self.source_indices.get(&name) {
+        let key = normalize_path(&name);
+        if let Some(existing_index) = self.source_indices.get(&key) {
             self.sources[*existing_index].1 = content;
         } else {
-            self.source_indices.insert(name.clone(), self.sources.len());
+            self.source_indices.insert(key, self.sources.len());
             self.sources.push((name, content));
         }
     }
@@ -81,7 +140,41 @@ impl SourceSet {
     }
 
     pub(crate) fn find_source(&self, name: &str) -> Option<usize> {
-        self.source_indices.get(name).cloned()
+        self.source_indices.get(&normalize_path(name)).cloned()
+    }
+
+    /// Finds the source that `path` refers to when it is included from the source at index
+    /// `from`, using the source loader if it has not been added yet. The candidates given by the
+    /// loader are tried first so that a file next to the including file wins over an unrelated
+    /// source which happens to have the same name. If nothing could be found, returns every name
+    /// that was tried.
+    pub(crate) fn find_or_load_include(
+        &mut self,
+        from: usize,
+        path: &str,
+    ) -> Result<usize, Vec<String>> {
+        let candidates = self.loader.candidates(&self.sources[from].0, path);
+        for candidate in &candidates {
+            if let Some(index) = self.find_source(candidate) {
+                return Ok(index);
+            }
+            if let Some(content) = self.loader.load(candidate) {
+                self.add_source(normalize_path(candidate), content);
+                return Ok(self.sources.len() - 1);
+            }
+        }
+        // Sources added directly to the compiler, like the standard library, can also be included
+        // by their exact name from anywhere.
+        if let Some(index) = self.find_source(path) {
+            return Ok(index);
+        }
+        let mut tried = Vec::new();
+        for candidate in candidates.into_iter().chain(Some(normalize_path(path))) {
+            if !tried.contains(&candidate) {
+                tried.push(candidate);
+            }
+        }
+        Err(tried)
     }
 
     pub(crate) fn borrow_source(&self, index: usize) -> &(String, String) {
@@ -101,6 +194,22 @@ pub struct Compiler {
     source_set: SourceSet,
     performance_counters: PerformanceCounters,
     error_width: usize,
+    assert_mode: AssertMode,
+    // Passes which were turned on or off with set_pass_enabled, overriding the defaults of the
+    // backend.
+    #[cfg(not(feature = "no-trivial"))]
+    pass_settings: Vec<(crate::trivial::passes::Pass, bool)>,
+    #[cfg(not(feature = "no-llvmir"))]
+    llvm_backend: crate::llvmir::LlvmJitBackend,
+    #[cfg(not(feature = "no-vague"))]
+    extern_functions: Vec<crate::vague::structure::ExternFunction>,
+    #[cfg(not(feature = "no-vague"))]
+    constants: Vec<(String, crate::vague::structure::KnownData)>,
+    #[cfg(not(feature = "no-vague"))]
+    preludes: Vec<usize>,
+    // Programs compiled by compile_with_params, keyed by the source name and the parameters.
+    #[cfg(not(feature = "no-llvmir"))]
+    specializations: HashMap<(String, String), Rc<crate::llvmir::structure::Program>>,
 }
 
 impl Compiler {
@@ -109,25 +218,208 @@ impl Compiler {
             source_set: SourceSet::new(),
             performance_counters: Default::default(),
             error_width: 80,
+            assert_mode: AssertMode::Keep,
+            #[cfg(not(feature = "no-trivial"))]
+            pass_settings: Vec::new(),
+            #[cfg(not(feature = "no-llvmir"))]
+            llvm_backend: crate::llvmir::LlvmJitBackend::new(),
+            #[cfg(not(feature = "no-vague"))]
+            extern_functions: Vec::new(),
+            #[cfg(not(feature = "no-vague"))]
+            constants: Vec::new(),
+            #[cfg(not(feature = "no-vague"))]
+            preludes: Vec::new(),
+            #[cfg(not(feature = "no-llvmir"))]
+            specializations: HashMap::new(),
         }
     }
 
+    // Called whenever something changes which could make previously compiled programs different
+    // from what would be compiled now.
+    fn clear_specializations(&mut self) {
+        #[cfg(not(feature = "no-llvmir"))]
+        self.specializations.clear();
+    }
+
     pub fn set_error_width(&mut self, width: usize) {
         self.error_width = width;
     }
 
+    pub fn set_assert_mode(&mut self, mode: AssertMode) {
+        self.assert_mode = mode;
+        self.clear_specializations();
+    }
+
+    /// Controls whether the given optimization is run on the trivial IR of compiled programs. By
+    /// default, every pass is run for backends other than LLVM and by `compile_to_trivial`, while
+    /// programs compiled with LLVM skip them because LLVM does the same optimizations itself. See
+    /// `Backend::default_passes`. Calling this overrides the default for every backend.
+    #[cfg(not(feature = "no-trivial"))]
+    pub fn set_pass_enabled(&mut self, pass: crate::trivial::passes::Pass, enabled: bool) {
+        self.pass_settings.retain(|(other, _)| *other != pass);
+        self.pass_settings.push((pass, enabled));
+        self.clear_specializations();
+    }
+
+    // Returns the passes to run, in their usual order, when the backend would run `defaults`.
+    #[cfg(not(feature = "no-trivial"))]
+    fn choose_passes(
+        &self,
+        defaults: &[crate::trivial::passes::Pass],
+    ) -> Vec<crate::trivial::passes::Pass> {
+        crate::trivial::passes::Pass::ALL
+            .iter()
+            .cloned()
+            .filter(
+                |pass| match self.pass_settings.iter().find(|(other, _)| other == pass) {
+                    Some((_, enabled)) => *enabled,
+                    None => defaults.contains(pass),
+                },
+            )
+            .collect()
+    }
+
+    /// Makes programs compiled with LLVM store local variables which take up more than
+    /// `threshold` bytes in caller-provided scratch memory instead of on the stack. Use
+    /// `Program::scratch_size` to find out how much scratch memory a program needs. `None`, the
+    /// default, keeps everything on the stack.
+    #[cfg(not(feature = "no-llvmir"))]
+    pub fn set_scratch_threshold(&mut self, threshold: Option<usize>) {
+        self.llvm_backend.set_scratch_threshold(threshold);
+        self.clear_specializations();
+    }
+
+    /// Makes programs compiled with LLVM able to run `count` instances at once through
+    /// `Program::execute_instances`, which is faster than running each instance separately when
+    /// the same program is used for many voices. `None`, the default, disables this.
+    #[cfg(not(feature = "no-llvmir"))]
+    pub fn set_instance_count(&mut self, count: Option<usize>) {
+        self.llvm_backend.set_instance_count(count);
+        self.clear_specializations();
+    }
+
+    /// Makes a function from the host program callable from Nodespeak code as a macro named
+    /// `name`. See `ExternFunction` for how the inputs and outputs are passed to the function.
+    ///
+    /// # Safety
+    /// `address` must point to an `extern "C"` function whose signature matches `inputs` and
+    /// `outputs`, and it must remain valid for as long as any program compiled by this compiler
+    /// is being used.
+    #[cfg(not(feature = "no-vague"))]
+    pub unsafe fn add_extern_function(
+        &mut self,
+        name: &str,
+        inputs: Vec<crate::vague::structure::DataType>,
+        outputs: Vec<crate::vague::structure::DataType>,
+        address: *const std::ffi::c_void,
+    ) {
+        self.extern_functions
+            .push(crate::vague::structure::ExternFunction::new(
+                name.to_owned(),
+                inputs,
+                outputs,
+                address,
+            ));
+        self.clear_specializations();
+    }
+
+    /// Defines a constant which can be used by any Nodespeak code compiled by this compiler, in
+    /// the same way as builtins like `PI`. Its value is known at compile time, so it can be used
+    /// for things like array sizes. Returns an error if the value is a macro, macros have to be
+    /// defined in a prelude instead.
+    #[cfg(not(feature = "no-vague"))]
+    pub fn define_constant(
+        &mut self,
+        name: &str,
+        value: crate::vague::structure::KnownData,
+    ) -> Result<(), String> {
+        Self::check_constant(name, &value)?;
+        self.constants.push((name.to_owned(), value));
+        self.clear_specializations();
+        Ok(())
+    }
+
+    #[cfg(not(feature = "no-vague"))]
+    fn check_constant(
+        name: &str,
+        value: &crate::vague::structure::KnownData,
+    ) -> Result<(), String> {
+        use crate::vague::structure::KnownData;
+        match value {
+            KnownData::Macro(..) => Err(format!(
+                "The value of {} is a macro. Macros cannot be defined as constants, define them in a prelude instead.",
+                name
+            )),
+            KnownData::Array(items) => {
+                for item in items {
+                    Self::check_constant(name, item)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Like `define_constant`, but the value is given as a literal such as `"48000"` or
+    /// `"[1.0, 2.0]"`. Returns an error if the literal could not be parsed.
+    #[cfg(not(any(feature = "no-vague", feature = "no-trivial")))]
+    pub fn define_constant_from_str(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let value = crate::util::parse_known_data(value)?;
+        self.define_constant(name, value)
+    }
+
+    /// Makes `name` usable as another name for the given data type.
+    #[cfg(not(feature = "no-vague"))]
+    pub fn define_type_alias(&mut self, name: &str, data_type: crate::vague::structure::DataType) {
+        self.define_constant(
+            name,
+            crate::vague::structure::KnownData::DataType(data_type),
+        )
+        .expect("Data types are always valid constants.");
+    }
+
+    /// Adds a source whose definitions (such as macros) are visible to all code compiled by this
+    /// compiler, as if they were builtins. Preludes can only contain code which is evaluated at
+    /// compile time.
+    #[cfg(not(feature = "no-vague"))]
+    pub fn add_prelude(&mut self, name: String, content: String) {
+        self.source_set.add_source(name.clone(), content);
+        let index = self.source_set.find_source(&name).unwrap();
+        if !self.preludes.contains(&index) {
+            self.preludes.push(index);
+        }
+        self.clear_specializations();
+    }
+
+    /// Sets what is used to find files named by `include` statements which were not added
+    /// through `add_source` or a similar method. By default, a `FileSystemLoader` without any
+    /// search paths is used.
+    pub fn set_source_loader(&mut self, loader: Box<dyn SourceLoader>) {
+        self.source_set.loader = loader;
+        self.clear_specializations();
+    }
+
     pub fn add_source(&mut self, name: String, content: String) {
-        self.source_set.add_source(name, content)
+        self.source_set.add_source(name, content);
+        self.clear_specializations();
     }
 
     pub fn add_source_from_file(&mut self, file_path: String) -> std::io::Result<()> {
-        self.source_set.add_source_from_file(file_path)
+        self.source_set.add_source_from_file(file_path)?;
+        self.clear_specializations();
+        Ok(())
     }
 
     pub fn borrow_performance_counters(&self) -> &PerformanceCounters {
         &self.performance_counters
     }
 
+    /// Renders an error produced while running a program compiled by this compiler, including a
+    /// snippet of the code which caused it.
+    pub fn format_runtime_error(&self, error: &RuntimeError) -> String {
+        error.format(self.error_width, &self.source_set)
+    }
+
     fn format_error<T>(&self, result: Result<T, CompileProblem>) -> Result<T, String> {
         result.map_err(|e| e.format(self.error_width, &self.source_set))
     }
@@ -159,16 +451,21 @@ impl Compiler {
         &mut self,
         source_name: &str,
     ) -> Result<crate::vague::structure::Program, String> {
-        let source = self.source_set.find_source_err(source_name)?;
+        // Copied so that the source set can be modified by includes while the AST is alive.
+        let source = self.source_set.find_source_err(source_name)?.to_owned();
         let source_id = self.source_set.find_source(source_name).unwrap(); // woo yay its 2am
-        let result = Self::compile_to_ast_impl(&mut self.performance_counters, source, source_id);
+        let result = Self::compile_to_ast_impl(&mut self.performance_counters, &source, source_id);
         let mut source = self.format_error(result)?;
 
         let timer = Instant::now();
         let result = crate::vague::ingest(
             &mut source,
-            &self.source_set,
+            source_id,
+            &mut self.source_set,
             &mut self.performance_counters,
+            &self.extern_functions,
+            &self.constants,
+            &self.preludes,
         );
         self.performance_counters.vague.time += timer.elapsed().as_millis();
         self.performance_counters.vague.num_invocations += 1;
@@ -180,7 +477,15 @@ impl Compiler {
         &mut self,
         source_name: &str,
     ) -> Result<crate::resolved::structure::Program, String> {
-        let mut source = self.compile_to_vague(source_name)?;
+        let source = self.compile_to_vague(source_name)?;
+        self.resolve(source)
+    }
+
+    #[cfg(not(feature = "no-resolved"))]
+    fn resolve(
+        &mut self,
+        mut source: crate::vague::structure::Program,
+    ) -> Result<crate::resolved::structure::Program, String> {
         let timer = Instant::now();
         let result = crate::resolved::ingest(&mut source);
         self.performance_counters.resolved.time += timer.elapsed().as_millis();
@@ -193,25 +498,93 @@ impl Compiler {
         &mut self,
         source_name: &str,
     ) -> Result<crate::trivial::structure::Program, String> {
-        let mut source = self.compile_to_resolved(source_name)?;
+        let source = self.compile_to_resolved(source_name)?;
+        self.trivialize(source, crate::trivial::passes::Pass::ALL)
+    }
+
+    #[cfg(not(feature = "no-trivial"))]
+    fn trivialize(
+        &mut self,
+        source: crate::resolved::structure::Program,
+        default_passes: &[crate::trivial::passes::Pass],
+    ) -> Result<crate::trivial::structure::Program, String> {
         let timer = Instant::now();
-        let result = crate::trivial::ingest(&mut source, &self.source_set);
+        let passes = self.choose_passes(default_passes);
+        let result = crate::trivial::ingest(&source, &self.source_set, self.assert_mode).map(
+            |mut program| {
+                crate::trivial::passes::optimize(&mut program, &passes);
+                program
+            },
+        );
         self.performance_counters.trivial.time += timer.elapsed().as_millis();
         self.performance_counters.trivial.num_invocations += 1;
         self.format_error(result)
     }
 
+    /// Compiles the program to the trivial IR and then passes it to the given backend. This is
+    /// how every other `compile_*` method which produces something runnable is implemented, and
+    /// can be used with backends defined outside of this crate.
+    #[cfg(not(feature = "no-trivial"))]
+    pub fn compile_with<B: Backend>(
+        &mut self,
+        source_name: &str,
+        backend: &mut B,
+    ) -> Result<B::Output, String> {
+        let source = self.compile_to_resolved(source_name)?;
+        let source = self.trivialize(source, backend.default_passes())?;
+        self.run_backend(backend, &source)
+    }
+
+    #[cfg(not(feature = "no-trivial"))]
+    fn run_backend<B: Backend>(
+        &mut self,
+        backend: &mut B,
+        source: &crate::trivial::structure::Program,
+    ) -> Result<B::Output, String> {
+        let timer = Instant::now();
+        let result = backend.compile(source);
+        let counter = self
+            .performance_counters
+            .backend_counter(backend.borrow_name());
+        counter.time += timer.elapsed().as_millis();
+        counter.num_invocations += 1;
+        result
+    }
+
     #[cfg(not(feature = "no-llvmir"))]
     pub fn compile_to_llvmir(
         &mut self,
         source_name: &str,
     ) -> Result<crate::llvmir::structure::Program, String> {
-        let mut source = self.compile_to_trivial(source_name)?;
-        let timer = Instant::now();
-        let result = crate::llvmir::ingest(&mut source);
-        self.performance_counters.llvmir.time += timer.elapsed().as_millis();
-        self.performance_counters.llvmir.num_invocations += 1;
-        Ok(result)
+        let mut backend = self.llvm_backend;
+        self.compile_with(source_name, &mut backend)
+    }
+
+    /// Compiles the program to a self-contained C99 file for targets where LLVM is not available.
+    /// Every type and function in the file starts with `prefix`, see `c::ingest` for details.
+    #[cfg(not(feature = "no-c"))]
+    pub fn compile_to_c(&mut self, source_name: &str, prefix: &str) -> Result<String, String> {
+        self.compile_with(source_name, &mut crate::c::CBackend::new(prefix))
+    }
+
+    /// Compiles the program to a WebAssembly module, see `wasm::ingest` for what it imports and
+    /// exports.
+    #[cfg(not(feature = "no-wasm"))]
+    pub fn compile_to_wasm(
+        &mut self,
+        source_name: &str,
+    ) -> Result<crate::wasm::structure::Program, String> {
+        self.compile_with(source_name, &mut crate::wasm::WasmBackend)
+    }
+
+    /// Compiles the program with Cranelift instead of LLVM. This is much faster but produces less
+    /// optimized code, which is useful when the program changes often.
+    #[cfg(feature = "cranelift")]
+    pub fn compile_with_cranelift(
+        &mut self,
+        source_name: &str,
+    ) -> Result<crate::cranelift::structure::Program, String> {
+        self.compile_with(source_name, &mut crate::cranelift::CraneliftBackend)
     }
 
     #[cfg(not(feature = "no-llvmir"))]
@@ -221,4 +594,69 @@ impl Compiler {
     ) -> Result<crate::llvmir::structure::Program, String> {
         self.compile_to_llvmir(source_name)
     }
+
+    /// Compiles a version of the program where each parameter is a constant which is known at
+    /// compile time, like the ones added by `define_constant`. Each combination of source and
+    /// parameter values is only compiled once, later calls return the same program. The cache is
+    /// cleared whenever sources or other settings of the compiler are changed. Like
+    /// `define_constant`, returns an error if any of the values is a macro.
+    #[cfg(not(feature = "no-llvmir"))]
+    pub fn compile_with_params(
+        &mut self,
+        source_name: &str,
+        params: &[(&str, crate::vague::structure::KnownData)],
+    ) -> Result<Rc<crate::llvmir::structure::Program>, String> {
+        // Sorted so that giving the same parameters in a different order uses the same entry. The
+        // sort is stable, so repeated names still keep their order.
+        let mut sorted_params = params.to_vec();
+        sorted_params.sort_by(|a, b| a.0.cmp(b.0));
+        let key = (source_name.to_owned(), format!("{:?}", sorted_params));
+        if let Some(program) = self.specializations.get(&key) {
+            return Ok(Rc::clone(program));
+        }
+        for (name, value) in params {
+            Self::check_constant(name, value)?;
+        }
+        let num_constants = self.constants.len();
+        for (name, value) in params {
+            self.constants.push(((*name).to_owned(), value.clone()));
+        }
+        let result = self.compile(source_name);
+        self.constants.truncate(num_constants);
+        let program = Rc::new(result?);
+        self.specializations.insert(key, Rc::clone(&program));
+        Ok(program)
+    }
+
+    /// Compiles a version of the program where each of the given specializable inputs has a value
+    /// which is known at compile time. The program still takes the same input data as the
+    /// generic version, but the values of the specialized inputs in it are ignored.
+    #[cfg(not(feature = "no-llvmir"))]
+    pub fn compile_specialized(
+        &mut self,
+        source_name: &str,
+        values: &[(&str, crate::vague::structure::KnownData)],
+    ) -> Result<crate::llvmir::structure::Program, String> {
+        let mut source = self.compile_to_vague(source_name)?;
+        for (name, value) in values {
+            if !source.specialize_input(name, value.clone()) {
+                return Err(format!("There is no specializable input named {}.", name));
+            }
+        }
+        let source = self.resolve(source)?;
+        let mut backend = self.llvm_backend;
+        let source = self.trivialize(source, backend.default_passes())?;
+        self.run_backend(&mut backend, &source)
+    }
+
+    /// Compiles a generic version of the program which can be specialized for particular values
+    /// of its specializable inputs as the program is being run. See `SpecializingProgram`.
+    #[cfg(not(feature = "no-llvmir"))]
+    pub fn compile_specializing(
+        &mut self,
+        source_name: &str,
+    ) -> Result<SpecializingProgram, String> {
+        let generic = self.compile(source_name)?;
+        Ok(SpecializingProgram::new(source_name.to_owned(), generic))
+    }
 }
@@ -1,2 +1,10 @@
+#[cfg(not(feature = "no-trivial"))]
+pub mod backend;
 pub mod compiler;
 pub mod problem;
+pub mod scratch;
+pub mod source_loader;
+#[cfg(not(feature = "no-llvmir"))]
+pub mod specializing;
+#[cfg(not(feature = "no-trivial"))]
+pub mod static_data;
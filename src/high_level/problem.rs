@@ -4,6 +4,7 @@ use pest::error::InputLocation;
 use pest::iterators::Pair;
 use pest::RuleType;
 use std::cmp;
+use std::fmt;
 use std::iter::FromIterator;
 use std::ops::Add;
 
@@ -23,6 +24,16 @@ impl PartialEq for FilePosition {
 }
 
 impl FilePosition {
+    /// `file` is the index of the source in the compiler that created the position, and `start`
+    /// and `end` are byte offsets into that source.
+    pub fn new(start: usize, end: usize, file: usize) -> FilePosition {
+        FilePosition {
+            start_pos: start,
+            end_pos: end,
+            file,
+        }
+    }
+
     pub fn from_pair<R: RuleType>(pair: &Pair<R>, file: usize) -> FilePosition {
         let span = pair.as_span();
         FilePosition {
@@ -60,6 +71,18 @@ impl FilePosition {
         }
     }
 
+    pub fn get_start(&self) -> usize {
+        self.start_pos
+    }
+
+    pub fn get_end(&self) -> usize {
+        self.end_pos
+    }
+
+    pub fn get_file(&self) -> usize {
+        self.file
+    }
+
     pub fn union(parts: &[&FilePosition]) -> FilePosition {
         assert!(parts.len() > 0);
         let mut result = parts[0].clone();
@@ -121,6 +144,82 @@ impl ProblemDescriptor {
     }
 }
 
+/// The different categories of problem that can cause a compiled program to abort at runtime.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RuntimeErrorKind {
+    Assert,
+    Bounds,
+    DivisionByZero,
+    UserAbort,
+    /// The program returned an error code that does not correspond to any error it was compiled
+    /// with.
+    Unknown,
+}
+
+/// Describes why a compiled program aborted. Every non-success error code a program can return
+/// corresponds to exactly one of these, created when the program was compiled.
+#[derive(Clone, Debug)]
+pub struct RuntimeError {
+    code: u32,
+    kind: RuntimeErrorKind,
+    message: String,
+    position: FilePosition,
+}
+
+impl RuntimeError {
+    pub fn new(code: u32, kind: RuntimeErrorKind, message: String, position: FilePosition) -> Self {
+        Self {
+            code,
+            kind,
+            message,
+            position,
+        }
+    }
+
+    /// Describes an error code which is not one of the errors the program was compiled with.
+    pub fn unknown(code: u32) -> Self {
+        Self::new(
+            code,
+            RuntimeErrorKind::Unknown,
+            format!("Invalid non-success error code {}.", code),
+            FilePosition::placeholder(),
+        )
+    }
+
+    pub fn get_code(&self) -> u32 {
+        self.code
+    }
+
+    pub fn get_kind(&self) -> RuntimeErrorKind {
+        self.kind
+    }
+
+    pub fn borrow_message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn borrow_position(&self) -> &FilePosition {
+        &self.position
+    }
+
+    /// Renders the error in the same style as a CompileProblem, including a snippet of the code
+    /// that caused it.
+    pub fn format(&self, width: usize, source_set: &SourceSet) -> String {
+        CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+            self.position.clone(),
+            ProblemType::Error,
+            &self.message,
+        )])
+        .format(width, source_set)
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} (error code {})", self.message, self.code)
+    }
+}
+
 pub struct CompileProblem {
     descriptors: Vec<ProblemDescriptor>,
 }
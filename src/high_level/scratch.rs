@@ -0,0 +1,56 @@
+/// The unit scratch memory is allocated in, which makes the memory aligned enough for any value a
+/// program can store in it.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, align(16))]
+struct Chunk([u8; Scratch::ALIGNMENT]);
+
+/// Memory provided by the host which a compiled program uses to hold large temporary values
+/// instead of putting them on the stack. Its contents do not need to be preserved between runs,
+/// so one `Scratch` can be shared by every program that runs on the same thread as long as it is
+/// at least as big as the largest `scratch_size()` of those programs.
+#[derive(Clone, Debug, Default)]
+pub struct Scratch {
+    data: Vec<Chunk>,
+    size: usize,
+}
+
+impl Scratch {
+    /// The alignment of the start of the scratch memory in bytes.
+    pub const ALIGNMENT: usize = 16;
+
+    pub fn new(size: usize) -> Self {
+        let mut new = Self::default();
+        new.reserve(size);
+        new
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    /// Makes the scratch memory at least `size` bytes big. This allocates, so it should be done
+    /// before running a program on a thread which must not allocate.
+    pub fn reserve(&mut self, size: usize) {
+        if self.size < size {
+            let chunks = size.div_ceil(Self::ALIGNMENT);
+            self.data.resize(chunks, Chunk::default());
+            self.size = size;
+        }
+    }
+
+    /// The pointer which should be passed to compiled code as the scratch memory. It is always a
+    /// multiple of `ALIGNMENT`.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.data.as_mut_ptr() as *mut u8
+    }
+
+    /// Panics if the scratch memory is smaller than `required` bytes.
+    pub fn assert_fits(&self, required: usize) {
+        assert!(
+            self.size >= required,
+            "Scratch memory is too small, expected at least {} bytes, got {}.",
+            required,
+            self.size
+        );
+    }
+}
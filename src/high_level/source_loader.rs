@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Used by the compiler to find files named by `include` statements which were not already added
+/// through `Compiler::add_source` or a similar method.
+pub trait SourceLoader {
+    /// Returns every name that `path` could refer to when it is included from the source named
+    /// `from`, in the order they should be tried. The first name which is either already known to
+    /// the compiler or which `load` succeeds on is used. The compiler normalizes the names before
+    /// comparing them, so `lib/./x.ns` and `lib/x.ns` refer to the same source.
+    fn candidates(&self, from: &str, path: &str) -> Vec<String>;
+
+    /// Returns the content of the source with the given name, or `None` if it does not exist.
+    fn load(&self, name: &str) -> Option<String>;
+}
+
+/// Removes `.` components and resolves `..` components where possible without looking at the
+/// filesystem, so that `./a/../x.ns` and `x.ns` end up as the same name.
+pub(crate) fn normalize_path(path: &str) -> String {
+    let mut normalized = PathBuf::new();
+    // How many normal components are in `normalized` which a `..` could remove.
+    let mut depth = 0;
+    for component in Path::new(path).components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if depth > 0 => {
+                normalized.pop();
+                depth -= 1;
+            }
+            Component::Normal(..) => {
+                normalized.push(component);
+                depth += 1;
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized.to_string_lossy().into_owned()
+}
+
+fn relative_to(from: &str, path: &str) -> String {
+    let dir = Path::new(from).parent().unwrap_or_else(|| Path::new(""));
+    normalize_path(&dir.join(path).to_string_lossy())
+}
+
+/// Loads includes from the filesystem, first relative to the directory of the including file and
+/// then relative to each search path in the order they were added.
+#[derive(Clone, Debug, Default)]
+pub struct FileSystemLoader {
+    search_paths: Vec<PathBuf>,
+}
+
+impl FileSystemLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_search_path(&mut self, path: impl Into<PathBuf>) {
+        self.search_paths.push(path.into());
+    }
+}
+
+impl SourceLoader for FileSystemLoader {
+    fn candidates(&self, from: &str, path: &str) -> Vec<String> {
+        let mut candidates = vec![relative_to(from, path)];
+        for search_path in &self.search_paths {
+            candidates.push(normalize_path(&search_path.join(path).to_string_lossy()));
+        }
+        candidates
+    }
+
+    fn load(&self, name: &str) -> Option<String> {
+        std::fs::read_to_string(name).ok()
+    }
+}
+
+/// Loads includes from a fixed set of files kept in memory, resolving paths relative to the
+/// including file and then relative to the root of the set. Mostly useful for tests.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryLoader {
+    files: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_file(&mut self, name: String, content: String) {
+        self.files.insert(normalize_path(&name), content);
+    }
+}
+
+impl SourceLoader for MemoryLoader {
+    fn candidates(&self, from: &str, path: &str) -> Vec<String> {
+        vec![relative_to(from, path), normalize_path(path)]
+    }
+
+    fn load(&self, name: &str) -> Option<String> {
+        self.files.get(&normalize_path(name)).cloned()
+    }
+}
@@ -0,0 +1,263 @@
+use crate::high_level::compiler::Compiler;
+use crate::high_level::problem::RuntimeError;
+use crate::llvmir::structure::{
+    Program, Scratch, SpecializableInput, StaticCopyPlan, StaticData, StaticDataOwner,
+};
+use crate::trivial::structure::DataType;
+use crate::vague::structure::KnownData;
+use std::collections::HashMap;
+use std::mem;
+
+/// Runs a program while keeping track of the values its specializable inputs have. Whenever those
+/// values change to a combination that has not been seen before, the generic version of the
+/// program is used and the combination is queued. Nothing is compiled on its own: the host has to
+/// call `compile_pending` between runs to compile versions of the program where those values are
+/// known at compile time. Those are used from then on whenever the inputs have the same values
+/// again. Each version has its own static data, and the values of the static variables it has in
+/// common with the generic version are copied over whenever the program switches to or from it.
+/// Static data and scratch memory for every version is allocated and initialized when the program
+/// is first run and in `compile_pending`, so switching between versions never does either.
+/// Finding the version to use does not allocate either, except to queue a combination the first
+/// time it is seen.
+pub struct SpecializingProgram {
+    source_name: String,
+    generic: Program,
+    inputs: Vec<SpecializableInput>,
+    // Keyed by the bytes the specializable inputs had in the input data.
+    specializations: HashMap<Vec<u8>, Specialization>,
+    // Combinations which have to be compiled by compile_pending.
+    pending: Vec<Vec<u8>>,
+    // Reused by every run to build the key of the current values without allocating.
+    key: Vec<u8>,
+    versions: Vec<Version>,
+    generic_static_data: Option<StaticData>,
+    // The version whose static data holds the current values of the static variables, or None
+    // if it is the generic version.
+    current: Option<usize>,
+    scratch: Scratch,
+}
+
+#[derive(Clone, Copy)]
+enum Specialization {
+    /// Waiting for `compile_pending`, the generic version is used until then.
+    Pending,
+    /// Compiling failed, so the generic version is used for these values.
+    Generic,
+    /// The index of the version in `versions`.
+    Compiled(usize),
+}
+
+/// A specialized version of the program along with its static data.
+struct Version {
+    program: Program,
+    static_data: StaticData,
+    // Copy the static variables this version has in common with the generic version. Variables
+    // are matched by their keys, so this works even if the version skips code that declares
+    // static variables or that calls macros.
+    from_generic: StaticCopyPlan,
+    to_generic: StaticCopyPlan,
+}
+
+fn value_size(data_type: &DataType) -> usize {
+    match data_type {
+        DataType::B1 => 1,
+        DataType::I32 | DataType::F32 => 4,
+        DataType::Array(..) => unreachable!("Specializable inputs cannot be arrays."),
+    }
+}
+
+fn read_value(data_type: &DataType, bytes: &[u8]) -> KnownData {
+    let mut raw = [0; 4];
+    match data_type {
+        DataType::B1 => KnownData::Bool(bytes[0] & 1 == 1),
+        DataType::I32 => {
+            raw.copy_from_slice(bytes);
+            KnownData::Int(i32::from_ne_bytes(raw) as i64)
+        }
+        DataType::F32 => {
+            raw.copy_from_slice(bytes);
+            KnownData::Float(f32::from_ne_bytes(raw) as f64)
+        }
+        DataType::Array(..) => unreachable!("Specializable inputs cannot be arrays."),
+    }
+}
+
+impl SpecializingProgram {
+    pub(crate) fn new(source_name: String, generic: Program) -> Self {
+        let inputs = generic.borrow_specializable_inputs().clone();
+        let key_size = inputs
+            .iter()
+            .map(|input| value_size(input.borrow_data_type()))
+            .sum();
+        let scratch = generic.create_scratch();
+        Self {
+            source_name,
+            generic,
+            inputs,
+            specializations: HashMap::new(),
+            pending: Vec::new(),
+            key: Vec::with_capacity(key_size),
+            versions: Vec::new(),
+            generic_static_data: None,
+            current: None,
+            scratch,
+        }
+    }
+
+    pub fn borrow_generic(&self) -> &Program {
+        &self.generic
+    }
+
+    /// How many specialized versions of the program have been compiled so far.
+    pub fn get_num_specializations(&self) -> usize {
+        self.versions.len()
+    }
+
+    /// How many combinations of values are waiting to be compiled by `compile_pending`.
+    pub fn get_num_pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Copies the bytes of every specializable input into `self.key`. The input data has to
+    /// already have been checked to have the right size.
+    fn make_key(&mut self, input_data: &[u8]) {
+        self.key.clear();
+        for input in &self.inputs {
+            let start = input.get_offset();
+            let end = start + value_size(input.borrow_data_type());
+            self.key.extend_from_slice(&input_data[start..end]);
+        }
+    }
+
+    /// Compiles a specialized version of the program for every combination of values which was
+    /// encountered since the last time this was called, and creates static data for it. This is
+    /// the only place where specialized versions are compiled. The generic version keeps being
+    /// used for those values until this is called, so it can be done whenever it is convenient
+    /// for the host, such as between two audio blocks or once `get_num_pending` is not zero.
+    /// Returns how many versions were compiled. If a version fails to compile or its static init
+    /// code fails, the generic version is used for its values from then on and the error is
+    /// returned.
+    ///
+    /// # Safety
+    /// This runs the static init code of each new version, the same as
+    /// `Program::create_static_data`.
+    pub unsafe fn compile_pending(&mut self, compiler: &mut Compiler) -> Result<usize, String> {
+        let mut num_compiled = 0;
+        while let Some(key) = self.pending.pop() {
+            let mut values = Vec::new();
+            let mut offset = 0;
+            for input in &self.inputs {
+                let data_type = input.borrow_data_type();
+                let size = value_size(data_type);
+                let value = read_value(data_type, &key[offset..offset + size]);
+                values.push((input.borrow_name().as_str(), value));
+                offset += size;
+            }
+            let version = compiler
+                .compile_specialized(&self.source_name, &values)
+                .and_then(|program| {
+                    let static_data = program.create_static_data().map_err(|err| {
+                        format!("Static init of specialized version failed: {}", err)
+                    })?;
+                    Ok((program, static_data))
+                });
+            match version {
+                Ok((program, static_data)) => {
+                    self.scratch.reserve(program.scratch_size());
+                    let generic_layout = self.generic.borrow_static_layout();
+                    let layout = program.borrow_static_layout();
+                    self.versions.push(Version {
+                        from_generic: StaticCopyPlan::new(generic_layout, layout),
+                        to_generic: StaticCopyPlan::new(layout, generic_layout),
+                        program,
+                        static_data,
+                    });
+                    self.specializations
+                        .insert(key, Specialization::Compiled(self.versions.len() - 1));
+                    num_compiled += 1;
+                }
+                Err(err) => {
+                    self.specializations.insert(key, Specialization::Generic);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(num_compiled)
+    }
+
+    /// Runs the program, using a specialized version if one has been compiled for the current
+    /// values of the specializable inputs. If there is none, the generic version is used and the
+    /// values are queued for the next call to `compile_pending`.
+    ///
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly. The size of
+    /// the input and output data is checked the same way as `Program::execute_raw`.
+    pub unsafe fn execute_raw(
+        &mut self,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+    ) -> Result<(), RuntimeError> {
+        self.generic
+            .assert_io_size(input_data.len(), output_data.len());
+        self.make_key(input_data);
+        let version = match self.specializations.get(self.key.as_slice()) {
+            Some(Specialization::Compiled(index)) => Some(*index),
+            Some(Specialization::Pending) | Some(Specialization::Generic) => None,
+            None => {
+                self.specializations
+                    .insert(self.key.clone(), Specialization::Pending);
+                self.pending.push(self.key.clone());
+                None
+            }
+        };
+        if self.generic_static_data.is_none() {
+            self.generic_static_data = Some(self.generic.create_static_data()?);
+        }
+        let generic_data = self.generic_static_data.as_mut().unwrap();
+        if version != self.current {
+            // The generic version has every static variable that the specialized ones share, so
+            // its data is used to carry the values between them.
+            if let Some(index) = self.current {
+                let old = &self.versions[index];
+                generic_data.copy_planned(&old.static_data, &old.to_generic);
+            }
+            if let Some(index) = version {
+                let new = &mut self.versions[index];
+                new.static_data
+                    .copy_planned(generic_data, &new.from_generic);
+            }
+            self.current = version;
+        }
+        let scratch = &mut self.scratch;
+        match version {
+            Some(index) => {
+                let Version {
+                    program,
+                    static_data,
+                    ..
+                } = &mut self.versions[index];
+                program.execute_raw(input_data, output_data, static_data, scratch)
+            }
+            None => self
+                .generic
+                .execute_raw(input_data, output_data, generic_data, scratch),
+        }
+    }
+
+    /// Like `execute_raw`, but with the input and output data given as structs.
+    ///
+    /// # Safety
+    /// `T` and `U` must have the same layout as the program's input and output data. Only their
+    /// sizes are checked.
+    pub unsafe fn execute_data<T: Sized, U: Sized>(
+        &mut self,
+        input_data: &mut T,
+        output_data: &mut U,
+    ) -> Result<(), RuntimeError> {
+        let input_data =
+            std::slice::from_raw_parts_mut(input_data as *mut T as *mut u8, mem::size_of::<T>());
+        let output_data =
+            std::slice::from_raw_parts_mut(output_data as *mut U as *mut u8, mem::size_of::<U>());
+        self.execute_raw(input_data, output_data)
+    }
+}
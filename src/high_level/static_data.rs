@@ -0,0 +1,438 @@
+use crate::trivial::structure::DataType;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+
+/// The number of bytes at the start of serialized static data which store the layout fingerprint.
+const FINGERPRINT_SIZE: usize = mem::size_of::<u64>();
+
+/// Describes where a variable exported from a static block lives inside of [`StaticData`].
+#[derive(Clone, Debug)]
+pub struct StaticVariable {
+    offset: usize,
+    data_type: DataType,
+}
+
+impl StaticVariable {
+    pub fn new(offset: usize, data_type: DataType) -> Self {
+        Self { offset, data_type }
+    }
+
+    /// The offset in bytes from the start of the static data.
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn borrow_data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    /// Returns the base type of the variable and how many elements of that type it contains.
+    fn flatten(&self) -> (&DataType, usize) {
+        let mut base = &self.data_type;
+        let mut count = 1;
+        while let DataType::Array(len, etype) = base {
+            count *= len;
+            base = etype;
+        }
+        (base, count)
+    }
+
+    fn byte_size(&self) -> usize {
+        let (base, count) = self.flatten();
+        let element_size = match base {
+            DataType::B1 => bool::SIZE,
+            DataType::I32 => i32::SIZE,
+            DataType::F32 => f32::SIZE,
+            DataType::Array(..) => unreachable!("flatten() returns the base type"),
+        };
+        element_size * count
+    }
+}
+
+/// Describes what happened to each static variable when migrating static data from an old version
+/// of a program to a new one.
+#[derive(Clone, Debug, Default)]
+pub struct StaticMigration {
+    preserved: Vec<String>,
+    reinitialized: Vec<String>,
+    dropped: Vec<String>,
+}
+
+impl StaticMigration {
+    /// Variables whose values were copied over from the old data.
+    pub fn borrow_preserved(&self) -> &Vec<String> {
+        &self.preserved
+    }
+
+    /// Variables in the new program which were given fresh values by its static init code, either
+    /// because they did not exist in the old program or because their type changed.
+    pub fn borrow_reinitialized(&self) -> &Vec<String> {
+        &self.reinitialized
+    }
+
+    /// Variables in the old program whose values were discarded, either because they no longer
+    /// exist or because their type changed.
+    pub fn borrow_dropped(&self) -> &Vec<String> {
+        &self.dropped
+    }
+}
+
+/// Lists which parts of static data created for one layout hold the same variables as parts of
+/// static data created for another layout, so that the values of those variables can be copied
+/// between the two without looking anything up.
+#[derive(Clone)]
+pub struct StaticCopyPlan {
+    from: Arc<StaticLayout>,
+    to: Arc<StaticLayout>,
+    // The offset of each variable in the source data, its offset in the destination data, and
+    // its size in bytes.
+    ranges: Vec<(usize, usize, usize)>,
+}
+
+impl StaticCopyPlan {
+    /// Plans to copy every variable which has the same key and type in both layouts.
+    pub fn new(from: &Arc<StaticLayout>, to: &Arc<StaticLayout>) -> Self {
+        let mut ranges = Vec::new();
+        for (key, to_var) in to.keyed_variables.iter() {
+            match from.keyed_variables.get(key) {
+                Some(from_var) if from_var.data_type == to_var.data_type => {
+                    ranges.push((from_var.offset, to_var.offset, to_var.byte_size()));
+                }
+                _ => (),
+            }
+        }
+        ranges.sort();
+        Self {
+            from: Arc::clone(from),
+            to: Arc::clone(to),
+            ranges,
+        }
+    }
+
+    /// How many variables are copied.
+    pub fn get_num_variables(&self) -> usize {
+        self.ranges.len()
+    }
+}
+
+/// A type which can be read from or written to a static variable by the host.
+pub trait StaticValue: Copy {
+    const SIZE: usize;
+    fn data_type() -> DataType;
+    fn read(bytes: &[u8]) -> Self;
+    fn write(self, bytes: &mut [u8]);
+}
+
+impl StaticValue for bool {
+    const SIZE: usize = 1;
+    fn data_type() -> DataType {
+        DataType::B1
+    }
+    fn read(bytes: &[u8]) -> Self {
+        bytes[0] & 1 == 1
+    }
+    fn write(self, bytes: &mut [u8]) {
+        bytes[0] = self as u8;
+    }
+}
+
+impl StaticValue for i32 {
+    const SIZE: usize = 4;
+    fn data_type() -> DataType {
+        DataType::I32
+    }
+    fn read(bytes: &[u8]) -> Self {
+        let mut raw = [0; 4];
+        raw.copy_from_slice(bytes);
+        i32::from_ne_bytes(raw)
+    }
+    fn write(self, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self.to_ne_bytes());
+    }
+}
+
+impl StaticValue for f32 {
+    const SIZE: usize = 4;
+    fn data_type() -> DataType {
+        DataType::F32
+    }
+    fn read(bytes: &[u8]) -> Self {
+        let mut raw = [0; 4];
+        raw.copy_from_slice(bytes);
+        f32::from_ne_bytes(raw)
+    }
+    fn write(self, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self.to_ne_bytes());
+    }
+}
+
+/// Describes how the static variables of a particular program are arranged in its static data.
+pub struct StaticLayout {
+    fingerprint: u64,
+    size: usize,
+    variables: HashMap<String, StaticVariable>,
+    keyed_variables: HashMap<String, StaticVariable>,
+}
+
+impl StaticLayout {
+    /// `description` should be a textual description of the layout which changes whenever the
+    /// layout does. It is hashed along with the name, offset, and type of every variable to create
+    /// the fingerprint stored in serialized data. `variables` contains the named variables, while
+    /// `keyed_variables` contains every variable that has a key, named or not.
+    pub fn new(
+        description: &[u8],
+        size: usize,
+        variables: HashMap<String, StaticVariable>,
+        keyed_variables: HashMap<String, StaticVariable>,
+    ) -> Self {
+        let mut hashed = description.to_owned();
+        let mut describe = |label: &str, variables: &HashMap<String, StaticVariable>| {
+            let mut entries: Vec<_> = variables
+                .iter()
+                .map(|(id, var)| format!("\n{} {} @{}: {:?}", label, id, var.offset, var.data_type))
+                .collect();
+            entries.sort();
+            for entry in entries {
+                hashed.extend_from_slice(entry.as_bytes());
+            }
+        };
+        describe("var", &variables);
+        // The keys of named variables contain source positions, which change whenever code
+        // before them is edited. Unnamed variables can only be identified by their keys.
+        let named_offsets: Vec<_> = variables.values().map(|var| var.offset).collect();
+        let unnamed = keyed_variables
+            .iter()
+            .filter(|(_, var)| !named_offsets.contains(&var.offset))
+            .map(|(key, var)| (key.clone(), var.clone()))
+            .collect();
+        describe("unnamed", &unnamed);
+        Self {
+            fingerprint: fingerprint(&hashed),
+            size,
+            variables,
+            keyed_variables,
+        }
+    }
+
+    pub fn borrow_variables(&self) -> &HashMap<String, StaticVariable> {
+        &self.variables
+    }
+
+    /// Returns every variable which has a key, indexed by that key. Keys stay the same across
+    /// versions of a program compiled with different values for its specializable inputs.
+    pub fn borrow_keyed_variables(&self) -> &HashMap<String, StaticVariable> {
+        &self.keyed_variables
+    }
+
+    /// Panics if `data` was not created for a program with this layout.
+    pub fn assert_matches(&self, data: &StaticData) {
+        assert!(
+            self.size == data.data.len(),
+            "Expected {}, got {}.",
+            self.size,
+            data.data.len()
+        );
+        assert!(
+            self.fingerprint == data.layout.fingerprint,
+            "Static data was created for a program with a different layout."
+        );
+    }
+}
+
+/// Implemented by compiled programs which store their static variables in [`StaticData`].
+pub trait StaticDataOwner {
+    fn borrow_static_layout(&self) -> &Arc<StaticLayout>;
+}
+
+#[derive(Clone)]
+pub struct StaticData {
+    data: Vec<u8>,
+    layout: Arc<StaticLayout>,
+}
+
+impl StaticData {
+    /// Creates zeroed data for the given layout. It still needs to be initialized by running the
+    /// static init code of the program the layout came from.
+    pub fn new(layout: &Arc<StaticLayout>) -> Self {
+        Self {
+            data: vec![0; layout.size],
+            layout: Arc::clone(layout),
+        }
+    }
+
+    /// The pointer which should be passed to compiled code as the static data.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.data.as_mut_ptr()
+    }
+
+    /// Copies the values of any variables in `old_data` that have the same name and type as a
+    /// variable in this data, returning which variables were copied.
+    pub fn copy_matching_variables(&mut self, old_data: &StaticData) -> StaticMigration {
+        let mut migration = StaticMigration::default();
+        let old_vars = &old_data.layout.variables;
+        for (name, new_var) in self.layout.variables.iter() {
+            match old_vars.get(name) {
+                Some(old_var) if old_var.data_type == new_var.data_type => {
+                    let size = new_var.byte_size();
+                    let (old_start, new_start) = (old_var.offset, new_var.offset);
+                    self.data[new_start..new_start + size]
+                        .copy_from_slice(&old_data.data[old_start..old_start + size]);
+                    migration.preserved.push(name.clone());
+                }
+                _ => migration.reinitialized.push(name.clone()),
+            }
+        }
+        for name in old_vars.keys() {
+            if !migration.preserved.contains(name) {
+                migration.dropped.push(name.clone());
+            }
+        }
+        migration.preserved.sort();
+        migration.reinitialized.sort();
+        migration.dropped.sort();
+        migration
+    }
+
+    /// Copies the variables listed in `plan` from `from` to this data. Panics if either one was
+    /// not created for the layout the plan was made for.
+    pub fn copy_planned(&mut self, from: &StaticData, plan: &StaticCopyPlan) {
+        assert!(
+            Arc::ptr_eq(&from.layout, &plan.from) && Arc::ptr_eq(&self.layout, &plan.to),
+            "Static data was created for a different program than the plan."
+        );
+        for &(from_start, to_start, size) in &plan.ranges {
+            self.data[to_start..to_start + size]
+                .copy_from_slice(&from.data[from_start..from_start + size]);
+        }
+    }
+
+    /// Serializes the data so that it can be restored later with `from_bytes`. The result starts
+    /// with a fingerprint of the layout of the program it was created for.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FINGERPRINT_SIZE + self.data.len());
+        bytes.extend_from_slice(&self.layout.fingerprint.to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Restores data serialized by `to_bytes`. Fails if the data was created for a program with a
+    /// different static layout than the specified one.
+    pub fn from_bytes(program: &impl StaticDataOwner, bytes: &[u8]) -> Result<StaticData, String> {
+        if bytes.len() < FINGERPRINT_SIZE {
+            return Err(format!(
+                "Expected at least {} bytes of static data, got {}.",
+                FINGERPRINT_SIZE,
+                bytes.len()
+            ));
+        }
+        let mut fingerprint = [0; FINGERPRINT_SIZE];
+        fingerprint.copy_from_slice(&bytes[..FINGERPRINT_SIZE]);
+        let fingerprint = u64::from_le_bytes(fingerprint);
+        let layout = program.borrow_static_layout();
+        if fingerprint != layout.fingerprint {
+            return Err(format!(
+                "Static data layout fingerprint {:016x} does not match the program's layout {:016x}.",
+                fingerprint, layout.fingerprint
+            ));
+        }
+        let data = &bytes[FINGERPRINT_SIZE..];
+        if data.len() != layout.size {
+            return Err(format!(
+                "Expected {} bytes of static data, got {}.",
+                layout.size,
+                data.len()
+            ));
+        }
+        Ok(StaticData {
+            data: data.to_owned(),
+            layout: Arc::clone(layout),
+        })
+    }
+
+    /// Returns every variable exported from a static block, indexed by the name it was exported
+    /// as.
+    pub fn borrow_variables(&self) -> &HashMap<String, StaticVariable> {
+        &self.layout.variables
+    }
+
+    /// Finds the variable with the given name and checks that it is made of values of type T.
+    /// Returns the byte range of the variable and how many values it contains.
+    fn find_variable<T: StaticValue>(&self, name: &str) -> Result<(usize, usize), String> {
+        let variable = self
+            .layout
+            .variables
+            .get(name)
+            .ok_or_else(|| format!("There is no static variable named {}.", name))?;
+        let (base, count) = variable.flatten();
+        if base != &T::data_type() {
+            return Err(format!(
+                "The static variable {} has type {:?}, which cannot be accessed as {:?}.",
+                name,
+                variable.data_type,
+                T::data_type()
+            ));
+        }
+        Ok((variable.offset, count))
+    }
+
+    /// Reads a static variable which is a single value of type T.
+    pub fn get<T: StaticValue>(&self, name: &str) -> Result<T, String> {
+        let (offset, count) = self.find_variable::<T>(name)?;
+        if count != 1 {
+            return Err(format!("The static variable {} is an array.", name));
+        }
+        Ok(T::read(&self.data[offset..offset + T::SIZE]))
+    }
+
+    /// Writes a static variable which is a single value of type T.
+    pub fn set<T: StaticValue>(&mut self, name: &str, value: T) -> Result<(), String> {
+        let (offset, count) = self.find_variable::<T>(name)?;
+        if count != 1 {
+            return Err(format!("The static variable {} is an array.", name));
+        }
+        value.write(&mut self.data[offset..offset + T::SIZE]);
+        Ok(())
+    }
+
+    /// Reads every element of a static array of T. Multidimensional arrays are flattened so that
+    /// the last index changes fastest.
+    pub fn get_array<T: StaticValue>(&self, name: &str) -> Result<Vec<T>, String> {
+        let (offset, count) = self.find_variable::<T>(name)?;
+        Ok((0..count)
+            .map(|index| {
+                let start = offset + index * T::SIZE;
+                T::read(&self.data[start..start + T::SIZE])
+            })
+            .collect())
+    }
+
+    /// Overwrites every element of a static array of T, using the same order as `get_array`.
+    pub fn set_array<T: StaticValue>(&mut self, name: &str, values: &[T]) -> Result<(), String> {
+        let (offset, count) = self.find_variable::<T>(name)?;
+        if values.len() != count {
+            return Err(format!(
+                "The static variable {} contains {} elements, got {}.",
+                name,
+                count,
+                values.len()
+            ));
+        }
+        for (index, value) in values.iter().enumerate() {
+            let start = offset + index * T::SIZE;
+            value.write(&mut self.data[start..start + T::SIZE]);
+        }
+        Ok(())
+    }
+}
+
+/// A 64-bit FNV-1a hash. Used instead of std's hashers because the result is written to disk, so it
+/// needs to be the same across builds.
+fn fingerprint(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
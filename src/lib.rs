@@ -4,6 +4,10 @@
 extern crate pest_derive;
 
 pub mod ast;
+#[cfg(not(feature = "no-c"))]
+pub mod c;
+#[cfg(all(feature = "cranelift", not(feature = "no-trivial")))]
+pub mod cranelift;
 mod high_level;
 #[cfg(not(feature = "no-llvmir"))]
 pub mod llvmir;
@@ -15,5 +19,19 @@ pub mod trivial;
 pub mod util;
 #[cfg(not(feature = "no-vague"))]
 pub mod vague;
+#[cfg(not(feature = "no-wasm"))]
+pub mod wasm;
 
-pub use high_level::compiler::Compiler;
+#[cfg(not(feature = "no-trivial"))]
+pub use high_level::backend::{Backend, CompiledProgram};
+pub use high_level::compiler::{AssertMode, Compiler};
+pub use high_level::problem::{RuntimeError, RuntimeErrorKind};
+pub use high_level::scratch::Scratch;
+pub use high_level::source_loader::{FileSystemLoader, MemoryLoader, SourceLoader};
+#[cfg(not(feature = "no-llvmir"))]
+pub use high_level::specializing::SpecializingProgram;
+#[cfg(not(feature = "no-trivial"))]
+pub use high_level::static_data::{
+    StaticCopyPlan, StaticData, StaticDataOwner, StaticLayout, StaticMigration, StaticValue,
+    StaticVariable,
+};
@@ -3,6 +3,7 @@ use crate::shared::{self, ProxyMode};
 use crate::trivial::structure as i;
 use llvm_sys::core::*;
 use llvm_sys::prelude::*;
+use llvm_sys::target_machine::*;
 use llvm_sys::transforms as llvmt;
 use llvm_sys::*;
 use std::collections::{HashMap, HashSet};
@@ -24,6 +25,7 @@ struct Intrinsics {
     floor_f32: LLVMValueRef,
     ceil_f32: LLVMValueRef,
     trunc_f32: LLVMValueRef,
+    assume: LLVMValueRef,
 }
 
 impl Intrinsics {
@@ -68,24 +70,57 @@ impl Intrinsics {
             floor_f32: make(b"llvm.floor.f32\0"),
             ceil_f32: make(b"llvm.ceil.f32\0"),
             trunc_f32: make(b"llvm.trunc.f32\0"),
+            assume: unsafe {
+                let mut arg_types = [LLVMInt1TypeInContext(context)];
+                let fn_type =
+                    LLVMFunctionType(LLVMVoidTypeInContext(context), arg_types.as_mut_ptr(), 1, 0);
+                LLVMAddFunction(module, b"llvm.assume\0".as_ptr() as *const _, fn_type)
+            },
         }
     }
 }
 
+/// The types used for multi-instance execution, where every variable in the input, static, and
+/// output structs is replaced by an array holding its value for each instance.
+struct InstanceTypes {
+    count: usize,
+    input_pointer_type: LLVMTypeRef,
+    static_pointer_type: LLVMTypeRef,
+    output_pointer_type: LLVMTypeRef,
+    /// An array holding a separate copy of the main body's scratch struct for every instance.
+    scratch_type: LLVMTypeRef,
+}
+
 struct Converter<'a> {
     source: &'a i::Program,
     input_pointer_type: LLVMTypeRef,
     output_pointer_type: LLVMTypeRef,
     static_pointer_type: LLVMTypeRef,
+    /// The input and output pointer types of each entry point.
+    entry_pointer_types: Vec<(LLVMTypeRef, LLVMTypeRef)>,
+    /// Local variables of the main body and entry points which are stored in scratch memory
+    /// instead of on the stack.
+    scratch_vars: HashSet<i::VariableId>,
+    /// The scratch data type of the main body followed by that of each entry point.
+    scratch_types: Vec<LLVMTypeRef>,
+    /// Set when the program should also be compiled for multi-instance execution.
+    instance_types: Option<InstanceTypes>,
 
     context: LLVMContextRef,
     module: LLVMModuleRef,
     builder: LLVMBuilderRef,
     intrinsics: Intrinsics,
+    /// The declaration of each extern function, in the same order as the source program.
+    extern_functions: Vec<LLVMValueRef>,
 
     value_pointers: HashMap<i::VariableId, LLVMValueRef>,
-    label_blocks: Vec<LLVMBasicBlockRef>,
+    label_blocks: HashMap<i::LabelId, LLVMBasicBlockRef>,
     current_block_terminated: bool,
+    /// The index of the instance being processed while converting a `.lane` function.
+    lane: Option<LLVMValueRef>,
+    /// Points to the error code of the instance being processed while converting `main.lane`.
+    /// Aborting stores the code there instead of returning so that the body has no early exits.
+    lane_error_code: Option<LLVMValueRef>,
 }
 
 impl<'a> Converter<'a> {
@@ -184,6 +219,14 @@ impl<'a> Converter<'a> {
                     assert!(value.dimensions.len() == 0);
                 }
                 unsafe {
+                    // An instance which has aborted must not change its static data or outputs.
+                    let content = match self.build_lane_ok() {
+                        Some(ok) => {
+                            let old = LLVMBuildLoad(self.builder, ptr, UNNAMED);
+                            LLVMBuildSelect(self.builder, ok, content, old, UNNAMED)
+                        }
+                        None => content,
+                    };
                     LLVMBuildStore(self.builder, content, ptr);
                 }
             }
@@ -345,7 +388,7 @@ impl<'a> Converter<'a> {
     }
 
     fn get_block_for_label(&self, id: &i::LabelId) -> LLVMBasicBlockRef {
-        self.label_blocks[id.raw()]
+        self.label_blocks[id]
     }
 
     fn usize_vec_to_u32(vec: Vec<usize>) -> Vec<u32> {
@@ -439,6 +482,19 @@ impl<'a> Converter<'a> {
         }
     }
 
+    fn convert_select(&mut self, condition: &i::Value, a: &i::Value, b: &i::Value, x: &i::Value) {
+        let dimensions = x.dimensions.iter().map(|(len, _)| *len).collect();
+        for position in shared::NDIndexIter::new(dimensions) {
+            let coord = Self::usize_vec_to_u32(position);
+            let cr = self.load_value(condition, &coord[..]);
+            let ar = self.load_value(a, &coord[..]);
+            let br = self.load_value(b, &coord[..]);
+
+            let xr = unsafe { LLVMBuildSelect(self.builder, cr, ar, br, UNNAMED) };
+            self.store_value(x, xr, &coord[..]);
+        }
+    }
+
     fn do_binary_op(
         &mut self,
         op: &i::BinaryOperator,
@@ -449,8 +505,14 @@ impl<'a> Converter<'a> {
             i::BinaryOperator::AddI => unsafe { LLVMBuildAdd(self.builder, ar, br, UNNAMED) },
             i::BinaryOperator::SubI => unsafe { LLVMBuildSub(self.builder, ar, br, UNNAMED) },
             i::BinaryOperator::MulI => unsafe { LLVMBuildMul(self.builder, ar, br, UNNAMED) },
-            i::BinaryOperator::DivI => unsafe { LLVMBuildSDiv(self.builder, ar, br, UNNAMED) },
-            i::BinaryOperator::ModI => unsafe { LLVMBuildSRem(self.builder, ar, br, UNNAMED) },
+            i::BinaryOperator::DivI => {
+                let br = self.build_lane_divisor(br);
+                unsafe { LLVMBuildSDiv(self.builder, ar, br, UNNAMED) }
+            }
+            i::BinaryOperator::ModI => {
+                let br = self.build_lane_divisor(br);
+                unsafe { LLVMBuildSRem(self.builder, ar, br, UNNAMED) }
+            }
             i::BinaryOperator::AddF => unsafe { LLVMBuildFAdd(self.builder, ar, br, UNNAMED) },
             i::BinaryOperator::SubF => unsafe { LLVMBuildFSub(self.builder, ar, br, UNNAMED) },
             i::BinaryOperator::MulF => unsafe { LLVMBuildFMul(self.builder, ar, br, UNNAMED) },
@@ -503,7 +565,11 @@ impl<'a> Converter<'a> {
         let dimensions = from.dimensions.iter().map(|(len, _)| *len).collect();
         let mut dyn_indexes: Vec<_> = to_indexes
             .iter()
-            .map(|value| self.load_value(value, &[]))
+            .enumerate()
+            .map(|(position, value)| {
+                let index = self.load_value(value, &[]);
+                self.build_lane_index(index, to.dimensions[position].0)
+            })
             .collect();
         dyn_indexes.insert(0, self.u32_const(0));
         for position in shared::NDIndexIter::new(dimensions) {
@@ -521,7 +587,11 @@ impl<'a> Converter<'a> {
         let dimensions = to.dimensions.iter().map(|(len, _)| *len).collect();
         let mut dyn_indexes: Vec<_> = from_indexes
             .iter()
-            .map(|value| self.load_value(value, &[]))
+            .enumerate()
+            .map(|(position, value)| {
+                let index = self.load_value(value, &[]);
+                self.build_lane_index(index, from.dimensions[position].0)
+            })
             .collect();
         dyn_indexes.insert(0, self.u32_const(0));
         for position in shared::NDIndexIter::new(dimensions) {
@@ -563,12 +633,138 @@ impl<'a> Converter<'a> {
     }
 
     fn convert_abort(&mut self, error_code: u32) {
+        if let Some(error_pointer) = self.lane_error_code {
+            // Only the first error of each instance is kept. The instance keeps running afterwards
+            // so that LLVM can turn the branch around the abort into a select, but everything it
+            // does from then on is discarded (see `build_lane_ok`).
+            unsafe {
+                let old = LLVMBuildLoad(self.builder, error_pointer, UNNAMED);
+                let zero = self.u32_const(0);
+                let first = LLVMBuildICmp(
+                    self.builder,
+                    LLVMIntPredicate::LLVMIntEQ,
+                    old,
+                    zero,
+                    UNNAMED,
+                );
+                let code = self.u32_const(error_code);
+                let new = LLVMBuildSelect(self.builder, first, code, old, UNNAMED);
+                LLVMBuildStore(self.builder, new, error_pointer);
+            }
+            return;
+        }
         unsafe {
             LLVMBuildRet(self.builder, self.u32_const(error_code));
         }
         self.current_block_terminated = true;
     }
 
+    /// Inside `main.lane`, returns whether the current instance has not aborted yet. Instances keep
+    /// running after they abort, so this is used to keep them from storing values or calling
+    /// extern functions afterwards.
+    fn build_lane_ok(&self) -> Option<LLVMValueRef> {
+        let error_pointer = self.lane_error_code?;
+        unsafe {
+            let code = LLVMBuildLoad(self.builder, error_pointer, UNNAMED);
+            Some(LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntEQ,
+                code,
+                self.u32_const(0),
+                UNNAMED,
+            ))
+        }
+    }
+
+    /// Inside `main.lane`, an assert checking that a dynamic index is in bounds does not stop the
+    /// access it guards, so this replaces an index outside of `0..len` with zero.
+    fn build_lane_index(&self, index: LLVMValueRef, len: usize) -> LLVMValueRef {
+        if self.lane_error_code.is_none() {
+            return index;
+        }
+        unsafe {
+            let in_bounds = LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntULT,
+                index,
+                self.i32_const(len as i32),
+                UNNAMED,
+            );
+            LLVMBuildSelect(self.builder, in_bounds, index, self.i32_const(0), UNNAMED)
+        }
+    }
+
+    /// Inside `main.lane`, an instance keeps running after it aborts, so integer division could
+    /// be reached with a divisor that an assert was supposed to rule out. This replaces a divisor
+    /// of zero with one there so that it cannot trap.
+    fn build_lane_divisor(&self, divisor: LLVMValueRef) -> LLVMValueRef {
+        if self.lane_error_code.is_none() {
+            return divisor;
+        }
+        unsafe {
+            let zero = LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntEQ,
+                divisor,
+                self.i32_const(0),
+                UNNAMED,
+            );
+            LLVMBuildSelect(self.builder, zero, self.i32_const(1), divisor, UNNAMED)
+        }
+    }
+
+    fn convert_assume(&mut self, condition: &i::Value) {
+        // What is assumed may not hold for an instance that has already aborted.
+        if self.lane_error_code.is_some() {
+            return;
+        }
+        let condition = self.load_value(condition, &[]);
+        self.build_call(self.intrinsics.assume, &mut [condition]);
+    }
+
+    fn borrow_variable_pointer(&self, value: &i::Value) -> LLVMValueRef {
+        if let i::ValueBase::Variable(id) = &value.base {
+            *self
+                .value_pointers
+                .get(id)
+                .expect("A variable was not given a pointer.")
+        } else {
+            unreachable!("Only variables can be passed by pointer.")
+        }
+    }
+
+    fn convert_call_extern(&mut self, function: usize, inputs: &[i::Value], outputs: &[i::Value]) {
+        let mut args = Vec::new();
+        for input in inputs {
+            if !input.dimensions.is_empty() {
+                args.push(self.borrow_variable_pointer(input));
+            } else {
+                args.push(self.load_value(input, &[]));
+            }
+        }
+        for output in outputs {
+            args.push(self.borrow_variable_pointer(output));
+        }
+        let ok = match self.build_lane_ok() {
+            Some(ok) => ok,
+            None => {
+                self.build_call(self.extern_functions[function], &mut args[..]);
+                return;
+            }
+        };
+        // The host should not be called on behalf of an instance which has aborted.
+        unsafe {
+            let function_value = LLVMGetBasicBlockParent(LLVMGetInsertBlock(self.builder));
+            let call_block = self.append_block(function_value, b"extern\0");
+            let done_block = self.append_block(function_value, b"extern.done\0");
+            LLVMBuildCondBr(self.builder, ok, call_block, done_block);
+            LLVMPositionBuilderAtEnd(self.builder, call_block);
+            self.build_call(self.extern_functions[function], &mut args[..]);
+            LLVMBuildBr(self.builder, done_block);
+            LLVMPositionBuilderAtEnd(self.builder, done_block);
+        }
+    }
+
     fn convert_jump(&mut self, label: &i::LabelId) {
         unsafe {
             LLVMBuildBr(self.builder, self.get_block_for_label(label));
@@ -576,63 +772,78 @@ impl<'a> Converter<'a> {
         self.current_block_terminated = true;
     }
 
-    fn create_variable_pointers_for_main_body(
+    fn build_struct_element_pointer(
+        &self,
+        struct_pointer: LLVMValueRef,
+        index: u32,
+    ) -> LLVMValueRef {
+        let mut indices = [self.u32_const(0), self.u32_const(index)];
+        unsafe {
+            LLVMBuildGEP(
+                self.builder,
+                struct_pointer,
+                indices.as_mut_ptr(),
+                indices.len() as u32,
+                UNNAMED,
+            )
+        }
+    }
+
+    /// Returns a pointer to the variable stored in element `index` of an input, static, or output
+    /// struct. Inside `.lane` functions each element holds one value per instance, so the
+    /// pointer is to the value of the current instance.
+    fn build_variable_pointer(&self, struct_pointer: LLVMValueRef, index: u32) -> LLVMValueRef {
+        let mut indices = vec![self.u32_const(0), self.u32_const(index)];
+        if let Some(lane) = self.lane {
+            indices.push(lane);
+        }
+        unsafe {
+            LLVMBuildGEP(
+                self.builder,
+                struct_pointer,
+                indices.as_mut_ptr(),
+                indices.len() as u32,
+                UNNAMED,
+            )
+        }
+    }
+
+    /// Used for the main body and for entry points, which both receive an input, static, output,
+    /// and scratch pointer.
+    fn create_variable_pointers_for_body(
         &mut self,
+        body: i::CodeBody,
         input_pointer: LLVMValueRef,
         static_pointer: LLVMValueRef,
         output_pointer: LLVMValueRef,
+        scratch_pointer: LLVMValueRef,
     ) {
+        let input_location = body.get_input_location();
+        let output_location = body.get_output_location();
+        let local_location = body.get_local_location();
         let mut input_index = 0;
         let mut output_index = 0;
         let mut static_index = 0;
+        let mut scratch_index = 0;
         for var_id in self.source.iterate_all_variables() {
             let llvmt = llvm_type(self.context, self.source[var_id].borrow_type());
-            let ptr = match self.source[var_id].get_location() {
-                i::StorageLocation::Input => {
-                    let mut indices = [self.u32_const(0), self.u32_const(input_index as u32)];
-                    input_index += 1;
-                    unsafe {
-                        LLVMBuildGEP(
-                            self.builder,
-                            input_pointer,
-                            indices.as_mut_ptr(),
-                            indices.len() as u32,
-                            UNNAMED,
-                        )
-                    }
-                }
-                i::StorageLocation::Output => {
-                    let mut indices = [self.u32_const(0), self.u32_const(output_index as u32)];
-                    output_index += 1;
-                    unsafe {
-                        LLVMBuildGEP(
-                            self.builder,
-                            output_pointer,
-                            indices.as_mut_ptr(),
-                            indices.len() as u32,
-                            UNNAMED,
-                        )
-                    }
-                }
-                i::StorageLocation::Static => {
-                    let mut indices = [self.u32_const(0), self.u32_const(static_index as u32)];
-                    static_index += 1;
-                    unsafe {
-                        LLVMBuildGEP(
-                            self.builder,
-                            static_pointer,
-                            indices.as_mut_ptr(),
-                            indices.len() as u32,
-                            UNNAMED,
-                        )
-                    }
-                }
-                i::StorageLocation::StaticBody => {
-                    continue;
-                }
-                i::StorageLocation::MainBody => unsafe {
-                    LLVMBuildAlloca(self.builder, llvmt, UNNAMED)
-                },
+            let location = self.source[var_id].get_location();
+            let ptr = if Some(location) == input_location {
+                input_index += 1;
+                self.build_variable_pointer(input_pointer, input_index - 1)
+            } else if Some(location) == output_location {
+                output_index += 1;
+                self.build_variable_pointer(output_pointer, output_index - 1)
+            } else if location == i::StorageLocation::Static {
+                static_index += 1;
+                self.build_variable_pointer(static_pointer, static_index - 1)
+            } else if location == local_location && self.scratch_vars.contains(&var_id) {
+                scratch_index += 1;
+                self.build_struct_element_pointer(scratch_pointer, scratch_index - 1)
+            } else if location == local_location {
+                unsafe { LLVMBuildAlloca(self.builder, llvmt, UNNAMED) }
+            } else {
+                continue;
             };
             self.value_pointers.insert(var_id, ptr);
         }
@@ -650,22 +861,16 @@ impl<'a> Converter<'a> {
                     continue;
                 }
                 i::StorageLocation::Static => {
-                    let mut indices = [self.u32_const(0), self.u32_const(static_index as u32)];
                     static_index += 1;
-                    unsafe {
-                        LLVMBuildGEP(
-                            self.builder,
-                            static_pointer,
-                            indices.as_mut_ptr(),
-                            indices.len() as u32,
-                            UNNAMED,
-                        )
-                    }
+                    self.build_variable_pointer(static_pointer, static_index - 1)
                 }
                 i::StorageLocation::StaticBody => unsafe {
                     LLVMBuildAlloca(self.builder, llvmt, UNNAMED)
                 },
-                i::StorageLocation::MainBody => {
+                i::StorageLocation::MainBody
+                | i::StorageLocation::EntryInput(..)
+                | i::StorageLocation::EntryOutput(..)
+                | i::StorageLocation::EntryBody(..) => {
                     continue;
                 }
             };
@@ -673,29 +878,22 @@ impl<'a> Converter<'a> {
         }
     }
 
-    fn create_blocks_for_labels(&mut self, function: LLVMValueRef, static_body: bool) {
+    fn create_blocks_for_labels(&mut self, function: LLVMValueRef, body: i::CodeBody) {
         for label in self.source.iterate_all_labels() {
-            if self.source.is_label_in_static_body(label) != static_body {
+            if self.source.get_label_body(label) != body {
                 continue;
             }
-            self.label_blocks.push(unsafe {
+            let block = unsafe {
                 LLVMAppendBasicBlockInContext(
                     self.context,
                     function,
                     format!("{:?}\0", label).as_ptr() as *const _,
                 )
-            });
+            };
+            self.label_blocks.insert(label, block);
         }
     }
 
-    fn create_blocks_for_main_body_labels(&mut self, function: LLVMValueRef) {
-        self.create_blocks_for_labels(function, false);
-    }
-
-    fn create_blocks_for_static_body_labels(&mut self, function: LLVMValueRef) {
-        self.create_blocks_for_labels(function, true);
-    }
-
     fn reset(&mut self) {
         self.label_blocks.clear();
         self.value_pointers.clear();
@@ -714,6 +912,15 @@ impl<'a> Converter<'a> {
             );
 
             let pm = LLVMCreatePassManager();
+            let mut target_machine = None;
+            if self.instance_types.is_some() {
+                // The vectorizers need to know which SIMD instructions are available.
+                let machine = create_native_target_machine();
+                LLVMAddAnalysisPasses(machine, pm);
+                target_machine = Some(machine);
+                // Put the body of each .lane function in the loop of its .instances function.
+                llvmt::ipo::LLVMAddAlwaysInlinerPass(pm);
+            }
             // Convert all our stores / loads into flat, efficient SSA style code.
             llvmt::scalar::LLVMAddScalarReplAggregatesPassSSA(pm);
             llvmt::scalar::LLVMAddEarlyCSEPass(pm);
@@ -721,19 +928,38 @@ impl<'a> Converter<'a> {
             llvmt::scalar::LLVMAddReassociatePass(pm);
             llvmt::scalar::LLVMAddGVNPass(pm);
             llvmt::scalar::LLVMAddCFGSimplificationPass(pm);
+            if self.instance_types.is_some() {
+                // Process several instances at once using SIMD instructions where possible.
+                llvmt::vectorize::LLVMAddLoopVectorizePass(pm);
+                llvmt::vectorize::LLVMAddSLPVectorizePass(pm);
+                llvmt::scalar::LLVMAddInstructionCombiningPass(pm);
+                llvmt::scalar::LLVMAddCFGSimplificationPass(pm);
+            }
 
             LLVMRunPassManager(pm, self.module);
             LLVMDisposePassManager(pm);
+            if let Some(machine) = target_machine {
+                LLVMDisposeTargetMachine(machine);
+            }
         }
     }
 
     fn convert_instruction(&mut self, instruction: &i::Instruction) {
         match instruction {
             i::Instruction::Abort(error_code) => self.convert_abort(*error_code),
+            i::Instruction::Assume(condition) => self.convert_assume(condition),
+            i::Instruction::CallExtern {
+                function,
+                inputs,
+                outputs,
+            } => self.convert_call_extern(*function, inputs, outputs),
             i::Instruction::BinaryOperation { op, a, b, x } => {
                 self.convert_binary_expression(op, a, b, x)
             }
             i::Instruction::UnaryOperation { op, a, x } => self.convert_unary_expression(op, a, x),
+            i::Instruction::Select { condition, a, b, x } => {
+                self.convert_select(condition, a, b, x)
+            }
             i::Instruction::Move { from, to } => self.convert_move(from, to),
             i::Instruction::Label(id) => self.convert_label(id),
             i::Instruction::Branch {
@@ -755,56 +981,173 @@ impl<'a> Converter<'a> {
         }
     }
 
-    fn convert(&mut self) {
-        unsafe {
-            // LLVM related setup for main function.
-            let i32t = LLVMInt32TypeInContext(self.context);
-            let mut argts = [
+    /// The type of the scratch data used by `main.lane` and `main.instances`.
+    fn get_lane_scratch_type(&self) -> LLVMTypeRef {
+        self.instance_types
+            .as_ref()
+            .expect("Not compiling for instances.")
+            .scratch_type
+    }
+
+    /// Returns the input, static, and output pointer types used by the function for `body`.
+    fn get_pointer_types(&self, body: i::CodeBody, lane: bool) -> [LLVMTypeRef; 3] {
+        if lane {
+            let types = self
+                .instance_types
+                .as_ref()
+                .expect("Not compiling for instances.");
+            return [
+                types.input_pointer_type,
+                types.static_pointer_type,
+                types.output_pointer_type,
+            ];
+        }
+        match body {
+            i::CodeBody::Main => [
                 self.input_pointer_type,
                 self.static_pointer_type,
                 self.output_pointer_type,
+            ],
+            i::CodeBody::EntryPoint(index) => {
+                let (input_pointer_type, output_pointer_type) = self.entry_pointer_types[index];
+                [
+                    input_pointer_type,
+                    self.static_pointer_type,
+                    output_pointer_type,
+                ]
+            }
+            i::CodeBody::StaticInit => unreachable!("Static init has no input or output."),
+        }
+    }
+
+    /// `.lane` functions only exist to be inlined into the loop of an `.instances` function.
+    fn make_lane_function(&mut self, function: LLVMValueRef) {
+        let always_inline = b"alwaysinline";
+        unsafe {
+            LLVMSetLinkage(function, LLVMLinkage::LLVMInternalLinkage);
+            let kind = LLVMGetEnumAttributeKindForName(
+                always_inline.as_ptr() as *const _,
+                always_inline.len(),
+            );
+            let attribute = LLVMCreateEnumAttribute(self.context, kind, 0);
+            LLVMAddAttributeAtIndex(function, LLVMAttributeFunctionIndex, attribute);
+        }
+    }
+
+    /// Converts the main body or an entry point into a function taking an input, static, output,
+    /// and scratch pointer. If `lane` is set, the function takes the index of an instance as an
+    /// extra argument and uses the multi-instance layout of the input, static, and output data.
+    fn convert_body(
+        &mut self,
+        name_nullterm: &[u8],
+        body: i::CodeBody,
+        instructions: &[i::Instruction],
+        lane: bool,
+    ) -> LLVMValueRef {
+        let body_index = match body {
+            i::CodeBody::EntryPoint(index) => index + 1,
+            _ => 0,
+        };
+        let [input_pointer_type, static_pointer_type, output_pointer_type] =
+            self.get_pointer_types(body, lane);
+        unsafe {
+            // LLVM related setup for the function.
+            let i32t = LLVMInt32TypeInContext(self.context);
+            let scratch_type = if lane {
+                self.get_lane_scratch_type()
+            } else {
+                self.scratch_types[body_index]
+            };
+            let mut argts = vec![
+                input_pointer_type,
+                static_pointer_type,
+                output_pointer_type,
+                LLVMPointerType(scratch_type, 0),
             ];
+            if lane {
+                argts.push(i32t);
+            }
             let function_type = LLVMFunctionType(i32t, argts.as_mut_ptr(), argts.len() as u32, 0);
-            let main_fn =
-                LLVMAddFunction(self.module, b"main\0".as_ptr() as *const _, function_type);
+            let function = LLVMAddFunction(
+                self.module,
+                name_nullterm.as_ptr() as *const _,
+                function_type,
+            );
             let entry_block = LLVMAppendBasicBlockInContext(
                 self.context,
-                main_fn,
+                function,
                 b"entry\0".as_ptr() as *const _,
             );
             LLVMPositionBuilderAtEnd(self.builder, entry_block);
-            let input_pointer = LLVMGetParam(main_fn, 0);
-            let static_pointer = LLVMGetParam(main_fn, 1);
-            let output_pointer = LLVMGetParam(main_fn, 2);
+            let input_pointer = LLVMGetParam(function, 0);
+            let static_pointer = LLVMGetParam(function, 1);
+            let output_pointer = LLVMGetParam(function, 2);
+            let mut scratch_pointer = LLVMGetParam(function, 3);
 
-            // Self-related setup for main function.
+            // Self-related setup for the function.
             self.reset();
-            self.create_variable_pointers_for_main_body(
+            if lane {
+                self.make_lane_function(function);
+                let lane = LLVMGetParam(function, 4);
+                self.lane = Some(lane);
+                // Each instance has its own copy of the scratch struct.
+                let mut indices = [self.u32_const(0), lane];
+                scratch_pointer = LLVMBuildGEP(
+                    self.builder,
+                    scratch_pointer,
+                    indices.as_mut_ptr(),
+                    indices.len() as u32,
+                    UNNAMED,
+                );
+                let error_pointer = LLVMBuildAlloca(self.builder, i32t, UNNAMED);
+                LLVMBuildStore(self.builder, self.u32_const(0), error_pointer);
+                self.lane_error_code = Some(error_pointer);
+            }
+            self.create_variable_pointers_for_body(
+                body,
                 input_pointer,
                 static_pointer,
                 output_pointer,
+                scratch_pointer,
             );
-            self.create_blocks_for_main_body_labels(main_fn);
+            self.create_blocks_for_labels(function, body);
 
             // Convert instructions.
-            for instruction in self.source.borrow_instructions().clone() {
+            for instruction in instructions {
                 self.convert_instruction(instruction);
             }
 
             // Add OK return if missing.
             if !self.current_block_terminated {
-                LLVMBuildRet(self.builder, self.u32_const(0));
+                let error_code = match self.lane_error_code {
+                    Some(error_pointer) => LLVMBuildLoad(self.builder, error_pointer, UNNAMED),
+                    None => self.u32_const(0),
+                };
+                LLVMBuildRet(self.builder, error_code);
             }
+            self.lane = None;
+            self.lane_error_code = None;
+            function
         }
+    }
 
+    /// Converts the static init body into a function taking a static pointer. `lane` works the
+    /// same as in `convert_body`.
+    fn convert_static_init(&mut self, name_nullterm: &[u8], lane: bool) -> LLVMValueRef {
         unsafe {
             // LLVM related setup for static init function.
             let i32t = LLVMInt32TypeInContext(self.context);
-            let mut argts = [self.static_pointer_type];
+            let mut argts = vec![match &self.instance_types {
+                Some(types) if lane => types.static_pointer_type,
+                _ => self.static_pointer_type,
+            }];
+            if lane {
+                argts.push(i32t);
+            }
             let function_type = LLVMFunctionType(i32t, argts.as_mut_ptr(), argts.len() as u32, 0);
             let static_init_fn = LLVMAddFunction(
                 self.module,
-                b"static_init\0".as_ptr() as *const _,
+                name_nullterm.as_ptr() as *const _,
                 function_type,
             );
             let entry_block = LLVMAppendBasicBlockInContext(
@@ -817,11 +1160,16 @@ impl<'a> Converter<'a> {
 
             // Self-related setup for main function.
             self.reset();
+            if lane {
+                self.make_lane_function(static_init_fn);
+                self.lane = Some(LLVMGetParam(static_init_fn, 1));
+            }
             self.create_variable_pointers_for_static_body(static_pointer);
-            self.create_blocks_for_static_body_labels(static_init_fn);
+            self.create_blocks_for_labels(static_init_fn, i::CodeBody::StaticInit);
 
             // Convert instructions.
-            for instruction in self.source.borrow_static_init_instructions().clone() {
+            let source = self.source;
+            for instruction in source.borrow_static_init_instructions() {
                 self.convert_instruction(instruction);
             }
 
@@ -829,6 +1177,181 @@ impl<'a> Converter<'a> {
             if !self.current_block_terminated {
                 LLVMBuildRet(self.builder, self.u32_const(0));
             }
+            self.lane = None;
+            static_init_fn
+        }
+    }
+
+    fn append_block(&self, function: LLVMValueRef, name_nullterm: &[u8]) -> LLVMBasicBlockRef {
+        unsafe {
+            LLVMAppendBasicBlockInContext(
+                self.context,
+                function,
+                name_nullterm.as_ptr() as *const _,
+            )
+        }
+    }
+
+    /// Creates `main.instances`, which runs the main body once for every instance. The error code
+    /// of each instance is written to the array passed as the last argument. Aborts in `main.lane`
+    /// only record the error code, so the loop has no early exits and LLVM can turn the branches
+    /// in it into selects and vectorize it.
+    fn convert_main_instances(&mut self, count: usize, main_lane: LLVMValueRef) {
+        let [input_pointer_type, static_pointer_type, output_pointer_type] =
+            self.get_pointer_types(i::CodeBody::Main, true);
+        unsafe {
+            let i32t = LLVMInt32TypeInContext(self.context);
+            let mut argts = [
+                input_pointer_type,
+                static_pointer_type,
+                output_pointer_type,
+                LLVMPointerType(self.get_lane_scratch_type(), 0),
+                LLVMPointerType(i32t, 0),
+            ];
+            let function_type = LLVMFunctionType(
+                LLVMVoidTypeInContext(self.context),
+                argts.as_mut_ptr(),
+                argts.len() as u32,
+                0,
+            );
+            let function = LLVMAddFunction(
+                self.module,
+                b"main.instances\0".as_ptr() as *const _,
+                function_type,
+            );
+            // The host always passes separate buffers for each argument.
+            let noalias = b"noalias";
+            let kind = LLVMGetEnumAttributeKindForName(noalias.as_ptr() as *const _, noalias.len());
+            for index in 0..argts.len() {
+                let attribute = LLVMCreateEnumAttribute(self.context, kind, 0);
+                LLVMAddAttributeAtIndex(function, index as u32 + 1, attribute);
+            }
+            let entry_block = self.append_block(function, b"entry\0");
+            let loop_block = self.append_block(function, b"instance\0");
+            let exit_block = self.append_block(function, b"exit\0");
+
+            LLVMPositionBuilderAtEnd(self.builder, entry_block);
+            LLVMBuildBr(self.builder, loop_block);
+
+            LLVMPositionBuilderAtEnd(self.builder, loop_block);
+            let lane = LLVMBuildPhi(self.builder, i32t, UNNAMED);
+            let mut args: Vec<_> = (0..4).map(|index| LLVMGetParam(function, index)).collect();
+            args.push(lane);
+            let error_code = self.build_call(main_lane, &mut args[..]);
+            let mut indices = [lane];
+            let error_pointer = LLVMBuildGEP(
+                self.builder,
+                LLVMGetParam(function, 4),
+                indices.as_mut_ptr(),
+                indices.len() as u32,
+                UNNAMED,
+            );
+            LLVMBuildStore(self.builder, error_code, error_pointer);
+            let next = LLVMBuildAdd(self.builder, lane, self.u32_const(1), UNNAMED);
+            let count = self.u32_const(count as u32);
+            let more = LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntULT,
+                next,
+                count,
+                UNNAMED,
+            );
+            LLVMBuildCondBr(self.builder, more, loop_block, exit_block);
+            let mut incoming_values = [self.u32_const(0), next];
+            let mut incoming_blocks = [entry_block, loop_block];
+            LLVMAddIncoming(
+                lane,
+                incoming_values.as_mut_ptr(),
+                incoming_blocks.as_mut_ptr(),
+                incoming_values.len() as u32,
+            );
+
+            LLVMPositionBuilderAtEnd(self.builder, exit_block);
+            LLVMBuildRetVoid(self.builder);
+        }
+    }
+
+    /// Creates `static_init.instances`, which runs the static init body for every instance,
+    /// stopping at the first one which fails.
+    fn convert_static_init_instances(&mut self, count: usize, static_init_lane: LLVMValueRef) {
+        unsafe {
+            let i32t = LLVMInt32TypeInContext(self.context);
+            let static_pointer_type = self.get_pointer_types(i::CodeBody::Main, true)[1];
+            let mut argts = [static_pointer_type];
+            let function_type = LLVMFunctionType(i32t, argts.as_mut_ptr(), argts.len() as u32, 0);
+            let function = LLVMAddFunction(
+                self.module,
+                b"static_init.instances\0".as_ptr() as *const _,
+                function_type,
+            );
+            let entry_block = self.append_block(function, b"entry\0");
+            let loop_block = self.append_block(function, b"instance\0");
+            let next_block = self.append_block(function, b"next\0");
+            let failed_block = self.append_block(function, b"failed\0");
+            let exit_block = self.append_block(function, b"exit\0");
+
+            LLVMPositionBuilderAtEnd(self.builder, entry_block);
+            LLVMBuildBr(self.builder, loop_block);
+
+            LLVMPositionBuilderAtEnd(self.builder, loop_block);
+            let lane = LLVMBuildPhi(self.builder, i32t, UNNAMED);
+            let mut args = [LLVMGetParam(function, 0), lane];
+            let error_code = self.build_call(static_init_lane, &mut args);
+            let zero = self.u32_const(0);
+            let failed = LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntNE,
+                error_code,
+                zero,
+                UNNAMED,
+            );
+            LLVMBuildCondBr(self.builder, failed, failed_block, next_block);
+
+            LLVMPositionBuilderAtEnd(self.builder, next_block);
+            let next = LLVMBuildAdd(self.builder, lane, self.u32_const(1), UNNAMED);
+            let count = self.u32_const(count as u32);
+            let more = LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntULT,
+                next,
+                count,
+                UNNAMED,
+            );
+            LLVMBuildCondBr(self.builder, more, loop_block, exit_block);
+            let mut incoming_values = [zero, next];
+            let mut incoming_blocks = [entry_block, next_block];
+            LLVMAddIncoming(
+                lane,
+                incoming_values.as_mut_ptr(),
+                incoming_blocks.as_mut_ptr(),
+                incoming_values.len() as u32,
+            );
+
+            LLVMPositionBuilderAtEnd(self.builder, failed_block);
+            LLVMBuildRet(self.builder, error_code);
+            LLVMPositionBuilderAtEnd(self.builder, exit_block);
+            LLVMBuildRet(self.builder, zero);
+        }
+    }
+
+    fn convert(&mut self) {
+        let source = self.source;
+        let main = i::CodeBody::Main;
+        self.convert_body(b"main\0", main, &source.borrow_instructions()[..], false);
+        for (index, entry_point) in source.borrow_entry_points().iter().enumerate() {
+            let name = format!("entry.{}\0", entry_point.borrow_name());
+            let body = i::CodeBody::EntryPoint(index);
+            let instructions = &entry_point.borrow_instructions()[..];
+            self.convert_body(name.as_bytes(), body, instructions, false);
+        }
+        self.convert_static_init(b"static_init\0", false);
+
+        if let Some(count) = self.instance_types.as_ref().map(|types| types.count) {
+            let instructions = &source.borrow_instructions()[..];
+            let main_lane = self.convert_body(b"main.lane\0", main, instructions, true);
+            self.convert_main_instances(count, main_lane);
+            let static_init_lane = self.convert_static_init(b"static_init.lane\0", true);
+            self.convert_static_init_instances(count, static_init_lane);
         }
 
         unsafe {
@@ -852,6 +1375,108 @@ impl<'a> Converter<'a> {
     }
 }
 
+/// Creates a target machine for the computer the program is running on, with the same settings
+/// the JIT uses when generating code.
+unsafe fn create_native_target_machine() -> LLVMTargetMachineRef {
+    assert!(
+        llvm_sys::target::LLVM_InitializeNativeTarget() != 1,
+        "Failed to initialize native target."
+    );
+    let triple = LLVMGetDefaultTargetTriple();
+    let mut target = std::ptr::null_mut();
+    let mut error = std::ptr::null_mut();
+    if LLVMGetTargetFromTriple(triple, &mut target, &mut error) != 0 {
+        let message = std::ffi::CStr::from_ptr(error)
+            .to_string_lossy()
+            .into_owned();
+        LLVMDisposeMessage(error);
+        panic!("Failed to find native target: {}", message);
+    }
+    let machine = LLVMCreateTargetMachine(
+        target,
+        triple,
+        UNNAMED,
+        UNNAMED,
+        LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+        LLVMRelocMode::LLVMRelocDefault,
+        LLVMCodeModel::LLVMCodeModelJITDefault,
+    );
+    LLVMDisposeMessage(triple);
+    machine
+}
+
+/// Creates a packed struct containing every variable stored in `location`, in the order they were
+/// declared.
+fn packed_struct_type(
+    context: LLVMContextRef,
+    source: &i::Program,
+    location: i::StorageLocation,
+) -> LLVMTypeRef {
+    let mut types: Vec<_> = source
+        .iterate_all_variables()
+        .filter(|var| source[*var].get_location() == location)
+        .map(|var| llvm_type(context, source[var].borrow_type()))
+        .collect();
+    unsafe { LLVMStructTypeInContext(context, types.as_mut_ptr(), types.len() as u32, 1) }
+}
+
+/// Creates a struct containing every variable in `scratch_vars` which is local to `body`, in the
+/// order they were declared. Unlike the other structs it is not packed, so every variable in it is
+/// aligned as long as the scratch memory itself is.
+fn scratch_struct_type(
+    context: LLVMContextRef,
+    source: &i::Program,
+    body: i::CodeBody,
+    scratch_vars: &HashSet<i::VariableId>,
+) -> LLVMTypeRef {
+    let location = body.get_local_location();
+    let mut types: Vec<_> = source
+        .iterate_all_variables()
+        .filter(|var| source[*var].get_location() == location && scratch_vars.contains(var))
+        .map(|var| llvm_type(context, source[var].borrow_type()))
+        .collect();
+    unsafe { LLVMStructTypeInContext(context, types.as_mut_ptr(), types.len() as u32, 0) }
+}
+
+/// Declares a function matching the calling convention described by `vague::ExternFunction`.
+fn declare_extern_function(
+    context: LLVMContextRef,
+    module: LLVMModuleRef,
+    function: &i::ExternFunction,
+) -> LLVMValueRef {
+    let mut arg_types = Vec::new();
+    for typ in function.borrow_inputs() {
+        let ltype = llvm_type(context, typ);
+        arg_types.push(match typ {
+            i::DataType::Array(..) => unsafe { LLVMPointerType(ltype, 0) },
+            _ => ltype,
+        });
+    }
+    for typ in function.borrow_outputs() {
+        arg_types.push(unsafe { LLVMPointerType(llvm_type(context, typ), 0) });
+    }
+    unsafe {
+        let function_type = LLVMFunctionType(
+            LLVMVoidTypeInContext(context),
+            arg_types.as_mut_ptr(),
+            arg_types.len() as u32,
+            0,
+        );
+        let name = format!("extern.{}\0", function.borrow_name());
+        let declaration = LLVMAddFunction(module, name.as_ptr() as *const _, function_type);
+        // Hosts expect bools to be passed as a full byte which is either 0 or 1.
+        let zeroext = b"zeroext";
+        let zeroext = LLVMGetEnumAttributeKindForName(zeroext.as_ptr() as *const _, zeroext.len());
+        for (index, typ) in function.borrow_inputs().iter().enumerate() {
+            if typ == &i::DataType::B1 {
+                let attribute = LLVMCreateEnumAttribute(context, zeroext, 0);
+                LLVMAddAttributeAtIndex(declaration, index as u32 + 1, attribute);
+            }
+        }
+        declaration
+    }
+}
+
 fn llvm_type(context: LLVMContextRef, trivial_type: &i::DataType) -> LLVMTypeRef {
     unsafe {
         match trivial_type {
@@ -863,7 +1488,32 @@ fn llvm_type(context: LLVMContextRef, trivial_type: &i::DataType) -> LLVMTypeRef
     }
 }
 
-pub fn ingest(source: &i::Program) -> o::Program {
+/// Creates a struct where each element is an array holding `count` values of the corresponding
+/// element of `types`.
+fn instance_struct_type(
+    context: LLVMContextRef,
+    types: &[LLVMTypeRef],
+    count: usize,
+    packed: bool,
+) -> LLVMTypeRef {
+    let mut types: Vec<_> = types
+        .iter()
+        .map(|typ| unsafe { LLVMArrayType(*typ, count as u32) })
+        .collect();
+    let packed = if packed { 1 } else { 0 };
+    unsafe { LLVMStructTypeInContext(context, types.as_mut_ptr(), types.len() as u32, packed) }
+}
+
+/// Converts a trivial program to LLVM IR and compiles it. If `scratch_threshold` is given, local
+/// variables of the main body and entry points which take up more than that many bytes are stored
+/// in the scratch memory passed to the program instead of on the stack. If `instance_count` is
+/// given, the program can also run the main body for that many instances at once, see
+/// `Program::execute_instances`.
+pub fn ingest(
+    source: &i::Program,
+    scratch_threshold: Option<usize>,
+    instance_count: Option<usize>,
+) -> o::Program {
     unsafe {
         let context = LLVMContextCreate();
         let module = LLVMModuleCreateWithNameInContext(b"nsprog\0".as_ptr() as *const _, context);
@@ -872,12 +1522,30 @@ pub fn ingest(source: &i::Program) -> o::Program {
         let mut input_types = Vec::new();
         let mut output_types = Vec::new();
         let mut static_types = Vec::new();
+        let mut static_vars = Vec::new();
+        let mut static_keys = Vec::new();
+        let mut specializable_inputs = Vec::new();
         for var in source.iterate_all_variables() {
             let ltype = llvm_type(context, source[var].borrow_type());
             match source[var].get_location() {
-                i::StorageLocation::Input => input_types.push(ltype),
+                i::StorageLocation::Input => {
+                    if let Some(name) = source.borrow_specializable_input_name(var) {
+                        let typ = source[var].borrow_type().clone();
+                        specializable_inputs.push((input_types.len(), name.clone(), typ));
+                    }
+                    input_types.push(ltype);
+                }
                 i::StorageLocation::Output => output_types.push(ltype),
-                i::StorageLocation::Static => static_types.push(ltype),
+                i::StorageLocation::Static => {
+                    let typ = source[var].borrow_type();
+                    if let Some(name) = source.borrow_static_var_name(var) {
+                        static_vars.push((static_types.len(), name.clone(), typ.clone()));
+                    }
+                    if let Some(key) = source.borrow_static_var_key(var) {
+                        static_keys.push((static_types.len(), key.clone(), typ.clone()));
+                    }
+                    static_types.push(ltype);
+                }
                 _ => (),
             }
         }
@@ -905,23 +1573,99 @@ pub fn ingest(source: &i::Program) -> o::Program {
             0,
         );
         let static_pointer_type = LLVMPointerType(static_data_type, 0);
+        let mut entry_data_types = Vec::new();
+        let mut entry_pointer_types = Vec::new();
+        for index in 0..source.borrow_entry_points().len() {
+            let input_type =
+                packed_struct_type(context, source, i::StorageLocation::EntryInput(index));
+            let output_type =
+                packed_struct_type(context, source, i::StorageLocation::EntryOutput(index));
+            entry_data_types.push((input_type, output_type));
+            entry_pointer_types.push((
+                LLVMPointerType(input_type, 0),
+                LLVMPointerType(output_type, 0),
+            ));
+        }
+
+        let scratch_vars: HashSet<_> = match scratch_threshold {
+            Some(threshold) => source
+                .iterate_all_variables()
+                .filter(|var| match source[*var].get_location() {
+                    i::StorageLocation::MainBody | i::StorageLocation::EntryBody(..) => {
+                        source[*var].borrow_type().byte_size() > threshold
+                    }
+                    _ => false,
+                })
+                .collect(),
+            None => HashSet::new(),
+        };
+        let mut scratch_types = vec![scratch_struct_type(
+            context,
+            source,
+            i::CodeBody::Main,
+            &scratch_vars,
+        )];
+        for index in 0..source.borrow_entry_points().len() {
+            let body = i::CodeBody::EntryPoint(index);
+            scratch_types.push(scratch_struct_type(context, source, body, &scratch_vars));
+        }
+
+        // Inputs, outputs, and static data of every instance are stored structure-of-arrays style
+        // so that consecutive instances of the same variable are next to each other in memory.
+        let instance_data_types = instance_count.map(|count| {
+            assert!(
+                count > 0,
+                "Programs must be compiled for at least one instance."
+            );
+            (
+                count,
+                instance_struct_type(context, &input_types, count, true),
+                instance_struct_type(context, &static_types, count, false),
+                instance_struct_type(context, &output_types, count, true),
+            )
+        });
+        let instance_types =
+            instance_data_types.map(|(count, input, static_, output)| InstanceTypes {
+                count,
+                input_pointer_type: LLVMPointerType(input, 0),
+                static_pointer_type: LLVMPointerType(static_, 0),
+                output_pointer_type: LLVMPointerType(output, 0),
+                scratch_type: LLVMArrayType(scratch_types[0], count as u32),
+            });
+        // The program needs enough scratch memory for whichever function needs the most.
+        let mut program_scratch_types = scratch_types.clone();
+        program_scratch_types.extend(instance_types.as_ref().map(|types| types.scratch_type));
 
         let intrinsics = Intrinsics::new(module, context);
+        let mut extern_functions = Vec::new();
+        let mut extern_mappings = Vec::new();
+        for function in source.borrow_extern_functions() {
+            let declaration = declare_extern_function(context, module, function);
+            extern_functions.push(declaration);
+            extern_mappings.push((declaration, function.get_address()));
+        }
 
         let mut converter = Converter {
             source,
             input_pointer_type,
             output_pointer_type,
             static_pointer_type,
+            entry_pointer_types,
+            scratch_vars,
+            scratch_types,
+            instance_types,
 
             context,
             module,
             builder,
             intrinsics,
+            extern_functions,
 
             value_pointers: HashMap::new(),
-            label_blocks: Vec::with_capacity(source.iterate_all_labels().count()),
+            label_blocks: HashMap::new(),
             current_block_terminated: false,
+            lane: None,
+            lane_error_code: None,
         };
 
         converter.convert();
@@ -929,13 +1673,31 @@ pub fn ingest(source: &i::Program) -> o::Program {
         let Converter {
             context, module, ..
         } = converter;
-        o::Program::new(
+        let mut program = o::Program::new(
             context,
             module,
-            input_data_type,
-            output_data_type,
-            static_data_type,
-            source.borrow_error_descriptions().clone(),
-        )
+            (input_data_type, output_data_type, static_data_type),
+            (static_vars.clone(), static_keys.clone()),
+            source.borrow_errors().clone(),
+            extern_mappings,
+            program_scratch_types,
+        );
+        if let Some((count, input_type, static_type, output_type)) = instance_data_types {
+            program.add_instances(
+                count,
+                (input_data_type, input_type),
+                (output_data_type, output_type),
+                static_type,
+                (static_vars, static_keys),
+            );
+        }
+        for (index, entry_point) in source.borrow_entry_points().iter().enumerate() {
+            let (input_type, output_type) = entry_data_types[index];
+            program.add_entry_point(entry_point.borrow_name().clone(), input_type, output_type);
+        }
+        for (index, name, typ) in specializable_inputs {
+            program.add_specializable_input(name, input_data_type, index, typ);
+        }
+        program
     }
 }
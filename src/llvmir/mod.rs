@@ -2,3 +2,56 @@ mod ingest;
 pub mod structure;
 
 pub use ingest::ingest;
+
+use crate::high_level::backend::Backend;
+use crate::trivial::passes::Pass;
+
+/// Compiles programs to native code with LLVM's JIT. This is the backend used by
+/// `Compiler::compile`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LlvmJitBackend {
+    scratch_threshold: Option<usize>,
+    instance_count: Option<usize>,
+}
+
+impl LlvmJitBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Local variables of the main body and entry points which take up more than `threshold`
+    /// bytes are stored in the scratch memory passed to `execute_*` instead of on the stack, so
+    /// that large arrays do not overflow a small stack. `None`, the default, keeps everything on
+    /// the stack.
+    pub fn set_scratch_threshold(&mut self, threshold: Option<usize>) {
+        self.scratch_threshold = threshold;
+    }
+
+    /// Also compiles a version of the main body which runs `count` instances of the program at
+    /// once, each with its own inputs, outputs, static data, and scratch data. See
+    /// `Program::execute_instances`. `None`, the default, only compiles the single-instance
+    /// version.
+    pub fn set_instance_count(&mut self, count: Option<usize>) {
+        self.instance_count = count;
+    }
+}
+
+impl Backend for LlvmJitBackend {
+    type Output = structure::Program;
+
+    fn borrow_name(&self) -> &str {
+        "llvmir"
+    }
+
+    fn default_passes(&self) -> &'static [Pass] {
+        // LLVM does all of these optimizations on its own.
+        &[]
+    }
+
+    fn compile(
+        &mut self,
+        source: &crate::trivial::structure::Program,
+    ) -> Result<Self::Output, String> {
+        Ok(ingest(source, self.scratch_threshold, self.instance_count))
+    }
+}
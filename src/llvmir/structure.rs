@@ -1,32 +1,135 @@
+use crate::high_level::backend::CompiledProgram;
+use crate::high_level::problem::RuntimeError;
+pub use crate::high_level::scratch::Scratch;
+pub use crate::high_level::static_data::{
+    StaticCopyPlan, StaticData, StaticDataOwner, StaticLayout, StaticMigration, StaticValue,
+    StaticVariable,
+};
+use crate::trivial::structure::DataType;
 use llvm_sys::core::*;
 use llvm_sys::execution_engine::*;
 use llvm_sys::prelude::*;
 use llvm_sys::target::*;
+use std::collections::HashMap;
+use std::ffi::c_void;
 use std::fmt::{self, Debug, Formatter};
 use std::mem::{self, MaybeUninit};
 use std::ptr;
+use std::sync::Arc;
 
-pub struct StaticData {
-    data: Vec<u8>,
+/// Describes where an input marked as specializable lives inside of the program's input data.
+#[derive(Clone, Debug)]
+pub struct SpecializableInput {
+    name: String,
+    offset: usize,
+    data_type: DataType,
+}
+
+impl SpecializableInput {
+    pub fn borrow_name(&self) -> &String {
+        &self.name
+    }
+
+    /// The offset in bytes from the start of the input data.
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// This is always a single value, never an array.
+    pub fn borrow_data_type(&self) -> &DataType {
+        &self.data_type
+    }
+}
+
+/// Takes the input, static, output, and scratch data and returns an error code.
+type BodyFunction = extern "C" fn(*mut u8, *mut u8, *mut u8, *mut u8) -> u32;
+
+struct EntryFunction {
+    function: BodyFunction,
+    in_size: usize,
+    out_size: usize,
+}
+
+/// The index of each element in a static struct which holds a particular kind of variable, along
+/// with the name or key of the variable and its type.
+type StaticElements = Vec<(usize, String, DataType)>;
+
+/// Takes the input, static, output, and scratch data of every instance along with an array which
+/// the error code of each instance is written to.
+type InstancesFunction = extern "C" fn(*mut u8, *mut u8, *mut u8, *mut u8, *mut u32);
+
+/// Where an element of the input or output struct of a single instance is stored in the
+/// multi-instance version of the struct.
+struct InstanceField {
+    offset: usize,
+    instance_offset: usize,
+    size: usize,
+}
+
+/// The functions and layouts used to run multiple instances of a program at once.
+struct Instances {
+    count: usize,
+    function: InstancesFunction,
+    static_init: extern "C" fn(*mut u8) -> u32,
+    in_size: usize,
+    out_size: usize,
+    in_fields: Vec<InstanceField>,
+    out_fields: Vec<InstanceField>,
+    static_layout: Arc<StaticLayout>,
+}
+
+/// The static data of every instance run by `Program::execute_instances`. Each static variable is
+/// stored as an array with one element per instance, so a variable of type `FLOAT` can be read
+/// with `borrow_static_data().get_array::<f32>(name)`.
+pub struct InstanceStates {
+    static_data: StaticData,
+    error_codes: Vec<u32>,
+    // Used to rearrange the inputs and outputs passed to execute_instances without allocating.
+    input_data: Vec<u8>,
+    output_data: Vec<u8>,
+}
+
+impl InstanceStates {
+    pub fn get_instance_count(&self) -> usize {
+        self.error_codes.len()
+    }
+
+    pub fn borrow_static_data(&self) -> &StaticData {
+        &self.static_data
+    }
+
+    pub fn borrow_static_data_mut(&mut self) -> &mut StaticData {
+        &mut self.static_data
+    }
 }
 
 pub struct Program {
     execution_engine: LLVMExecutionEngineRef,
-    function: extern "C" fn(*mut u8, *mut u8, *mut u8) -> u32,
+    function: BodyFunction,
     static_init: extern "C" fn(*mut u8) -> u32,
     context: LLVMContextRef,
     module: LLVMModuleRef,
     in_size: usize,
     out_size: usize,
-    static_size: usize,
-    error_descriptions: Vec<String>,
+    scratch_size: usize,
+    static_layout: Arc<StaticLayout>,
+    errors: Vec<RuntimeError>,
+    entry_points: HashMap<String, EntryFunction>,
+    specializable_inputs: Vec<SpecializableInput>,
+    instances: Option<Instances>,
 }
 
 impl Debug for Program {
     fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
         writeln!(formatter, "error codes:")?;
-        for (code, description) in self.error_descriptions.iter().enumerate() {
-            writeln!(formatter, "  {}: {}", code, description)?;
+        for error in self.errors.iter() {
+            writeln!(
+                formatter,
+                "  {}: {:?} {}",
+                error.get_code(),
+                error.get_kind(),
+                error.borrow_message()
+            )?;
         }
         unsafe {
             let content = LLVMPrintModuleToString(self.module);
@@ -41,6 +144,51 @@ impl Debug for Program {
     }
 }
 
+impl StaticDataOwner for Program {
+    fn borrow_static_layout(&self) -> &Arc<StaticLayout> {
+        &self.static_layout
+    }
+}
+
+impl CompiledProgram for Program {
+    fn borrow_errors(&self) -> &Vec<RuntimeError> {
+        Program::borrow_errors(self)
+    }
+
+    fn has_entry_point(&self, name: &str) -> bool {
+        Program::has_entry_point(self, name)
+    }
+
+    fn scratch_size(&self) -> usize {
+        Program::scratch_size(self)
+    }
+
+    unsafe fn reinit_static_data(&self, data: &mut StaticData) -> Result<(), RuntimeError> {
+        Program::reinit_static_data(self, data)
+    }
+
+    unsafe fn execute_raw(
+        &self,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+        static_data: &mut StaticData,
+        scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError> {
+        Program::execute_raw(self, input_data, output_data, static_data, scratch)
+    }
+
+    unsafe fn execute_entry_raw(
+        &self,
+        name: &str,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+        static_data: &mut StaticData,
+        scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError> {
+        Program::execute_entry_raw(self, name, input_data, output_data, static_data, scratch)
+    }
+}
+
 impl Drop for Program {
     fn drop(&mut self) {
         unsafe {
@@ -53,13 +201,21 @@ impl Drop for Program {
 
 impl Program {
     /// After this, the prrogram will handle dropping the module and context automatically.
+    /// The data types are the structs used for the input, output and static data.
+    /// `static_vars` lists the named variables in the static struct along with the index of the
+    /// struct element they are stored in, and `static_keys` lists the variables which have keys
+    /// the same way. `extern_functions` pairs the declaration of each extern
+    /// function in the module with the address it should be linked to. `scratch_types` are the
+    /// types of the scratch data used by each function, the largest of which decides how much
+    /// scratch memory the program needs.
     pub fn new(
         context: LLVMContextRef,
         module: LLVMModuleRef,
-        in_type: LLVMTypeRef,
-        out_type: LLVMTypeRef,
-        static_type: LLVMTypeRef,
-        error_descriptions: Vec<String>,
+        (in_type, out_type, static_type): (LLVMTypeRef, LLVMTypeRef, LLVMTypeRef),
+        (static_vars, static_keys): (StaticElements, StaticElements),
+        errors: Vec<RuntimeError>,
+        extern_functions: Vec<(LLVMValueRef, *const c_void)>,
+        scratch_types: Vec<LLVMTypeRef>,
     ) -> Self {
         let execution_engine = unsafe {
             let mut ee_ref = MaybeUninit::uninit();
@@ -76,7 +232,11 @@ impl Program {
             // This takes ownership of the module so disposing the EE disposes the module.
             LLVMCreateExecutionEngineForModule(ee_ref.as_mut_ptr(), module, &mut creation_error);
 
-            ee_ref.assume_init()
+            let ee = ee_ref.assume_init();
+            for (declaration, address) in extern_functions {
+                LLVMAddGlobalMapping(ee, declaration, address as *mut _);
+            }
+            ee
         };
         let function = unsafe {
             let func_addr =
@@ -95,6 +255,29 @@ impl Program {
             let static_size = LLVMSizeOfTypeInBits(target_data, static_type) / 8;
             (in_size as usize, out_size as usize, static_size as usize)
         };
+        let scratch_size = unsafe {
+            let target_data = LLVMGetExecutionEngineTargetData(execution_engine);
+            scratch_types
+                .into_iter()
+                .map(|typ| {
+                    let alignment = LLVMABIAlignmentOfType(target_data, typ) as usize;
+                    assert!(
+                        alignment <= Scratch::ALIGNMENT,
+                        "Scratch data needs {} byte alignment, but scratch memory only has {}.",
+                        alignment,
+                        Scratch::ALIGNMENT
+                    );
+                    LLVMABISizeOfType(target_data, typ) as usize
+                })
+                .max()
+                .unwrap_or(0)
+        };
+        let static_layout = Arc::new(create_static_layout(
+            execution_engine,
+            static_type,
+            static_size,
+            (static_vars, static_keys),
+        ));
         Self {
             execution_engine,
             function,
@@ -103,12 +286,146 @@ impl Program {
             module,
             in_size,
             out_size,
-            static_size,
-            error_descriptions,
+            scratch_size,
+            static_layout,
+            errors,
+            entry_points: HashMap::new(),
+            specializable_inputs: Vec::new(),
+            instances: None,
         }
     }
 
-    fn assert_size(&self, in_size: usize, out_size: usize, static_size: usize) {
+    /// Makes the function `entry.<name>` in the module callable through `execute_entry`. The
+    /// function must have the same signature as the main function.
+    pub(crate) fn add_entry_point(
+        &mut self,
+        name: String,
+        in_type: LLVMTypeRef,
+        out_type: LLVMTypeRef,
+    ) {
+        let function = unsafe {
+            let fn_name = format!("entry.{}\0", name);
+            let func_addr =
+                LLVMGetFunctionAddress(self.execution_engine, fn_name.as_ptr() as *const _);
+            mem::transmute::<u64, BodyFunction>(func_addr)
+        };
+        let (in_size, out_size) = unsafe {
+            let target_data = LLVMGetExecutionEngineTargetData(self.execution_engine);
+            let in_size = LLVMSizeOfTypeInBits(target_data, in_type) / 8;
+            let out_size = LLVMSizeOfTypeInBits(target_data, out_type) / 8;
+            (in_size as usize, out_size as usize)
+        };
+        self.entry_points.insert(
+            name,
+            EntryFunction {
+                function,
+                in_size,
+                out_size,
+            },
+        );
+    }
+
+    /// Makes the functions `main.instances` and `static_init.instances` in the module callable
+    /// through `execute_instances`. `in_types` and `out_types` pair the input and output struct of
+    /// a single instance with the struct holding the inputs and outputs of every instance.
+    /// `static_type` is the struct holding the static data of every instance, and `static_vars`
+    /// lists its named and keyed elements the same way as in `new`.
+    pub(crate) fn add_instances(
+        &mut self,
+        count: usize,
+        in_types: (LLVMTypeRef, LLVMTypeRef),
+        out_types: (LLVMTypeRef, LLVMTypeRef),
+        static_type: LLVMTypeRef,
+        (static_vars, static_keys): (StaticElements, StaticElements),
+    ) {
+        let get_function = |name_nullterm: &[u8]| unsafe {
+            LLVMGetFunctionAddress(self.execution_engine, name_nullterm.as_ptr() as *const _)
+        };
+        let function =
+            unsafe { mem::transmute::<u64, InstancesFunction>(get_function(b"main.instances\0")) };
+        let static_init = unsafe {
+            let address = get_function(b"static_init.instances\0");
+            mem::transmute::<u64, extern "C" fn(*mut u8) -> u32>(address)
+        };
+        unsafe {
+            let target_data = LLVMGetExecutionEngineTargetData(self.execution_engine);
+            let fields = |(single_type, instance_type): (LLVMTypeRef, LLVMTypeRef)| {
+                (0..LLVMCountStructElementTypes(single_type))
+                    .map(|index| InstanceField {
+                        offset: LLVMOffsetOfElement(target_data, single_type, index) as usize,
+                        instance_offset: LLVMOffsetOfElement(target_data, instance_type, index)
+                            as usize,
+                        size: LLVMABISizeOfType(
+                            target_data,
+                            LLVMStructGetTypeAtIndex(single_type, index),
+                        ) as usize,
+                    })
+                    .collect()
+            };
+            let size = |typ: LLVMTypeRef| (LLVMSizeOfTypeInBits(target_data, typ) / 8) as usize;
+            let per_instance = |elements: StaticElements| {
+                elements
+                    .into_iter()
+                    .map(|(index, name, data_type)| {
+                        (index, name, DataType::Array(count, Box::new(data_type)))
+                    })
+                    .collect()
+            };
+            let static_layout = create_static_layout(
+                self.execution_engine,
+                static_type,
+                size(static_type),
+                (per_instance(static_vars), per_instance(static_keys)),
+            );
+            self.instances = Some(Instances {
+                count,
+                function,
+                static_init,
+                in_size: size(in_types.1),
+                out_size: size(out_types.1),
+                in_fields: fields(in_types),
+                out_fields: fields(out_types),
+                static_layout: Arc::new(static_layout),
+            });
+        }
+    }
+
+    /// Records that element `index` of the input struct `in_type` is a specializable input.
+    pub(crate) fn add_specializable_input(
+        &mut self,
+        name: String,
+        in_type: LLVMTypeRef,
+        index: usize,
+        data_type: DataType,
+    ) {
+        let offset = unsafe {
+            let target_data = LLVMGetExecutionEngineTargetData(self.execution_engine);
+            LLVMOffsetOfElement(target_data, in_type, index as u32) as usize
+        };
+        self.specializable_inputs.push(SpecializableInput {
+            name,
+            offset,
+            data_type,
+        });
+    }
+
+    fn assert_static_layout(&self, data: &StaticData) {
+        self.static_layout.assert_matches(data);
+    }
+
+    fn assert_size(
+        &self,
+        in_size: usize,
+        out_size: usize,
+        static_data: &StaticData,
+        scratch: &Scratch,
+    ) {
+        self.assert_io_size(in_size, out_size);
+        self.assert_static_layout(static_data);
+        scratch.assert_fits(self.scratch_size);
+    }
+
+    pub(crate) fn assert_io_size(&self, in_size: usize, out_size: usize) {
         assert!(
             self.in_size == in_size,
             "Expected {}, got {}.",
@@ -121,41 +438,97 @@ impl Program {
             self.out_size,
             out_size
         );
-        assert!(
-            self.static_size == static_size,
-            "Expected {}, got {}.",
-            self.static_size,
-            static_size
-        );
     }
 
-    fn parse_error_code(&self, error_code: u32) -> Result<(), &str> {
+    /// Returns every variable exported from a static block, indexed by the name it was exported
+    /// as.
+    pub fn borrow_static_variables(&self) -> &HashMap<String, StaticVariable> {
+        self.static_layout.borrow_variables()
+    }
+
+    /// Returns every input marked as specializable, in the order they were declared.
+    pub fn borrow_specializable_inputs(&self) -> &Vec<SpecializableInput> {
+        &self.specializable_inputs
+    }
+
+    pub fn has_entry_point(&self, name: &str) -> bool {
+        self.entry_points.contains_key(name)
+    }
+
+    fn borrow_entry_point(&self, name: &str) -> &EntryFunction {
+        match self.entry_points.get(name) {
+            Some(entry_point) => entry_point,
+            None => panic!("The program has no entry point named {}.", name),
+        }
+    }
+
+    pub fn borrow_errors(&self) -> &Vec<RuntimeError> {
+        &self.errors
+    }
+
+    /// How many bytes of scratch memory must be passed to `execute_*`. This is zero unless the
+    /// program was compiled with a scratch threshold, see `LlvmJitBackend::set_scratch_threshold`.
+    pub fn scratch_size(&self) -> usize {
+        self.scratch_size
+    }
+
+    /// Creates scratch memory which is big enough to run this program with.
+    pub fn create_scratch(&self) -> Scratch {
+        Scratch::new(self.scratch_size)
+    }
+
+    /// How many instances `execute_instances` runs at once, or `None` if the program was not
+    /// compiled for multi-instance execution, see `LlvmJitBackend::set_instance_count`.
+    pub fn get_instance_count(&self) -> Option<usize> {
+        self.instances.as_ref().map(|instances| instances.count)
+    }
+
+    fn borrow_instances(&self) -> &Instances {
+        match &self.instances {
+            Some(instances) => instances,
+            None => panic!("The program was not compiled for multiple instances."),
+        }
+    }
+
+    fn parse_error_code(&self, error_code: u32) -> Result<(), RuntimeError> {
         if error_code == 0 {
-            Ok(())
-        } else if (error_code as usize) < self.error_descriptions.len() && error_code > 0 {
-            Err(&self.error_descriptions[error_code as usize])
-        } else {
-            Err("Invalid non-success error code")
+            return Ok(());
+        }
+        // Error codes start at 1, 0 is reserved for success.
+        match self.errors.get(error_code as usize - 1) {
+            Some(error) => Err(error.clone()),
+            None => Err(RuntimeError::unknown(error_code)),
         }
     }
 
-    pub unsafe fn create_static_data(&self) -> Result<StaticData, &str> {
-        let mut data = StaticData {
-            data: vec![0; self.static_size],
-        };
-        let error_code = (self.static_init)(data.data.as_mut_ptr());
+    pub unsafe fn create_static_data(&self) -> Result<StaticData, RuntimeError> {
+        let mut data = StaticData::new(&self.static_layout);
+        let error_code = (self.static_init)(data.as_mut_ptr());
         self.parse_error_code(error_code)?;
         Ok(data)
     }
 
-    pub unsafe fn reinit_static_data(&self, data: &mut StaticData) -> Result<(), &str> {
-        assert!(
-            self.static_size == data.data.len(),
-            "Expected {}, got {}.",
-            self.static_size,
-            data.data.len()
-        );
-        let error_code = (self.static_init)(data.data.as_mut_ptr());
+    /// Creates static data for this program, copying the values of any static variables in
+    /// `old_data` that have the same name and type as a variable in this program. Everything else
+    /// is initialized by this program's static init code. `old_data` must have been created for
+    /// `old_program`.
+    ///
+    /// # Safety
+    /// This runs the new program's static init code, the same as `create_static_data`.
+    pub unsafe fn migrate_static_data(
+        &self,
+        old_program: &Program,
+        old_data: &StaticData,
+    ) -> Result<(StaticData, StaticMigration), RuntimeError> {
+        old_program.assert_static_layout(old_data);
+        let mut data = self.create_static_data()?;
+        let migration = data.copy_matching_variables(old_data);
+        Ok((data, migration))
+    }
+
+    pub unsafe fn reinit_static_data(&self, data: &mut StaticData) -> Result<(), RuntimeError> {
+        self.assert_static_layout(data);
+        let error_code = (self.static_init)(data.as_mut_ptr());
         self.parse_error_code(error_code)
     }
 
@@ -164,16 +537,15 @@ impl Program {
         input_data: &mut T,
         output_data: &mut U,
         static_data: &mut StaticData,
-    ) -> Result<(), &str> {
-        self.assert_size(
-            mem::size_of::<T>(),
-            mem::size_of::<U>(),
-            static_data.data.len(),
-        );
+        scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError> {
+        let (in_size, out_size) = (mem::size_of::<T>(), mem::size_of::<U>());
+        self.assert_size(in_size, out_size, static_data, scratch);
         let error_code = (self.function)(
             input_data as *mut T as *mut u8,
-            static_data.data.as_mut_ptr(),
+            static_data.as_mut_ptr(),
             output_data as *mut U as *mut u8,
+            scratch.as_mut_ptr(),
         );
         self.parse_error_code(error_code)
     }
@@ -183,13 +555,316 @@ impl Program {
         input_data: &mut [u8],
         output_data: &mut [u8],
         static_data: &mut StaticData,
-    ) -> Result<(), &str> {
-        self.assert_size(input_data.len(), output_data.len(), static_data.data.len());
+        scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError> {
+        self.assert_size(input_data.len(), output_data.len(), static_data, scratch);
         let error_code = (self.function)(
             input_data.as_mut_ptr(),
-            static_data.data.as_mut_ptr(),
+            static_data.as_mut_ptr(),
+            output_data.as_mut_ptr(),
+            scratch.as_mut_ptr(),
+        );
+        self.parse_error_code(error_code)
+    }
+
+    /// Runs the entry point with the given name. Panics if there is no such entry point.
+    ///
+    /// # Safety
+    /// `T` and `U` must have the same layout as the entry point's input and output structs. Only
+    /// their sizes are checked.
+    pub unsafe fn execute_entry<T: Sized, U: Sized>(
+        &self,
+        name: &str,
+        input_data: &mut T,
+        output_data: &mut U,
+        static_data: &mut StaticData,
+        scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError> {
+        let entry_point = self.borrow_entry_point(name);
+        assert_entry_size(entry_point, mem::size_of::<T>(), mem::size_of::<U>());
+        self.assert_static_layout(static_data);
+        scratch.assert_fits(self.scratch_size);
+        let error_code = (entry_point.function)(
+            input_data as *mut T as *mut u8,
+            static_data.as_mut_ptr(),
+            output_data as *mut U as *mut u8,
+            scratch.as_mut_ptr(),
+        );
+        self.parse_error_code(error_code)
+    }
+
+    /// Like `execute_entry`, but with the input and output structs given as bytes.
+    ///
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    pub unsafe fn execute_entry_raw(
+        &self,
+        name: &str,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+        static_data: &mut StaticData,
+        scratch: &mut Scratch,
+    ) -> Result<(), RuntimeError> {
+        let entry_point = self.borrow_entry_point(name);
+        assert_entry_size(entry_point, input_data.len(), output_data.len());
+        self.assert_static_layout(static_data);
+        scratch.assert_fits(self.scratch_size);
+        let error_code = (entry_point.function)(
+            input_data.as_mut_ptr(),
+            static_data.as_mut_ptr(),
             output_data.as_mut_ptr(),
+            scratch.as_mut_ptr(),
         );
         self.parse_error_code(error_code)
     }
 }
+
+impl Program {
+    /// Creates static data for every instance, running the static init code once per instance.
+    /// Panics if the program was not compiled for multiple instances.
+    ///
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    pub unsafe fn create_instance_states(&self) -> Result<InstanceStates, RuntimeError> {
+        let instances = self.borrow_instances();
+        let mut states = InstanceStates {
+            static_data: StaticData::new(&instances.static_layout),
+            error_codes: vec![0; instances.count],
+            input_data: vec![0; instances.in_size],
+            output_data: vec![0; instances.out_size],
+        };
+        self.reinit_instance_states(&mut states)?;
+        Ok(states)
+    }
+
+    /// Runs the static init code again for every instance in `states`, which must have been
+    /// created for this program.
+    ///
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    pub unsafe fn reinit_instance_states(
+        &self,
+        states: &mut InstanceStates,
+    ) -> Result<(), RuntimeError> {
+        let instances = self.borrow_instances();
+        instances.static_layout.assert_matches(&states.static_data);
+        let error_code = (instances.static_init)(states.static_data.as_mut_ptr());
+        self.parse_error_code(error_code)
+    }
+
+    /// Runs the main body once for each instance, where `input_data[n]` and `output_data[n]` are
+    /// the inputs and outputs of instance `n`. Every instance is run even if some of them fail,
+    /// in which case the index and error of the first instance which failed is returned. An
+    /// instance which fails an assert stops changing its outputs and static data and does not
+    /// call any more extern functions, the same as if it had stopped running.
+    /// Panics if the program was not compiled for multiple instances or if the number of inputs
+    /// or outputs does not match the number of instances.
+    ///
+    /// # Safety
+    /// `T` and `U` must have the same layout as the program's input and output structs, the same
+    /// as with `execute_data`. Only their sizes are checked.
+    pub unsafe fn execute_instances<T: Sized, U: Sized>(
+        &self,
+        input_data: &mut [T],
+        output_data: &mut [U],
+        states: &mut InstanceStates,
+        scratch: &mut Scratch,
+    ) -> Result<(), (usize, RuntimeError)> {
+        let instances = self.borrow_instances();
+        let (in_size, out_size) = (mem::size_of::<T>(), mem::size_of::<U>());
+        assert!(
+            self.in_size == in_size,
+            "Expected {}, got {}.",
+            self.in_size,
+            in_size
+        );
+        assert!(
+            self.out_size == out_size,
+            "Expected {}, got {}.",
+            self.out_size,
+            out_size
+        );
+        scratch.assert_fits(self.scratch_size);
+        self.assert_instance_count(input_data.len(), output_data.len());
+        // The program does not write to outputs which are never assigned, so they are passed in
+        // as well to keep their old values.
+        let in_bytes = as_instance_bytes(input_data);
+        gather_instances(
+            &instances.in_fields,
+            in_size,
+            in_bytes,
+            &mut states.input_data,
+        );
+        let out_bytes = as_instance_bytes(output_data);
+        gather_instances(
+            &instances.out_fields,
+            out_size,
+            out_bytes,
+            &mut states.output_data,
+        );
+        let result = self.run_instances(
+            states.input_data.as_mut_ptr(),
+            states.output_data.as_mut_ptr(),
+            &mut states.static_data,
+            &mut states.error_codes,
+            scratch,
+        );
+        scatter_instances(
+            &instances.out_fields,
+            out_size,
+            &states.output_data,
+            out_bytes,
+        );
+        result
+    }
+
+    /// Like `execute_instances`, but with the inputs and outputs of every instance given as
+    /// bytes in the layout used by the compiled code. This is the same as the program's input or
+    /// output struct, except that each element is replaced by an array holding its value for
+    /// every instance.
+    ///
+    /// # Safety
+    /// This runs compiled code, which trusts that the program was compiled correctly.
+    pub unsafe fn execute_instances_raw(
+        &self,
+        input_data: &mut [u8],
+        output_data: &mut [u8],
+        states: &mut InstanceStates,
+        scratch: &mut Scratch,
+    ) -> Result<(), (usize, RuntimeError)> {
+        let instances = self.borrow_instances();
+        assert!(
+            instances.in_size == input_data.len(),
+            "Expected {}, got {}.",
+            instances.in_size,
+            input_data.len()
+        );
+        assert!(
+            instances.out_size == output_data.len(),
+            "Expected {}, got {}.",
+            instances.out_size,
+            output_data.len()
+        );
+        scratch.assert_fits(self.scratch_size);
+        self.run_instances(
+            input_data.as_mut_ptr(),
+            output_data.as_mut_ptr(),
+            &mut states.static_data,
+            &mut states.error_codes,
+            scratch,
+        )
+    }
+
+    fn assert_instance_count(&self, num_inputs: usize, num_outputs: usize) {
+        let count = self.borrow_instances().count;
+        assert!(
+            count == num_inputs,
+            "Expected inputs for {} instances, got {}.",
+            count,
+            num_inputs
+        );
+        assert!(
+            count == num_outputs,
+            "Expected outputs for {} instances, got {}.",
+            count,
+            num_outputs
+        );
+    }
+
+    unsafe fn run_instances(
+        &self,
+        input_data: *mut u8,
+        output_data: *mut u8,
+        static_data: &mut StaticData,
+        error_codes: &mut [u32],
+        scratch: &mut Scratch,
+    ) -> Result<(), (usize, RuntimeError)> {
+        let instances = self.borrow_instances();
+        instances.static_layout.assert_matches(static_data);
+        (instances.function)(
+            input_data,
+            static_data.as_mut_ptr(),
+            output_data,
+            scratch.as_mut_ptr(),
+            error_codes.as_mut_ptr(),
+        );
+        for (index, error_code) in error_codes.iter().enumerate() {
+            self.parse_error_code(*error_code)
+                .map_err(|error| (index, error))?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the layout of `static_type`, where `static_vars` lists the named variables in it along
+/// with the index of the struct element they are stored in and `static_keys` does the same for
+/// the variables which have keys.
+fn create_static_layout(
+    execution_engine: LLVMExecutionEngineRef,
+    static_type: LLVMTypeRef,
+    static_size: usize,
+    (static_vars, static_keys): (StaticElements, StaticElements),
+) -> StaticLayout {
+    unsafe {
+        let target_data = LLVMGetExecutionEngineTargetData(execution_engine);
+        let description = LLVMPrintTypeToString(static_type);
+        let locate = |elements: StaticElements| {
+            let mut variables = HashMap::new();
+            for (index, name, data_type) in elements {
+                let offset = LLVMOffsetOfElement(target_data, static_type, index as u32);
+                variables.insert(name, StaticVariable::new(offset as usize, data_type));
+            }
+            variables
+        };
+        let layout = StaticLayout::new(
+            std::ffi::CStr::from_ptr(description).to_bytes(),
+            static_size,
+            locate(static_vars),
+            locate(static_keys),
+        );
+        LLVMDisposeMessage(description);
+        layout
+    }
+}
+
+unsafe fn as_instance_bytes<T: Sized>(data: &mut [T]) -> &mut [u8] {
+    std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, mem::size_of_val(data))
+}
+
+/// Copies each field of each instance in `data`, which holds consecutive structs of `size` bytes,
+/// to where it belongs in the multi-instance layout.
+fn gather_instances(fields: &[InstanceField], size: usize, data: &[u8], instance_data: &mut [u8]) {
+    for (index, item) in data.chunks(size).enumerate() {
+        for field in fields {
+            let start = field.instance_offset + index * field.size;
+            instance_data[start..start + field.size]
+                .copy_from_slice(&item[field.offset..field.offset + field.size]);
+        }
+    }
+}
+
+/// The opposite of `gather_instances`.
+fn scatter_instances(fields: &[InstanceField], size: usize, instance_data: &[u8], data: &mut [u8]) {
+    for (index, item) in data.chunks_mut(size).enumerate() {
+        for field in fields {
+            let start = field.instance_offset + index * field.size;
+            item[field.offset..field.offset + field.size]
+                .copy_from_slice(&instance_data[start..start + field.size]);
+        }
+    }
+}
+
+fn assert_entry_size(entry_point: &EntryFunction, in_size: usize, out_size: usize) {
+    assert!(
+        entry_point.in_size == in_size,
+        "Expected {}, got {}.",
+        entry_point.in_size,
+        in_size
+    );
+    assert!(
+        entry_point.out_size == out_size,
+        "Expected {}, got {}.",
+        entry_point.out_size,
+        out_size
+    );
+}
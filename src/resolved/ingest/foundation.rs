@@ -39,6 +39,21 @@ pub fn ingest(program: &mut i::Program) -> Result<o::Program, CompileProblem> {
             return Err(problems::compile_time_input(pos, &dtype));
         }
     }
+    for (name, input) in resolver.source.borrow_specializable_inputs().clone() {
+        let (id, dtype) = resolver
+            .get_var_info(input)
+            .expect("undefined input, should be caught in vague phase.")
+            .clone();
+        match dtype {
+            i::DataType::Bool | i::DataType::Int | i::DataType::Float => (),
+            _ => {
+                let pos = resolver.source[input].get_definition().clone();
+                return Err(problems::specializable_input_not_scalar(pos, &dtype));
+            }
+        }
+        // Already checked that all inputs have a run time type.
+        resolver.target.add_specializable_input(id.unwrap(), name);
+    }
     for (index, (id, dtype)) in outputs.into_iter().enumerate() {
         if let Option::Some(var_id) = id {
             resolver.target.add_output(var_id);
@@ -81,11 +96,37 @@ pub(super) struct ScopeResolver<'a> {
     // we don't know if the branch body will occur.
     dirty_values: HashSet<i::VariableId>,
     dirty_values_stack: Vec<HashSet<i::VariableId>>,
+    // Each macro call we are currently inside of, written the way it appears in the source and
+    // followed by how many identical calls came before it at the same level. Used to give static
+    // variables unique names.
+    macro_call_path: Vec<String>,
+    macro_call_positions: Vec<FilePosition>,
+    macro_call_counts: HashMap<String, usize>,
+    static_var_names: HashSet<String>,
+    static_var_keys: HashSet<String>,
+    // The interval of each every block along with the static counter used to decide when it runs
+    // and where the first block with that interval is.
+    pub(super) every_counters: Vec<(i64, o::VariableId, FilePosition)>,
+    // Each every block we are currently inside of along with the variables declared since we
+    // entered it. Those are the only non-static variables the block is allowed to assign to.
+    pub(super) every_blocks: Vec<(FilePosition, HashSet<i::VariableId>)>,
+    // True while resolving the body of an entry point other than the main body.
+    pub(super) in_entry_point: bool,
 }
 
 impl<'a> ScopeResolver<'a> {
     fn new(source: &'a mut i::Program) -> ScopeResolver<'a> {
-        let target = o::Program::new();
+        let mut target = o::Program::new();
+        for function in source.borrow_extern_functions() {
+            // Extern functions can only be created with run time types.
+            let resolve = |typ| Self::resolve_data_type(typ).unwrap();
+            target.add_extern_function(o::ExternFunction::new(
+                function.borrow_name().clone(),
+                function.borrow_inputs().iter().map(resolve).collect(),
+                function.borrow_outputs().iter().map(resolve).collect(),
+                function.get_address(),
+            ));
+        }
         let entry_point = target.get_entry_point();
         ScopeResolver {
             source,
@@ -96,6 +137,14 @@ impl<'a> ScopeResolver<'a> {
             temp_values: HashMap::new(),
             dirty_values: HashSet::new(),
             dirty_values_stack: Vec::new(),
+            macro_call_path: Vec::new(),
+            macro_call_positions: Vec::new(),
+            macro_call_counts: HashMap::new(),
+            static_var_names: HashSet::new(),
+            static_var_keys: HashSet::new(),
+            every_counters: Vec::new(),
+            every_blocks: Vec::new(),
+            in_entry_point: false,
         }
     }
 
@@ -112,6 +161,82 @@ impl<'a> ScopeResolver<'a> {
             .expect("Encountered extra unexpected stack pop");
     }
 
+    /// `text` is the macro call as written in the source, see `i::VPExpression::MacroCall`.
+    pub(super) fn push_macro_call(&mut self, text: &str, position: FilePosition) {
+        self.macro_call_positions.push(position);
+        let key = self.qualify_name(text);
+        let count = self.macro_call_counts.entry(key).or_insert(0);
+        let entry = if *count == 0 {
+            text.to_owned()
+        } else {
+            format!("{}#{}", text, count)
+        };
+        *count += 1;
+        self.macro_call_path.push(entry);
+    }
+
+    pub(super) fn pop_macro_call(&mut self) {
+        self.macro_call_path
+            .pop()
+            .expect("Encountered extra unexpected macro call pop");
+        self.macro_call_positions.pop();
+    }
+
+    /// The position of the innermost macro call being resolved, if any.
+    pub(super) fn borrow_macro_call_position(&self) -> Option<&FilePosition> {
+        self.macro_call_positions.last()
+    }
+
+    fn qualify_name(&self, name: &str) -> String {
+        let mut qualified = String::new();
+        for entry in &self.macro_call_path {
+            qualified.push_str(entry);
+            qualified.push('.');
+        }
+        qualified.push_str(name);
+        qualified
+    }
+
+    /// Creates a name for a static variable that is unique across the whole program. Static
+    /// variables declared inside macros are prefixed with the macro calls they were created by,
+    /// E.G. `smooth(input, 0.5).state`. Calls are named by what they look like instead of by
+    /// their order so that adding a call does not rename the variables of the calls after it,
+    /// which would make `StaticData::copy_matching_variables` mix up their values. A call which
+    /// looks exactly like an earlier one gets a suffix like `smooth(input, 0.5)#1`.
+    pub(super) fn make_static_var_name(&mut self, name: &str) -> String {
+        let base = self.qualify_name(name);
+        let mut unique = base.clone();
+        let mut index = 1;
+        // This can happen when a static block is inside an unrolled loop.
+        while self.static_var_names.contains(&unique) {
+            unique = format!("{}#{}", base, index);
+            index += 1;
+        }
+        self.static_var_names.insert(unique.clone());
+        unique
+    }
+
+    /// Creates a key for a static variable which, unlike its name, does not change when other code
+    /// is skipped because it depends on a specializable input. It is made from the positions of
+    /// the macro calls the variable was created by and of the code that declared it.
+    pub(super) fn make_static_var_key(&mut self, position: &FilePosition, name: &str) -> String {
+        let mut base = String::new();
+        for call in self.macro_call_positions.iter().chain(Some(position)) {
+            let (file, start, end) = (call.get_file(), call.get_start(), call.get_end());
+            base.push_str(&format!("{}:{}..{}/", file, start, end));
+        }
+        base.push_str(name);
+        let mut unique = base.clone();
+        let mut index = 1;
+        // Like with names, this can happen when a static block is inside an unrolled loop.
+        while self.static_var_keys.contains(&unique) {
+            unique = format!("{}#{}", base, index);
+            index += 1;
+        }
+        self.static_var_keys.insert(unique.clone());
+        unique
+    }
+
     pub(super) fn borrow_table(&self) -> &ResolverTable {
         &self.table
     }
@@ -178,6 +303,9 @@ impl<'a> ScopeResolver<'a> {
             "Cannot have multiple sets of info for a single variable."
         );
         self.table.variables.insert(var, (resolved_var, dtype));
+        for (_, declared) in &mut self.every_blocks {
+            declared.insert(var);
+        }
         self.reset_temporary_value(var);
     }
 
@@ -252,6 +380,32 @@ impl<'a> ScopeResolver<'a> {
             .insert(var, PossiblyKnownData::unknown_array(&dims[..]));
     }
 
+    /// Forgets the value of every variable which exists at run time. Used before resolving code
+    /// which does not run directly after the code that has been resolved so far.
+    pub(super) fn reset_runtime_temporary_values(&mut self) {
+        let runtime_vars: Vec<_> = self
+            .table
+            .variables
+            .iter()
+            .filter(|(_, (resolved, _))| resolved.is_some())
+            .map(|(var, _)| *var)
+            .collect();
+        for var in runtime_vars {
+            self.reset_temporary_value(var);
+        }
+    }
+
+    pub(super) fn clone_temporary_values(&self) -> HashMap<i::VariableId, PossiblyKnownData> {
+        self.temp_values.clone()
+    }
+
+    pub(super) fn restore_temporary_values(
+        &mut self,
+        values: HashMap<i::VariableId, PossiblyKnownData>,
+    ) {
+        self.temp_values = values;
+    }
+
     pub(super) fn reset_temporary_range(&mut self, var: i::VariableId, indexes: &[usize]) {
         let dims = if let Some((_, typ)) = self.get_var_info(var) {
             typ.collect_dims()
@@ -315,8 +469,11 @@ impl<'a> ScopeResolver<'a> {
     fn entry_point(&mut self, root_scope: i::ScopeId) -> Result<o::ScopeId, CompileProblem> {
         let builtin_scope = self.source.get_builtins_scope();
         for statement in self.source[builtin_scope].borrow_body().clone() {
+            // Builtins added by the compiler itself never do this, but preludes might.
             if let ResolvedStatement::Modified(..) = self.resolve_statement(&statement)? {
-                unreachable!("Builtin code should not have any part evaluated at run time.");
+                return Err(problems::run_time_code_in_prelude(
+                    statement.clone_position(),
+                ));
             }
         }
         self.current_scope = self.target.get_entry_point();
@@ -326,6 +483,7 @@ impl<'a> ScopeResolver<'a> {
                 self.target[self.current_scope].add_statement(new);
             }
         }
+        self.advance_every_counters();
         Ok(self.current_scope)
     }
 }
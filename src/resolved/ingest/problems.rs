@@ -429,3 +429,108 @@ pub fn compile_time_output(output_decl_pos: FilePosition, typ: &i::DataType) ->
         ),
     )])
 }
+
+pub fn specializable_input_not_scalar(
+    input_pos: FilePosition,
+    typ: &i::DataType,
+) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        input_pos,
+        Error,
+        &format!(
+            concat!(
+                "Specializable Input Not Scalar\nThe highlighted input was given the data type ",
+                "{:?}, but only BOOL, INT, and FLOAT inputs can be specializable."
+            ),
+            typ
+        ),
+    )])
+}
+
+pub fn every_interval_not_constant(interval_pos: FilePosition) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        interval_pos,
+        Error,
+        concat!(
+            "Interval Not Constant\nThe interval of an every block must be known at compile time, ",
+            "but the highlighted expression can only be computed at run time."
+        ),
+    )])
+}
+
+pub fn every_interval_not_positive(interval_pos: FilePosition, interval: i64) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        interval_pos,
+        Error,
+        &format!(
+            concat!(
+                "Interval Not Positive\nThe interval of an every block must be at least 1, but ",
+                "the highlighted expression has the value {}."
+            ),
+            interval
+        ),
+    )])
+}
+
+pub fn every_assigns_outer_variable(
+    assign_pos: FilePosition,
+    declaration_pos: FilePosition,
+    every_pos: FilePosition,
+) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![
+        ProblemDescriptor::new(
+            assign_pos,
+            Error,
+            concat!(
+                "Every Block Assigns Outer Variable\nThe highlighted assignment is inside an every ",
+                "block but changes a variable declared outside of it. On the runs where the block ",
+                "is skipped, the variable would not keep the value assigned here. Declare the ",
+                "variable inside the every block or in a static block instead.",
+            ),
+        ),
+        ProblemDescriptor::new(declaration_pos, Hint, "The variable is declared here:"),
+        ProblemDescriptor::new(every_pos, Hint, "The every block is here:"),
+    ])
+}
+
+pub fn every_in_entry_point(every_pos: FilePosition) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        every_pos,
+        Error,
+        concat!(
+            "Every Block In Entry Point\nEvery blocks count how many times the main body has ",
+            "run, so they cannot be used inside entry points or inside macros called from them.",
+        ),
+    )])
+}
+
+pub fn extern_input_wrong_type(
+    call_pos: FilePosition,
+    function_name: &str,
+    input_number: usize,
+    expected: &i::DataType,
+    found: &i::DataType,
+) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        call_pos,
+        Error,
+        &format!(
+            concat!(
+                "Wrong Data Type\nInput {} of the extern function {} must be a {:?}, but a {:?} ",
+                "was given instead."
+            ),
+            input_number, function_name, expected, found
+        ),
+    )])
+}
+
+pub fn run_time_code_in_prelude(statement_pos: FilePosition) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        statement_pos,
+        Error,
+        concat!(
+            "Run Time Code In Prelude\nPreludes are evaluated entirely at compile time, but the ",
+            "highlighted statement can only be executed at run time."
+        ),
+    )])
+}
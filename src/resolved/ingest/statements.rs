@@ -5,6 +5,7 @@ use super::{
 use crate::high_level::problem::{CompileProblem, FilePosition};
 use crate::resolved::structure as o;
 use crate::vague::structure as i;
+use std::collections::HashSet;
 
 impl<'a> ScopeResolver<'a> {
     fn resolve_creation_point(
@@ -87,6 +88,7 @@ impl<'a> ScopeResolver<'a> {
     ) -> Result<ResolvedStatement, CompileProblem> {
         let lhs = self.resolve_vc_expression(target)?;
         let rhs = self.resolve_vp_expression(value)?;
+        self.check_every_assign(lhs.get_base(), position)?;
         let mut resolved_out_type = None;
         if lhs.borrow_data_type().is_automatic() {
             let old_auto_type = &self.get_var_info(lhs.get_base()).unwrap().1;
@@ -191,6 +193,40 @@ impl<'a> ScopeResolver<'a> {
         }
     }
 
+    /// Returns an error if `target` is a run time variable which is declared outside the every
+    /// block we are currently in. Such a variable would lose whatever the block assigned to it on
+    /// the runs where the block is skipped. Static variables and outputs are fine because they are
+    /// either kept between runs or written out on every run.
+    fn check_every_assign(
+        &self,
+        target: i::VariableId,
+        position: &FilePosition,
+    ) -> Result<(), CompileProblem> {
+        let (every_pos, declared) = if let Some(block) = self.every_blocks.last() {
+            block
+        } else {
+            return Ok(());
+        };
+        if declared.contains(&target) {
+            return Ok(());
+        }
+        let resolved = if let Some((Some(resolved), _)) = self.get_var_info(target) {
+            *resolved
+        } else {
+            // Compile time only, so there is nothing to keep between runs.
+            return Ok(());
+        };
+        let main_outputs = self.source[self.source.get_entry_point()].borrow_outputs();
+        if main_outputs.contains(&target) || self.target.borrow_static_vars().contains(&resolved) {
+            return Ok(());
+        }
+        Err(problems::every_assigns_outer_variable(
+            position.clone(),
+            self.source[target].get_definition().clone(),
+            every_pos.clone(),
+        ))
+    }
+
     // Use for clauses that might be executed but we don't know.
     fn resolve_clause_body(&mut self, body: i::ScopeId) -> Result<o::ScopeId, CompileProblem> {
         let scope = self.target.create_scope();
@@ -380,10 +416,190 @@ impl<'a> ScopeResolver<'a> {
         }))
     }
 
+    fn resolve_every(
+        &mut self,
+        interval: &i::VPExpression,
+        body: i::ScopeId,
+        position: &FilePosition,
+    ) -> Result<ResolvedStatement, CompileProblem> {
+        let rinterval = self.resolve_vp_expression(interval)?;
+        if rinterval.borrow_data_type() != &i::DataType::Int {
+            return Err(problems::vpe_wrong_type(
+                interval.clone_position(),
+                &i::DataType::Int,
+                rinterval.borrow_data_type(),
+            ));
+        }
+        let interval_value = if let ResolvedVPExpression::Interpreted(value, ..) = &rinterval {
+            // We just checked that it is an int.
+            value.require_int()
+        } else {
+            return Err(problems::every_interval_not_constant(
+                interval.clone_position(),
+            ));
+        };
+        if interval_value < 1 {
+            return Err(problems::every_interval_not_positive(
+                interval.clone_position(),
+                interval_value,
+            ));
+        }
+
+        if self.in_entry_point {
+            return Err(problems::every_in_entry_point(position.clone()));
+        }
+
+        let counter = self.get_every_counter(interval_value, position);
+        self.every_blocks.push((position.clone(), HashSet::new()));
+        let rbody = self.resolve_clause_body(body)?;
+        self.every_blocks.pop();
+        // Promote everything declared in the block to static storage so that the values are still
+        // there during the runs where the block is skipped. Sorted so that the static layout does
+        // not change between compilations.
+        let mut declared: Vec<_> = self.source[body]
+            .borrow_symbols()
+            .clone()
+            .into_iter()
+            .collect();
+        declared.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, var) in declared {
+            if let Some((Some(rvar), _)) = self.get_var_info(var) {
+                let rvar = *rvar;
+                let key = self.make_static_var_key(position, &name);
+                let name = self.make_static_var_name(&name);
+                self.target.add_static_var(rvar, Some(name), key);
+            }
+        }
+
+        let condition = o::VPExpression::BinaryOperation {
+            lhs: Box::new(o::VPExpression::Variable(counter, position.clone())),
+            op: o::BinaryOperator::Equal,
+            rhs: Box::new(o::VPExpression::Literal(
+                o::KnownData::Int(0),
+                position.clone(),
+            )),
+            typ: o::DataType::Bool,
+            position: position.clone(),
+        };
+        Ok(ResolvedStatement::Modified(o::Statement::Branch {
+            clauses: vec![(condition, rbody)],
+            else_clause: None,
+            position: position.clone(),
+        }))
+    }
+
+    /// Returns the counter used by every block with the given interval, creating it if this is the
+    /// first such block. The counters are only advanced once at the start of each run of the main
+    /// body (see `advance_every_counters`) so that a block inside a loop still runs every `interval` runs
+    /// instead of every `interval` iterations.
+    fn get_every_counter(&mut self, interval: i64, position: &FilePosition) -> o::VariableId {
+        if let Some((_, counter, _)) = self.every_counters.iter().find(|c| c.0 == interval) {
+            return *counter;
+        }
+        // The counter is an implementation detail, so it is not given a name that the host could
+        // look it up by.
+        let counter = o::Variable::new(position.clone(), o::DataType::Int);
+        let counter = self.target.adopt_variable(counter);
+        // There is only one counter for each interval, so the interval is enough to identify it.
+        let key = format!("every {}", interval);
+        self.target.add_static_var(counter, None, key);
+        // It is advanced before anything else happens, so this makes the first run see 0.
+        let static_init = self.target.get_static_init();
+        self.target[static_init].add_statement(o::Statement::Assign {
+            target: Box::new(o::VCExpression::variable(counter, position.clone())),
+            value: Box::new(o::VPExpression::Literal(
+                o::KnownData::Int(interval - 1),
+                position.clone(),
+            )),
+            position: position.clone(),
+        });
+        self.every_counters
+            .push((interval, counter, position.clone()));
+        counter
+    }
+
+    /// Adds code to the start of the entry point which advances the counter of every interval used
+    /// by an every block.
+    pub(super) fn advance_every_counters(&mut self) {
+        let entry_point = self.target.get_entry_point();
+        for (index, (interval, counter, position)) in self.every_counters.iter().enumerate() {
+            let int_literal =
+                |value| o::VPExpression::Literal(o::KnownData::Int(value), position.clone());
+            let next_count = o::VPExpression::BinaryOperation {
+                lhs: Box::new(o::VPExpression::Variable(*counter, position.clone())),
+                op: o::BinaryOperator::Add,
+                rhs: Box::new(int_literal(1)),
+                typ: o::DataType::Int,
+                position: position.clone(),
+            };
+            let next_count = o::VPExpression::BinaryOperation {
+                lhs: Box::new(next_count),
+                op: o::BinaryOperator::Modulo,
+                rhs: Box::new(int_literal(*interval)),
+                typ: o::DataType::Int,
+                position: position.clone(),
+            };
+            self.target[entry_point].insert_statement(
+                index,
+                o::Statement::Assign {
+                    target: Box::new(o::VCExpression::variable(*counter, position.clone())),
+                    value: Box::new(next_count),
+                    position: position.clone(),
+                },
+            );
+        }
+    }
+
+    fn resolve_call_extern(
+        &mut self,
+        function: usize,
+        inputs: &[i::VariableId],
+        outputs: &[i::VariableId],
+        position: &FilePosition,
+    ) -> Result<ResolvedStatement, CompileProblem> {
+        let signature = self.source.borrow_extern_functions()[function].clone();
+        // This statement only appears inside the macro created for the extern function, so the
+        // call to that macro is the most useful place to point to when something goes wrong.
+        let call_pos = self
+            .borrow_macro_call_position()
+            .unwrap_or(position)
+            .clone();
+        let mut rinputs = Vec::new();
+        for (index, input) in inputs.iter().enumerate() {
+            let input = i::VPExpression::Variable(*input, call_pos.clone());
+            let rinput = self.resolve_vp_expression(&input)?;
+            let expected = &signature.borrow_inputs()[index];
+            if rinput.borrow_data_type() != expected {
+                return Err(problems::extern_input_wrong_type(
+                    call_pos,
+                    signature.borrow_name(),
+                    index + 1,
+                    expected,
+                    rinput.borrow_data_type(),
+                ));
+            }
+            rinputs.push(rinput.as_vp_expression()?);
+        }
+        let mut routputs = Vec::new();
+        for output in outputs {
+            // Outputs are always declared with run time types, so they always have a resolved
+            // variable.
+            let routput = self.get_var_info(*output).unwrap().0.unwrap();
+            routputs.push(o::VCExpression::variable(routput, call_pos.clone()));
+            self.reset_temporary_value(*output);
+        }
+        Ok(ResolvedStatement::Modified(o::Statement::CallExtern {
+            function,
+            inputs: rinputs,
+            outputs: routputs,
+            position: call_pos,
+        }))
+    }
+
     fn resolve_static_init(
         &mut self,
         body: i::ScopeId,
-        exports: &Vec<i::VariableId>,
+        exports: &Vec<(String, i::VariableId)>,
         position: &FilePosition,
     ) -> Result<ResolvedStatement, CompileProblem> {
         let old_scope = self.current_scope;
@@ -397,11 +613,14 @@ impl<'a> ScopeResolver<'a> {
             }
         }
         let mut exported_var_info = Vec::new();
-        for export in exports {
+        for (name, export) in exports {
             let info = self.get_var_info(*export);
             if let Some((Some(id), typ)) = info {
-                exported_var_info.push((*export, *id, typ.clone()));
-                self.target.add_static_var(*id);
+                let id = *id;
+                exported_var_info.push((*export, id, typ.clone()));
+                let key = self.make_static_var_key(position, name);
+                let name = self.make_static_var_name(name);
+                self.target.add_static_var(id, Some(name), key);
             } else {
                 panic!("TODO: Nice error, cannot export ct-only variable.");
             }
@@ -417,6 +636,57 @@ impl<'a> ScopeResolver<'a> {
         Ok(ResolvedStatement::Interpreted)
     }
 
+    fn resolve_entry_point(
+        &mut self,
+        name: &str,
+        body: i::ScopeId,
+    ) -> Result<ResolvedStatement, CompileProblem> {
+        let old_scope = self.current_scope;
+        let rscope = self.target.create_scope();
+        self.current_scope = rscope;
+        self.push_table();
+        // The entry point can be called at any time, so nothing we figured out about the values of
+        // run time variables while resolving the main body applies to it. Once we are done, the
+        // main body should also not be affected by anything the entry point does.
+        let old_values = self.clone_temporary_values();
+        self.reset_runtime_temporary_values();
+        self.in_entry_point = true;
+        for statement in self.source[body].borrow_body().clone() {
+            if let ResolvedStatement::Modified(new) = self.resolve_statement(&statement)? {
+                self.target[rscope].add_statement(new);
+            }
+        }
+        self.in_entry_point = false;
+        let mut inputs = Vec::new();
+        for input in self.source[body].borrow_inputs().clone() {
+            match self.get_var_info(input) {
+                Some((Some(id), _)) => inputs.push(*id),
+                Some((None, dtype)) => {
+                    let pos = self.source[input].get_definition().clone();
+                    return Err(problems::compile_time_input(pos, dtype));
+                }
+                None => unreachable!("undefined input, should be caught in vague phase."),
+            }
+        }
+        let mut outputs = Vec::new();
+        for output in self.source[body].borrow_outputs().clone() {
+            match self.get_var_info(output) {
+                Some((Some(id), _)) => outputs.push(*id),
+                Some((None, dtype)) => {
+                    let pos = self.source[output].get_definition().clone();
+                    return Err(problems::compile_time_output(pos, dtype));
+                }
+                None => unreachable!("undefined output, should be caught in vague phase."),
+            }
+        }
+        self.pop_table();
+        self.restore_temporary_values(old_values);
+        self.current_scope = old_scope;
+        let entry_point = o::EntryPoint::new(name.to_owned(), rscope, inputs, outputs);
+        self.target.add_entry_point(entry_point);
+        Ok(ResolvedStatement::Interpreted)
+    }
+
     fn resolve_raw_vp_expression(
         &mut self,
         expr: &i::VPExpression,
@@ -466,11 +736,23 @@ impl<'a> ScopeResolver<'a> {
                 body,
                 position,
             } => self.resolve_for_loop(*allow_unroll, *counter, start, end, *body, position),
+            i::Statement::Every {
+                interval,
+                body,
+                position,
+            } => self.resolve_every(interval, *body, position),
             i::Statement::StaticInit {
                 body,
                 exports,
                 position,
             } => self.resolve_static_init(*body, exports, position),
+            i::Statement::EntryPoint { name, body, .. } => self.resolve_entry_point(name, *body),
+            i::Statement::CallExtern {
+                function,
+                inputs,
+                outputs,
+                position,
+            } => self.resolve_call_extern(*function, inputs, outputs, position),
             i::Statement::RawVPExpression(expr) => self.resolve_raw_vp_expression(expr),
         }
     }
@@ -463,6 +463,70 @@ impl<'a> ScopeResolver<'a> {
         }
     }
 
+    fn resolve_select(
+        &mut self,
+        condition: &i::VPExpression,
+        a: &i::VPExpression,
+        b: &i::VPExpression,
+        position: &FilePosition,
+    ) -> Result<ResolvedVPExpression, CompileProblem> {
+        let res_condition = self.resolve_vp_expression(condition)?;
+        let res_a = self.resolve_vp_expression(a)?;
+        let res_b = self.resolve_vp_expression(b)?;
+        let condition_type = res_condition.borrow_data_type();
+        let bool_type = condition_type.with_different_base(i::DataType::Bool);
+        if condition_type != &bool_type {
+            return Err(problems::vpe_wrong_type(
+                condition.clone_position(),
+                &bool_type,
+                condition_type,
+            ));
+        }
+        let bct = if let Ok(bct) =
+            Self::biggest_type(res_a.borrow_data_type(), res_b.borrow_data_type())
+        {
+            bct
+        } else {
+            return Err(problems::no_bct(
+                position.clone(),
+                a.clone_position(),
+                res_a.borrow_data_type(),
+                b.clone_position(),
+                res_b.borrow_data_type(),
+            ));
+        };
+        // The condition only contributes its dimensions to the result.
+        let condition_shape = condition_type.with_different_base(i::DataType::Automatic);
+        let bct = if let Ok(bct) = Self::biggest_type(&condition_shape, &bct) {
+            bct
+        } else {
+            return Err(problems::no_bct(
+                position.clone(),
+                condition.clone_position(),
+                condition_type,
+                a.clone_position(),
+                &bct,
+            ));
+        };
+        if let ResolvedVPExpression::Interpreted(i::KnownData::Bool(value), ..) = &res_condition {
+            let chosen = if *value { &res_a } else { &res_b };
+            if chosen.borrow_data_type() == &bct {
+                return Ok(if *value { res_a } else { res_b });
+            }
+        }
+        Ok(ResolvedVPExpression::Modified(
+            o::VPExpression::Select {
+                condition: Box::new(res_condition.as_vp_expression()?),
+                a: Box::new(res_a.as_vp_expression()?),
+                b: Box::new(res_b.as_vp_expression()?),
+                typ: Self::resolve_data_type(&bct)
+                    .expect("Resolving a or b should have failed if the result is ct-only."),
+                position: position.clone(),
+            },
+            bct,
+        ))
+    }
+
     fn resolve_vp_index(
         &mut self,
         base: &i::VPExpression,
@@ -483,6 +547,7 @@ impl<'a> ScopeResolver<'a> {
         mcro: &i::VPExpression,
         inputs: &Vec<i::VPExpression>,
         outputs: &Vec<i::FuncCallOutput>,
+        text: &str,
         position: &FilePosition,
     ) -> Result<ResolvedVPExpression, CompileProblem> {
         // Find out what macro we are calling.
@@ -509,6 +574,7 @@ impl<'a> ScopeResolver<'a> {
             rinputs.push(self.resolve_vp_expression(input)?);
         }
         let body_scope = macro_data.get_body();
+        self.push_macro_call(text, position.clone());
         let rscope = self.target.create_scope();
         let old_scope = self.current_scope;
         self.current_scope = rscope;
@@ -611,6 +677,7 @@ impl<'a> ScopeResolver<'a> {
         };
 
         self.pop_table();
+        self.pop_macro_call();
         self.current_scope = old_scope;
         // Add a statement to call the body we just made.
         self.target[self.current_scope].add_statement(o::Statement::MacroCall {
@@ -643,6 +710,12 @@ impl<'a> ScopeResolver<'a> {
             i::VPExpression::BinaryOperation(lhs, operator, rhs, position) => {
                 self.resolve_binary_operation(lhs, *operator, rhs, position)?
             }
+            i::VPExpression::Select {
+                condition,
+                a,
+                b,
+                position,
+            } => self.resolve_select(condition, a, b, position)?,
             i::VPExpression::Index {
                 base,
                 indexes,
@@ -652,8 +725,9 @@ impl<'a> ScopeResolver<'a> {
                 mcro,
                 inputs,
                 outputs,
+                text,
                 position,
-            } => self.resolve_macro_call(mcro, inputs, outputs, position)?,
+            } => self.resolve_macro_call(mcro, inputs, outputs, text, position)?,
         })
     }
 }
@@ -120,6 +120,13 @@ pub enum VPExpression {
         typ: DataType,
         position: FilePosition,
     },
+    Select {
+        condition: Box<VPExpression>,
+        a: Box<VPExpression>,
+        b: Box<VPExpression>,
+        typ: DataType,
+        position: FilePosition,
+    },
     Index {
         base: Box<VPExpression>,
         indexes: Vec<VPExpression>,
@@ -146,6 +153,17 @@ impl Debug for VPExpression {
             Self::BinaryOperation {
                 lhs, op, rhs, typ, ..
             } => write!(formatter, "({:?} {:?} {:?} as {:?})", lhs, op, rhs, typ),
+            Self::Select {
+                condition,
+                a,
+                b,
+                typ,
+                ..
+            } => write!(
+                formatter,
+                "(select({:?}, {:?}, {:?}) as {:?})",
+                condition, a, b, typ
+            ),
             Self::Index { base, indexes, .. } => {
                 write!(formatter, "{:?}", base)?;
                 for index in indexes.iter() {
@@ -165,6 +183,7 @@ impl VPExpression {
             | Self::Collect(_, position)
             | Self::UnaryOperation(_, _, position)
             | Self::BinaryOperation { position, .. }
+            | Self::Select { position, .. }
             | Self::Index { position, .. } => position.clone(),
         }
     }
@@ -258,6 +277,13 @@ pub enum Statement {
         mcro: ScopeId,
         position: FilePosition,
     },
+    /// Calls the extern function with the specified index in the program.
+    CallExtern {
+        function: usize,
+        inputs: Vec<VPExpression>,
+        outputs: Vec<VCExpression>,
+        position: FilePosition,
+    },
 }
 
 impl Debug for Statement {
@@ -293,6 +319,16 @@ impl Debug for Statement {
                 counter, start, end, body
             ),
             Self::MacroCall { mcro, .. } => write!(formatter, "call {:?}", mcro),
+            Self::CallExtern {
+                function,
+                inputs,
+                outputs,
+                ..
+            } => write!(
+                formatter,
+                "call extern {} {:?} -> {:?};",
+                function, inputs, outputs
+            ),
         }
     }
 }
@@ -305,7 +341,8 @@ impl Statement {
             | Self::Assign { position, .. }
             | Self::Branch { position, .. }
             | Self::ForLoop { position, .. }
-            | Self::MacroCall { position, .. } => position.clone(),
+            | Self::MacroCall { position, .. }
+            | Self::CallExtern { position, .. } => position.clone(),
         }
     }
 }
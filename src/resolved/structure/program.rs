@@ -1,4 +1,6 @@
 use crate::resolved::structure::{DataType, Scope, Variable};
+use std::collections::HashMap;
+use std::ffi::c_void;
 use std::fmt::{self, Debug, Formatter};
 use std::ops::{Index, IndexMut};
 
@@ -24,6 +26,90 @@ impl Debug for VariableId {
     }
 }
 
+/// An additional function which the host can call. It shares static data with the main body but
+/// has its own inputs and outputs.
+#[derive(Clone, Debug)]
+pub struct EntryPoint {
+    name: String,
+    body: ScopeId,
+    inputs: Vec<VariableId>,
+    outputs: Vec<VariableId>,
+}
+
+impl EntryPoint {
+    pub fn new(
+        name: String,
+        body: ScopeId,
+        inputs: Vec<VariableId>,
+        outputs: Vec<VariableId>,
+    ) -> EntryPoint {
+        EntryPoint {
+            name,
+            body,
+            inputs,
+            outputs,
+        }
+    }
+
+    pub fn borrow_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_body(&self) -> ScopeId {
+        self.body
+    }
+
+    pub fn borrow_inputs(&self) -> &[VariableId] {
+        &self.inputs[..]
+    }
+
+    pub fn borrow_outputs(&self) -> &[VariableId] {
+        &self.outputs[..]
+    }
+}
+
+/// A function provided by the host program. See the vague version of this struct for how it is
+/// called.
+#[derive(Clone, Debug)]
+pub struct ExternFunction {
+    name: String,
+    inputs: Vec<DataType>,
+    outputs: Vec<DataType>,
+    address: *const c_void,
+}
+
+impl ExternFunction {
+    pub fn new(
+        name: String,
+        inputs: Vec<DataType>,
+        outputs: Vec<DataType>,
+        address: *const c_void,
+    ) -> ExternFunction {
+        ExternFunction {
+            name,
+            inputs,
+            outputs,
+            address,
+        }
+    }
+
+    pub fn borrow_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn borrow_inputs(&self) -> &[DataType] {
+        &self.inputs[..]
+    }
+
+    pub fn borrow_outputs(&self) -> &[DataType] {
+        &self.outputs[..]
+    }
+
+    pub fn get_address(&self) -> *const c_void {
+        self.address
+    }
+}
+
 /// Represents an entire program written in the Nodespeak language.
 pub struct Program {
     scopes: Vec<Scope>,
@@ -31,8 +117,13 @@ pub struct Program {
     entry_point: ScopeId,
     variables: Vec<Variable>,
     static_vars: Vec<VariableId>,
+    static_var_names: HashMap<VariableId, String>,
+    static_var_keys: HashMap<VariableId, String>,
+    specializable_input_names: HashMap<VariableId, String>,
     inputs: Vec<VariableId>,
     outputs: Vec<VariableId>,
+    entry_points: Vec<EntryPoint>,
+    extern_functions: Vec<ExternFunction>,
 }
 
 impl Debug for Program {
@@ -40,6 +131,12 @@ impl Debug for Program {
         write!(formatter, "static initialization: {:?}", self.static_init)?;
         for (index, static_var) in self.static_vars.iter().enumerate() {
             write!(formatter, "\nstatic var {}: {:?}", index, static_var)?;
+            if let Some(name) = self.static_var_names.get(static_var) {
+                write!(formatter, " ({})", name)?;
+            }
+            if let Some(key) = self.static_var_keys.get(static_var) {
+                write!(formatter, " [{}]", key)?;
+            }
         }
         write!(formatter, "\nentry point: {:?}", self.entry_point)?;
         for (index, input) in self.inputs.iter().enumerate() {
@@ -48,6 +145,16 @@ impl Debug for Program {
         for (index, output) in self.outputs.iter().enumerate() {
             write!(formatter, "\noutput {}: {:?}", index, output)?;
         }
+        for entry in self.entry_points.iter() {
+            write!(
+                formatter,
+                "\nentry point {}: {:?}, inputs {:?}, outputs {:?}",
+                entry.name, entry.body, entry.inputs, entry.outputs
+            )?;
+        }
+        for (index, function) in self.extern_functions.iter().enumerate() {
+            write!(formatter, "\nextern function {}: {:?}", index, function)?;
+        }
         for (index, scope) in self.scopes.iter().enumerate() {
             write!(formatter, "\ncontents of {:?}:\n", ScopeId(index))?;
             write!(
@@ -104,8 +211,13 @@ impl Program {
             entry_point: ScopeId(1),
             variables: Vec::new(),
             static_vars: Vec::new(),
+            static_var_names: HashMap::new(),
+            static_var_keys: HashMap::new(),
+            specializable_input_names: HashMap::new(),
             inputs: Vec::new(),
             outputs: Vec::new(),
+            entry_points: Vec::new(),
+            extern_functions: Vec::new(),
         }
     }
 
@@ -143,8 +255,34 @@ impl Program {
         &self.static_vars[..]
     }
 
-    pub fn add_static_var(&mut self, static_var: VariableId) {
+    /// The name is what the variable was exported as from its static block. It is used to let
+    /// the host look up the variable in the program's static data. The key identifies the
+    /// variable across versions of the program compiled with different values for specializable
+    /// inputs, so that they can share static data.
+    pub fn add_static_var(&mut self, static_var: VariableId, name: Option<String>, key: String) {
         self.static_vars.push(static_var);
+        if let Some(name) = name {
+            self.static_var_names.insert(static_var, name);
+        }
+        self.static_var_keys.insert(static_var, key);
+    }
+
+    pub fn borrow_static_var_name(&self, static_var: VariableId) -> Option<&String> {
+        self.static_var_names.get(&static_var)
+    }
+
+    pub fn borrow_static_var_key(&self, static_var: VariableId) -> Option<&String> {
+        self.static_var_keys.get(&static_var)
+    }
+
+    /// The name is what the input was called in the source code. It is used to let the host
+    /// specialize the program for particular values of the input.
+    pub fn add_specializable_input(&mut self, input: VariableId, name: String) {
+        self.specializable_input_names.insert(input, name);
+    }
+
+    pub fn borrow_specializable_input_name(&self, input: VariableId) -> Option<&String> {
+        self.specializable_input_names.get(&input)
     }
 
     pub fn borrow_inputs(&self) -> &[VariableId] {
@@ -162,4 +300,20 @@ impl Program {
     pub fn add_output(&mut self, output: VariableId) {
         self.outputs.push(output);
     }
+
+    pub fn borrow_entry_points(&self) -> &[EntryPoint] {
+        &self.entry_points[..]
+    }
+
+    pub fn add_entry_point(&mut self, entry_point: EntryPoint) {
+        self.entry_points.push(entry_point);
+    }
+
+    pub fn borrow_extern_functions(&self) -> &[ExternFunction] {
+        &self.extern_functions[..]
+    }
+
+    pub fn add_extern_function(&mut self, function: ExternFunction) {
+        self.extern_functions.push(function);
+    }
 }
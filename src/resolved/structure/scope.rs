@@ -24,6 +24,10 @@ impl Scope {
         self.body.push(statement)
     }
 
+    pub fn insert_statement(&mut self, index: usize, statement: Statement) {
+        self.body.insert(index, statement)
+    }
+
     pub fn borrow_body(&self) -> &Vec<Statement> {
         &self.body
     }
@@ -0,0 +1,424 @@
+//! Element-wise operations on arrays are broadcast over every element of their result. Instead of
+//! giving each operation its own full-size temporary, a whole tree of them is computed one element
+//! at a time inside a single loop, so something like `a * b + c` only needs scalar temporaries and
+//! writes each element of the result exactly once.
+
+use super::Trivializer;
+use crate::high_level::problem::CompileProblem;
+use crate::resolved::structure as i;
+use crate::trivial::structure as o;
+
+/// An expression whose result can be computed separately for each of its elements.
+pub(super) enum ElementWise {
+    /// A value which is already available before the loop starts. If `prefix` is not empty, the
+    /// value is the part of an array selected by those indexes.
+    Leaf {
+        value: o::Value,
+        prefix: Vec<o::Value>,
+        base: o::DataType,
+        dims: Vec<usize>,
+    },
+    Unary {
+        op: o::UnaryOperator,
+        a: Box<ElementWise>,
+        base: o::DataType,
+        dims: Vec<usize>,
+    },
+    Binary {
+        op: o::BinaryOperator,
+        a: Box<ElementWise>,
+        b: Box<ElementWise>,
+        base: o::DataType,
+        dims: Vec<usize>,
+    },
+    Select {
+        condition: Box<ElementWise>,
+        a: Box<ElementWise>,
+        b: Box<ElementWise>,
+        base: o::DataType,
+        dims: Vec<usize>,
+    },
+}
+
+impl ElementWise {
+    fn borrow_base(&self) -> &o::DataType {
+        match self {
+            Self::Leaf { base, .. }
+            | Self::Unary { base, .. }
+            | Self::Binary { base, .. }
+            | Self::Select { base, .. } => base,
+        }
+    }
+
+    fn borrow_dims(&self) -> &[usize] {
+        match self {
+            Self::Leaf { dims, .. }
+            | Self::Unary { dims, .. }
+            | Self::Binary { dims, .. }
+            | Self::Select { dims, .. } => dims,
+        }
+    }
+
+    /// Returns true if every part of the expression which reads from `variable` has the given
+    /// dimensions. When storing into `variable`, element `i` of the result then only depends on
+    /// element `i` of each part, which has not been overwritten yet.
+    fn reads_matching_elements(&self, variable: o::VariableId, target_dims: &[usize]) -> bool {
+        match self {
+            Self::Leaf { value, dims, .. } => match &value.base {
+                o::ValueBase::Variable(id) => *id != variable || dims[..] == target_dims[..],
+                o::ValueBase::Literal(..) => true,
+            },
+            Self::Unary { a, .. } => a.reads_matching_elements(variable, target_dims),
+            Self::Binary { a, b, .. } => {
+                a.reads_matching_elements(variable, target_dims)
+                    && b.reads_matching_elements(variable, target_dims)
+            }
+            Self::Select {
+                condition, a, b, ..
+            } => {
+                condition.reads_matching_elements(variable, target_dims)
+                    && a.reads_matching_elements(variable, target_dims)
+                    && b.reads_matching_elements(variable, target_dims)
+            }
+        }
+    }
+
+    /// Returns true if the expression can be written into `target` one element at a time without
+    /// overwriting anything that the remaining elements still need to read.
+    pub(super) fn can_store_directly(&self, target: o::VariableId, target_dims: &[usize]) -> bool {
+        !target_dims.is_empty() && self.reads_matching_elements(target, target_dims)
+    }
+}
+
+/// Converts indexes into an array with `to_dims` into indexes of an array with `from_dims` that
+/// was broadcast to that shape.
+fn broadcast_indexes(
+    from_dims: &[usize],
+    to_dims: &[usize],
+    indexes: &[o::Value],
+) -> Vec<o::Value> {
+    (0..from_dims.len())
+        .map(|index| {
+            if from_dims[index] == to_dims[index] {
+                indexes[index].clone()
+            } else {
+                o::Value::literal(o::KnownData::Int(0))
+            }
+        })
+        .collect()
+}
+
+fn array_type(base: &o::DataType, dims: &[usize]) -> o::DataType {
+    dims.iter().rev().fold(base.clone(), |typ, len| {
+        o::DataType::Array(*len, Box::new(typ))
+    })
+}
+
+fn element_type(typ: &o::DataType) -> o::DataType {
+    let mut base = typ.clone();
+    while let o::DataType::Array(_, etype) = base {
+        base = *etype;
+    }
+    base
+}
+
+impl<'a> Trivializer<'a> {
+    fn build_leaf(&mut self, expression: &i::VPExpression) -> Result<ElementWise, CompileProblem> {
+        // Arrays that are indexed into are read directly from the original variable inside the
+        // loop instead of being copied out first.
+        if let i::VPExpression::Index { base, indexes, .. } = expression {
+            if let i::VPExpression::Variable(id, ..) = &**base {
+                let variable = self.trivialize_variable(*id)?;
+                let typ = self.target[variable].borrow_type().clone();
+                let (prefix, result_type) = self.trivialize_indexes(indexes, typ)?;
+                let dims = result_type.collect_dimensions();
+                if !dims.is_empty() {
+                    return Ok(ElementWise::Leaf {
+                        value: o::Value::variable(variable, &self.target),
+                        prefix,
+                        base: element_type(&result_type),
+                        dims,
+                    });
+                }
+                let holder = self.create_variable(result_type.clone());
+                let holder = o::Value::variable(holder, &self.target);
+                self.add_instruction(o::Instruction::Load {
+                    from: o::Value::variable(variable, &self.target),
+                    to: holder.clone(),
+                    from_indexes: prefix,
+                });
+                return Ok(ElementWise::Leaf {
+                    value: holder,
+                    prefix: Vec::new(),
+                    base: result_type,
+                    dims,
+                });
+            }
+        }
+        let mut value = self.trivialize_vp_expression(expression)?;
+        let typ = value.get_type(&self.target);
+        // Backends would have to rebuild literal arrays every time they are indexed with a value
+        // that is not known ahead of time, so they are stored in a variable first.
+        if let o::ValueBase::Literal(o::KnownData::Array(..)) = &value.base {
+            let var = self.create_variable(typ.clone());
+            let var = o::Value::variable(var, &self.target);
+            self.add_instruction(o::Instruction::Move {
+                from: value,
+                to: var.clone(),
+            });
+            value = var;
+        }
+        Ok(ElementWise::Leaf {
+            value,
+            prefix: Vec::new(),
+            base: element_type(&typ),
+            dims: typ.collect_dimensions(),
+        })
+    }
+
+    /// Parts of the expression which produce a single value are computed right away instead of
+    /// being recomputed for every element.
+    fn compute_if_scalar(&mut self, tree: ElementWise) -> ElementWise {
+        if !tree.borrow_dims().is_empty() {
+            return tree;
+        }
+        if let ElementWise::Leaf { .. } = &tree {
+            return tree;
+        }
+        let value = self.compute_element(&tree, &[]);
+        ElementWise::Leaf {
+            value,
+            prefix: Vec::new(),
+            base: tree.borrow_base().clone(),
+            dims: Vec::new(),
+        }
+    }
+
+    pub(super) fn build_element_wise(
+        &mut self,
+        expression: &i::VPExpression,
+    ) -> Result<ElementWise, CompileProblem> {
+        let tree = match expression {
+            i::VPExpression::UnaryOperation(operator, rhs, ..) => {
+                let a = self.build_element_wise(rhs)?;
+                let base = match operator {
+                    i::UnaryOperator::Itof => o::DataType::F32,
+                    i::UnaryOperator::Ftoi => o::DataType::I32,
+                    _ => a.borrow_base().clone(),
+                };
+                ElementWise::Unary {
+                    op: Self::trivialize_unary_operator(*operator, a.borrow_base()),
+                    dims: a.borrow_dims().to_owned(),
+                    a: Box::new(a),
+                    base,
+                }
+            }
+            i::VPExpression::BinaryOperation {
+                lhs, op, rhs, typ, ..
+            } => {
+                let a = self.build_element_wise(lhs)?;
+                let b = self.build_element_wise(rhs)?;
+                ElementWise::Binary {
+                    op: Self::trivialize_binary_operator(*op, a.borrow_base()),
+                    dims: Self::bct_dimensions(a.borrow_dims(), b.borrow_dims()),
+                    base: element_type(&Self::trivialize_data_type(typ)),
+                    a: Box::new(a),
+                    b: Box::new(b),
+                }
+            }
+            i::VPExpression::Select {
+                condition,
+                a,
+                b,
+                typ,
+                ..
+            } => {
+                let condition = self.build_element_wise(condition)?;
+                let a = self.build_element_wise(a)?;
+                let b = self.build_element_wise(b)?;
+                let dims = Self::bct_dimensions(a.borrow_dims(), b.borrow_dims());
+                ElementWise::Select {
+                    dims: Self::bct_dimensions(condition.borrow_dims(), &dims),
+                    base: element_type(&Self::trivialize_data_type(typ)),
+                    condition: Box::new(condition),
+                    a: Box::new(a),
+                    b: Box::new(b),
+                }
+            }
+            _ => return self.build_leaf(expression),
+        };
+        Ok(self.compute_if_scalar(tree))
+    }
+
+    /// Adds instructions which compute a single element of the expression, given the index of
+    /// that element in each dimension.
+    fn compute_element(&mut self, tree: &ElementWise, indexes: &[o::Value]) -> o::Value {
+        let dims = tree.borrow_dims();
+        let (instruction, x) = match tree {
+            ElementWise::Leaf {
+                value,
+                prefix,
+                base,
+                ..
+            } => {
+                if prefix.is_empty() && indexes.is_empty() {
+                    return value.clone();
+                }
+                let x = o::Value::variable(self.create_variable(base.clone()), &self.target);
+                let mut from_indexes = prefix.clone();
+                from_indexes.extend(indexes.iter().cloned());
+                let instruction = o::Instruction::Load {
+                    from: value.clone(),
+                    to: x.clone(),
+                    from_indexes,
+                };
+                (instruction, x)
+            }
+            ElementWise::Unary { op, a, base, .. } => {
+                let a = self.compute_element(a, &broadcast_indexes(a.borrow_dims(), dims, indexes));
+                let x = o::Value::variable(self.create_variable(base.clone()), &self.target);
+                let instruction = o::Instruction::UnaryOperation {
+                    op: *op,
+                    a,
+                    x: x.clone(),
+                };
+                (instruction, x)
+            }
+            ElementWise::Binary { op, a, b, base, .. } => {
+                let a = self.compute_element(a, &broadcast_indexes(a.borrow_dims(), dims, indexes));
+                let b = self.compute_element(b, &broadcast_indexes(b.borrow_dims(), dims, indexes));
+                let x = o::Value::variable(self.create_variable(base.clone()), &self.target);
+                let instruction = o::Instruction::BinaryOperation {
+                    op: *op,
+                    a,
+                    b,
+                    x: x.clone(),
+                };
+                (instruction, x)
+            }
+            ElementWise::Select {
+                condition,
+                a,
+                b,
+                base,
+                ..
+            } => {
+                let condition_indexes = broadcast_indexes(condition.borrow_dims(), dims, indexes);
+                let condition = self.compute_element(condition, &condition_indexes);
+                let a = self.compute_element(a, &broadcast_indexes(a.borrow_dims(), dims, indexes));
+                let b = self.compute_element(b, &broadcast_indexes(b.borrow_dims(), dims, indexes));
+                let x = o::Value::variable(self.create_variable(base.clone()), &self.target);
+                let instruction = o::Instruction::Select {
+                    condition,
+                    a,
+                    b,
+                    x: x.clone(),
+                };
+                (instruction, x)
+            }
+        };
+        self.add_instruction(instruction);
+        x
+    }
+
+    fn store_element_wise_loop(
+        &mut self,
+        tree: &ElementWise,
+        target: &o::Value,
+        target_indexes: &[o::Value],
+        target_dims: &[usize],
+        indexes: &mut Vec<o::Value>,
+    ) {
+        if indexes.len() == target_dims.len() {
+            let from = self.compute_element(
+                tree,
+                &broadcast_indexes(tree.borrow_dims(), target_dims, indexes),
+            );
+            let mut to_indexes = target_indexes.to_owned();
+            to_indexes.extend(indexes.iter().cloned());
+            self.add_instruction(o::Instruction::Store {
+                from,
+                to: target.clone(),
+                to_indexes,
+            });
+            return;
+        }
+        let len = target_dims[indexes.len()];
+        if len == 1 {
+            indexes.push(o::Value::literal(o::KnownData::Int(0)));
+            self.store_element_wise_loop(tree, target, target_indexes, target_dims, indexes);
+            indexes.pop();
+            return;
+        }
+
+        // Arrays always have at least one element, so the condition only needs to be checked
+        // after each iteration.
+        let (start_label, end_label) = (self.create_label(), self.create_label());
+        let counter = self.create_variable(o::DataType::I32);
+        let counter = o::Value::variable(counter, &self.target);
+        let condition = self.create_variable(o::DataType::B1);
+        let condition = o::Value::variable(condition, &self.target);
+        self.add_instruction(o::Instruction::Move {
+            from: o::Value::literal(o::KnownData::Int(0)),
+            to: counter.clone(),
+        });
+        self.add_instruction(o::Instruction::Label(start_label));
+        indexes.push(counter.clone());
+        self.store_element_wise_loop(tree, target, target_indexes, target_dims, indexes);
+        indexes.pop();
+        self.add_instruction(o::Instruction::BinaryOperation {
+            a: counter.clone(),
+            b: o::Value::literal(o::KnownData::Int(1)),
+            x: counter.clone(),
+            op: o::BinaryOperator::AddI,
+        });
+        self.add_instruction(o::Instruction::BinaryOperation {
+            a: counter,
+            b: o::Value::literal(o::KnownData::Int(len as i64)),
+            x: condition.clone(),
+            op: o::BinaryOperator::CompI(o::Condition::LessThan),
+        });
+        self.add_instruction(o::Instruction::Branch {
+            condition,
+            true_target: start_label,
+            false_target: end_label,
+        });
+        self.add_instruction(o::Instruction::Label(end_label));
+    }
+
+    /// Adds a loop which stores every element of the expression into `target`, after indexing it
+    /// with `target_indexes`. The expression is broadcast to `target_dims` if needed.
+    pub(super) fn store_element_wise(
+        &mut self,
+        tree: &ElementWise,
+        target: &o::Value,
+        target_indexes: Vec<o::Value>,
+        target_dims: &[usize],
+    ) {
+        let mut indexes = Vec::with_capacity(target_dims.len());
+        self.store_element_wise_loop(tree, target, &target_indexes, target_dims, &mut indexes);
+    }
+
+    /// Returns a value holding the result of the expression, storing it in a new variable if it
+    /// is an array.
+    pub(super) fn finish_element_wise(&mut self, tree: ElementWise) -> o::Value {
+        if let ElementWise::Leaf { value, prefix, .. } = &tree {
+            if prefix.is_empty() {
+                return value.clone();
+            }
+        }
+        let dims = tree.borrow_dims().to_owned();
+        let typ = array_type(tree.borrow_base(), &dims);
+        let target = o::Value::variable(self.create_variable(typ), &self.target);
+        self.store_element_wise(&tree, &target, Vec::new(), &dims);
+        target
+    }
+
+    pub(super) fn trivialize_element_wise(
+        &mut self,
+        expression: &i::VPExpression,
+    ) -> Result<o::Value, CompileProblem> {
+        let tree = self.build_element_wise(expression)?;
+        Ok(self.finish_element_wise(tree))
+    }
+}
@@ -1,15 +1,19 @@
-use crate::high_level::compiler::SourceSet;
-use crate::high_level::problem::{CompileProblem, FilePosition};
+use crate::high_level::compiler::{AssertMode, SourceSet};
+use crate::high_level::problem::{CompileProblem, FilePosition, RuntimeErrorKind};
 use crate::resolved::structure as i;
 use crate::shared as s;
 use crate::trivial::structure as o;
-use std::borrow::Borrow;
 use std::collections::{HashMap, HashSet};
 
+mod fusion;
 mod problems;
 
-pub fn ingest(program: &i::Program, sources: &SourceSet) -> Result<o::Program, CompileProblem> {
-    let mut trivializer = Trivializer::new(program, sources);
+pub fn ingest(
+    program: &i::Program,
+    sources: &SourceSet,
+    assert_mode: AssertMode,
+) -> Result<o::Program, CompileProblem> {
+    let mut trivializer = Trivializer::new(program, sources, assert_mode);
     trivializer.entry_point()?;
     Result::Ok(trivializer.target)
 }
@@ -17,59 +21,107 @@ pub fn ingest(program: &i::Program, sources: &SourceSet) -> Result<o::Program, C
 struct Trivializer<'a> {
     source: &'a i::Program,
     source_set: &'a SourceSet,
+    assert_mode: AssertMode,
     target: o::Program,
     variable_map: HashMap<i::VariableId, o::VariableId>,
     illegal_vars: HashSet<i::VariableId>,
-    trivializing_static_init: bool,
+    current_body: o::CodeBody,
 }
 
 impl<'a> Trivializer<'a> {
-    fn new<'n>(source: &'n i::Program, source_set: &'n SourceSet) -> Trivializer<'n> {
+    fn new<'n>(
+        source: &'n i::Program,
+        source_set: &'n SourceSet,
+        assert_mode: AssertMode,
+    ) -> Trivializer<'n> {
         Trivializer {
             source,
             source_set,
+            assert_mode,
             target: o::Program::new(),
             variable_map: HashMap::new(),
             illegal_vars: HashSet::new(),
-            trivializing_static_init: true,
+            current_body: o::CodeBody::StaticInit,
         }
     }
 
     fn entry_point(&mut self) -> Result<(), CompileProblem> {
+        for function in self.source.borrow_extern_functions() {
+            let trivialize = |typ| Self::trivialize_data_type(typ);
+            self.target.add_extern_function(o::ExternFunction::new(
+                function.borrow_name().clone(),
+                function.borrow_inputs().iter().map(trivialize).collect(),
+                function.borrow_outputs().iter().map(trivialize).collect(),
+                function.get_address(),
+            ));
+        }
         let source_static_vars = self.source.borrow_static_vars();
         for static_var in source_static_vars {
-            self.trivialize_variable_custom_location(*static_var, o::StorageLocation::Static)?;
+            let id =
+                self.trivialize_variable_custom_location(*static_var, o::StorageLocation::Static)?;
+            let name = self.source.borrow_static_var_name(*static_var).cloned();
+            let key = self.source.borrow_static_var_key(*static_var).cloned();
+            self.target.add_static_var(id, name, key);
         }
         // We want to keep the static init and main body separated, they should not share any
         // variable IDs except for the static variables.
         let old_var_map = self.variable_map.clone();
         let source_inputs = self.source.borrow_inputs();
         for input in source_inputs {
-            self.trivialize_variable_custom_location(*input, o::StorageLocation::Input)?;
+            let id = self.trivialize_variable_custom_location(*input, o::StorageLocation::Input)?;
+            if let Some(name) = self.source.borrow_specializable_input_name(*input) {
+                self.target.add_specializable_input(id, name.clone());
+            }
         }
         let source_outputs = self.source.borrow_outputs();
         for output in source_outputs {
             self.trivialize_variable_custom_location(*output, o::StorageLocation::Output)?;
         }
         let source_entry_point = self.source.get_entry_point();
-        self.trivializing_static_init = false;
+        self.current_body = o::CodeBody::Main;
         for statement in self.source[source_entry_point].borrow_body().clone() {
             self.trivialize_statement(&statement)?;
         }
 
         // Every var used in the main body, including static vars.
         let main_and_static_vars: HashSet<_> = self.variable_map.keys().cloned().collect();
-        self.variable_map = old_var_map;
+        self.variable_map = old_var_map.clone();
         // All static vars.
         let static_vars: HashSet<_> = self.variable_map.keys().cloned().collect();
-        // If we encounter a variable in the static init body that is in this list, it is illegal
-        // because it only exists in the main body.
+        // If we encounter a variable in the static init body or an entry point that is in this
+        // list, it is illegal because it only exists in the main body.
         self.illegal_vars = main_and_static_vars
             .difference(&static_vars)
             .cloned()
             .collect();
+
+        for entry_point in self.source.borrow_entry_points() {
+            let index = self
+                .target
+                .add_entry_point(entry_point.borrow_name().clone());
+            self.current_body = o::CodeBody::EntryPoint(index);
+            for input in entry_point.borrow_inputs() {
+                let location = o::StorageLocation::EntryInput(index);
+                self.trivialize_variable_custom_location(*input, location)?;
+            }
+            for output in entry_point.borrow_outputs() {
+                let location = o::StorageLocation::EntryOutput(index);
+                self.trivialize_variable_custom_location(*output, location)?;
+            }
+            for statement in self.source[entry_point.get_body()].borrow_body().clone() {
+                self.trivialize_statement(&statement)?;
+            }
+            let entry_vars: Vec<_> = self.variable_map.keys().cloned().collect();
+            self.variable_map = old_var_map.clone();
+            for var in entry_vars {
+                if !static_vars.contains(&var) {
+                    self.illegal_vars.insert(var);
+                }
+            }
+        }
+
         let source_static_init = self.source.get_static_init();
-        self.trivializing_static_init = true;
+        self.current_body = o::CodeBody::StaticInit;
         for statement in self.source[source_static_init].borrow_body().clone() {
             self.trivialize_statement(&statement)?;
         }
@@ -77,20 +129,20 @@ impl<'a> Trivializer<'a> {
     }
 
     fn add_instruction(&mut self, instruction: o::Instruction) {
-        if self.trivializing_static_init {
-            self.target.add_static_init_instruction(instruction);
-        } else {
-            self.target.add_instruction(instruction);
+        match self.current_body {
+            o::CodeBody::Main => self.target.add_instruction(instruction),
+            o::CodeBody::StaticInit => self.target.add_static_init_instruction(instruction),
+            o::CodeBody::EntryPoint(index) => {
+                self.target.add_entry_point_instruction(index, instruction)
+            }
         }
     }
 
     fn create_label(&mut self) -> o::LabelId {
-        self.target.create_label(self.trivializing_static_init)
+        self.target.create_label(self.current_body)
     }
 
-    fn bct_dimensions(type1: &o::DataType, type2: &o::DataType) -> Vec<usize> {
-        let t1dims = type1.collect_dimensions();
-        let t2dims = type2.collect_dimensions();
+    fn bct_dimensions(t1dims: &[usize], t2dims: &[usize]) -> Vec<usize> {
         let mut bctdims = Vec::new();
         for index in 0..(t1dims.len().max(t2dims.len())) {
             let dim = if index >= t1dims.len() {
@@ -138,11 +190,7 @@ impl<'a> Trivializer<'a> {
     }
 
     fn default_storage_location(&self) -> o::StorageLocation {
-        if self.trivializing_static_init {
-            o::StorageLocation::StaticBody
-        } else {
-            o::StorageLocation::MainBody
-        }
+        self.current_body.get_local_location()
     }
 
     fn create_variable_custom_location(
@@ -172,8 +220,12 @@ impl<'a> Trivializer<'a> {
         //     assert this_is_what_the_check_is_for == 3333;
         // }
         // assert fine == 12;
-        if self.trivializing_static_init && self.illegal_vars.contains(&variable) {
-            panic!("TODO: Nice error, a variable from the main body was used in static init.");
+        if self.current_body != o::CodeBody::Main && self.illegal_vars.contains(&variable) {
+            let definition = self.source[variable].get_definition().clone();
+            return Err(match self.current_body {
+                o::CodeBody::EntryPoint(..) => problems::main_body_var_in_entry_point(definition),
+                _ => problems::runtime_var_in_static_init(definition),
+            });
         }
         Result::Ok(match self.variable_map.get(&variable) {
             Some(trivialized) => *trivialized,
@@ -194,26 +246,11 @@ impl<'a> Trivializer<'a> {
         self.trivialize_variable_custom_location(variable, self.default_storage_location())
     }
 
-    fn trivialize_unary_expression(
-        &mut self,
+    fn trivialize_unary_operator(
         operator: i::UnaryOperator,
-        right: &i::VPExpression,
-    ) -> Result<o::Value, CompileProblem> {
-        let a = self.trivialize_vp_expression(right)?;
-        let out_typ = a.get_type(&self.target);
-        let mut base = out_typ.clone();
-        while let o::DataType::Array(_, etype) = base {
-            base = *etype;
-        }
-        let out_base = match operator {
-            i::UnaryOperator::Itof => o::DataType::F32,
-            i::UnaryOperator::Ftoi => o::DataType::I32,
-            _ => base.clone(),
-        };
-        let out_typ = out_typ.with_different_base(out_base);
-        let x_var = self.create_variable(out_typ);
-        let x = o::Value::variable(x_var, &self.target);
-        let toperator = match operator {
+        base: &o::DataType,
+    ) -> o::UnaryOperator {
+        match operator {
             i::UnaryOperator::Absolute => match base {
                 o::DataType::F32 => o::UnaryOperator::FAbs,
                 o::DataType::I32 => o::UnaryOperator::IAbs,
@@ -240,36 +277,14 @@ impl<'a> Trivializer<'a> {
             i::UnaryOperator::Truncate => o::UnaryOperator::FTrunc,
             i::UnaryOperator::Ftoi => o::UnaryOperator::Ftoi,
             i::UnaryOperator::Itof => o::UnaryOperator::Itof,
-        };
-        self.add_instruction(o::Instruction::UnaryOperation {
-            a,
-            x: x.clone(),
-            op: toperator,
-        });
-        Ok(x)
+        }
     }
 
-    fn trivialize_binary_expression(
-        &mut self,
-        left: &i::VPExpression,
+    fn trivialize_binary_operator(
         operator: i::BinaryOperator,
-        right: &i::VPExpression,
-        out_typ: &i::DataType,
-    ) -> Result<o::Value, CompileProblem> {
-        let mut a = self.trivialize_vp_expression(left)?;
-        let mut b = self.trivialize_vp_expression(right)?;
-        let bct_dims = Self::bct_dimensions(&a.get_type(&self.target), &b.get_type(&self.target));
-        a.inflate(&bct_dims[..]);
-        b.inflate(&bct_dims[..]);
-        let out_typ = Self::trivialize_data_type(out_typ);
-        let mut base = a.get_type(&self.target);
-        while let o::DataType::Array(_, etype) = base {
-            base = *etype;
-        }
-        let x_var = self.create_variable(out_typ);
-        let x = o::Value::variable(x_var, &self.target);
-        let x2 = x.clone();
-        let toperator = match operator {
+        base: &o::DataType,
+    ) -> o::BinaryOperator {
+        match operator {
             i::BinaryOperator::Add => match base {
                 o::DataType::F32 => o::BinaryOperator::AddF,
                 o::DataType::I32 => o::BinaryOperator::AddI,
@@ -301,7 +316,7 @@ impl<'a> Trivializer<'a> {
                 o::DataType::Array(..) => unreachable!(),
             },
             i::BinaryOperator::Power => {
-                assert!(base == o::DataType::F32);
+                assert!(*base == o::DataType::F32);
                 o::BinaryOperator::PowF
             }
 
@@ -390,16 +405,7 @@ impl<'a> Trivializer<'a> {
                 o::DataType::B1 => unimplemented!(),
                 o::DataType::Array(..) => unreachable!(),
             },
-        };
-
-        self.add_instruction(o::Instruction::BinaryOperation {
-            op: toperator,
-            a,
-            b,
-            x,
-        });
-
-        Result::Ok(x2)
+        }
     }
 
     fn trivialize_collect(
@@ -430,7 +436,14 @@ impl<'a> Trivializer<'a> {
         condition: &i::VPExpression,
         position: &FilePosition,
     ) -> Result<(), CompileProblem> {
+        if self.assert_mode == AssertMode::Elide {
+            return Ok(());
+        }
         let tcondition = self.trivialize_vp_expression(condition)?;
+        if self.assert_mode == AssertMode::Assume {
+            self.add_instruction(o::Instruction::Assume(tcondition));
+            return Ok(());
+        }
         let abort_label = self.create_label();
         let skip_label = self.create_label();
         self.add_instruction(o::Instruction::Branch {
@@ -440,9 +453,11 @@ impl<'a> Trivializer<'a> {
         });
         self.add_instruction(o::Instruction::Label(abort_label));
         let location = position.create_line_column_ref(self.source_set);
-        let error_code = self
-            .target
-            .add_error(format!("Assert failed at {}", location));
+        let error_code = self.target.add_error(
+            RuntimeErrorKind::Assert,
+            format!("Assert failed at {}", location),
+            position.clone(),
+        );
         self.add_instruction(o::Instruction::Abort(error_code));
         self.add_instruction(o::Instruction::Label(skip_label));
         Ok(())
@@ -457,7 +472,21 @@ impl<'a> Trivializer<'a> {
         let base = self.trivialize_variable(target.base)?;
         let base_type = self.target[base].borrow_type().clone();
         let (new_indexes, base_type) = self.trivialize_indexes(&target.indexes, base_type)?;
-        let mut tvalue = self.trivialize_vp_expression(value.borrow())?;
+        let mut tvalue = match value {
+            i::VPExpression::UnaryOperation(..)
+            | i::VPExpression::BinaryOperation { .. }
+            | i::VPExpression::Select { .. } => {
+                let tree = self.build_element_wise(value)?;
+                let dims = base_type.collect_dimensions();
+                if tree.can_store_directly(base, &dims) {
+                    let base = o::Value::variable(base, &self.target);
+                    self.store_element_wise(&tree, &base, new_indexes, &dims);
+                    return Ok(());
+                }
+                self.finish_element_wise(tree)
+            }
+            _ => self.trivialize_vp_expression(value)?,
+        };
         tvalue.inflate(&base_type.collect_dimensions());
         let base = o::Value::variable(base, &self.target);
 
@@ -476,6 +505,43 @@ impl<'a> Trivializer<'a> {
         Ok(())
     }
 
+    fn trivialize_call_extern(
+        &mut self,
+        function: usize,
+        inputs: &[i::VPExpression],
+        outputs: &[i::VCExpression],
+    ) -> Result<(), CompileProblem> {
+        let mut tinputs = Vec::new();
+        for input in inputs {
+            let mut tinput = self.trivialize_vp_expression(input)?;
+            // Arrays are passed by pointer, so they have to be stored somewhere first.
+            if let o::ValueBase::Literal(o::KnownData::Array(..)) = &tinput.base {
+                let typ = tinput.get_type(&self.target);
+                let var = self.create_variable(typ);
+                let var = o::Value::variable(var, &self.target);
+                self.add_instruction(o::Instruction::Move {
+                    from: tinput,
+                    to: var.clone(),
+                });
+                tinput = var;
+            }
+            tinputs.push(tinput);
+        }
+        let mut toutputs = Vec::new();
+        for output in outputs {
+            // The resolved phase only ever gives us plain variables as outputs.
+            debug_assert!(output.indexes.is_empty());
+            let var = self.trivialize_variable(output.base)?;
+            toutputs.push(o::Value::variable(var, &self.target));
+        }
+        self.add_instruction(o::Instruction::CallExtern {
+            function,
+            inputs: tinputs,
+            outputs: toutputs,
+        });
+        Ok(())
+    }
+
     fn trivialize_branch(
         &mut self,
         clauses: &Vec<(i::VPExpression, i::ScopeId)>,
@@ -623,12 +689,9 @@ impl<'a> Trivializer<'a> {
             }
             i::VPExpression::Index { base, indexes, .. } => self.trivialize_index(base, indexes)?,
 
-            i::VPExpression::UnaryOperation(op, rhs, ..) => {
-                self.trivialize_unary_expression(*op, rhs)?
-            }
-            i::VPExpression::BinaryOperation {
-                lhs, op, rhs, typ, ..
-            } => self.trivialize_binary_expression(lhs, *op, rhs, typ)?,
+            i::VPExpression::UnaryOperation(..)
+            | i::VPExpression::BinaryOperation { .. }
+            | i::VPExpression::Select { .. } => self.trivialize_element_wise(expression)?,
 
             i::VPExpression::Collect(items, ..) => self.trivialize_collect(items)?,
         })
@@ -659,6 +722,14 @@ impl<'a> Trivializer<'a> {
             } => {
                 self.trivialize_for_loop(*counter, start, end, *body)?;
             }
+            i::Statement::CallExtern {
+                function,
+                inputs,
+                outputs,
+                ..
+            } => {
+                self.trivialize_call_extern(*function, inputs, outputs)?;
+            }
             i::Statement::MacroCall { mcro, .. } => {
                 for statement in self.source[*mcro].borrow_body().clone() {
                     self.trivialize_statement(&statement)?;
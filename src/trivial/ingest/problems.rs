@@ -9,3 +9,29 @@ pub fn void_value(value_pos: FilePosition) -> CompileProblem {
         "Void Value\nThe highlighted expression does not return anything:",
     )])
 }
+
+pub fn runtime_var_in_static_init(var_pos: FilePosition) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        var_pos,
+        Error,
+        concat!(
+            "Runtime Value In Static Block\n",
+            "The highlighted variable only has a value while the main body is running, but it is ",
+            "used inside a static block, which runs before that. If the static block is inside a ",
+            "macro, it can only use inputs of that macro which are known at compile time:"
+        ),
+    )])
+}
+
+pub fn main_body_var_in_entry_point(var_pos: FilePosition) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        var_pos,
+        Error,
+        concat!(
+            "Main Body Variable In Entry Point\n",
+            "The highlighted variable only exists while the main body is running, but it is used ",
+            "inside an entry point. Entry points can only share static variables with the main ",
+            "body:"
+        ),
+    )])
+}
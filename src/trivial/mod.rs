@@ -1,4 +1,6 @@
 mod ingest;
+pub mod passes;
 pub mod structure;
+pub mod text;
 
 pub use ingest::ingest;
@@ -0,0 +1,197 @@
+use super::flow::{self, Block};
+use crate::trivial::structure::{
+    BinaryOperator, Condition, Instruction, KnownData, Program, UnaryOperator, Value, ValueBase,
+    VariableId,
+};
+use std::collections::HashMap;
+
+/// Evaluates the operation the same way the backends do. Returns None for operations whose
+/// results might differ between platforms or which would be undefined at runtime, so that they
+/// are left for the backend to deal with.
+fn fold_unary(op: &UnaryOperator, a: &KnownData) -> Option<KnownData> {
+    Some(match (op, a) {
+        (UnaryOperator::NegI, KnownData::Int(a)) => {
+            KnownData::Int((*a as i32).wrapping_neg() as i64)
+        }
+        (UnaryOperator::BNot, KnownData::Int(a)) => KnownData::Int(!(*a as i32) as i64),
+        (UnaryOperator::Not, KnownData::Bool(a)) => KnownData::Bool(!a),
+        (UnaryOperator::Itof, KnownData::Int(a)) => KnownData::Float(*a as i32 as f32 as f64),
+        (UnaryOperator::Ftoi, KnownData::Float(a)) => {
+            let a = *a as f32;
+            // Anything else is undefined in LLVM.
+            if !(a > -2_147_483_649.0 && a < 2_147_483_648.0) {
+                return None;
+            }
+            KnownData::Int(a as i32 as i64)
+        }
+        (_, KnownData::Float(a)) => {
+            let a = *a as f32;
+            KnownData::Float(match op {
+                // The backends compute this as 0 - a, which is different for zero.
+                UnaryOperator::NegF => 0.0 - a,
+                UnaryOperator::FAbs => a.abs(),
+                UnaryOperator::FFloor => a.floor(),
+                UnaryOperator::FCeil => a.ceil(),
+                UnaryOperator::FTrunc => a.trunc(),
+                UnaryOperator::FSqrt => a.sqrt(),
+                _ => return None,
+            } as f64)
+        }
+        _ => return None,
+    })
+}
+
+fn compare<T: PartialOrd>(condition: &Condition, a: T, b: T) -> bool {
+    match condition {
+        Condition::LessThan => a < b,
+        Condition::GreaterThan => a > b,
+        Condition::LessThanOrEqual => a <= b,
+        Condition::GreaterThanOrEqual => a >= b,
+        Condition::Equal => a == b,
+        Condition::NotEqual => a != b,
+    }
+}
+
+fn fold_binary(op: &BinaryOperator, a: &KnownData, b: &KnownData) -> Option<KnownData> {
+    Some(match (a, b) {
+        (KnownData::Int(a), KnownData::Int(b)) => {
+            let (a, b) = (*a as i32, *b as i32);
+            if let BinaryOperator::CompI(condition) = op {
+                return Some(KnownData::Bool(compare(condition, a, b)));
+            }
+            KnownData::Int(match op {
+                BinaryOperator::AddI => a.wrapping_add(b),
+                BinaryOperator::SubI => a.wrapping_sub(b),
+                BinaryOperator::MulI => a.wrapping_mul(b),
+                BinaryOperator::DivI => a.checked_div(b)?,
+                BinaryOperator::ModI => a.checked_rem(b)?,
+                BinaryOperator::BAnd => a & b,
+                BinaryOperator::BOr => a | b,
+                BinaryOperator::BXor => a ^ b,
+                BinaryOperator::LeftShift if (0..32).contains(&b) => ((a as u32) << b) as i32,
+                BinaryOperator::RightShift if (0..32).contains(&b) => ((a as u32) >> b) as i32,
+                _ => return None,
+            } as i64)
+        }
+        (KnownData::Float(a), KnownData::Float(b)) => {
+            let (a, b) = (*a as f32, *b as f32);
+            if let BinaryOperator::CompF(condition) = op {
+                // Every comparison is false if either side is NaN, including NotEqual.
+                let ordered = !a.is_nan() && !b.is_nan();
+                return Some(KnownData::Bool(ordered && compare(condition, a, b)));
+            }
+            KnownData::Float(match op {
+                BinaryOperator::AddF => a + b,
+                BinaryOperator::SubF => a - b,
+                BinaryOperator::MulF => a * b,
+                BinaryOperator::DivF => a / b,
+                BinaryOperator::ModF => a % b,
+                _ => return None,
+            } as f64)
+        }
+        (KnownData::Bool(a), KnownData::Bool(b)) => KnownData::Bool(match op {
+            BinaryOperator::And => *a && *b,
+            BinaryOperator::Or => *a || *b,
+            BinaryOperator::Xor => *a != *b,
+            _ => return None,
+        }),
+        _ => return None,
+    })
+}
+
+/// Returns the data of a value which is a single literal, not a broadcast one.
+fn scalar_literal(value: &Value) -> Option<&KnownData> {
+    match &value.base {
+        ValueBase::Literal(data) if value.dimensions.is_empty() => match data {
+            KnownData::Array(..) => None,
+            _ => Some(data),
+        },
+        _ => None,
+    }
+}
+
+fn fold(instruction: &Instruction) -> Option<Instruction> {
+    match instruction {
+        Instruction::UnaryOperation { op, a, x } if x.dimensions.is_empty() => {
+            let result = fold_unary(op, scalar_literal(a)?)?;
+            Some(Instruction::Move {
+                from: Value::literal(result),
+                to: x.clone(),
+            })
+        }
+        Instruction::BinaryOperation { op, a, b, x } if x.dimensions.is_empty() => {
+            let result = fold_binary(op, scalar_literal(a)?, scalar_literal(b)?)?;
+            Some(Instruction::Move {
+                from: Value::literal(result),
+                to: x.clone(),
+            })
+        }
+        Instruction::Select { condition, a, b, x } if x.dimensions.is_empty() => {
+            let from = match scalar_literal(condition)? {
+                KnownData::Bool(true) => a,
+                KnownData::Bool(false) => b,
+                _ => return None,
+            };
+            Some(Instruction::Move {
+                from: from.clone(),
+                to: x.clone(),
+            })
+        }
+        Instruction::Branch {
+            condition,
+            true_target,
+            false_target,
+        } => match scalar_literal(condition)? {
+            KnownData::Bool(true) => Some(Instruction::Jump {
+                label: *true_target,
+            }),
+            KnownData::Bool(false) => Some(Instruction::Jump {
+                label: *false_target,
+            }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn run_on_block(program: &Program, instructions: &mut [Instruction], block: &Block) -> bool {
+    let mut changed = false;
+    let mut known: HashMap<VariableId, KnownData> = HashMap::new();
+    for instruction in &mut instructions[block.start..block.end] {
+        for operand in flow::borrow_operands_mut(instruction) {
+            if let ValueBase::Variable(id) = &operand.base {
+                if let Some(data) = known.get(id) {
+                    operand.base = ValueBase::Literal(data.clone());
+                    changed = true;
+                }
+            }
+        }
+        if let Some(folded) = fold(instruction) {
+            *instruction = folded;
+            changed = true;
+        }
+        for variable in flow::written_variables(instruction) {
+            known.remove(&variable);
+        }
+        if let Instruction::Move { from, to } = instruction {
+            if let (Some(data), ValueBase::Variable(id)) = (scalar_literal(from), &to.base) {
+                if to.dimensions.is_empty() && flow::is_scalar(program, *id) {
+                    known.insert(*id, data.clone());
+                }
+            }
+        }
+    }
+    changed
+}
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let mut changed = false;
+    for body in program.get_all_bodies() {
+        let mut instructions = std::mem::take(program.borrow_body_mut(body));
+        for block in flow::split_blocks(&instructions) {
+            changed |= run_on_block(program, &mut instructions, &block);
+        }
+        *program.borrow_body_mut(body) = instructions;
+    }
+    changed
+}
@@ -0,0 +1,69 @@
+use super::flow::{self, Block};
+use crate::trivial::structure::{Instruction, Program, ValueBase, VariableId};
+use std::collections::HashMap;
+
+/// If the instruction copies one scalar variable into another of the same type, returns the
+/// source and destination.
+fn as_copy(program: &Program, instruction: &Instruction) -> Option<(VariableId, VariableId)> {
+    if let Instruction::Move { from, to } = instruction {
+        if let (ValueBase::Variable(from_id), ValueBase::Variable(to_id)) = (&from.base, &to.base) {
+            let scalars = from.dimensions.is_empty()
+                && to.dimensions.is_empty()
+                && flow::is_scalar(program, *from_id)
+                && program[*from_id].borrow_type() == program[*to_id].borrow_type();
+            if scalars {
+                return Some((*from_id, *to_id));
+            }
+        }
+    }
+    None
+}
+
+fn run_on_block(program: &Program, instructions: &mut [Instruction], block: &Block) -> bool {
+    let mut changed = false;
+    // Maps each variable to a variable it is currently a copy of.
+    let mut copies: HashMap<VariableId, VariableId> = HashMap::new();
+    for instruction in &mut instructions[block.start..block.end] {
+        for operand in flow::borrow_operands_mut(instruction) {
+            if let ValueBase::Variable(id) = &mut operand.base {
+                if let Some(source) = copies.get(id) {
+                    *id = *source;
+                    changed = true;
+                }
+            }
+        }
+        for variable in flow::written_variables(instruction) {
+            copies.remove(&variable);
+            copies.retain(|_, source| *source != variable);
+        }
+        if let Some((from, to)) = as_copy(program, instruction) {
+            if from != to {
+                copies.insert(to, from);
+            }
+        }
+    }
+    changed
+}
+
+/// Returns true if the instruction is a move from a variable to itself.
+fn is_self_copy(program: &Program, instruction: &Instruction) -> bool {
+    match as_copy(program, instruction) {
+        Some((from, to)) => from == to,
+        None => false,
+    }
+}
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let mut changed = false;
+    for body in program.get_all_bodies() {
+        let mut instructions = std::mem::take(program.borrow_body_mut(body));
+        for block in flow::split_blocks(&instructions) {
+            changed |= run_on_block(program, &mut instructions, &block);
+        }
+        let old_len = instructions.len();
+        instructions.retain(|instruction| !is_self_copy(program, instruction));
+        changed |= instructions.len() != old_len;
+        *program.borrow_body_mut(body) = instructions;
+    }
+    changed
+}
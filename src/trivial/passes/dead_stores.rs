@@ -0,0 +1,102 @@
+use super::flow;
+use crate::trivial::structure::{CodeBody, Instruction, Program, VariableId};
+use std::collections::HashSet;
+
+/// Updates `live` to what is live before the instruction, given what is live after it. Only
+/// variables in `tracked` are considered.
+fn step_backwards(
+    instruction: &Instruction,
+    live: &mut HashSet<VariableId>,
+    tracked: &HashSet<VariableId>,
+    overwritten: Option<VariableId>,
+) {
+    if let Some(variable) = overwritten {
+        live.remove(&variable);
+    }
+    for variable in flow::read_variables(instruction) {
+        if tracked.contains(&variable) {
+            live.insert(variable);
+        }
+    }
+}
+
+fn run_on_body(program: &Program, body: CodeBody, instructions: &mut Vec<Instruction>) -> bool {
+    // Only local scalars are considered, since other variables are still visible after the body
+    // finishes and arrays are usually only partially overwritten.
+    let tracked: HashSet<VariableId> = program
+        .iterate_all_variables()
+        .filter(|id| flow::is_local(program, *id, body) && flow::is_scalar(program, *id))
+        .collect();
+    if tracked.is_empty() {
+        return false;
+    }
+    let blocks = flow::split_blocks(instructions);
+    let overwrites: Vec<_> = instructions
+        .iter()
+        .map(|instruction| {
+            flow::pure_scalar_write(program, instruction).filter(|id| tracked.contains(id))
+        })
+        .collect();
+
+    // Find out what is live at the start of each block by repeatedly propagating liveness
+    // backwards until nothing changes.
+    let mut live_in: Vec<HashSet<VariableId>> = vec![HashSet::new(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (index, block) in blocks.iter().enumerate().rev() {
+            let mut live = HashSet::new();
+            for successor in &block.successors {
+                live.extend(live_in[*successor].iter().cloned());
+            }
+            for position in (block.start..block.end).rev() {
+                step_backwards(
+                    &instructions[position],
+                    &mut live,
+                    &tracked,
+                    overwrites[position],
+                );
+            }
+            if live != live_in[index] {
+                live_in[index] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let mut dead = vec![false; instructions.len()];
+    for block in &blocks {
+        let mut live = HashSet::new();
+        for successor in &block.successors {
+            live.extend(live_in[*successor].iter().cloned());
+        }
+        for position in (block.start..block.end).rev() {
+            if let Some(variable) = overwrites[position] {
+                if !live.contains(&variable) {
+                    dead[position] = true;
+                    continue;
+                }
+            }
+            step_backwards(
+                &instructions[position],
+                &mut live,
+                &tracked,
+                overwrites[position],
+            );
+        }
+    }
+    let old_len = instructions.len();
+    let mut dead = dead.into_iter();
+    instructions.retain(|_| !dead.next().unwrap());
+    instructions.len() != old_len
+}
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let mut changed = false;
+    for body in program.get_all_bodies() {
+        let mut instructions = std::mem::take(program.borrow_body_mut(body));
+        changed |= run_on_body(program, body, &mut instructions);
+        *program.borrow_body_mut(body) = instructions;
+    }
+    changed
+}
@@ -0,0 +1,60 @@
+use super::flow;
+use crate::trivial::structure::{Instruction, Program, ValueBase, VariableId};
+use std::collections::HashSet;
+
+/// If the instruction has no effect other than changing the value of a variable, returns that
+/// variable. Extern functions may do anything, so calls to them are never included.
+fn only_writes_to(instruction: &Instruction) -> Option<VariableId> {
+    match instruction {
+        Instruction::Move { .. }
+        | Instruction::Load { .. }
+        | Instruction::Store { .. }
+        | Instruction::UnaryOperation { .. }
+        | Instruction::BinaryOperation { .. }
+        | Instruction::Select { .. } => flow::written_variables(instruction).into_iter().next(),
+        _ => None,
+    }
+}
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let bodies = program.get_all_bodies();
+    let mut read = HashSet::new();
+    for body in bodies.iter() {
+        for instruction in program.borrow_body(*body) {
+            read.extend(flow::read_variables(instruction));
+        }
+    }
+    let unread: HashSet<VariableId> = program
+        .iterate_all_variables()
+        .filter(|id| !read.contains(id) && flow::is_local_anywhere(program, *id))
+        .collect();
+
+    let mut changed = false;
+    let mut used = HashSet::new();
+    for body in bodies {
+        let instructions = program.borrow_body_mut(body);
+        let old_len = instructions.len();
+        instructions.retain(|instruction| match only_writes_to(instruction) {
+            Some(variable) => !unread.contains(&variable),
+            None => true,
+        });
+        changed |= instructions.len() != old_len;
+        for instruction in instructions.iter() {
+            for value in instruction.borrow_values() {
+                if let ValueBase::Variable(id) = &value.base {
+                    used.insert(*id);
+                }
+            }
+        }
+    }
+
+    let removable: HashSet<VariableId> = program
+        .iterate_all_variables()
+        .filter(|id| !used.contains(id) && flow::is_local_anywhere(program, *id))
+        .collect();
+    if !removable.is_empty() {
+        program.retain_variables(|id| !removable.contains(&id));
+        changed = true;
+    }
+    changed
+}
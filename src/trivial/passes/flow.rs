@@ -0,0 +1,209 @@
+use crate::trivial::structure::{
+    CodeBody, Instruction, LabelId, Program, Value, ValueBase, VariableId,
+};
+use std::collections::{HashMap, HashSet};
+
+/// A sequence of instructions which is always run from start to finish. If it starts with a
+/// label, that label is the only way to jump into it.
+pub(super) struct Block {
+    pub start: usize,
+    pub end: usize,
+    pub successors: Vec<usize>,
+}
+
+/// Splits a body into blocks. A new block is started at every label and after every jump,
+/// branch, or abort.
+pub(super) fn split_blocks(instructions: &[Instruction]) -> Vec<Block> {
+    let mut starts = vec![0];
+    for (index, instruction) in instructions.iter().enumerate() {
+        let start = match instruction {
+            Instruction::Label(..) => index,
+            _ if instruction.is_terminator() => index + 1,
+            _ => continue,
+        };
+        if start > *starts.last().unwrap() && start < instructions.len() {
+            starts.push(start);
+        }
+    }
+    let mut label_blocks = HashMap::new();
+    for (block, start) in starts.iter().enumerate() {
+        if let Some(Instruction::Label(label)) = instructions.get(*start) {
+            label_blocks.insert(*label, block);
+        }
+    }
+    let mut blocks = Vec::with_capacity(starts.len());
+    for (block, start) in starts.iter().enumerate() {
+        let end = starts.get(block + 1).cloned().unwrap_or(instructions.len());
+        let successors = match instructions[..end].last() {
+            Some(Instruction::Jump { label }) => vec![label_blocks[label]],
+            Some(Instruction::Branch {
+                true_target,
+                false_target,
+                ..
+            }) => vec![label_blocks[true_target], label_blocks[false_target]],
+            Some(Instruction::Abort(..)) => Vec::new(),
+            _ if end < instructions.len() => vec![block + 1],
+            _ => Vec::new(),
+        };
+        blocks.push(Block {
+            start: *start,
+            end,
+            successors,
+        });
+    }
+    blocks
+}
+
+/// Values that the instruction reads the whole value of, which can be replaced with any other
+/// value of the same type. Values which are passed to extern functions by pointer or indexed into
+/// are not included.
+pub(super) fn borrow_operands_mut(instruction: &mut Instruction) -> Vec<&mut Value> {
+    match instruction {
+        Instruction::Move { from, .. } => vec![from],
+        Instruction::Load { from_indexes, .. } => from_indexes.iter_mut().collect(),
+        Instruction::Store {
+            from, to_indexes, ..
+        } => {
+            let mut values = vec![from];
+            values.extend(to_indexes.iter_mut());
+            values
+        }
+        Instruction::UnaryOperation { a, .. } => vec![a],
+        Instruction::BinaryOperation { a, b, .. } => vec![a, b],
+        Instruction::Select {
+            condition, a, b, ..
+        } => vec![condition, a, b],
+        Instruction::Branch { condition, .. } => vec![condition],
+        Instruction::Assume(condition) => vec![condition],
+        _ => Vec::new(),
+    }
+}
+
+fn variable_of(value: &Value) -> Option<VariableId> {
+    match &value.base {
+        ValueBase::Variable(id) => Some(*id),
+        ValueBase::Literal(..) => None,
+    }
+}
+
+/// Every variable whose value the instruction depends on. The variable stored into by `Store`
+/// is not included, since only part of it is read by the instruction.
+pub(super) fn read_variables(instruction: &Instruction) -> Vec<VariableId> {
+    let values: Vec<&Value> = match instruction {
+        Instruction::Move { from, .. } => vec![from],
+        Instruction::Load {
+            from, from_indexes, ..
+        } => Some(from).into_iter().chain(from_indexes.iter()).collect(),
+        Instruction::Store {
+            from, to_indexes, ..
+        } => Some(from).into_iter().chain(to_indexes.iter()).collect(),
+        Instruction::UnaryOperation { a, .. } => vec![a],
+        Instruction::BinaryOperation { a, b, .. } => vec![a, b],
+        Instruction::Select {
+            condition, a, b, ..
+        } => vec![condition, a, b],
+        Instruction::Branch { condition, .. } => vec![condition],
+        Instruction::Assume(condition) => vec![condition],
+        Instruction::CallExtern { inputs, .. } => inputs.iter().collect(),
+        Instruction::Label(..) | Instruction::Jump { .. } | Instruction::Abort(..) => Vec::new(),
+    };
+    values.into_iter().filter_map(variable_of).collect()
+}
+
+/// Every variable which is completely or partially changed by the instruction.
+pub(super) fn written_variables(instruction: &Instruction) -> Vec<VariableId> {
+    let values: Vec<&Value> = match instruction {
+        Instruction::Move { to, .. }
+        | Instruction::Load { to, .. }
+        | Instruction::Store { to, .. } => vec![to],
+        Instruction::UnaryOperation { x, .. }
+        | Instruction::BinaryOperation { x, .. }
+        | Instruction::Select { x, .. } => vec![x],
+        Instruction::CallExtern { outputs, .. } => outputs.iter().collect(),
+        _ => Vec::new(),
+    };
+    values.into_iter().filter_map(variable_of).collect()
+}
+
+/// If the instruction does nothing except overwrite the entire value of a scalar variable,
+/// returns that variable. Such instructions can be removed if the value is never used.
+pub(super) fn pure_scalar_write(
+    program: &Program,
+    instruction: &Instruction,
+) -> Option<VariableId> {
+    let to = match instruction {
+        Instruction::Move { to, .. }
+        | Instruction::Load { to, .. }
+        | Instruction::UnaryOperation { x: to, .. }
+        | Instruction::BinaryOperation { x: to, .. }
+        | Instruction::Select { x: to, .. } => to,
+        _ => return None,
+    };
+    variable_of(to).filter(|id| is_scalar(program, *id) && to.dimensions.is_empty())
+}
+
+pub(super) fn is_scalar(program: &Program, variable: VariableId) -> bool {
+    program[variable]
+        .borrow_type()
+        .collect_dimensions()
+        .is_empty()
+}
+
+/// Returns true if the variable only exists while the given body is running, so its value does
+/// not matter once the body has finished.
+pub(super) fn is_local(program: &Program, variable: VariableId, body: CodeBody) -> bool {
+    program[variable].get_location() == body.get_local_location()
+}
+
+/// Returns true if the variable only exists while some body is running.
+pub(super) fn is_local_anywhere(program: &Program, variable: VariableId) -> bool {
+    program
+        .get_all_bodies()
+        .into_iter()
+        .any(|body| is_local(program, variable, body))
+}
+
+/// Every label which some jump or branch in the given instructions can go to.
+pub(super) fn jump_targets<'a>(
+    instructions: impl IntoIterator<Item = &'a Instruction>,
+) -> Vec<LabelId> {
+    let mut targets = Vec::new();
+    for instruction in instructions {
+        match instruction {
+            Instruction::Jump { label } => targets.push(*label),
+            Instruction::Branch {
+                true_target,
+                false_target,
+                ..
+            } => {
+                targets.push(*true_target);
+                targets.push(*false_target);
+            }
+            _ => (),
+        }
+    }
+    targets
+}
+
+/// Removes every label which is no longer marked anywhere in the program's code. Backends expect
+/// every label to be marked somewhere, so passes which remove labels from the code call this
+/// afterwards. Returns true if anything was removed.
+pub(super) fn remove_unmarked_labels(program: &mut Program) -> bool {
+    let mut marked = HashSet::new();
+    for body in program.get_all_bodies() {
+        for instruction in program.borrow_body(body) {
+            if let Instruction::Label(label) = instruction {
+                marked.insert(*label);
+            }
+        }
+    }
+    let unmarked: Vec<LabelId> = program
+        .iterate_all_labels()
+        .filter(|label| !marked.contains(label))
+        .collect();
+    if unmarked.is_empty() {
+        return false;
+    }
+    program.retain_labels(|label| !unmarked.contains(&label));
+    true
+}
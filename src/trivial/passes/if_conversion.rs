@@ -0,0 +1,236 @@
+use super::flow;
+use crate::trivial::structure::{
+    BinaryOperator, CodeBody, DataType, Instruction, LabelId, Program, UnaryOperator, Value,
+    ValueBase, Variable, VariableId,
+};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The most instructions the two sides of a branch can have in total for it to be replaced with
+/// selects. Both sides are always run afterwards, so large bodies would cost more than the branch.
+const MAX_BODY_SIZE: usize = 8;
+
+/// A branch whose sides both end up at the same label without doing anything else in between.
+struct Diamond {
+    condition: Value,
+    true_side: Range<usize>,
+    false_side: Range<usize>,
+    /// The position of the label that both sides end at.
+    join: usize,
+}
+
+/// Returns true if the instruction can be run even when its result would not be used. Integer
+/// division, float to int conversion and shifts are left out because the C backend leaves them
+/// undefined for some inputs, such as dividing by zero or shifting by more than 31 bits.
+fn can_speculate(program: &Program, instruction: &Instruction) -> bool {
+    let pure = match instruction {
+        Instruction::Move { .. } | Instruction::Select { .. } => true,
+        Instruction::UnaryOperation { op, .. } => !matches!(op, UnaryOperator::Ftoi),
+        Instruction::BinaryOperation { op, .. } => !matches!(
+            op,
+            BinaryOperator::DivI
+                | BinaryOperator::ModI
+                | BinaryOperator::LeftShift
+                | BinaryOperator::RightShift
+        ),
+        _ => false,
+    };
+    pure && flow::pure_scalar_write(program, instruction).is_some()
+}
+
+/// Returns the end of the run of instructions starting at `start` which can all be speculated.
+fn speculatable_end(program: &Program, instructions: &[Instruction], start: usize) -> usize {
+    let mut end = start;
+    while end < instructions.len() && can_speculate(program, &instructions[end]) {
+        end += 1;
+    }
+    end
+}
+
+/// Matches the code generated for an if statement starting at the branch at `position`, either
+/// `if c jump to T else F; labl T; ...; jump to E; labl F; ...; labl E` or, once the jump has been
+/// threaded away, `if c jump to T else F; labl T; ...; labl F`.
+fn match_diamond(
+    program: &Program,
+    instructions: &[Instruction],
+    position: usize,
+    target_counts: &HashMap<LabelId, usize>,
+) -> Option<Diamond> {
+    let (condition, true_target, false_target) = match &instructions[position] {
+        Instruction::Branch {
+            condition,
+            true_target,
+            false_target,
+        } => (condition, *true_target, *false_target),
+        _ => return None,
+    };
+    let only_from_here = |label: LabelId| target_counts.get(&label) == Some(&1);
+    match instructions.get(position + 1) {
+        Some(Instruction::Label(label)) if *label == true_target && only_from_here(*label) => (),
+        _ => return None,
+    }
+    let true_side = position + 2..speculatable_end(program, instructions, position + 2);
+    let (false_side, join) = match &instructions[true_side.end..] {
+        [Instruction::Label(label), ..] if *label == false_target => {
+            (true_side.end..true_side.end, true_side.end)
+        }
+        [Instruction::Jump { label: end_label }, Instruction::Label(label), ..]
+            if *label == false_target && only_from_here(false_target) =>
+        {
+            let start = true_side.end + 2;
+            let false_side = start..speculatable_end(program, instructions, start);
+            match instructions.get(false_side.end) {
+                Some(Instruction::Label(label)) if label == end_label => (),
+                _ => return None,
+            }
+            let join = false_side.end;
+            (false_side, join)
+        }
+        _ => return None,
+    };
+    if true_side.len() + false_side.len() > MAX_BODY_SIZE {
+        return None;
+    }
+    Some(Diamond {
+        condition: condition.clone(),
+        true_side,
+        false_side,
+        join,
+    })
+}
+
+/// Moves the result of every instruction into a new local variable, returning the instructions
+/// along with the new variable that holds the final value of each variable they wrote to.
+fn rename_side(
+    program: &mut Program,
+    body: CodeBody,
+    side: &[Instruction],
+) -> (Vec<Instruction>, HashMap<VariableId, VariableId>) {
+    let mut renamed: HashMap<VariableId, VariableId> = HashMap::new();
+    let mut instructions = Vec::with_capacity(side.len());
+    for instruction in side {
+        let mut instruction = instruction.clone();
+        for operand in flow::borrow_operands_mut(&mut instruction) {
+            if let ValueBase::Variable(id) = &mut operand.base {
+                if let Some(new_id) = renamed.get(id) {
+                    *id = *new_id;
+                }
+            }
+        }
+        let variable = flow::pure_scalar_write(program, &instruction).unwrap();
+        let new_variable = match renamed.get(&variable) {
+            Some(new_variable) => *new_variable,
+            None => {
+                let typ = program[variable].borrow_type().clone();
+                let new_variable =
+                    program.adopt_variable(Variable::new(typ, body.get_local_location()));
+                renamed.insert(variable, new_variable);
+                new_variable
+            }
+        };
+        match &mut instruction {
+            Instruction::Move { to: x, .. }
+            | Instruction::UnaryOperation { x, .. }
+            | Instruction::BinaryOperation { x, .. }
+            | Instruction::Select { x, .. } => x.base = ValueBase::Variable(new_variable),
+            _ => unreachable!("Only speculatable instructions are renamed."),
+        }
+        instructions.push(instruction);
+    }
+    (instructions, renamed)
+}
+
+/// Returns the variables written by the instructions, in the order they are first written.
+fn written_in_order(program: &Program, sides: &[&[Instruction]]) -> Vec<VariableId> {
+    let mut written = Vec::new();
+    for instruction in sides.iter().flat_map(|side| side.iter()) {
+        let variable = flow::pure_scalar_write(program, instruction).unwrap();
+        if !written.contains(&variable) {
+            written.push(variable);
+        }
+    }
+    written
+}
+
+/// Runs both sides of the branch unconditionally, writing their results to new variables, then
+/// picks the right result for each variable with a select.
+fn convert(
+    program: &mut Program,
+    body: CodeBody,
+    instructions: &[Instruction],
+    diamond: Diamond,
+) -> Vec<Instruction> {
+    let true_side = &instructions[diamond.true_side.clone()];
+    let false_side = &instructions[diamond.false_side.clone()];
+    let written = written_in_order(program, &[true_side, false_side]);
+
+    let mut converted = Vec::new();
+    let mut condition = diamond.condition;
+    if let ValueBase::Variable(id) = &condition.base {
+        // The selects at the end may overwrite the condition before they are all done.
+        if written.contains(id) {
+            let copy =
+                program.adopt_variable(Variable::new(DataType::B1, body.get_local_location()));
+            let copy = Value::variable(copy, program);
+            converted.push(Instruction::Move {
+                from: condition,
+                to: copy.clone(),
+            });
+            condition = copy;
+        }
+    }
+    let (mut true_instructions, true_renamed) = rename_side(program, body, true_side);
+    let (mut false_instructions, false_renamed) = rename_side(program, body, false_side);
+    converted.append(&mut true_instructions);
+    converted.append(&mut false_instructions);
+    for variable in written {
+        let result = |renamed: &HashMap<VariableId, VariableId>| {
+            Value::variable(*renamed.get(&variable).unwrap_or(&variable), program)
+        };
+        converted.push(Instruction::Select {
+            condition: condition.clone(),
+            a: result(&true_renamed),
+            b: result(&false_renamed),
+            x: Value::variable(variable, program),
+        });
+    }
+    converted
+}
+
+fn run_on_body(program: &mut Program, body: CodeBody, instructions: &mut Vec<Instruction>) -> bool {
+    let mut target_counts: HashMap<LabelId, usize> = HashMap::new();
+    for label in flow::jump_targets(instructions.iter()) {
+        *target_counts.entry(label).or_insert(0) += 1;
+    }
+    let mut changed = false;
+    // Going backwards converts the inner branches of an else if chain first, so that the outer
+    // ones can be converted in the same run.
+    for position in (0..instructions.len()).rev() {
+        let diamond = match match_diamond(program, instructions, position, &target_counts) {
+            Some(diamond) => diamond,
+            None => continue,
+        };
+        for label in flow::jump_targets(&instructions[position..diamond.join]) {
+            *target_counts.get_mut(&label).unwrap() -= 1;
+        }
+        let join = diamond.join;
+        let converted = convert(program, body, instructions, diamond);
+        instructions.splice(position..join, converted);
+        changed = true;
+    }
+    changed
+}
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let mut changed = false;
+    for body in program.get_all_bodies() {
+        let mut instructions = std::mem::take(program.borrow_body_mut(body));
+        changed |= run_on_body(program, body, &mut instructions);
+        *program.borrow_body_mut(body) = instructions;
+    }
+    // The labels of the converted branches are gone from the code.
+    if changed {
+        flow::remove_unmarked_labels(program);
+    }
+    changed
+}
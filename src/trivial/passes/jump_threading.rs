@@ -0,0 +1,89 @@
+use crate::trivial::structure::{Instruction, LabelId, Program};
+use std::collections::HashMap;
+
+/// Returns the instruction that runs after jumping to the label at `position`, skipping over any
+/// other labels directly after it.
+fn first_after_labels(instructions: &[Instruction], position: usize) -> Option<&Instruction> {
+    instructions[position..]
+        .iter()
+        .find(|instruction| !matches!(instruction, Instruction::Label(..)))
+}
+
+fn run_on_body(instructions: &mut Vec<Instruction>) -> bool {
+    let mut forwards = HashMap::new();
+    for (position, instruction) in instructions.iter().enumerate() {
+        if let Instruction::Label(label) = instruction {
+            if let Some(Instruction::Jump { label: target }) =
+                first_after_labels(instructions, position)
+            {
+                forwards.insert(*label, *target);
+            }
+        }
+    }
+    // Follows a chain of labels which immediately jump to other labels. Infinite loops are left
+    // alone.
+    let resolve = |label: LabelId| {
+        let mut current = label;
+        for _ in 0..forwards.len() {
+            match forwards.get(&current) {
+                Some(next) if *next != label => current = *next,
+                _ => break,
+            }
+        }
+        current
+    };
+
+    let mut changed = false;
+    for instruction in instructions.iter_mut() {
+        if let Instruction::Label(..) = instruction {
+            continue;
+        }
+        for label in instruction.borrow_labels_mut() {
+            let target = resolve(*label);
+            if target != *label {
+                *label = target;
+                changed = true;
+            }
+        }
+        if let Instruction::Branch {
+            true_target,
+            false_target,
+            ..
+        } = instruction
+        {
+            if true_target == false_target {
+                *instruction = Instruction::Jump {
+                    label: *true_target,
+                };
+                changed = true;
+            }
+        }
+    }
+
+    // Jumps to a label that would be reached anyway by continuing to the next instruction are
+    // not needed.
+    let mut position = 0;
+    while position < instructions.len() {
+        if let Instruction::Jump { label } = &instructions[position] {
+            let falls_through = instructions[position + 1..]
+                .iter()
+                .take_while(|instruction| matches!(instruction, Instruction::Label(..)))
+                .any(|instruction| matches!(instruction, Instruction::Label(l) if l == label));
+            if falls_through {
+                instructions.remove(position);
+                changed = true;
+                continue;
+            }
+        }
+        position += 1;
+    }
+    changed
+}
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let mut changed = false;
+    for body in program.get_all_bodies() {
+        changed |= run_on_body(program.borrow_body_mut(body));
+    }
+    changed
+}
@@ -0,0 +1,80 @@
+//! Optimizations which transform a trivial program into a simpler one with the same behavior.
+//! LLVM does all of these on its own, but other backends generate code more or less directly from
+//! the trivial program, so they only benefit from optimizations done here.
+
+mod constant_propagation;
+mod copy_propagation;
+mod dead_stores;
+mod dead_variables;
+mod flow;
+mod if_conversion;
+mod jump_threading;
+mod unreachable_labels;
+
+use crate::trivial::structure::Program;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Pass {
+    /// Replaces reads of scalar variables whose values are known with literals, then evaluates
+    /// operations whose operands are all literals and turns branches on literals into jumps.
+    ConstantPropagation,
+    /// After `move a -> b`, replaces reads of scalar variable `b` with reads of `a`.
+    CopyPropagation,
+    /// Removes instructions which write to local scalar variables when the value written is never
+    /// read.
+    DeadStoreElimination,
+    /// Removes local variables which are never read, along with any instructions which do nothing
+    /// but write to them.
+    DeadVariableElimination,
+    /// Replaces branches whose sides only do a few assignments to scalars with code that runs
+    /// both sides and uses `select` to pick the results, so that there is nothing to mispredict.
+    IfConversion,
+    /// Makes jumps and branches to labels which are immediately followed by another jump go
+    /// straight to the final destination.
+    JumpThreading,
+    /// Removes code which can never be reached and labels which nothing jumps to.
+    UnreachableLabelRemoval,
+}
+
+impl Pass {
+    /// Every pass, in the order that they are run by `optimize`.
+    pub const ALL: &'static [Pass] = &[
+        Pass::ConstantPropagation,
+        Pass::CopyPropagation,
+        Pass::DeadStoreElimination,
+        Pass::DeadVariableElimination,
+        Pass::IfConversion,
+        Pass::JumpThreading,
+        Pass::UnreachableLabelRemoval,
+    ];
+
+    /// Runs the pass once, returning true if it changed the program.
+    pub fn run(self, program: &mut Program) -> bool {
+        match self {
+            Pass::ConstantPropagation => constant_propagation::run(program),
+            Pass::CopyPropagation => copy_propagation::run(program),
+            Pass::DeadStoreElimination => dead_stores::run(program),
+            Pass::DeadVariableElimination => dead_variables::run(program),
+            Pass::IfConversion => if_conversion::run(program),
+            Pass::JumpThreading => jump_threading::run(program),
+            Pass::UnreachableLabelRemoval => unreachable_labels::run(program),
+        }
+    }
+}
+
+/// The most times `optimize` will run the whole list of passes. Each pass usually creates
+/// opportunities for the others, but a few rounds are enough to get nearly everything.
+const MAX_ROUNDS: usize = 8;
+
+/// Runs the given passes in order, repeating them until none of them change the program.
+pub fn optimize(program: &mut Program, passes: &[Pass]) {
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = false;
+        for pass in passes {
+            changed |= pass.run(program);
+        }
+        if !changed {
+            break;
+        }
+    }
+}
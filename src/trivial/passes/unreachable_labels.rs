@@ -0,0 +1,54 @@
+use super::flow;
+use crate::trivial::structure::{Instruction, LabelId, Program};
+use std::collections::HashSet;
+
+/// Removes every block which cannot be reached from the start of the body, then every label
+/// which nothing jumps to.
+fn run_on_body(instructions: &mut Vec<Instruction>) -> bool {
+    let blocks = flow::split_blocks(instructions);
+    let mut reachable = vec![false; blocks.len()];
+    let mut stack = vec![0];
+    while let Some(block) = stack.pop() {
+        if block >= blocks.len() || reachable[block] {
+            continue;
+        }
+        reachable[block] = true;
+        stack.extend(blocks[block].successors.iter().cloned());
+    }
+    let mut keep = vec![true; instructions.len()];
+    for (block, reachable) in blocks.iter().zip(reachable) {
+        if !reachable {
+            for keep in &mut keep[block.start..block.end] {
+                *keep = false;
+            }
+        }
+    }
+
+    let kept = instructions
+        .iter()
+        .zip(keep.iter())
+        .filter(|(_, keep)| **keep)
+        .map(|(instruction, _)| instruction);
+    let targets: HashSet<LabelId> = flow::jump_targets(kept).into_iter().collect();
+    for (instruction, keep) in instructions.iter().zip(keep.iter_mut()) {
+        if let Instruction::Label(label) = instruction {
+            if !targets.contains(label) {
+                *keep = false;
+            }
+        }
+    }
+
+    let old_len = instructions.len();
+    let mut keep = keep.into_iter();
+    instructions.retain(|_| keep.next().unwrap());
+    instructions.len() != old_len
+}
+
+pub(super) fn run(program: &mut Program) -> bool {
+    let mut changed = false;
+    for body in program.get_all_bodies() {
+        changed |= run_on_body(program.borrow_body_mut(body));
+    }
+    changed |= flow::remove_unmarked_labels(program);
+    changed
+}
@@ -25,6 +25,7 @@ impl Condition {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum UnaryOperator {
     NegI,
     NegF,
@@ -47,6 +48,7 @@ pub enum UnaryOperator {
     Itof,
 }
 
+#[derive(Clone, Copy)]
 pub enum BinaryOperator {
     AddI,
     SubI,
@@ -74,6 +76,7 @@ pub enum BinaryOperator {
     CompF(Condition),
 }
 
+#[derive(Clone)]
 pub enum Instruction {
     Move {
         from: Value,
@@ -101,6 +104,14 @@ pub enum Instruction {
         b: Value,
         x: Value,
     },
+    /// Element-wise, x = condition ? a : b. Both a and b are evaluated, so this is only used in
+    /// place of a branch when neither has side effects.
+    Select {
+        condition: Value,
+        a: Value,
+        b: Value,
+        x: Value,
+    },
 
     Label(LabelId),
     Jump {
@@ -112,6 +123,106 @@ pub enum Instruction {
         false_target: LabelId,
     },
     Abort(u32),
+    /// Tells the optimizer that the condition is always true without checking it.
+    Assume(Value),
+    /// Calls the extern function with the specified index in the program. Array inputs must be
+    /// variables since they are passed by pointer.
+    CallExtern {
+        function: usize,
+        inputs: Vec<Value>,
+        outputs: Vec<Value>,
+    },
+}
+
+impl Instruction {
+    /// Every value used by the instruction, whether it is read or written.
+    pub fn borrow_values(&self) -> Vec<&Value> {
+        match self {
+            Self::Move { from, to } => vec![from, to],
+            Self::Load {
+                from,
+                from_indexes,
+                to,
+            } => {
+                let mut values = vec![from, to];
+                values.extend(from_indexes.iter());
+                values
+            }
+            Self::Store {
+                from,
+                to,
+                to_indexes,
+            } => {
+                let mut values = vec![from, to];
+                values.extend(to_indexes.iter());
+                values
+            }
+            Self::UnaryOperation { a, x, .. } => vec![a, x],
+            Self::BinaryOperation { a, b, x, .. } => vec![a, b, x],
+            Self::Select { condition, a, b, x } => vec![condition, a, b, x],
+            Self::Branch { condition, .. } => vec![condition],
+            Self::Assume(condition) => vec![condition],
+            Self::CallExtern {
+                inputs, outputs, ..
+            } => inputs.iter().chain(outputs.iter()).collect(),
+            Self::Label(..) | Self::Jump { .. } | Self::Abort(..) => Vec::new(),
+        }
+    }
+
+    /// Like `borrow_values`, but allows the values to be modified.
+    pub fn borrow_values_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Self::Move { from, to } => vec![from, to],
+            Self::Load {
+                from,
+                from_indexes,
+                to,
+            } => {
+                let mut values = vec![from, to];
+                values.extend(from_indexes.iter_mut());
+                values
+            }
+            Self::Store {
+                from,
+                to,
+                to_indexes,
+            } => {
+                let mut values = vec![from, to];
+                values.extend(to_indexes.iter_mut());
+                values
+            }
+            Self::UnaryOperation { a, x, .. } => vec![a, x],
+            Self::BinaryOperation { a, b, x, .. } => vec![a, b, x],
+            Self::Select { condition, a, b, x } => vec![condition, a, b, x],
+            Self::Branch { condition, .. } => vec![condition],
+            Self::Assume(condition) => vec![condition],
+            Self::CallExtern {
+                inputs, outputs, ..
+            } => inputs.iter_mut().chain(outputs.iter_mut()).collect(),
+            Self::Label(..) | Self::Jump { .. } | Self::Abort(..) => Vec::new(),
+        }
+    }
+
+    /// Every label the instruction refers to, including the label it marks if it is a `Label`.
+    pub fn borrow_labels_mut(&mut self) -> Vec<&mut LabelId> {
+        match self {
+            Self::Label(label) | Self::Jump { label } => vec![label],
+            Self::Branch {
+                true_target,
+                false_target,
+                ..
+            } => vec![true_target, false_target],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns true if control never continues to the next instruction after this one.
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self,
+            Self::Jump { .. } | Self::Branch { .. } | Self::Abort(..)
+        )
+    }
 }
 
 impl Debug for Instruction {
@@ -186,13 +297,13 @@ impl Debug for Instruction {
                     BinaryOperator::PowF => "powf".to_owned(),
 
                     BinaryOperator::BAnd => "band".to_owned(),
-                    BinaryOperator::BOr => "bor ".to_owned(),
+                    BinaryOperator::BOr => "bor".to_owned(),
                     BinaryOperator::BXor => "bxor".to_owned(),
                     BinaryOperator::LeftShift => "<<".to_owned(),
                     BinaryOperator::RightShift => ">>".to_owned(),
 
                     BinaryOperator::And => "and".to_owned(),
-                    BinaryOperator::Or => "or ".to_owned(),
+                    BinaryOperator::Or => "or".to_owned(),
                     BinaryOperator::Xor => "xor".to_owned(),
                     BinaryOperator::CompI(cond) => format!("compi {:?}", cond),
                     BinaryOperator::CompF(cond) => format!("compf {:?}", cond),
@@ -201,6 +312,11 @@ impl Debug for Instruction {
                 b,
                 x
             ),
+            Instruction::Select { condition, a, b, x } => write!(
+                formatter,
+                "select {:?}, {:?}, {:?} -> {:?}",
+                condition, a, b, x
+            ),
 
             Instruction::Label(id) => write!(formatter, "labl {:?}", id),
             Instruction::Jump { label } => write!(formatter, "jump to {:?}", label),
@@ -214,6 +330,16 @@ impl Debug for Instruction {
                 condition, true_target, false_target
             ),
             Instruction::Abort(error_code) => write!(formatter, "abort {}", error_code),
+            Instruction::Assume(condition) => write!(formatter, "assume {:?}", condition),
+            Instruction::CallExtern {
+                function,
+                inputs,
+                outputs,
+            } => write!(
+                formatter,
+                "call extern {} {:?} -> {:?}",
+                function, inputs, outputs
+            ),
         }
     }
 }
@@ -1,5 +1,8 @@
-use super::Variable;
-use crate::trivial::structure::Instruction;
+use super::{CodeBody, DataType, Variable};
+use crate::high_level::problem::{FilePosition, RuntimeError, RuntimeErrorKind};
+use crate::trivial::structure::{Instruction, ValueBase};
+use std::collections::HashMap;
+use std::ffi::c_void;
 use std::fmt::{self, Debug, Formatter};
 use std::ops::{Index, IndexMut};
 
@@ -14,7 +17,7 @@ impl Debug for VariableId {
 
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 struct Label {
-    occurs_in_static_body: bool,
+    body: CodeBody,
 }
 
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
@@ -32,15 +35,79 @@ impl Debug for LabelId {
     }
 }
 
+/// An additional function which the host can call. Its inputs and outputs are stored in variables
+/// with the locations `EntryInput` and `EntryOutput`, using the index of the entry point.
+pub struct EntryPoint {
+    name: String,
+    instructions: Vec<Instruction>,
+}
+
+impl EntryPoint {
+    pub fn borrow_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn borrow_instructions(&self) -> &Vec<Instruction> {
+        &self.instructions
+    }
+}
+
+/// A function provided by the host program. See the vague version of this struct for how it is
+/// called.
+#[derive(Clone, Debug)]
+pub struct ExternFunction {
+    name: String,
+    inputs: Vec<DataType>,
+    outputs: Vec<DataType>,
+    address: *const c_void,
+}
+
+impl ExternFunction {
+    pub fn new(
+        name: String,
+        inputs: Vec<DataType>,
+        outputs: Vec<DataType>,
+        address: *const c_void,
+    ) -> ExternFunction {
+        ExternFunction {
+            name,
+            inputs,
+            outputs,
+            address,
+        }
+    }
+
+    pub fn borrow_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn borrow_inputs(&self) -> &Vec<DataType> {
+        &self.inputs
+    }
+
+    pub fn borrow_outputs(&self) -> &Vec<DataType> {
+        &self.outputs
+    }
+
+    pub fn get_address(&self) -> *const c_void {
+        self.address
+    }
+}
+
 pub struct Program {
     static_init: Vec<Instruction>,
     instructions: Vec<Instruction>,
     variables: Vec<Variable>,
     static_vars: Vec<VariableId>,
+    static_var_names: HashMap<VariableId, String>,
+    static_var_keys: HashMap<VariableId, String>,
+    specializable_input_names: HashMap<VariableId, String>,
     inputs: Vec<VariableId>,
     outputs: Vec<VariableId>,
-    errors: Vec<String>,
+    errors: Vec<RuntimeError>,
     labels: Vec<Label>,
+    entry_points: Vec<EntryPoint>,
+    extern_functions: Vec<ExternFunction>,
 }
 
 impl Debug for Program {
@@ -52,6 +119,12 @@ impl Debug for Program {
         write!(formatter, "static vars:")?;
         for variable in self.static_vars.iter() {
             write!(formatter, " {:?}", variable)?;
+            if let Some(name) = self.static_var_names.get(variable) {
+                write!(formatter, " ({})", name)?;
+            }
+            if let Some(key) = self.static_var_keys.get(variable) {
+                write!(formatter, " [{}]", key)?;
+            }
         }
         writeln!(formatter)?;
         write!(formatter, "inputs:")?;
@@ -64,10 +137,19 @@ impl Debug for Program {
             write!(formatter, " {:?}", variable)?;
         }
         writeln!(formatter)?;
+        for (index, function) in self.extern_functions.iter().enumerate() {
+            writeln!(formatter, "extern function {}: {:?}", index, function)?;
+        }
         writeln!(formatter, "{} labels", self.labels.len())?;
         writeln!(formatter, "error codes:")?;
-        for (code, description) in self.errors.iter().enumerate() {
-            writeln!(formatter, "  {}: {}", code, description)?;
+        for error in self.errors.iter() {
+            writeln!(
+                formatter,
+                "  {}: {:?} {}",
+                error.get_code(),
+                error.get_kind(),
+                error.borrow_message()
+            )?;
         }
         writeln!(formatter, "static init:")?;
         for instruction in self.static_init.iter() {
@@ -77,6 +159,12 @@ impl Debug for Program {
         for instruction in self.instructions.iter() {
             writeln!(formatter, "  {:?}", instruction)?;
         }
+        for (index, entry) in self.entry_points.iter().enumerate() {
+            writeln!(formatter, "entry {} ({}):", index, entry.name)?;
+            for instruction in entry.instructions.iter() {
+                writeln!(formatter, "  {:?}", instruction)?;
+            }
+        }
         write!(formatter, "")
     }
 }
@@ -102,10 +190,15 @@ impl Program {
             static_init: Vec::new(),
             variables: Vec::new(),
             static_vars: Vec::new(),
+            static_var_names: HashMap::new(),
+            static_var_keys: HashMap::new(),
+            specializable_input_names: HashMap::new(),
             inputs: Vec::new(),
             outputs: Vec::new(),
-            errors: vec!["Success".to_owned()],
+            errors: Vec::new(),
             labels: Vec::new(),
+            entry_points: Vec::new(),
+            extern_functions: Vec::new(),
         }
     }
 
@@ -125,6 +218,57 @@ impl Program {
         &self.static_init
     }
 
+    /// Returns the index of the new entry point.
+    pub fn add_entry_point(&mut self, name: String) -> usize {
+        self.entry_points.push(EntryPoint {
+            name,
+            instructions: Vec::new(),
+        });
+        self.entry_points.len() - 1
+    }
+
+    pub fn add_entry_point_instruction(&mut self, entry_point: usize, instruction: Instruction) {
+        self.entry_points[entry_point]
+            .instructions
+            .push(instruction);
+    }
+
+    /// Returns every code body in the program, starting with the static init body and the main
+    /// body.
+    pub fn get_all_bodies(&self) -> Vec<CodeBody> {
+        let mut bodies = vec![CodeBody::StaticInit, CodeBody::Main];
+        bodies.extend((0..self.entry_points.len()).map(CodeBody::EntryPoint));
+        bodies
+    }
+
+    pub fn borrow_body(&self, body: CodeBody) -> &Vec<Instruction> {
+        match body {
+            CodeBody::Main => &self.instructions,
+            CodeBody::StaticInit => &self.static_init,
+            CodeBody::EntryPoint(index) => &self.entry_points[index].instructions,
+        }
+    }
+
+    pub fn borrow_body_mut(&mut self, body: CodeBody) -> &mut Vec<Instruction> {
+        match body {
+            CodeBody::Main => &mut self.instructions,
+            CodeBody::StaticInit => &mut self.static_init,
+            CodeBody::EntryPoint(index) => &mut self.entry_points[index].instructions,
+        }
+    }
+
+    pub fn borrow_entry_points(&self) -> &Vec<EntryPoint> {
+        &self.entry_points
+    }
+
+    pub fn add_extern_function(&mut self, function: ExternFunction) {
+        self.extern_functions.push(function);
+    }
+
+    pub fn borrow_extern_functions(&self) -> &Vec<ExternFunction> {
+        &self.extern_functions
+    }
+
     pub fn adopt_variable(&mut self, variable: Variable) -> VariableId {
         let id = VariableId(self.variables.len());
         self.variables.push(variable);
@@ -139,34 +283,152 @@ impl Program {
         &mut self.variables[id.0]
     }
 
+    pub fn add_static_var(
+        &mut self,
+        static_var: VariableId,
+        name: Option<String>,
+        key: Option<String>,
+    ) {
+        self.static_vars.push(static_var);
+        if let Some(name) = name {
+            self.static_var_names.insert(static_var, name);
+        }
+        if let Some(key) = key {
+            self.static_var_keys.insert(static_var, key);
+        }
+    }
+
+    pub fn borrow_static_vars(&self) -> &Vec<VariableId> {
+        &self.static_vars
+    }
+
+    pub fn borrow_static_var_name(&self, static_var: VariableId) -> Option<&String> {
+        self.static_var_names.get(&static_var)
+    }
+
+    /// See `resolved::Program::add_static_var`.
+    pub fn borrow_static_var_key(&self, static_var: VariableId) -> Option<&String> {
+        self.static_var_keys.get(&static_var)
+    }
+
+    pub fn add_specializable_input(&mut self, input: VariableId, name: String) {
+        self.specializable_input_names.insert(input, name);
+    }
+
+    pub fn borrow_specializable_input_name(&self, input: VariableId) -> Option<&String> {
+        self.specializable_input_names.get(&input)
+    }
+
+    /// Removes every variable for which `keep` returns false, giving the remaining variables new
+    /// IDs. The removed variables must not be used by any instruction.
+    pub fn retain_variables(&mut self, mut keep: impl FnMut(VariableId) -> bool) {
+        let mut new_ids = Vec::with_capacity(self.variables.len());
+        let mut variables = Vec::new();
+        for (index, variable) in self.variables.drain(..).enumerate() {
+            if keep(VariableId(index)) {
+                new_ids.push(Some(VariableId(variables.len())));
+                variables.push(variable);
+            } else {
+                new_ids.push(None);
+            }
+        }
+        self.variables = variables;
+        let remap = |id: &VariableId| new_ids[id.0].expect("Removed variable is still used.");
+        for body in self.get_all_bodies() {
+            for instruction in self.borrow_body_mut(body) {
+                for value in instruction.borrow_values_mut() {
+                    if let ValueBase::Variable(id) = &mut value.base {
+                        *id = remap(id);
+                    }
+                }
+            }
+        }
+        for id in self
+            .static_vars
+            .iter_mut()
+            .chain(self.inputs.iter_mut())
+            .chain(self.outputs.iter_mut())
+        {
+            *id = remap(id);
+        }
+        self.static_var_names = self
+            .static_var_names
+            .drain()
+            .map(|(id, name)| (remap(&id), name))
+            .collect();
+        self.static_var_keys = self
+            .static_var_keys
+            .drain()
+            .map(|(id, key)| (remap(&id), key))
+            .collect();
+        self.specializable_input_names = self
+            .specializable_input_names
+            .drain()
+            .map(|(id, name)| (remap(&id), name))
+            .collect();
+    }
+
     pub fn iterate_all_variables(&self) -> impl Iterator<Item = VariableId> {
         (0..self.variables.len()).map(|i| VariableId(i))
     }
 
-    pub fn create_label(&mut self, occurs_in_static_body: bool) -> LabelId {
+    pub fn create_label(&mut self, body: CodeBody) -> LabelId {
         let id = LabelId(self.labels.len());
-        self.labels.push(Label {
-            occurs_in_static_body,
-        });
+        self.labels.push(Label { body });
         id
     }
 
     pub fn is_label_in_static_body(&self, label: LabelId) -> bool {
+        self.get_label_body(label) == CodeBody::StaticInit
+    }
+
+    pub fn get_label_body(&self, label: LabelId) -> CodeBody {
         assert!(label.0 < self.labels.len());
-        self.labels[label.0].occurs_in_static_body
+        self.labels[label.0].body
+    }
+
+    /// Removes every label for which `keep` returns false, giving the remaining labels new IDs.
+    /// The removed labels must not be used by any instruction.
+    pub fn retain_labels(&mut self, mut keep: impl FnMut(LabelId) -> bool) {
+        let mut new_ids = Vec::with_capacity(self.labels.len());
+        let mut labels = Vec::new();
+        for (index, label) in self.labels.drain(..).enumerate() {
+            if keep(LabelId(index)) {
+                new_ids.push(Some(LabelId(labels.len())));
+                labels.push(label);
+            } else {
+                new_ids.push(None);
+            }
+        }
+        self.labels = labels;
+        for body in self.get_all_bodies() {
+            for instruction in self.borrow_body_mut(body) {
+                for label in instruction.borrow_labels_mut() {
+                    *label = new_ids[label.0].expect("Removed label is still used.");
+                }
+            }
+        }
     }
 
     pub fn iterate_all_labels(&self) -> impl Iterator<Item = LabelId> {
         (0..self.labels.len()).map(|i| LabelId(i))
     }
 
-    pub fn add_error(&mut self, description: String) -> u32 {
-        let code = self.errors.len() as u32;
-        self.errors.push(description);
+    /// Returns the error code that should be used to signal the error. Error code 0 is reserved
+    /// for success, so the first error will have a code of 1.
+    pub fn add_error(
+        &mut self,
+        kind: RuntimeErrorKind,
+        message: String,
+        position: FilePosition,
+    ) -> u32 {
+        let code = self.errors.len() as u32 + 1;
+        self.errors
+            .push(RuntimeError::new(code, kind, message, position));
         code
     }
 
-    pub fn borrow_error_descriptions(&self) -> &Vec<String> {
+    pub fn borrow_errors(&self) -> &Vec<RuntimeError> {
         &self.errors
     }
 }
@@ -25,6 +25,16 @@ impl DataType {
         result
     }
 
+    /// How many bytes a value of this type takes up in the packed structs used to pass data to
+    /// and from compiled programs.
+    pub fn byte_size(&self) -> usize {
+        match self {
+            Self::B1 => 1,
+            Self::I32 | Self::F32 => 4,
+            Self::Array(len, etype) => len * etype.byte_size(),
+        }
+    }
+
     pub fn with_different_base(&self, new_base: DataType) -> Self {
         match self {
             Self::Array(size, etyp) => {
@@ -46,7 +56,7 @@ impl Debug for DataType {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub enum StorageLocation {
     /// Indicates a variable which is part of the input struct which is passed to the main body.
     Input,
@@ -60,21 +70,71 @@ pub enum StorageLocation {
     StaticBody,
     /// Indicates a variable which only exists inside the body of the main function.
     MainBody,
+    /// Indicates a variable which is part of the input struct passed to the entry point with the
+    /// specified index.
+    EntryInput(usize),
+    /// Indicates a variable which is part of the output struct passed to the entry point with the
+    /// specified index.
+    EntryOutput(usize),
+    /// Indicates a variable which only exists inside the body of the entry point with the
+    /// specified index.
+    EntryBody(usize),
 }
 
 impl Debug for StorageLocation {
     fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
-        write!(
-            formatter,
-            "{}",
-            match self {
-                Self::Input => "in the input data struct",
-                Self::Output => "in the output data struct",
-                Self::Static => "in the static data struct",
-                Self::StaticBody => "in the body of the static init function",
-                Self::MainBody => "in the body of the main function",
+        match self {
+            Self::Input => write!(formatter, "in the input data struct"),
+            Self::Output => write!(formatter, "in the output data struct"),
+            Self::Static => write!(formatter, "in the static data struct"),
+            Self::StaticBody => write!(formatter, "in the body of the static init function"),
+            Self::MainBody => write!(formatter, "in the body of the main function"),
+            Self::EntryInput(index) => {
+                write!(formatter, "in the input data struct of entry {}", index)
+            }
+            Self::EntryOutput(index) => {
+                write!(formatter, "in the output data struct of entry {}", index)
             }
-        )
+            Self::EntryBody(index) => write!(formatter, "in the body of entry {}", index),
+        }
+    }
+}
+
+/// Identifies one of the functions that make up a program.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CodeBody {
+    Main,
+    StaticInit,
+    /// The entry point with the specified index.
+    EntryPoint(usize),
+}
+
+impl CodeBody {
+    /// Where variables passed in by the host as inputs to this body are stored.
+    pub fn get_input_location(&self) -> Option<StorageLocation> {
+        match self {
+            Self::Main => Some(StorageLocation::Input),
+            Self::StaticInit => None,
+            Self::EntryPoint(index) => Some(StorageLocation::EntryInput(*index)),
+        }
+    }
+
+    /// Where variables passed back to the host as outputs of this body are stored.
+    pub fn get_output_location(&self) -> Option<StorageLocation> {
+        match self {
+            Self::Main => Some(StorageLocation::Output),
+            Self::StaticInit => None,
+            Self::EntryPoint(index) => Some(StorageLocation::EntryOutput(*index)),
+        }
+    }
+
+    /// Where variables which only exist while this body is running are stored.
+    pub fn get_local_location(&self) -> StorageLocation {
+        match self {
+            Self::Main => StorageLocation::MainBody,
+            Self::StaticInit => StorageLocation::StaticBody,
+            Self::EntryPoint(index) => StorageLocation::EntryBody(*index),
+        }
     }
 }
 
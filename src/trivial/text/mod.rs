@@ -0,0 +1,32 @@
+//! A textual format for trivial programs, so that they can be inspected and so that later stages
+//! of the compiler can be tested without going through the whole pipeline. A program looks like
+//! this:
+//!
+//! ```text
+//! var tv0: f32 input
+//! var tv1: [4]i32 static
+//! var tv2: b1 main_body
+//! static tv1 "history" key "0:12..80/history"
+//! label l0 in main
+//! error 1 assert at 1:10..25 "Assert failed."
+//! static_init {
+//!     move {4>X}0i32 -> {4}tv1
+//! }
+//! main {
+//!     compf GreaterThan tv0, 0f32 -> tv2
+//!     if tv2 jump to l0 else l0
+//!     labl l0
+//! }
+//! entry 0 "reset" {
+//!     move {4>X}0i32 -> {4}tv1
+//! }
+//! ```
+//!
+//! Instructions use the same syntax as their `Debug` implementations. Lines starting with `#` are
+//! comments.
+
+mod parse;
+mod print;
+
+pub use parse::parse;
+pub use print::print;
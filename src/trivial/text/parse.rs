@@ -0,0 +1,510 @@
+use crate::high_level::problem::{FilePosition, RuntimeErrorKind};
+use crate::shared::ProxyMode;
+use crate::trivial::structure::{
+    BinaryOperator, CodeBody, Condition, DataType, ExternFunction, Instruction, KnownData, LabelId,
+    Program, StorageLocation, UnaryOperator, Value, ValueBase, Variable, VariableId,
+};
+use pest::iterators::{Pair, Pairs};
+use pest::Parser;
+use std::ptr;
+
+#[derive(Parser)]
+#[grammar = "trivial/text/grammar.pest"]
+struct TrivialParser;
+
+fn error_at(pair: &Pair<Rule>, message: String) -> String {
+    let (line, column) = pair.as_span().start_pos().line_col();
+    format!("{}:{}: {}", line, column, message)
+}
+
+fn next<'a>(pairs: &mut Pairs<'a, Rule>) -> Pair<'a, Rule> {
+    pairs.next().expect("Required by grammar.")
+}
+
+fn parse_index(pair: Pair<Rule>) -> Result<usize, String> {
+    pair.as_str()
+        .parse()
+        .map_err(|_| error_at(&pair, format!("{} is too large.", pair.as_str())))
+}
+
+fn parse_string(pair: Pair<Rule>) -> Result<String, String> {
+    let content = next(&mut pair.clone().into_inner());
+    let mut result = String::new();
+    let mut chars = content.as_str().chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let escaped = chars.next().expect("Required by grammar.");
+        result.push(match escaped {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            '\\' | '"' | '\'' => escaped,
+            'u' => {
+                let code: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                u32::from_str_radix(&code, 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+                    .ok_or_else(|| error_at(&pair, format!("Invalid escape \\u{{{}}}.", code)))?
+            }
+            _ => return Err(error_at(&pair, format!("Invalid escape \\{}.", escaped))),
+        });
+    }
+    Ok(result)
+}
+
+fn parse_data_type(pair: Pair<Rule>) -> Result<DataType, String> {
+    Ok(match pair.as_rule() {
+        Rule::b1_type => DataType::B1,
+        Rule::i32_type => DataType::I32,
+        Rule::f32_type => DataType::F32,
+        Rule::array_type => {
+            let mut children = pair.into_inner();
+            let len = parse_index(next(&mut children))?;
+            DataType::Array(len, Box::new(parse_data_type(next(&mut children))?))
+        }
+        _ => unreachable!("No other data types in grammar."),
+    })
+}
+
+fn parse_type_list(pair: Pair<Rule>) -> Result<Vec<DataType>, String> {
+    pair.into_inner().map(parse_data_type).collect()
+}
+
+fn parse_literal(pair: Pair<Rule>) -> Result<KnownData, String> {
+    Ok(match pair.as_rule() {
+        Rule::int_literal => {
+            let value = next(&mut pair.clone().into_inner());
+            KnownData::Int(
+                value.as_str().parse().map_err(|_| {
+                    error_at(&pair, format!("{} is not a valid int.", value.as_str()))
+                })?,
+            )
+        }
+        Rule::float_literal => {
+            let value = next(&mut pair.clone().into_inner());
+            KnownData::Float(value.as_str().parse().map_err(|_| {
+                error_at(&pair, format!("{} is not a valid float.", value.as_str()))
+            })?)
+        }
+        Rule::bool_literal => {
+            KnownData::Bool(next(&mut pair.into_inner()).as_rule() == Rule::bool_true)
+        }
+        Rule::array_literal => KnownData::Array(
+            pair.into_inner()
+                .map(parse_literal)
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => unreachable!("No other literals in grammar."),
+    })
+}
+
+fn parse_location(pair: Pair<Rule>) -> Result<StorageLocation, String> {
+    Ok(match pair.as_rule() {
+        Rule::input_location => StorageLocation::Input,
+        Rule::output_location => StorageLocation::Output,
+        Rule::static_location => StorageLocation::Static,
+        Rule::static_body_location => StorageLocation::StaticBody,
+        Rule::main_body_location => StorageLocation::MainBody,
+        Rule::entry_input_location => {
+            StorageLocation::EntryInput(parse_index(next(&mut pair.into_inner()))?)
+        }
+        Rule::entry_output_location => {
+            StorageLocation::EntryOutput(parse_index(next(&mut pair.into_inner()))?)
+        }
+        Rule::entry_body_location => {
+            StorageLocation::EntryBody(parse_index(next(&mut pair.into_inner()))?)
+        }
+        _ => unreachable!("No other locations in grammar."),
+    })
+}
+
+fn parse_code_body(pair: Pair<Rule>) -> Result<CodeBody, String> {
+    Ok(match pair.as_rule() {
+        Rule::main_code => CodeBody::Main,
+        Rule::static_init_code => CodeBody::StaticInit,
+        Rule::entry_code => CodeBody::EntryPoint(parse_index(next(&mut pair.into_inner()))?),
+        _ => unreachable!("No other code bodies in grammar."),
+    })
+}
+
+fn parse_error_kind(pair: Pair<Rule>) -> RuntimeErrorKind {
+    match pair.as_rule() {
+        Rule::assert_error => RuntimeErrorKind::Assert,
+        Rule::bounds_error => RuntimeErrorKind::Bounds,
+        Rule::division_by_zero_error => RuntimeErrorKind::DivisionByZero,
+        Rule::user_abort_error => RuntimeErrorKind::UserAbort,
+        Rule::unknown_error => RuntimeErrorKind::Unknown,
+        _ => unreachable!("No other error kinds in grammar."),
+    }
+}
+
+fn parse_condition(pair: Pair<Rule>) -> Condition {
+    match pair.as_str() {
+        "LessThan" => Condition::LessThan,
+        "GreaterThan" => Condition::GreaterThan,
+        "LessThanOrEqual" => Condition::LessThanOrEqual,
+        "GreaterThanOrEqual" => Condition::GreaterThanOrEqual,
+        "Equal" => Condition::Equal,
+        "NotEqual" => Condition::NotEqual,
+        _ => unreachable!("No other conditions in grammar."),
+    }
+}
+
+fn parse_unary_operator(pair: Pair<Rule>) -> UnaryOperator {
+    match pair.as_str() {
+        "negi" => UnaryOperator::NegI,
+        "negf" => UnaryOperator::NegF,
+        "not" => UnaryOperator::Not,
+        "bnot" => UnaryOperator::BNot,
+        "fsin" => UnaryOperator::FSin,
+        "fcos" => UnaryOperator::FCos,
+        "fsqrt" => UnaryOperator::FSqrt,
+        "fexp" => UnaryOperator::FExp,
+        "fexp2" => UnaryOperator::FExp2,
+        "flog" => UnaryOperator::FLog,
+        "flog10" => UnaryOperator::FLog10,
+        "flog2" => UnaryOperator::FLog2,
+        "fabs" => UnaryOperator::FAbs,
+        "iabs" => UnaryOperator::IAbs,
+        "ffloor" => UnaryOperator::FFloor,
+        "fceil" => UnaryOperator::FCeil,
+        "ftrunc" => UnaryOperator::FTrunc,
+        "ftoi" => UnaryOperator::Ftoi,
+        "itof" => UnaryOperator::Itof,
+        _ => unreachable!("No other unary operators in grammar."),
+    }
+}
+
+fn parse_binary_operator(pair: Pair<Rule>) -> BinaryOperator {
+    match pair.as_rule() {
+        Rule::compi => BinaryOperator::CompI(parse_condition(next(&mut pair.into_inner()))),
+        Rule::compf => BinaryOperator::CompF(parse_condition(next(&mut pair.into_inner()))),
+        _ => match pair.as_str() {
+            "addi" => BinaryOperator::AddI,
+            "subi" => BinaryOperator::SubI,
+            "muli" => BinaryOperator::MulI,
+            "divi" => BinaryOperator::DivI,
+            "modi" => BinaryOperator::ModI,
+            "addf" => BinaryOperator::AddF,
+            "subf" => BinaryOperator::SubF,
+            "mulf" => BinaryOperator::MulF,
+            "divf" => BinaryOperator::DivF,
+            "modf" => BinaryOperator::ModF,
+            "powf" => BinaryOperator::PowF,
+            "band" => BinaryOperator::BAnd,
+            "bor" => BinaryOperator::BOr,
+            "bxor" => BinaryOperator::BXor,
+            "<<" => BinaryOperator::LeftShift,
+            ">>" => BinaryOperator::RightShift,
+            "and" => BinaryOperator::And,
+            "or" => BinaryOperator::Or,
+            "xor" => BinaryOperator::Xor,
+            _ => unreachable!("No other binary operators in grammar."),
+        },
+    }
+}
+
+/// Keeps track of everything that has been declared so far, so that instructions can refer to it.
+struct Context {
+    program: Program,
+    variables: Vec<VariableId>,
+    labels: Vec<LabelId>,
+    num_entry_points: usize,
+}
+
+impl Context {
+    /// Checks that a declaration uses the next unused index of its kind, since programs store
+    /// everything in the order it was added.
+    fn check_index(pair: &Pair<Rule>, index: usize, expected: usize) -> Result<(), String> {
+        if index == expected {
+            Ok(())
+        } else {
+            Err(error_at(
+                pair,
+                format!("Expected index {}, got {}.", expected, index),
+            ))
+        }
+    }
+
+    fn check_entry_index(&self, pair: &Pair<Rule>, index: usize) -> Result<(), String> {
+        if index < self.num_entry_points {
+            Ok(())
+        } else {
+            Err(error_at(
+                pair,
+                format!("There is no entry point {}.", index),
+            ))
+        }
+    }
+
+    fn lookup_variable(&self, pair: Pair<Rule>) -> Result<VariableId, String> {
+        let index = parse_index(next(&mut pair.clone().into_inner()))?;
+        match self.variables.get(index) {
+            Some(id) => Ok(*id),
+            None => Err(error_at(&pair, format!("tv{} was not declared.", index))),
+        }
+    }
+
+    fn lookup_label(&self, pair: Pair<Rule>) -> Result<LabelId, String> {
+        let index = parse_index(next(&mut pair.clone().into_inner()))?;
+        match self.labels.get(index) {
+            Some(id) => Ok(*id),
+            None => Err(error_at(&pair, format!("l{} was not declared.", index))),
+        }
+    }
+
+    fn parse_declaration(&mut self, pair: Pair<Rule>) -> Result<(), String> {
+        let rule = pair.as_rule();
+        let mut children = pair.clone().into_inner();
+        match rule {
+            Rule::variable_declaration => {
+                let index = parse_index(next(&mut next(&mut children).into_inner()))?;
+                Self::check_index(&pair, index, self.variables.len())?;
+                let data_type = parse_data_type(next(&mut children))?;
+                let location = parse_location(next(&mut children))?;
+                match location {
+                    StorageLocation::EntryInput(entry)
+                    | StorageLocation::EntryOutput(entry)
+                    | StorageLocation::EntryBody(entry) => self.check_entry_index(&pair, entry)?,
+                    _ => (),
+                }
+                let id = self
+                    .program
+                    .adopt_variable(Variable::new(data_type, location));
+                self.variables.push(id);
+            }
+            Rule::static_declaration => {
+                let variable = self.lookup_variable(next(&mut children))?;
+                let mut name = None;
+                let mut key = None;
+                for child in children {
+                    if child.as_rule() == Rule::static_key {
+                        key = Some(parse_string(next(&mut child.into_inner()))?);
+                    } else {
+                        name = Some(parse_string(child)?);
+                    }
+                }
+                self.program.add_static_var(variable, name, key);
+            }
+            Rule::specializable_declaration => {
+                let variable = self.lookup_variable(next(&mut children))?;
+                let name = parse_string(next(&mut children))?;
+                self.program.add_specializable_input(variable, name);
+            }
+            Rule::label_declaration => {
+                let index = parse_index(next(&mut next(&mut children).into_inner()))?;
+                Self::check_index(&pair, index, self.labels.len())?;
+                let body = parse_code_body(next(&mut children))?;
+                if let CodeBody::EntryPoint(entry) = body {
+                    self.check_entry_index(&pair, entry)?;
+                }
+                self.labels.push(self.program.create_label(body));
+            }
+            Rule::extern_declaration => {
+                let index = parse_index(next(&mut children))?;
+                let expected = self.program.borrow_extern_functions().len();
+                Self::check_index(&pair, index, expected)?;
+                let name = parse_string(next(&mut children))?;
+                let inputs = parse_type_list(next(&mut children))?;
+                let outputs = parse_type_list(next(&mut children))?;
+                self.program.add_extern_function(ExternFunction::new(
+                    name,
+                    inputs,
+                    outputs,
+                    ptr::null(),
+                ));
+            }
+            Rule::error_declaration => {
+                let code = parse_index(next(&mut children))?;
+                let expected = self.program.borrow_errors().len() + 1;
+                Self::check_index(&pair, code, expected)?;
+                let kind = parse_error_kind(next(&mut children));
+                let mut position = next(&mut children).into_inner();
+                let file = parse_index(next(&mut position))?;
+                let start = parse_index(next(&mut position))?;
+                let end = parse_index(next(&mut position))?;
+                let message = parse_string(next(&mut children))?;
+                let position = FilePosition::new(start, end, file);
+                self.program.add_error(kind, message, position);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn parse_value(&self, pair: Pair<Rule>) -> Result<Value, String> {
+        let mut dimensions = Vec::new();
+        let mut base = None;
+        for child in pair.into_inner() {
+            match child.as_rule() {
+                Rule::dimensions => {
+                    for dimension in child.into_inner() {
+                        let mut parts = dimension.into_inner();
+                        let len = parse_index(next(&mut parts))?;
+                        let mode = match next(&mut parts).as_rule() {
+                            Rule::discard => ProxyMode::Discard,
+                            Rule::collapse => ProxyMode::Collapse,
+                            _ => ProxyMode::Keep,
+                        };
+                        dimensions.push((len, mode));
+                    }
+                }
+                Rule::variable => base = Some(ValueBase::Variable(self.lookup_variable(child)?)),
+                _ => base = Some(ValueBase::Literal(parse_literal(child)?)),
+            }
+        }
+        Ok(Value {
+            base: base.expect("Required by grammar."),
+            dimensions,
+        })
+    }
+
+    fn parse_values(&self, pair: Pair<Rule>) -> Result<Vec<Value>, String> {
+        pair.into_inner()
+            .map(|value| self.parse_value(value))
+            .collect()
+    }
+
+    fn parse_instruction(&self, pair: Pair<Rule>) -> Result<Instruction, String> {
+        let rule = pair.as_rule();
+        let mut children = pair.clone().into_inner();
+        Ok(match rule {
+            Rule::move_instruction => Instruction::Move {
+                from: self.parse_value(next(&mut children))?,
+                to: self.parse_value(next(&mut children))?,
+            },
+            Rule::load_instruction => Instruction::Load {
+                from: self.parse_value(next(&mut children))?,
+                from_indexes: self.parse_values(next(&mut children))?,
+                to: self.parse_value(next(&mut children))?,
+            },
+            Rule::store_instruction => Instruction::Store {
+                from: self.parse_value(next(&mut children))?,
+                to: self.parse_value(next(&mut children))?,
+                to_indexes: self.parse_values(next(&mut children))?,
+            },
+            Rule::unary_instruction => Instruction::UnaryOperation {
+                op: parse_unary_operator(next(&mut children)),
+                a: self.parse_value(next(&mut children))?,
+                x: self.parse_value(next(&mut children))?,
+            },
+            Rule::binary_instruction => Instruction::BinaryOperation {
+                op: parse_binary_operator(next(&mut children)),
+                a: self.parse_value(next(&mut children))?,
+                b: self.parse_value(next(&mut children))?,
+                x: self.parse_value(next(&mut children))?,
+            },
+            Rule::select_instruction => Instruction::Select {
+                condition: self.parse_value(next(&mut children))?,
+                a: self.parse_value(next(&mut children))?,
+                b: self.parse_value(next(&mut children))?,
+                x: self.parse_value(next(&mut children))?,
+            },
+            Rule::label_instruction => Instruction::Label(self.lookup_label(next(&mut children))?),
+            Rule::jump_instruction => Instruction::Jump {
+                label: self.lookup_label(next(&mut children))?,
+            },
+            Rule::branch_instruction => Instruction::Branch {
+                condition: self.parse_value(next(&mut children))?,
+                true_target: self.lookup_label(next(&mut children))?,
+                false_target: self.lookup_label(next(&mut children))?,
+            },
+            Rule::abort_instruction => {
+                let code = parse_index(next(&mut children))?;
+                if code == 0 || code > self.program.borrow_errors().len() {
+                    return Err(error_at(&pair, format!("There is no error {}.", code)));
+                }
+                Instruction::Abort(code as u32)
+            }
+            Rule::assume_instruction => Instruction::Assume(self.parse_value(next(&mut children))?),
+            Rule::call_extern_instruction => {
+                let function = parse_index(next(&mut children))?;
+                if function >= self.program.borrow_extern_functions().len() {
+                    return Err(error_at(
+                        &pair,
+                        format!("There is no extern function {}.", function),
+                    ));
+                }
+                Instruction::CallExtern {
+                    function,
+                    inputs: self.parse_values(next(&mut children))?,
+                    outputs: self.parse_values(next(&mut children))?,
+                }
+            }
+            _ => unreachable!("No other instructions in grammar."),
+        })
+    }
+
+    fn parse_instructions(&self, pair: Pair<Rule>) -> Result<Vec<Instruction>, String> {
+        pair.into_inner()
+            .map(|instruction| self.parse_instruction(instruction))
+            .collect()
+    }
+
+    fn parse_body(&mut self, pair: Pair<Rule>) -> Result<(), String> {
+        let rule = pair.as_rule();
+        let mut children = pair.clone().into_inner();
+        match rule {
+            Rule::static_init_body => {
+                for instruction in self.parse_instructions(next(&mut children))? {
+                    self.program.add_static_init_instruction(instruction);
+                }
+            }
+            Rule::main_body => {
+                for instruction in self.parse_instructions(next(&mut children))? {
+                    self.program.add_instruction(instruction);
+                }
+            }
+            Rule::entry_point => {
+                let index = parse_index(next(&mut children))?;
+                let expected = self.program.borrow_entry_points().len();
+                Self::check_index(&pair, index, expected)?;
+                let name = parse_string(next(&mut children))?;
+                let instructions = self.parse_instructions(next(&mut children))?;
+                let entry = self.program.add_entry_point(name);
+                for instruction in instructions {
+                    self.program.add_entry_point_instruction(entry, instruction);
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Reads a program written in the format produced by `print`. Every variable, label, extern
+/// function, error and entry point must be declared with the next unused index of its kind, and
+/// every body can refer to anything declared anywhere in the text. Since addresses cannot be
+/// written in the text, extern functions are given null addresses, so a parsed program which
+/// calls extern functions can be inspected but not run.
+pub fn parse(source: &str) -> Result<Program, String> {
+    let root = match TrivialParser::parse(Rule::root, source) {
+        Ok(mut result) => next(&mut result),
+        Err(err) => return Err(format!("{}", err)),
+    };
+    let items: Vec<_> = root
+        .into_inner()
+        .filter(|item| item.as_rule() != Rule::EOI)
+        .collect();
+    let mut context = Context {
+        program: Program::new(),
+        variables: Vec::new(),
+        labels: Vec::new(),
+        num_entry_points: items
+            .iter()
+            .filter(|item| item.as_rule() == Rule::entry_point)
+            .count(),
+    };
+    // Declarations are handled first so that the bodies can refer to all of them.
+    for item in items.iter() {
+        context.parse_declaration(item.clone())?;
+    }
+    for item in items {
+        context.parse_body(item)?;
+    }
+    Ok(context.program)
+}
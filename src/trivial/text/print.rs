@@ -0,0 +1,120 @@
+use crate::high_level::problem::RuntimeErrorKind;
+use crate::trivial::structure::{CodeBody, DataType, Instruction, Program, StorageLocation};
+use std::fmt::Write;
+
+fn location_text(location: StorageLocation) -> String {
+    match location {
+        StorageLocation::Input => "input".to_owned(),
+        StorageLocation::Output => "output".to_owned(),
+        StorageLocation::Static => "static".to_owned(),
+        StorageLocation::StaticBody => "static_body".to_owned(),
+        StorageLocation::MainBody => "main_body".to_owned(),
+        StorageLocation::EntryInput(index) => format!("entry_input({})", index),
+        StorageLocation::EntryOutput(index) => format!("entry_output({})", index),
+        StorageLocation::EntryBody(index) => format!("entry_body({})", index),
+    }
+}
+
+fn code_body_text(body: CodeBody) -> String {
+    match body {
+        CodeBody::Main => "main".to_owned(),
+        CodeBody::StaticInit => "static_init".to_owned(),
+        CodeBody::EntryPoint(index) => format!("entry({})", index),
+    }
+}
+
+fn error_kind_text(kind: RuntimeErrorKind) -> &'static str {
+    match kind {
+        RuntimeErrorKind::Assert => "assert",
+        RuntimeErrorKind::Bounds => "bounds",
+        RuntimeErrorKind::DivisionByZero => "division_by_zero",
+        RuntimeErrorKind::UserAbort => "user_abort",
+        RuntimeErrorKind::Unknown => "unknown",
+    }
+}
+
+fn type_list_text(types: &[DataType]) -> String {
+    let types: Vec<_> = types.iter().map(|typ| format!("{:?}", typ)).collect();
+    format!("({})", types.join(", "))
+}
+
+fn write_instructions(output: &mut String, header: &str, instructions: &[Instruction]) {
+    writeln!(output, "{} {{", header).unwrap();
+    for instruction in instructions {
+        writeln!(output, "    {:?}", instruction).unwrap();
+    }
+    writeln!(output, "}}").unwrap();
+}
+
+/// Converts a program to the textual format read by `parse`. Declarations come first, in the
+/// order that they were added to the program, followed by the body of each function. The address
+/// of each extern function is not included.
+pub fn print(program: &Program) -> String {
+    let mut output = String::new();
+    for variable in program.iterate_all_variables() {
+        let data = &program[variable];
+        writeln!(
+            output,
+            "var {:?}: {:?} {}",
+            variable,
+            data.borrow_type(),
+            location_text(data.get_location())
+        )
+        .unwrap();
+    }
+    for variable in program.borrow_static_vars() {
+        write!(output, "static {:?}", variable).unwrap();
+        if let Some(name) = program.borrow_static_var_name(*variable) {
+            write!(output, " {:?}", name).unwrap();
+        }
+        if let Some(key) = program.borrow_static_var_key(*variable) {
+            write!(output, " key {:?}", key).unwrap();
+        }
+        writeln!(output).unwrap();
+    }
+    for variable in program.iterate_all_variables() {
+        if let Some(name) = program.borrow_specializable_input_name(variable) {
+            writeln!(output, "specializable {:?} {:?}", variable, name).unwrap();
+        }
+    }
+    for label in program.iterate_all_labels() {
+        let body = program.get_label_body(label);
+        writeln!(output, "label {:?} in {}", label, code_body_text(body)).unwrap();
+    }
+    for (index, function) in program.borrow_extern_functions().iter().enumerate() {
+        writeln!(
+            output,
+            "extern {} {:?} {} -> {}",
+            index,
+            function.borrow_name(),
+            type_list_text(function.borrow_inputs()),
+            type_list_text(function.borrow_outputs())
+        )
+        .unwrap();
+    }
+    for error in program.borrow_errors() {
+        let position = error.borrow_position();
+        writeln!(
+            output,
+            "error {} {} at {}:{}..{} {:?}",
+            error.get_code(),
+            error_kind_text(error.get_kind()),
+            position.get_file(),
+            position.get_start(),
+            position.get_end(),
+            error.borrow_message()
+        )
+        .unwrap();
+    }
+    write_instructions(
+        &mut output,
+        "static_init",
+        program.borrow_static_init_instructions(),
+    );
+    write_instructions(&mut output, "main", program.borrow_instructions());
+    for (index, entry) in program.borrow_entry_points().iter().enumerate() {
+        let header = format!("entry {} {:?}", index, entry.borrow_name());
+        write_instructions(&mut output, &header, entry.borrow_instructions());
+    }
+    output
+}
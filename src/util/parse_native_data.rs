@@ -10,14 +10,22 @@ use crate::vague::structure::KnownData as VagueKnownData;
 #[grammar = "util/known_data.pest"]
 struct KnownDataParser;
 
-fn parse_literal(source: Pair<Rule>) -> KnownData {
-    match source.as_rule() {
+fn parse_int(source: &str, skip: usize, radix: u32) -> Result<KnownData, String> {
+    let digits = source.replace("_", "");
+    i64::from_str_radix(&digits[skip..], radix)
+        .map(KnownData::Int)
+        .map_err(|_| format!("{} is too big to be an int.", source))
+}
+
+fn parse_literal(source: Pair<Rule>) -> Result<KnownData, String> {
+    Ok(match source.as_rule() {
         Rule::neg_float => KnownData::Float(
-            -parse_literal(source.into_inner().next().expect("Required by grammar."))
+            -parse_literal(source.into_inner().next().expect("Required by grammar."))?
                 .require_float(),
         ),
         Rule::neg_int => KnownData::Int(
-            -parse_literal(source.into_inner().next().expect("Required by grammar.")).require_int(),
+            -parse_literal(source.into_inner().next().expect("Required by grammar."))?
+                .require_int(),
         ),
         Rule::float => KnownData::Float(
             source
@@ -26,47 +34,39 @@ fn parse_literal(source: Pair<Rule>) -> KnownData {
                 .parse()
                 .expect("Valid float required by grammar."),
         ),
-        Rule::dec_int => KnownData::Int(
-            source
-                .as_str()
-                .replace("_", "")
-                .parse()
-                .expect("Valid int required by grammar."),
-        ),
-        Rule::hex_int => KnownData::Int(
-            i64::from_str_radix(&source.as_str().replace("_", "")[2..], 16)
-                .expect("Grammar requires valid hexadecimal int."),
-        ),
-        Rule::oct_int => KnownData::Int(
-            i64::from_str_radix(&source.as_str().replace("_", "")[2..], 8)
-                .expect("Grammar requires valid octal int."),
-        ),
-        Rule::legacy_oct_int => KnownData::Int(
-            i64::from_str_radix(&source.as_str().replace("_", "")[1..], 8)
-                .expect("Grammar requires valid octal int."),
-        ),
-        Rule::bin_int => KnownData::Int(
-            i64::from_str_radix(&source.as_str().replace("_", "")[2..], 2)
-                .expect("Grammar requires valid binary int."),
-        ),
+        Rule::dec_int => parse_int(source.as_str(), 0, 10)?,
+        Rule::hex_int => parse_int(source.as_str(), 2, 16)?,
+        Rule::oct_int => parse_int(source.as_str(), 2, 8)?,
+        Rule::legacy_oct_int => parse_int(source.as_str(), 1, 8)?,
+        Rule::bin_int => parse_int(source.as_str(), 2, 2)?,
         Rule::bool_true => KnownData::Bool(true),
         Rule::bool_false => KnownData::Bool(false),
         Rule::array_literal => {
-            let children: Vec<_> = source
+            let text = source.as_str();
+            let children = source
                 .into_inner()
                 .map(|child| parse_literal(child))
-                .collect();
+                .collect::<Result<Vec<_>, _>>()?;
             if children.len() == 0 {
-                panic!("TODO: Nice error, an array cannot have no children.");
-            } else {
-                KnownData::Array(children)
+                return Err(format!(
+                    "The array {} is empty, arrays need at least one item.",
+                    text
+                ));
+            }
+            let first_type = children[0].get_type();
+            if children.iter().any(|child| child.get_type() != first_type) {
+                return Err(format!(
+                    "The items in the array {} do not all have the same type.",
+                    text
+                ));
             }
+            KnownData::Array(children)
         }
         _ => {
             eprintln!("{}", source);
             unreachable!("No other possible children in grammar.")
         }
-    }
+    })
 }
 
 fn convert_native_to_vague(data: &KnownData) -> VagueKnownData {
@@ -94,7 +94,7 @@ pub fn parse_known_data(source: &str) -> Result<VagueKnownData, String> {
         .into_inner()
         .next()
         .expect("Grammar requires a literal.");
-    let native = parse_literal(literal);
+    let native = parse_literal(literal)?;
     Result::Ok(convert_native_to_vague(&native))
 }
 
@@ -111,7 +111,7 @@ pub fn parse_native_data(source: &str) -> Result<KnownData, String> {
         .into_inner()
         .next()
         .expect("Grammar requires a literal.");
-    Result::Ok(parse_literal(literal))
+    parse_literal(literal)
 }
 
 #[cfg(test)]
@@ -137,4 +137,18 @@ mod test {
         parse_known_data("   -0.94981  ")?;
         Result::Ok(())
     }
+
+    #[test]
+    fn bad_array_literal() {
+        assert!(parse_known_data("[]").is_err());
+        assert!(parse_native_data("[[1], []]").is_err());
+        assert!(parse_native_data("[1, 2.0]").is_err());
+        assert!(parse_native_data("[[1], [1, 2]]").is_err());
+    }
+
+    #[test]
+    fn int_literal_too_big() {
+        assert!(parse_known_data("99999999999999999999").is_err());
+        assert!(parse_native_data("0xFFFFFFFFFFFFFFFFFF").is_err());
+    }
 }
@@ -3,21 +3,31 @@ use crate::ast::structure as i;
 use crate::high_level::compiler::{PerformanceCounters, SourceSet};
 use crate::high_level::problem::{CompileProblem, FilePosition};
 use crate::vague::structure as o;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone, Default)]
 pub(super) struct AuxScopeData {
     pub(super) included_files: HashSet<usize>,
+    pub(super) imported_files: HashMap<usize, o::ScopeId>,
+    // Modules imported with a name, along with the file they came from.
+    pub(super) modules: HashMap<String, (o::ScopeId, String)>,
 }
 
 pub(super) struct VagueIngester<'a> {
     pub(super) target: o::Program,
     pub(super) current_scope: o::ScopeId,
     pub(super) current_file_id: usize,
-    pub(super) source_set: &'a SourceSet,
+    pub(super) source_set: &'a mut SourceSet,
     pub(super) perf_counters: &'a mut PerformanceCounters,
     pub(super) aux_scope_data: AuxScopeData,
     aux_scope_stack: Vec<AuxScopeData>,
+    // The root scope of the function currently being converted. This is the root of the program
+    // unless we are inside an entry point.
+    pub(super) function_scope: o::ScopeId,
+    pub(super) entry_point_positions: HashMap<String, FilePosition>,
+    // The root source followed by every file that is currently being imported. Unlike the files
+    // in AuxScopeData, this is shared by every scope, so it can be used to detect import cycles.
+    pub(super) importing_files: Vec<usize>,
 }
 
 impl<'a> VagueIngester<'a> {
@@ -29,6 +39,9 @@ impl<'a> VagueIngester<'a> {
         &self,
         node: &i::Node,
     ) -> Result<o::VariableId, CompileProblem> {
+        if node.as_rule() == i::Rule::qualified_identifier {
+            return self.lookup_qualified_identifier(node);
+        }
         debug_assert!(node.as_rule() == i::Rule::identifier);
         match self.target.lookup_symbol(self.current_scope, node.as_str()) {
             Option::Some(entity) => Result::Ok(entity),
@@ -39,6 +52,39 @@ impl<'a> VagueIngester<'a> {
         }
     }
 
+    fn lookup_qualified_identifier(&self, node: &i::Node) -> Result<o::VariableId, CompileProblem> {
+        let mut children = node.clone().into_inner();
+        let module_node = children.next().expect("bad AST");
+        let name_node = children.next().expect("bad AST");
+        if let Some((module, filename)) = self.aux_scope_data.modules.get(module_node.as_str()) {
+            self.lookup_in_module(*module, filename, &name_node)
+        } else {
+            let position = self.make_position(&module_node);
+            Err(problems::no_module_with_name(
+                position,
+                module_node.as_str(),
+            ))
+        }
+    }
+
+    /// Only finds things defined directly by the module, not builtins or things it can see from
+    /// other places.
+    pub(super) fn lookup_in_module(
+        &self,
+        module: o::ScopeId,
+        filename: &str,
+        name_node: &i::Node,
+    ) -> Result<o::VariableId, CompileProblem> {
+        debug_assert!(name_node.as_rule() == i::Rule::identifier);
+        let name = name_node.as_str();
+        if let Some(id) = self.target[module].borrow_symbols().get(name) {
+            Ok(*id)
+        } else {
+            let position = self.make_position(name_node);
+            Err(problems::not_exported_by_module(position, filename, name))
+        }
+    }
+
     pub(super) fn lookup_identifier_without_error(&self, name: &str) -> Option<o::VariableId> {
         self.target.lookup_symbol(self.current_scope, name)
     }
@@ -95,24 +141,58 @@ impl<'a> VagueIngester<'a> {
         }
         Ok(())
     }
+
+    fn execute_prelude(&mut self, file_index: usize) -> Result<(), CompileProblem> {
+        let content = self.source_set.borrow_source(file_index).1.clone();
+        let timer = std::time::Instant::now();
+        let mut ast = crate::ast::ingest(&content, file_index)?;
+        self.perf_counters.ast.time += timer.elapsed().as_millis();
+        self.perf_counters.ast.num_invocations += 1;
+
+        self.aux_scope_data.included_files.insert(file_index);
+        self.current_file_id = file_index;
+        self.execute(&mut ast)
+    }
 }
 
 pub fn ingest(
     source: &mut i::Program,
-    source_set: &SourceSet,
+    source_id: usize,
+    source_set: &mut SourceSet,
     perf_counters: &mut PerformanceCounters,
+    extern_functions: &[o::ExternFunction],
+    constants: &[(String, o::KnownData)],
+    preludes: &[usize],
 ) -> Result<o::Program, CompileProblem> {
-    let target = o::Program::new();
+    let mut target = o::Program::new();
+    for function in extern_functions {
+        target.add_extern_function(function.clone());
+    }
+    for (name, value) in constants {
+        target.define_constant(name, value.clone());
+    }
+    let builtins_scope = target.get_builtins_scope();
     let init_scope = target.get_entry_point();
     let mut ingester = VagueIngester {
         target,
-        current_scope: init_scope,
-        current_file_id: 1, // 0 is for fake builtin code.
+        current_scope: builtins_scope,
+        current_file_id: source_id,
         source_set,
         perf_counters,
         aux_scope_data: Default::default(),
         aux_scope_stack: Vec::new(),
+        function_scope: init_scope,
+        entry_point_positions: HashMap::new(),
+        importing_files: Vec::new(),
     };
+    // Preludes are converted directly into the builtins scope so that everything they define is
+    // visible to the main source just like the builtins are.
+    for prelude in preludes {
+        ingester.execute_prelude(*prelude)?;
+    }
+    ingester.current_scope = init_scope;
+    ingester.current_file_id = source_id;
+    ingester.importing_files.push(source_id);
     ingester.execute(source)?;
     Ok(ingester.target)
 }
@@ -13,6 +13,34 @@ pub fn no_entity_with_name(pos: FilePosition) -> CompileProblem {
     )])
 }
 
+pub fn no_module_with_name(pos: FilePosition, name: &str) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        pos,
+        Error,
+        &format!(
+            concat!(
+                "Invalid Module Name\nThere is no module named {} visible in this scope. Modules ",
+                "can be given names with statements like import \"file.ns\" as {};",
+            ),
+            name, name
+        ),
+    )])
+}
+
+pub fn not_exported_by_module(pos: FilePosition, filename: &str, name: &str) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        pos,
+        Error,
+        &format!(
+            concat!(
+                "Not Exported\nThe module \"{}\" does not define anything named {}. Only ",
+                "things defined at the top level of a module can be used from outside it.",
+            ),
+            filename, name
+        ),
+    )])
+}
+
 pub fn bad_property_name(pos: FilePosition) -> CompileProblem {
     CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
         pos,
@@ -122,7 +150,8 @@ pub fn io_inside_macro(declaration_pos: FilePosition) -> CompileProblem {
         declaration_pos,
         Error,
         concat!(
-            "I/O Inside Macro\nInput and output variables can only be declared in the root scope.",
+            "I/O Inside Macro\nInput and output variables can only be declared in the root scope ",
+            "or directly inside an entry point.",
         ),
     )])
 }
@@ -141,15 +170,35 @@ pub fn write_to_read_only_variable(var_pos: FilePosition, var_name: &str) -> Com
     )])
 }
 
-pub fn nonexistant_include(include_pos: FilePosition, file_name: &str) -> CompileProblem {
+pub fn nonexistant_include(
+    include_pos: FilePosition,
+    file_name: &str,
+    tried: &[String],
+) -> CompileProblem {
     CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
         include_pos,
         Error,
         &format!(
             concat!(
                 "Nonexistant Include\nCould not find a file named \"{}\". Check the spelling and ",
-                "ensure that the compiler knows that the file exists through Compiler::add_source ",
-                "or another similarly named method.",
+                "ensure that the file exists or was added through Compiler::add_source or another ",
+                "similarly named method. The following paths were tried:\n{}",
+            ),
+            file_name,
+            tried.join("\n")
+        ),
+    )])
+}
+
+pub fn cyclic_import(import_pos: FilePosition, file_name: &str) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        import_pos,
+        Error,
+        &format!(
+            concat!(
+                "Cyclic Import\nThe file \"{}\" ends up importing itself, either directly or ",
+                "through the files it imports. Move the code that the files in the cycle share ",
+                "into a separate file that each of them can import.",
             ),
             file_name
         ),
@@ -166,3 +215,47 @@ pub fn hint_encountered_while_including(
         "Encountered while including this file.",
     ));
 }
+
+pub fn entry_point_not_at_root(entry_pos: FilePosition) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        entry_pos,
+        Error,
+        concat!(
+            "Entry Point Not At Root\nEntry points can only be defined in the root scope, not ",
+            "inside macros, other entry points, or any other code block.",
+        ),
+    )])
+}
+
+pub fn specializable_entry_point_input(input_pos: FilePosition) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![ProblemDescriptor::new(
+        input_pos,
+        Error,
+        concat!(
+            "Specializable Entry Point Input\nOnly inputs of the main program can be ",
+            "specializable, not inputs of entry points.",
+        ),
+    )])
+}
+
+pub fn duplicate_entry_point(
+    entry_pos: FilePosition,
+    original_pos: FilePosition,
+    name: &str,
+) -> CompileProblem {
+    CompileProblem::from_descriptors(vec![
+        ProblemDescriptor::new(
+            entry_pos,
+            Error,
+            &format!(
+                "Duplicate Entry Point\nThere is already an entry point named {}.",
+                name
+            ),
+        ),
+        ProblemDescriptor::new(
+            original_pos,
+            Hint,
+            "The original entry point is defined here:",
+        ),
+    ])
+}
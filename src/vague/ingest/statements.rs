@@ -63,7 +63,11 @@ impl<'a> VagueIngester<'a> {
         }
 
         self.current_scope = old_current_scope;
-        let var = o::Variable::macro_def(o::MacroData::new(body_scope, header_pos));
+        let var = o::Variable::macro_def(o::MacroData::new(
+            body_scope,
+            header_pos,
+            macro_name.to_owned(),
+        ));
         let var_id = self
             .target
             .adopt_and_define_symbol(self.current_scope, macro_name, var);
@@ -108,7 +112,7 @@ impl<'a> VagueIngester<'a> {
     pub(super) fn convert_return_statement(&mut self, node: i::Node) -> Result<(), CompileProblem> {
         debug_assert!(node.as_rule() == i::Rule::return_statement);
         let position = self.make_position(&node);
-        if self.current_scope == self.target.get_entry_point() {
+        if self.current_scope == self.function_scope {
             Err(problems::return_from_root(position))
         } else {
             self.add_statement(o::Statement::Return(position));
@@ -211,16 +215,28 @@ impl<'a> VagueIngester<'a> {
         node: i::Node,
     ) -> Result<(), CompileProblem> {
         debug_assert!(node.as_rule() == i::Rule::input_variable_statement);
-        if self.current_scope != self.target.get_entry_point() {
+        if self.current_scope != self.function_scope {
             return Err(problems::io_inside_macro(self.make_position(&node)));
         }
-        let mut children = node.into_inner();
+        let position = self.make_position(&node);
+        let mut children = node.into_inner().peekable();
+        let specializable =
+            children.peek().expect("bad AST").as_rule() == i::Rule::specializable_keyword;
+        if specializable {
+            children.next();
+            if self.function_scope != self.target.get_entry_point() {
+                return Err(problems::specializable_entry_point_input(position));
+            }
+        }
         let data_type = self.convert_vpe(children.next().expect("bad AST"))?;
         for child in children {
             let pos = self.make_position(&child);
             let name = child.as_str();
             let var_id = self.create_variable(data_type.clone(), name, pos);
             self.target[self.current_scope].add_input(var_id);
+            if specializable {
+                self.target.add_specializable_input(name.to_owned(), var_id);
+            }
         }
         Ok(())
     }
@@ -230,7 +246,7 @@ impl<'a> VagueIngester<'a> {
         node: i::Node,
     ) -> Result<(), CompileProblem> {
         debug_assert!(node.as_rule() == i::Rule::output_variable_statement);
-        if self.current_scope != self.target.get_entry_point() {
+        if self.current_scope != self.function_scope {
             return Err(problems::io_inside_macro(self.make_position(&node)));
         }
         let mut children = node.into_inner();
@@ -244,13 +260,33 @@ impl<'a> VagueIngester<'a> {
         Ok(())
     }
 
-    // TODO: This will result in multiple static variables being created if a function is called
-    // multiple times. Or maybe don't fix it? Maybe make it a feature?
+    pub(super) fn convert_every_statement(&mut self, node: i::Node) -> Result<(), CompileProblem> {
+        debug_assert!(node.as_rule() == i::Rule::every_statement);
+        let position = self.make_position(&node);
+        let mut children = node.into_inner();
+        let interval = self.convert_vpe(children.next().expect("bad AST"))?;
+        let body = self.convert_code_block_in_new_scope(children.next().expect("bad AST"))?;
+        // Values computed in the block are meant to be used by the code after it, so everything
+        // declared inside is visible in the surrounding scope.
+        for (name, id) in self.target[body].borrow_symbols().clone() {
+            self.target[self.current_scope].define_symbol(&name, id);
+        }
+        self.add_statement(o::Statement::Every {
+            interval: Box::new(interval),
+            body,
+            position,
+        });
+        Ok(())
+    }
+
+    // If this is inside a macro, a separate copy of the static variables is created every time
+    // the macro is called.
     pub(super) fn convert_static_variable_statement(
         &mut self,
         node: i::Node,
     ) -> Result<(), CompileProblem> {
         debug_assert!(node.as_rule() == i::Rule::static_variable_statement);
+        let position = FilePosition::from_pair(&node, self.current_file_id);
         let mut exported_vars = Vec::new();
         for child in node.into_inner() {
             if child.as_rule() == i::Rule::identifier {
@@ -266,7 +302,7 @@ impl<'a> VagueIngester<'a> {
                 for (name, pos) in exported_vars {
                     if let Some(id) = self.lookup_identifier_without_error(&name) {
                         self.target[old_scope].define_symbol(&name, id);
-                        exported_ids.push(id);
+                        exported_ids.push((name, id));
                     } else {
                         return Err(problems::missing_export_definition(pos, &name));
                     }
@@ -275,7 +311,7 @@ impl<'a> VagueIngester<'a> {
                 self.add_statement(o::Statement::StaticInit {
                     body: static_scope,
                     exports: exported_ids,
-                    position: FilePosition::placeholder(), // TODO: Better position.
+                    position,
                 });
                 return Ok(());
             } else {
@@ -285,6 +321,43 @@ impl<'a> VagueIngester<'a> {
         unreachable!("bad AST");
     }
 
+    pub(super) fn convert_entry_point_statement(
+        &mut self,
+        node: i::Node,
+    ) -> Result<(), CompileProblem> {
+        debug_assert!(node.as_rule() == i::Rule::entry_point_statement);
+        let position = self.make_position(&node);
+        if self.current_scope != self.target.get_entry_point() {
+            return Err(problems::entry_point_not_at_root(position));
+        }
+        let mut children = node.into_inner();
+        let name_node = children.next().expect("bad AST");
+        let name = name_node.as_str().to_owned();
+        let name_pos = self.make_position(&name_node);
+        if let Some(original_pos) = self.entry_point_positions.get(&name) {
+            return Err(problems::duplicate_entry_point(
+                name_pos,
+                original_pos.clone(),
+                &name,
+            ));
+        }
+        self.entry_point_positions.insert(name.clone(), name_pos);
+
+        let body_scope = self.target.create_child_scope(self.current_scope);
+        let old_scope = self.current_scope;
+        self.current_scope = body_scope;
+        self.function_scope = body_scope;
+        self.convert_code_block(children.next().expect("bad AST"))?;
+        self.current_scope = old_scope;
+        self.function_scope = old_scope;
+        self.add_statement(o::Statement::EntryPoint {
+            name,
+            body: body_scope,
+            position,
+        });
+        Ok(())
+    }
+
     pub(super) fn convert_assign_statement(&mut self, node: i::Node) -> Result<(), CompileProblem> {
         debug_assert!(node.as_rule() == i::Rule::assign_statement);
         let position = self.make_position(&node);
@@ -305,40 +378,115 @@ impl<'a> VagueIngester<'a> {
         snailquote::unescape(text).expect("bad AST")
     }
 
+    fn find_included_file(
+        &mut self,
+        filename: &str,
+        position: &FilePosition,
+    ) -> Result<usize, CompileProblem> {
+        self.source_set
+            .find_or_load_include(self.current_file_id, filename)
+            .map_err(|tried| problems::nonexistant_include(position.clone(), filename, &tried))
+    }
+
+    // Converts all the code in the specified file into the current scope.
+    fn execute_included_file(
+        &mut self,
+        file_index: usize,
+        position: FilePosition,
+    ) -> Result<(), CompileProblem> {
+        let content = self.source_set.borrow_source(file_index).1.clone();
+        let timer = std::time::Instant::now();
+        let mut ast = match crate::ast::ingest(&content, file_index) {
+            Ok(ast) => ast,
+            Err(mut problem) => {
+                problems::hint_encountered_while_including(&mut problem, position);
+                return Err(problem);
+            }
+        };
+
+        self.perf_counters.ast.time += timer.elapsed().as_millis();
+        self.perf_counters.ast.num_invocations += 1;
+
+        let old_file_id = self.current_file_id;
+        self.current_file_id = file_index;
+        if let Err(mut problem) = self.execute(&mut ast) {
+            problems::hint_encountered_while_including(&mut problem, position);
+            return Err(problem);
+        }
+        self.current_file_id = old_file_id;
+        Ok(())
+    }
+
     fn convert_include_statement(&mut self, node: i::Node) -> Result<(), CompileProblem> {
         debug_assert!(node.as_rule() == i::Rule::include_statement);
         let position = self.make_position(&node);
         let mut children = node.into_inner();
         let filename_node = children.next().expect("bad AST");
         let filename = self.convert_string_literal(filename_node);
-        if let Some(file_index) = self.source_set.find_source(&filename) {
-            if self.aux_scope_data.included_files.contains(&file_index) {
-                // Don't include the file a second time.
-                return Ok(());
-            }
-            self.aux_scope_data.included_files.insert(file_index);
-            let (_name, content) = self.source_set.borrow_source(file_index);
-            let timer = std::time::Instant::now();
-            let mut ast = match crate::ast::ingest(content, file_index) {
-                Ok(ast) => ast,
-                Err(mut problem) => {
-                    problems::hint_encountered_while_including(&mut problem, position);
-                    return Err(problem);
-                }
-            };
+        let file_index = self.find_included_file(&filename, &position)?;
+        if self.aux_scope_data.included_files.contains(&file_index) {
+            // Don't include the file a second time.
+            return Ok(());
+        }
+        self.aux_scope_data.included_files.insert(file_index);
+        self.execute_included_file(file_index, position)
+    }
 
-            self.perf_counters.ast.time += timer.elapsed().as_millis();
-            self.perf_counters.ast.num_invocations += 1;
+    // Converts the file into a separate scope so that its definitions do not collide with the
+    // ones in the current scope. The code itself still runs where the import statement is.
+    fn import_module(
+        &mut self,
+        filename: &str,
+        position: FilePosition,
+    ) -> Result<o::ScopeId, CompileProblem> {
+        let file_index = self.find_included_file(filename, &position)?;
+        if let Some(module) = self.aux_scope_data.imported_files.get(&file_index) {
+            return Ok(*module);
+        }
+        if self.importing_files.contains(&file_index) {
+            return Err(problems::cyclic_import(position, filename));
+        }
+        let module = self.target.create_scope();
+        let old_scope = self.current_scope;
+        let old_aux_data = std::mem::take(&mut self.aux_scope_data);
+        self.current_scope = module;
+        self.aux_scope_data.included_files.insert(file_index);
+        self.importing_files.push(file_index);
+        let result = self.execute_included_file(file_index, position);
+        self.importing_files.pop();
+        self.current_scope = old_scope;
+        self.aux_scope_data = old_aux_data;
+        result?;
 
-            let old_file_id = self.current_file_id;
-            self.current_file_id = file_index;
-            if let Err(mut problem) = self.execute(&mut ast) {
-                problems::hint_encountered_while_including(&mut problem, position);
-                return Err(problem);
+        for statement in self.target[module].take_body() {
+            self.add_statement(statement);
+        }
+        self.aux_scope_data
+            .imported_files
+            .insert(file_index, module);
+        Ok(module)
+    }
+
+    fn convert_import_statement(&mut self, node: i::Node) -> Result<(), CompileProblem> {
+        debug_assert!(node.as_rule() == i::Rule::import_statement);
+        let position = self.make_position(&node);
+        let mut children = node.into_inner();
+        let first_child = children.next().expect("bad AST");
+        if first_child.as_rule() == i::Rule::import_names {
+            let filename = self.convert_string_literal(children.next().expect("bad AST"));
+            let module = self.import_module(&filename, position)?;
+            for name_node in first_child.into_inner() {
+                let name = name_node.as_str();
+                let id = self.lookup_in_module(module, &filename, &name_node)?;
+                self.target[self.current_scope].define_symbol(name, id);
             }
-            self.current_file_id = old_file_id;
         } else {
-            return Err(problems::nonexistant_include(position, &filename));
+            let filename = self.convert_string_literal(first_child);
+            let alias = children.next().expect("bad AST").as_str();
+            let module = self.import_module(&filename, position)?;
+            self.aux_scope_data
+                .modules
+                .insert(alias.to_owned(), (module, filename));
         }
         Ok(())
     }
@@ -353,9 +501,11 @@ impl<'a> VagueIngester<'a> {
             i::Rule::assert_statement => self.convert_assert_statement(child)?,
             i::Rule::if_statement => self.convert_if_statement(child)?,
             i::Rule::for_loop_statement => self.convert_for_loop_statement(child)?,
+            i::Rule::every_statement => self.convert_every_statement(child)?,
             i::Rule::input_variable_statement => self.convert_input_variable_statement(child)?,
             i::Rule::output_variable_statement => self.convert_output_variable_statement(child)?,
             i::Rule::static_variable_statement => self.convert_static_variable_statement(child)?,
+            i::Rule::entry_point_statement => self.convert_entry_point_statement(child)?,
             i::Rule::assign_statement => self.convert_assign_statement(child)?,
             i::Rule::macro_call => {
                 let expr = self.convert_macro_call(child, false)?;
@@ -365,6 +515,7 @@ impl<'a> VagueIngester<'a> {
                 self.convert_var_dec(child)?;
             }
             i::Rule::include_statement => self.convert_include_statement(child)?,
+            i::Rule::import_statement => self.convert_import_statement(child)?,
             _ => unreachable!("bad AST"),
         }
         Ok(())
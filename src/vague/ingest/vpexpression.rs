@@ -223,6 +223,8 @@ impl<'a> VagueIngester<'a> {
         let name_node = children.next().expect("bad AST");
         let input_list_node = children.next().expect("bad AST");
         let maybe_output_list_node = children.next();
+        let text = format!("{}{}", name_node.as_str(), input_list_node.as_str());
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
 
         let input_list = self.convert_macro_call_input_list(input_list_node)?;
         let output_list = if let Some(output_list_node) = maybe_output_list_node {
@@ -262,6 +264,7 @@ impl<'a> VagueIngester<'a> {
             )),
             inputs: input_list,
             outputs: output_list,
+            text,
             position,
         })
     }
@@ -1,7 +1,7 @@
 use crate::high_level::problem::FilePosition;
 use crate::vague::structure::{
-    DataType, KnownData, MacroData, Program, Statement, UnaryOperator, VCExpression, VPExpression,
-    Variable,
+    DataType, ExternFunction, KnownData, MacroData, Program, Statement, UnaryOperator,
+    VCExpression, VPExpression, Variable,
 };
 
 fn add_data_type(program: &mut Program, name: &str, dtype: DataType) {
@@ -19,7 +19,7 @@ fn add_data_type(program: &mut Program, name: &str, dtype: DataType) {
     });
 }
 
-fn add_constant(program: &mut Program, name: &str, data: KnownData) {
+pub(super) fn add_constant(program: &mut Program, name: &str, data: KnownData) {
     let scope = program.get_builtins_scope();
     let typ = data.get_data_type();
     let var = Variable::constant(FilePosition::placeholder(), data);
@@ -70,7 +70,106 @@ fn add_unary_op_macro(
         position: p.clone(),
     });
 
-    let var = Variable::macro_def(MacroData::new(body, p.clone()));
+    let var = Variable::macro_def(MacroData::new(body, p.clone(), name.to_owned()));
+    let var_id = program.adopt_and_define_symbol(root, name, var);
+    let data_type_literal = Box::new(VPExpression::Literal(
+        KnownData::DataType(DataType::Macro),
+        FilePosition::placeholder(),
+    ));
+    program[root].add_statement(Statement::CreationPoint {
+        var: var_id,
+        var_type: data_type_literal,
+        position: FilePosition::placeholder(),
+    });
+}
+
+fn add_select_macro(program: &mut Program) {
+    let root = program.get_builtins_scope();
+    let body = program.create_child_scope(root);
+    let p = FilePosition::placeholder();
+
+    let mut inputs = Vec::new();
+    for in_name in &["condition", "a", "b"] {
+        let in_var = Variable::variable(p.clone(), None);
+        let in_var_id = program.adopt_and_define_symbol(body, in_name, in_var);
+        program[body].add_input(in_var_id);
+        inputs.push(Box::new(VPExpression::Variable(in_var_id, p.clone())));
+    }
+    let out_var = Variable::variable(p.clone(), None);
+    let out_var_id = program.adopt_and_define_symbol(body, "result", out_var);
+    program[body].add_output(out_var_id);
+    let out_type = VPExpression::Literal(KnownData::DataType(DataType::Automatic), p.clone());
+    program[body].add_statement(Statement::CreationPoint {
+        var: out_var_id,
+        var_type: Box::new(out_type),
+        position: p.clone(),
+    });
+
+    let mut inputs = inputs.into_iter();
+    program[body].add_statement(Statement::Assign {
+        target: Box::new(VCExpression::Variable(out_var_id, p.clone())),
+        value: Box::new(VPExpression::Select {
+            condition: inputs.next().unwrap(),
+            a: inputs.next().unwrap(),
+            b: inputs.next().unwrap(),
+            position: p.clone(),
+        }),
+        position: p.clone(),
+    });
+
+    let var = Variable::macro_def(MacroData::new(body, p.clone(), "Select".to_owned()));
+    let var_id = program.adopt_and_define_symbol(root, "Select", var);
+    let data_type_literal = Box::new(VPExpression::Literal(
+        KnownData::DataType(DataType::Macro),
+        FilePosition::placeholder(),
+    ));
+    program[root].add_statement(Statement::CreationPoint {
+        var: var_id,
+        var_type: data_type_literal,
+        position: FilePosition::placeholder(),
+    });
+}
+
+pub(super) fn add_extern_function_macro(
+    program: &mut Program,
+    index: usize,
+    function: &ExternFunction,
+) {
+    let root = program.get_builtins_scope();
+    let body = program.create_child_scope(root);
+    let p = FilePosition::placeholder();
+
+    let mut inputs = Vec::new();
+    for input_index in 0..function.borrow_inputs().len() {
+        let in_var = Variable::variable(p.clone(), None);
+        let name = format!("input{}", input_index);
+        let in_var_id = program.adopt_and_define_symbol(body, &name, in_var);
+        program[body].add_input(in_var_id);
+        inputs.push(in_var_id);
+    }
+    let mut outputs = Vec::new();
+    for (output_index, typ) in function.borrow_outputs().iter().enumerate() {
+        let out_var = Variable::variable(p.clone(), None);
+        let name = format!("output{}", output_index);
+        let out_var_id = program.adopt_and_define_symbol(body, &name, out_var);
+        program[body].add_output(out_var_id);
+        let out_type = VPExpression::Literal(KnownData::DataType(typ.clone()), p.clone());
+        program[body].add_statement(Statement::CreationPoint {
+            var: out_var_id,
+            var_type: Box::new(out_type),
+            position: p.clone(),
+        });
+        outputs.push(out_var_id);
+    }
+    program[body].add_statement(Statement::CallExtern {
+        function: index,
+        inputs,
+        outputs,
+        position: p.clone(),
+    });
+
+    let name = function.borrow_name();
+    let var = Variable::macro_def(MacroData::new(body, p.clone(), name.clone()));
     let var_id = program.adopt_and_define_symbol(root, name, var);
     let data_type_literal = Box::new(VPExpression::Literal(
         KnownData::DataType(DataType::Macro),
@@ -118,4 +217,5 @@ pub fn add_builtins(program: &mut Program) {
     add_unary_op_macro(program, UnaryOperator::Floor, "Floor", "value", "result");
     add_unary_op_macro(program, UnaryOperator::Ceiling, "Ceil", "value", "result");
     add_unary_op_macro(program, UnaryOperator::Truncate, "Trunc", "value", "result");
+    add_select_macro(program);
 }
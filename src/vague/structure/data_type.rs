@@ -62,6 +62,16 @@ impl DataType {
         }
     }
 
+    /// Returns true if values of this type can exist at run time, I.E. it is made of bools, ints,
+    /// and floats.
+    pub fn is_runtime(&self) -> bool {
+        match self {
+            Self::Bool | Self::Int | Self::Float => true,
+            Self::Array(_, etype) => etype.is_runtime(),
+            _ => false,
+        }
+    }
+
     pub fn with_different_base(&self, new_base: DataType) -> Self {
         match self {
             Self::Array(size, etyp) => {
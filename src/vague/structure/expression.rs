@@ -152,6 +152,14 @@ pub enum VPExpression {
         Box<VPExpression>,
         FilePosition,
     ),
+    /// Element-wise, picks `a` where `condition` is true and `b` where it is false. Only appears
+    /// in the body of the builtin `Select` macro.
+    Select {
+        condition: Box<VPExpression>,
+        a: Box<VPExpression>,
+        b: Box<VPExpression>,
+        position: FilePosition,
+    },
     Index {
         base: Box<VPExpression>,
         indexes: Vec<(VPExpression, bool)>,
@@ -161,6 +169,9 @@ pub enum VPExpression {
         mcro: Box<VPExpression>,
         inputs: Vec<VPExpression>,
         outputs: Vec<FuncCallOutput>,
+        // The name of the macro and the inputs it is called with, as written in the source. Used
+        // to name the static variables the call creates.
+        text: String,
         position: FilePosition,
     },
 }
@@ -192,6 +203,11 @@ impl Debug for VPExpression {
             Self::BinaryOperation(v1, operator, v2, ..) => {
                 write!(formatter, "({:?} {:?} {:?})", v1, operator, v2)
             }
+            Self::Select {
+                condition, a, b, ..
+            } => {
+                write!(formatter, "select({:?}, {:?}, {:?})", condition, a, b)
+            }
             Self::Index { base, indexes, .. } => {
                 write!(formatter, "{:?}", base)?;
                 for (index, optional) in indexes.iter() {
@@ -234,6 +250,7 @@ impl VPExpression {
             | Self::BuildArrayType { position, .. }
             | Self::UnaryOperation(_, _, position)
             | Self::BinaryOperation(_, _, _, position)
+            | Self::Select { position, .. }
             | Self::Index { position, .. }
             | Self::MacroCall { position, .. } => position.clone(),
         }
@@ -329,9 +346,32 @@ pub enum Statement {
         body: ScopeId,
         position: FilePosition,
     },
+    /// Runs the body once every `interval` times the statement is reached. Variables declared in
+    /// the body are stored statically so their values persist between the runs.
+    Every {
+        interval: Box<VPExpression>,
+        body: ScopeId,
+        position: FilePosition,
+    },
     StaticInit {
         body: ScopeId,
-        exports: Vec<VariableId>,
+        /// The name each exported variable is visible as outside the static block.
+        exports: Vec<(String, VariableId)>,
+        position: FilePosition,
+    },
+    /// An additional function which can be called by the host, sharing static data with the main
+    /// body. Its inputs and outputs are those of the body scope.
+    EntryPoint {
+        name: String,
+        body: ScopeId,
+        position: FilePosition,
+    },
+    /// Calls the extern function with the specified index. This only appears in the bodies of the
+    /// builtin macros created for extern functions.
+    CallExtern {
+        function: usize,
+        inputs: Vec<VariableId>,
+        outputs: Vec<VariableId>,
         position: FilePosition,
     },
     RawVPExpression(Box<VPExpression>),
@@ -377,11 +417,27 @@ impl Debug for Statement {
                 if *allow_unroll { "" } else { " no_unroll" },
                 body
             ),
+            Self::Every { interval, body, .. } => {
+                write!(formatter, "every {:?} {{ {:?} }}", interval, body)
+            }
             Self::StaticInit { body, exports, .. } => write!(
                 formatter,
                 "static init at {:?}, exports {:?};",
                 body, exports
             ),
+            Self::EntryPoint { name, body, .. } => {
+                write!(formatter, "entry point {} at {:?};", name, body)
+            }
+            Self::CallExtern {
+                function,
+                inputs,
+                outputs,
+                ..
+            } => write!(
+                formatter,
+                "call extern {} {:?} -> {:?};",
+                function, inputs, outputs
+            ),
             Self::RawVPExpression(expr) => write!(formatter, "{:?}", expr),
         }
     }
@@ -396,7 +452,10 @@ impl Statement {
             | Self::Assign { position, .. }
             | Self::Branch { position, .. }
             | Self::ForLoop { position, .. } => position.clone(),
+            Self::Every { position, .. } => position.clone(),
             Self::StaticInit { position, .. } => position.clone(),
+            Self::EntryPoint { position, .. } => position.clone(),
+            Self::CallExtern { position, .. } => position.clone(),
             Self::RawVPExpression(expr) => expr.clone_position(),
         }
     }
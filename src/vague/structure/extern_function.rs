@@ -0,0 +1,68 @@
+use super::DataType;
+use std::ffi::c_void;
+use std::fmt::{self, Debug, Formatter};
+
+/// A function provided by the host program which can be called from Nodespeak code like a macro.
+///
+/// The function is called with the C calling convention. Each input of a basic type is passed by
+/// value, each array input is passed as a pointer to its data, and each output is passed as a
+/// pointer which the function should write the result to. Inputs come before outputs. Nothing is
+/// returned.
+#[derive(Clone)]
+pub struct ExternFunction {
+    name: String,
+    inputs: Vec<DataType>,
+    outputs: Vec<DataType>,
+    address: *const c_void,
+}
+
+impl Debug for ExternFunction {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "extern {}{:?} -> {:?} at {:?}",
+            self.name, self.inputs, self.outputs, self.address
+        )
+    }
+}
+
+impl ExternFunction {
+    /// Panics if any of the inputs or outputs have a type which cannot exist at run time.
+    pub fn new(
+        name: String,
+        inputs: Vec<DataType>,
+        outputs: Vec<DataType>,
+        address: *const c_void,
+    ) -> ExternFunction {
+        for typ in inputs.iter().chain(outputs.iter()) {
+            assert!(
+                typ.is_runtime(),
+                "Extern function {} cannot use the compile time only type {:?}.",
+                name,
+                typ
+            );
+        }
+        ExternFunction {
+            name,
+            inputs,
+            outputs,
+            address,
+        }
+    }
+
+    pub fn borrow_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn borrow_inputs(&self) -> &Vec<DataType> {
+        &self.inputs
+    }
+
+    pub fn borrow_outputs(&self) -> &Vec<DataType> {
+        &self.outputs
+    }
+
+    pub fn get_address(&self) -> *const c_void {
+        self.address
+    }
+}
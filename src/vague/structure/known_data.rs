@@ -8,6 +8,7 @@ use std::fmt::{self, Debug, Formatter};
 pub struct MacroData {
     body: ScopeId,
     header: FilePosition,
+    name: String,
     // Yeah, this is really ugly and hacky. But unfortunately I don't see a better way to allow
     // storing the scope a macro was defined in without creating even more of a headache.
     context: crate::resolved::ResolverTable,
@@ -20,10 +21,11 @@ impl PartialEq for MacroData {
 }
 
 impl MacroData {
-    pub fn new(body: ScopeId, header: FilePosition) -> MacroData {
+    pub fn new(body: ScopeId, header: FilePosition, name: String) -> MacroData {
         MacroData {
             body,
             header,
+            name,
             context: crate::resolved::ResolverTable::new(),
         }
     }
@@ -36,6 +38,11 @@ impl MacroData {
         &self.header
     }
 
+    /// The name the macro was given when it was defined.
+    pub fn borrow_name(&self) -> &str {
+        &self.name
+    }
+
     pub fn get_body(&self) -> ScopeId {
         self.body
     }
@@ -212,7 +219,11 @@ impl Debug for KnownData {
                 write!(formatter, "]")
             }
             KnownData::DataType(value) => write!(formatter, "{:?}", value),
-            KnownData::Macro(value) => write!(formatter, "macro with body at {:?}", value.body),
+            KnownData::Macro(value) => write!(
+                formatter,
+                "macro {} with body at {:?}",
+                value.name, value.body
+            ),
         }
     }
 }
@@ -1,6 +1,7 @@
 mod builtins;
 mod data_type;
 mod expression;
+mod extern_function;
 mod known_data;
 mod program;
 mod scope;
@@ -9,6 +10,7 @@ mod variable;
 pub use builtins::*;
 pub use data_type::*;
 pub use expression::*;
+pub use extern_function::*;
 pub use known_data::*;
 pub use program::*;
 pub use scope::*;
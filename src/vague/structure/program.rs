@@ -1,5 +1,5 @@
 use crate::high_level::problem::FilePosition;
-use crate::vague::structure::{self, Scope, Variable};
+use crate::vague::structure::{self, ExternFunction, KnownData, Scope, Variable};
 use std::fmt::{self, Debug, Formatter};
 use std::ops::{Index, IndexMut};
 
@@ -31,6 +31,8 @@ pub struct Program {
     builtins_scope: ScopeId,
     entry_point: ScopeId,
     variables: Vec<Variable>,
+    extern_functions: Vec<ExternFunction>,
+    specializable_inputs: Vec<(String, VariableId)>,
 }
 
 impl Debug for Program {
@@ -46,6 +48,9 @@ impl Debug for Program {
             )?;
         }
         writeln!(formatter, "\n")?;
+        for (index, function) in self.extern_functions.iter().enumerate() {
+            writeln!(formatter, "extern function {}: {:?}", index, function)?;
+        }
         for (index, variable) in self.variables.iter().enumerate() {
             writeln!(
                 formatter,
@@ -93,6 +98,8 @@ impl Program {
             builtins_scope: ScopeId(0),
             entry_point: ScopeId(1),
             variables: Vec::new(),
+            extern_functions: Vec::new(),
+            specializable_inputs: Vec::new(),
         };
         structure::add_builtins(&mut prog);
         prog
@@ -132,6 +139,55 @@ impl Program {
         id
     }
 
+    /// Makes the function callable from code in this program as a macro with the same name.
+    /// Returns the index of the function.
+    pub fn add_extern_function(&mut self, function: ExternFunction) -> usize {
+        let index = self.extern_functions.len();
+        structure::add_extern_function_macro(self, index, &function);
+        self.extern_functions.push(function);
+        index
+    }
+
+    /// Defines a constant in the builtins scope, making it visible from anywhere in the program
+    /// in the same way as builtins like `PI`. Defining a `DataType` value creates a type alias.
+    pub fn define_constant(&mut self, name: &str, value: KnownData) {
+        if let KnownData::Macro(..) = &value {
+            panic!("Macros cannot be defined as constants, define them in a prelude instead.");
+        }
+        structure::add_constant(self, name, value);
+    }
+
+    pub fn borrow_extern_functions(&self) -> &Vec<ExternFunction> {
+        &self.extern_functions
+    }
+
+    /// Marks an input of the program as one which the host can ask to be treated as a compile
+    /// time constant through `specialize_input`.
+    pub fn add_specializable_input(&mut self, name: String, input: VariableId) {
+        self.specializable_inputs.push((name, input));
+    }
+
+    pub fn borrow_specializable_inputs(&self) -> &Vec<(String, VariableId)> {
+        &self.specializable_inputs
+    }
+
+    /// Makes the specializable input with the given name have a known value at compile time. The
+    /// input is still part of the program's input data, but its value there is ignored. Returns
+    /// false if there is no specializable input with that name.
+    pub fn specialize_input(&mut self, name: &str, value: KnownData) -> bool {
+        let input = self
+            .specializable_inputs
+            .iter()
+            .find(|(input_name, _)| input_name == name)
+            .map(|(_, input)| *input);
+        if let Some(input) = input {
+            self[input].set_initial_value(Some(value));
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn get_entry_point(&self) -> ScopeId {
         self.entry_point
     }
@@ -93,6 +93,10 @@ impl Scope {
         self.outputs.push(output);
     }
 
+    pub fn take_body(&mut self) -> Vec<Statement> {
+        std::mem::take(&mut self.body)
+    }
+
     pub fn borrow_body(&self) -> &Vec<Statement> {
         &self.body
     }
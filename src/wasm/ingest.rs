@@ -0,0 +1,1060 @@
+use super::instruction::{self as w, Instruction as W, MemoryOp, ValueType};
+use super::structure as o;
+use crate::shared::{self, ProxyMode};
+use crate::trivial::structure as i;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Math functions which have no equivalent instruction, so they are imported from the `math`
+/// module instead. Each one takes the specified number of f32 arguments and returns an f32.
+const MATH_IMPORTS: &[(&str, usize)] = &[
+    ("sin", 1),
+    ("cos", 1),
+    ("exp", 1),
+    ("exp2", 1),
+    ("log", 1),
+    ("log10", 1),
+    ("log2", 1),
+    ("pow", 2),
+    ("fmod", 2),
+];
+
+fn math_function(name: &str) -> u32 {
+    MATH_IMPORTS
+        .iter()
+        .position(|(import, _)| *import == name)
+        .expect("Not a math import.") as u32
+}
+
+fn type_size(typ: &i::DataType) -> u32 {
+    match typ {
+        i::DataType::B1 => 1,
+        i::DataType::I32 | i::DataType::F32 => 4,
+        i::DataType::Array(len, etype) => *len as u32 * type_size(etype),
+    }
+}
+
+fn type_alignment(typ: &i::DataType) -> u32 {
+    match typ {
+        i::DataType::B1 => 1,
+        i::DataType::I32 | i::DataType::F32 => 4,
+        i::DataType::Array(_, etype) => type_alignment(etype),
+    }
+}
+
+fn base_type(typ: &i::DataType) -> &i::DataType {
+    match typ {
+        i::DataType::Array(_, etype) => base_type(etype),
+        _ => typ,
+    }
+}
+
+fn value_type(typ: &i::DataType) -> ValueType {
+    match base_type(typ) {
+        i::DataType::F32 => ValueType::F32,
+        _ => ValueType::I32,
+    }
+}
+
+// Bools take up a full byte in memory which is either 0 or 1, the same as in the LLVM version.
+fn load_op(typ: &i::DataType) -> MemoryOp {
+    match base_type(typ) {
+        i::DataType::B1 => MemoryOp::I32Load8U,
+        i::DataType::I32 => MemoryOp::I32Load,
+        _ => MemoryOp::F32Load,
+    }
+}
+
+fn store_op(typ: &i::DataType) -> MemoryOp {
+    match base_type(typ) {
+        i::DataType::B1 => MemoryOp::I32Store8,
+        i::DataType::I32 => MemoryOp::I32Store,
+        _ => MemoryOp::F32Store,
+    }
+}
+
+fn align(value: u32, alignment: u32) -> u32 {
+    value.div_ceil(alignment) * alignment
+}
+
+/// Assigns an offset to every variable stored in `location`, laid out the same way LLVM lays out
+/// packed or regular structs. Returns the size of the whole struct.
+fn layout_struct(
+    source: &i::Program,
+    location: i::StorageLocation,
+    packed: bool,
+    offsets: &mut HashMap<i::VariableId, u32>,
+) -> u32 {
+    let mut size = 0;
+    let mut max_alignment = 1;
+    for var_id in source.iterate_all_variables() {
+        let typ = source[var_id].borrow_type();
+        if source[var_id].get_location() != location {
+            continue;
+        }
+        if !packed {
+            size = align(size, type_alignment(typ));
+            max_alignment = max_alignment.max(type_alignment(typ));
+        }
+        offsets.insert(var_id, size);
+        size += type_size(typ);
+    }
+    if !packed {
+        size = align(size, max_alignment);
+    }
+    size
+}
+
+#[derive(Clone, Copy)]
+enum Storage {
+    /// A local of the function being written.
+    Local(u32),
+    /// Memory at a constant offset from the address held in the specified parameter, or from
+    /// address 0 if there is no parameter.
+    Memory(Option<u32>, u32),
+}
+
+enum Index<'v> {
+    Static(u32),
+    Dynamic(&'v i::Value),
+}
+
+fn static_indexes<'v>(coord: &[usize]) -> Vec<Index<'v>> {
+    coord
+        .iter()
+        .map(|index| Index::Static(*index as u32))
+        .collect()
+}
+
+fn dynamic_index(value: &i::Value) -> Index<'_> {
+    match &value.base {
+        i::ValueBase::Literal(i::KnownData::Int(index)) => Index::Static(*index as u32),
+        _ => Index::Dynamic(value),
+    }
+}
+
+fn apply_proxy<'v>(value: &i::Value, indexes: Vec<Index<'v>>) -> Vec<Index<'v>> {
+    debug_assert!(value.dimensions.len() == indexes.len());
+    let mut result = Vec::new();
+    for ((_, mode), index) in value.dimensions.iter().zip(indexes) {
+        match mode {
+            ProxyMode::Keep => result.push(index),
+            ProxyMode::Collapse => result.push(Index::Static(0)),
+            ProxyMode::Discard => (),
+        }
+    }
+    result
+}
+
+fn coordinates(value: &i::Value) -> shared::NDIndexIter {
+    shared::NDIndexIter::new(value.dimensions.iter().map(|(len, _)| *len).collect())
+}
+
+enum StoreTarget {
+    Local(u32),
+    Memory(MemoryOp, u32),
+}
+
+struct Function {
+    export_name: String,
+    params: Vec<ValueType>,
+    locals: Vec<ValueType>,
+    instructions: Vec<W>,
+}
+
+struct Converter<'a> {
+    source: &'a i::Program,
+    /// The offset of every variable in the input, output, and static data.
+    struct_offsets: HashMap<i::VariableId, u32>,
+    /// Variables which are passed to extern functions by pointer, so they have to be in memory.
+    addressed: HashSet<i::VariableId>,
+    /// The address of each array literal which is indexed at runtime, keyed by its contents.
+    literal_addresses: HashMap<String, u32>,
+    /// Where variables that only exist while a function is running are stored. Every function
+    /// uses the same space since only one runs at a time.
+    scratch_base: u32,
+    scratch_size: u32,
+
+    storage: HashMap<i::VariableId, Storage>,
+    num_params: u32,
+    locals: Vec<ValueType>,
+    instructions: Vec<W>,
+    /// Which segment of the function should run next, see `convert_body`.
+    state_local: u32,
+    temp_i32: u32,
+    temp_f32s: (u32, u32),
+    label_segments: HashMap<i::LabelId, u32>,
+    num_segments: u32,
+    current_segment: u32,
+}
+
+impl<'a> Converter<'a> {
+    fn push(&mut self, instruction: W) {
+        self.instructions.push(instruction);
+    }
+
+    fn add_local(&mut self, typ: ValueType) -> u32 {
+        self.locals.push(typ);
+        self.num_params + self.locals.len() as u32 - 1
+    }
+
+    /// Pushes the address of an element of a variable, returning the constant offset which should
+    /// be added to it.
+    fn emit_address(
+        &mut self,
+        base: Option<u32>,
+        offset: u32,
+        typ: &i::DataType,
+        indexes: &[Index],
+    ) -> u32 {
+        match base {
+            Some(param) => self.push(W::LocalGet(param)),
+            None => self.push(W::I32Const(0)),
+        }
+        let mut offset = offset;
+        let mut typ = typ;
+        for index in indexes {
+            let etype = if let i::DataType::Array(_, etype) = typ {
+                etype
+            } else {
+                unreachable!("Illegal indexes should be caught earlier.")
+            };
+            let stride = type_size(etype);
+            match index {
+                Index::Static(index) => offset += index * stride,
+                Index::Dynamic(value) => {
+                    self.emit_load(value, Vec::new());
+                    self.push(W::I32Const(stride as i32));
+                    self.push(w::I32_MUL);
+                    self.push(w::I32_ADD);
+                }
+            }
+            typ = etype;
+        }
+        offset
+    }
+
+    fn emit_constant(&mut self, data: &i::KnownData) {
+        match data {
+            i::KnownData::Bool(value) => self.push(W::I32Const(if *value { 1 } else { 0 })),
+            i::KnownData::Int(value) => self.push(W::I32Const(*value as i32)),
+            i::KnownData::Float(value) => self.push(W::F32Const(*value as f32)),
+            i::KnownData::Array(..) => unreachable!("Arrays cannot be pushed on the stack."),
+        }
+    }
+
+    /// Pushes the element of `value` at `indexes`, which should contain one index for every
+    /// dimension of the value before its proxy is applied.
+    fn emit_load(&mut self, value: &i::Value, indexes: Vec<Index>) {
+        let indexes = apply_proxy(value, indexes);
+        let source = self.source;
+        match &value.base {
+            i::ValueBase::Variable(id) => {
+                let typ = source[*id].borrow_type();
+                match self.storage[id] {
+                    Storage::Local(local) => {
+                        assert!(indexes.is_empty(), "Cannot index scalar data.");
+                        self.push(W::LocalGet(local));
+                    }
+                    Storage::Memory(base, offset) => {
+                        let offset = self.emit_address(base, offset, typ, &indexes);
+                        self.push(W::Memory(load_op(typ), offset));
+                    }
+                }
+            }
+            i::ValueBase::Literal(data) => {
+                let mut element = data;
+                for index in &indexes {
+                    match (index, element) {
+                        (Index::Static(index), i::KnownData::Array(items)) => {
+                            element = &items[*index as usize];
+                        }
+                        _ => break,
+                    }
+                }
+                if let i::KnownData::Array(..) = element {
+                    let address = self.literal_addresses[&format!("{:?}", data)];
+                    let typ = data.get_type();
+                    let offset = self.emit_address(None, address, &typ, &indexes);
+                    self.push(W::Memory(load_op(&typ), offset));
+                } else {
+                    self.emit_constant(element);
+                }
+            }
+        }
+    }
+
+    /// Pushes the address of a variable which is passed by pointer.
+    fn emit_pointer(&mut self, value: &i::Value) {
+        let source = self.source;
+        if let i::ValueBase::Variable(id) = &value.base {
+            if let Storage::Memory(base, offset) = self.storage[id] {
+                let offset = self.emit_address(base, offset, source[*id].borrow_type(), &[]);
+                if offset > 0 {
+                    self.push(W::I32Const(offset as i32));
+                    self.push(w::I32_ADD);
+                }
+            } else {
+                unreachable!("Variables passed by pointer should be stored in memory.")
+            }
+        } else {
+            unreachable!("Only variables can be passed by pointer.")
+        }
+    }
+
+    /// Pushes the address needed to store an element of `value`, if any. The content should be
+    /// pushed after this, followed by calling `finish_store`.
+    fn begin_store(&mut self, value: &i::Value, indexes: Vec<Index>) -> StoreTarget {
+        let indexes = apply_proxy(value, indexes);
+        let source = self.source;
+        match &value.base {
+            i::ValueBase::Variable(id) => {
+                let typ = source[*id].borrow_type();
+                match self.storage[id] {
+                    Storage::Local(local) => {
+                        assert!(indexes.is_empty(), "Cannot index scalar data.");
+                        StoreTarget::Local(local)
+                    }
+                    Storage::Memory(base, offset) => {
+                        let offset = self.emit_address(base, offset, typ, &indexes);
+                        StoreTarget::Memory(store_op(typ), offset)
+                    }
+                }
+            }
+            i::ValueBase::Literal(..) => panic!("Cannot store to a constant."),
+        }
+    }
+
+    fn finish_store(&mut self, target: StoreTarget) {
+        match target {
+            StoreTarget::Local(local) => self.push(W::LocalSet(local)),
+            StoreTarget::Memory(op, offset) => self.push(W::Memory(op, offset)),
+        }
+    }
+
+    fn emit_unary_op(&mut self, op: &i::UnaryOperator, a: &i::Value, coord: &[usize]) {
+        let math = |name| W::Call(math_function(name));
+        let instruction = match op {
+            i::UnaryOperator::NegI => {
+                self.push(W::I32Const(0));
+                self.emit_load(a, static_indexes(coord));
+                self.push(w::I32_SUB);
+                return;
+            }
+            i::UnaryOperator::IAbs => {
+                let temp = self.temp_i32;
+                self.emit_load(a, static_indexes(coord));
+                self.push(W::LocalSet(temp));
+                self.push(W::I32Const(0));
+                self.push(W::LocalGet(temp));
+                self.push(w::I32_SUB);
+                self.push(W::LocalGet(temp));
+                self.push(W::LocalGet(temp));
+                self.push(W::I32Const(0));
+                self.push(w::I32_LT_S);
+                self.push(w::SELECT);
+                return;
+            }
+            // Computed as 0 - a like the LLVM version, which gives 0 instead of -0 when a is 0.
+            i::UnaryOperator::NegF => {
+                self.push(W::F32Const(0.0));
+                self.emit_load(a, static_indexes(coord));
+                self.push(w::F32_SUB);
+                return;
+            }
+            i::UnaryOperator::BNot => {
+                self.emit_load(a, static_indexes(coord));
+                self.push(W::I32Const(-1));
+                self.push(w::I32_XOR);
+                return;
+            }
+            i::UnaryOperator::Not => w::I32_EQZ,
+            i::UnaryOperator::FAbs => w::F32_ABS,
+            i::UnaryOperator::FCeil => w::F32_CEIL,
+            i::UnaryOperator::FFloor => w::F32_FLOOR,
+            i::UnaryOperator::FTrunc => w::F32_TRUNC,
+            i::UnaryOperator::FSqrt => w::F32_SQRT,
+            i::UnaryOperator::Ftoi => w::I32_TRUNC_SAT_F32_S,
+            i::UnaryOperator::Itof => w::F32_CONVERT_I32_S,
+            i::UnaryOperator::FSin => math("sin"),
+            i::UnaryOperator::FCos => math("cos"),
+            i::UnaryOperator::FExp => math("exp"),
+            i::UnaryOperator::FExp2 => math("exp2"),
+            i::UnaryOperator::FLog => math("log"),
+            i::UnaryOperator::FLog10 => math("log10"),
+            i::UnaryOperator::FLog2 => math("log2"),
+        };
+        self.emit_load(a, static_indexes(coord));
+        self.push(instruction);
+    }
+
+    fn emit_binary_op(
+        &mut self,
+        op: &i::BinaryOperator,
+        a: &i::Value,
+        b: &i::Value,
+        coord: &[usize],
+    ) {
+        let instruction = match op {
+            i::BinaryOperator::AddI => w::I32_ADD,
+            i::BinaryOperator::SubI => w::I32_SUB,
+            i::BinaryOperator::MulI => w::I32_MUL,
+            i::BinaryOperator::DivI => w::I32_DIV_S,
+            i::BinaryOperator::ModI => w::I32_REM_S,
+            i::BinaryOperator::AddF => w::F32_ADD,
+            i::BinaryOperator::SubF => w::F32_SUB,
+            i::BinaryOperator::MulF => w::F32_MUL,
+            i::BinaryOperator::DivF => w::F32_DIV,
+            i::BinaryOperator::ModF => W::Call(math_function("fmod")),
+            i::BinaryOperator::PowF => W::Call(math_function("pow")),
+            i::BinaryOperator::BAnd | i::BinaryOperator::And => w::I32_AND,
+            i::BinaryOperator::BOr | i::BinaryOperator::Or => w::I32_OR,
+            i::BinaryOperator::BXor | i::BinaryOperator::Xor => w::I32_XOR,
+            i::BinaryOperator::LeftShift => w::I32_SHL,
+            i::BinaryOperator::RightShift => w::I32_SHR_U,
+            i::BinaryOperator::CompI(condition) => match condition {
+                i::Condition::LessThan => w::I32_LT_S,
+                i::Condition::GreaterThan => w::I32_GT_S,
+                i::Condition::LessThanOrEqual => w::I32_LE_S,
+                i::Condition::GreaterThanOrEqual => w::I32_GE_S,
+                i::Condition::Equal => w::I32_EQ,
+                i::Condition::NotEqual => w::I32_NE,
+            },
+            i::BinaryOperator::CompF(condition) => match condition {
+                i::Condition::LessThan => w::F32_LT,
+                i::Condition::GreaterThan => w::F32_GT,
+                i::Condition::LessThanOrEqual => w::F32_LE,
+                i::Condition::GreaterThanOrEqual => w::F32_GE,
+                i::Condition::Equal => w::F32_EQ,
+                i::Condition::NotEqual => {
+                    // f32.ne is true if either side is NaN, but the LLVM version uses an
+                    // ordered comparison which is false.
+                    let (ta, tb) = self.temp_f32s;
+                    self.emit_load(a, static_indexes(coord));
+                    self.push(W::LocalSet(ta));
+                    self.emit_load(b, static_indexes(coord));
+                    self.push(W::LocalSet(tb));
+                    self.push(W::LocalGet(ta));
+                    self.push(W::LocalGet(tb));
+                    self.push(w::F32_LT);
+                    self.push(W::LocalGet(ta));
+                    self.push(W::LocalGet(tb));
+                    self.push(w::F32_GT);
+                    self.push(w::I32_OR);
+                    return;
+                }
+            },
+        };
+        self.emit_load(a, static_indexes(coord));
+        self.emit_load(b, static_indexes(coord));
+        self.push(instruction);
+    }
+
+    fn convert_unary_expression(&mut self, op: &i::UnaryOperator, a: &i::Value, x: &i::Value) {
+        for coord in coordinates(x) {
+            let target = self.begin_store(x, static_indexes(&coord));
+            self.emit_unary_op(op, a, &coord);
+            self.finish_store(target);
+        }
+    }
+
+    fn convert_binary_expression(
+        &mut self,
+        op: &i::BinaryOperator,
+        a: &i::Value,
+        b: &i::Value,
+        x: &i::Value,
+    ) {
+        for coord in coordinates(x) {
+            let target = self.begin_store(x, static_indexes(&coord));
+            self.emit_binary_op(op, a, b, &coord);
+            self.finish_store(target);
+        }
+    }
+
+    fn convert_select(&mut self, condition: &i::Value, a: &i::Value, b: &i::Value, x: &i::Value) {
+        for coord in coordinates(x) {
+            let target = self.begin_store(x, static_indexes(&coord));
+            self.emit_load(a, static_indexes(&coord));
+            self.emit_load(b, static_indexes(&coord));
+            self.emit_load(condition, static_indexes(&coord));
+            self.push(w::SELECT);
+            self.finish_store(target);
+        }
+    }
+
+    fn convert_move(&mut self, from: &i::Value, to: &i::Value) {
+        for coord in coordinates(to) {
+            let target = self.begin_store(to, static_indexes(&coord));
+            self.emit_load(from, static_indexes(&coord));
+            self.finish_store(target);
+        }
+    }
+
+    fn convert_load(&mut self, from: &i::Value, from_indexes: &[i::Value], to: &i::Value) {
+        for coord in coordinates(to) {
+            let target = self.begin_store(to, static_indexes(&coord));
+            let mut indexes: Vec<_> = from_indexes.iter().map(dynamic_index).collect();
+            indexes.append(&mut static_indexes(&coord));
+            self.emit_load(from, indexes);
+            self.finish_store(target);
+        }
+    }
+
+    fn convert_store(&mut self, from: &i::Value, to: &i::Value, to_indexes: &[i::Value]) {
+        for coord in coordinates(from) {
+            let mut indexes: Vec<_> = to_indexes.iter().map(dynamic_index).collect();
+            indexes.append(&mut static_indexes(&coord));
+            let target = self.begin_store(to, indexes);
+            self.emit_load(from, static_indexes(&coord));
+            self.finish_store(target);
+        }
+    }
+
+    fn convert_call_extern(&mut self, function: usize, inputs: &[i::Value], outputs: &[i::Value]) {
+        for input in inputs {
+            if input.dimensions.is_empty() {
+                self.emit_load(input, Vec::new());
+            } else {
+                self.emit_pointer(input);
+            }
+        }
+        for output in outputs {
+            self.emit_pointer(output);
+        }
+        self.push(W::Call((MATH_IMPORTS.len() + function) as u32));
+    }
+
+    /// Sets which segment should run next and jumps back to the start of the dispatch loop.
+    fn emit_jump(&mut self, label: &i::LabelId) {
+        self.push(W::I32Const(self.label_segments[label] as i32));
+        self.push(W::LocalSet(self.state_local));
+        self.emit_jump_to_dispatch();
+    }
+
+    fn emit_jump_to_dispatch(&mut self) {
+        // Each segment is nested inside the blocks of all the segments after it.
+        let depth = self.num_segments - 1 - self.current_segment;
+        self.push(W::Br(depth));
+    }
+
+    fn convert_branch(
+        &mut self,
+        condition: &i::Value,
+        true_target: &i::LabelId,
+        false_target: &i::LabelId,
+    ) {
+        self.emit_load(condition, Vec::new());
+        self.push(W::If);
+        self.push(W::I32Const(self.label_segments[true_target] as i32));
+        self.push(W::LocalSet(self.state_local));
+        self.push(W::Else);
+        self.push(W::I32Const(self.label_segments[false_target] as i32));
+        self.push(W::LocalSet(self.state_local));
+        self.push(W::End);
+        self.emit_jump_to_dispatch();
+    }
+
+    fn convert_instruction(&mut self, instruction: &i::Instruction) {
+        match instruction {
+            i::Instruction::Abort(error_code) => {
+                self.push(W::I32Const(*error_code as i32));
+                self.push(w::RETURN);
+            }
+            // WebAssembly has nothing equivalent to an assume instruction.
+            i::Instruction::Assume(..) => (),
+            i::Instruction::CallExtern {
+                function,
+                inputs,
+                outputs,
+            } => self.convert_call_extern(*function, inputs, outputs),
+            i::Instruction::BinaryOperation { op, a, b, x } => {
+                self.convert_binary_expression(op, a, b, x)
+            }
+            i::Instruction::UnaryOperation { op, a, x } => self.convert_unary_expression(op, a, x),
+            i::Instruction::Select { condition, a, b, x } => {
+                self.convert_select(condition, a, b, x)
+            }
+            i::Instruction::Move { from, to } => self.convert_move(from, to),
+            i::Instruction::Label(..) => {
+                // Ends the block that the dispatcher exits to start the segment after the label.
+                self.push(W::End);
+                self.current_segment += 1;
+            }
+            i::Instruction::Branch {
+                condition,
+                true_target,
+                false_target,
+            } => self.convert_branch(condition, true_target, false_target),
+            i::Instruction::Jump { label } => self.emit_jump(label),
+            i::Instruction::Store {
+                from,
+                to,
+                to_indexes,
+            } => self.convert_store(from, to, to_indexes),
+            i::Instruction::Load {
+                from,
+                from_indexes,
+                to,
+            } => self.convert_load(from, from_indexes, to),
+        }
+    }
+
+    fn assign_storage(&mut self, body: i::CodeBody) {
+        let source = self.source;
+        let (input_param, static_param, output_param) = if body == i::CodeBody::StaticInit {
+            (None, 0, None)
+        } else {
+            (Some(0), 1, Some(2))
+        };
+        let input_location = body.get_input_location();
+        let output_location = body.get_output_location();
+        let local_location = body.get_local_location();
+        let mut scratch_size = 0;
+        for var_id in source.iterate_all_variables() {
+            let typ = source[var_id].borrow_type();
+            let location = source[var_id].get_location();
+            let storage = if Some(location) == input_location {
+                Storage::Memory(input_param, self.struct_offsets[&var_id])
+            } else if Some(location) == output_location {
+                Storage::Memory(output_param, self.struct_offsets[&var_id])
+            } else if location == i::StorageLocation::Static {
+                Storage::Memory(Some(static_param), self.struct_offsets[&var_id])
+            } else if location == local_location {
+                if let i::DataType::Array(..) = typ {
+                } else if !self.addressed.contains(&var_id) {
+                    let local = self.add_local(value_type(typ));
+                    self.storage.insert(var_id, Storage::Local(local));
+                    continue;
+                }
+                scratch_size = align(scratch_size, type_alignment(typ));
+                let address = self.scratch_base + scratch_size;
+                scratch_size += type_size(typ);
+                Storage::Memory(None, address)
+            } else {
+                continue;
+            };
+            self.storage.insert(var_id, storage);
+        }
+        self.scratch_size = self.scratch_size.max(scratch_size);
+    }
+
+    /// Converts the static init function, the main body, or an entry point into a function which
+    /// returns an error code. Since WebAssembly only has structured control flow, the code is
+    /// split into segments at each label. They are placed in a loop which starts by jumping to
+    /// the segment stored in `state_local`, and jumps to a label are done by setting it and
+    /// jumping back to the start of the loop.
+    fn convert_body(
+        &mut self,
+        export_name: String,
+        body: i::CodeBody,
+        instructions: &[i::Instruction],
+    ) -> Function {
+        let params = if body == i::CodeBody::StaticInit {
+            vec![ValueType::I32]
+        } else {
+            vec![ValueType::I32; 3]
+        };
+        self.num_params = params.len() as u32;
+        self.storage.clear();
+        self.label_segments.clear();
+        self.state_local = self.add_local(ValueType::I32);
+        self.temp_i32 = self.add_local(ValueType::I32);
+        self.temp_f32s = (
+            self.add_local(ValueType::F32),
+            self.add_local(ValueType::F32),
+        );
+        self.assign_storage(body);
+
+        for instruction in instructions {
+            if let i::Instruction::Label(id) = instruction {
+                let segment = self.label_segments.len() as u32 + 1;
+                self.label_segments.insert(*id, segment);
+            }
+        }
+        self.num_segments = self.label_segments.len() as u32 + 1;
+        self.current_segment = 0;
+
+        self.push(W::Loop);
+        for _ in 0..self.num_segments {
+            self.push(W::Block);
+        }
+        self.push(W::LocalGet(self.state_local));
+        self.push(W::BrTable((0..self.num_segments).collect(), 0));
+        self.push(W::End);
+        for instruction in instructions {
+            self.convert_instruction(instruction);
+        }
+        self.push(W::End);
+        self.push(W::I32Const(0));
+
+        Function {
+            export_name,
+            params,
+            locals: std::mem::take(&mut self.locals),
+            instructions: std::mem::take(&mut self.instructions),
+        }
+    }
+}
+
+/// Finds variables which have to be stored in memory because they are passed by pointer, and
+/// places array literals which are indexed at runtime in memory starting at address 0.
+fn prepare(source: &i::Program) -> (HashSet<i::VariableId>, HashMap<String, u32>, Vec<u8>) {
+    let mut addressed = HashSet::new();
+    let mut literal_addresses = HashMap::new();
+    let mut data = Vec::new();
+    let mut bodies = vec![
+        source.borrow_static_init_instructions(),
+        source.borrow_instructions(),
+    ];
+    for entry_point in source.borrow_entry_points() {
+        bodies.push(entry_point.borrow_instructions());
+    }
+    for instruction in bodies.into_iter().flatten() {
+        match instruction {
+            i::Instruction::CallExtern {
+                inputs, outputs, ..
+            } => {
+                for value in inputs.iter().chain(outputs.iter()) {
+                    if let i::ValueBase::Variable(id) = &value.base {
+                        addressed.insert(*id);
+                    }
+                }
+            }
+            i::Instruction::Load { from, .. } => {
+                if let i::ValueBase::Literal(data_value @ i::KnownData::Array(..)) = &from.base {
+                    let key = format!("{:?}", data_value);
+                    literal_addresses.entry(key).or_insert_with(|| {
+                        data.resize(align(data.len() as u32, 4) as usize, 0);
+                        let address = data.len() as u32;
+                        data.append(&mut data_value.arbitrary_len_binary_data());
+                        address
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+    (addressed, literal_addresses, data)
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    w::write_unsigned(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, count: usize, content: Vec<u8>) {
+    let mut section = Vec::new();
+    w::write_unsigned(&mut section, count as u64);
+    section.extend(content);
+    out.push(id);
+    w::write_unsigned(out, section.len() as u64);
+    out.append(&mut section);
+}
+
+type Signature = (Vec<ValueType>, Vec<ValueType>);
+
+fn add_type(types: &mut Vec<Signature>, params: Vec<ValueType>, results: Vec<ValueType>) -> u32 {
+    let signature = (params, results);
+    if let Some(index) = types.iter().position(|existing| existing == &signature) {
+        index as u32
+    } else {
+        types.push(signature);
+        types.len() as u32 - 1
+    }
+}
+
+fn signature_text(signature: &Signature) -> String {
+    let mut result = "(func".to_owned();
+    for (prefix, types) in &[("param", &signature.0), ("result", &signature.1)] {
+        if !types.is_empty() {
+            write!(result, " ({}", prefix).unwrap();
+            for typ in types.iter() {
+                write!(result, " {}", typ).unwrap();
+            }
+            result.push(')');
+        }
+    }
+    result.push(')');
+    result
+}
+
+/// Builds the binary and text versions of a module with the given imports, functions, and
+/// memory. Imports are tuples of module name, field name, and type index.
+fn build_module(
+    types: &[Signature],
+    imports: &[(&str, String, u32)],
+    functions: &[(Function, u32)],
+    min_pages: u32,
+    heap_base: u32,
+    data: &[u8],
+) -> (Vec<u8>, String) {
+    let mut binary = b"\0asm\x01\0\0\0".to_vec();
+    let mut text = "(module\n".to_owned();
+
+    let mut section = Vec::new();
+    for (index, signature) in types.iter().enumerate() {
+        section.push(0x60);
+        for types in &[&signature.0, &signature.1] {
+            w::write_unsigned(&mut section, types.len() as u64);
+            section.extend(types.iter().map(ValueType::code));
+        }
+        writeln!(text, "  (type (;{};) {})", index, signature_text(signature)).unwrap();
+    }
+    write_section(&mut binary, 1, types.len(), section);
+
+    let mut section = Vec::new();
+    for (index, (module, field, type_index)) in imports.iter().enumerate() {
+        write_name(&mut section, module);
+        write_name(&mut section, field);
+        section.push(0x00);
+        w::write_unsigned(&mut section, *type_index as u64);
+        writeln!(
+            text,
+            "  (import \"{}\" \"{}\" (func (;{};) (type {})))",
+            module, field, index, type_index
+        )
+        .unwrap();
+    }
+    write_section(&mut binary, 2, imports.len(), section);
+
+    let mut section = Vec::new();
+    for (_, type_index) in functions {
+        w::write_unsigned(&mut section, *type_index as u64);
+    }
+    write_section(&mut binary, 3, functions.len(), section);
+
+    let mut section = vec![0x00];
+    w::write_unsigned(&mut section, min_pages as u64);
+    write_section(&mut binary, 5, 1, section);
+
+    let mut section = vec![ValueType::I32.code(), 0x00];
+    W::I32Const(heap_base as i32).encode(&mut section);
+    section.push(0x0B);
+    write_section(&mut binary, 6, 1, section);
+
+    let mut section = Vec::new();
+    write_name(&mut section, "memory");
+    section.extend_from_slice(&[0x02, 0x00]);
+    write_name(&mut section, "heap_base");
+    section.extend_from_slice(&[0x03, 0x00]);
+    for (index, (function, _)) in functions.iter().enumerate() {
+        write_name(&mut section, &function.export_name);
+        section.push(0x00);
+        w::write_unsigned(&mut section, (imports.len() + index) as u64);
+    }
+    write_section(&mut binary, 7, functions.len() + 2, section);
+
+    let mut section = Vec::new();
+    for (index, (function, type_index)) in functions.iter().enumerate() {
+        let mut body = Vec::new();
+        let mut groups: Vec<(u32, ValueType)> = Vec::new();
+        for local in &function.locals {
+            match groups.last_mut() {
+                Some((count, typ)) if typ == local => *count += 1,
+                _ => groups.push((1, *local)),
+            }
+        }
+        w::write_unsigned(&mut body, groups.len() as u64);
+        for (count, typ) in &groups {
+            w::write_unsigned(&mut body, *count as u64);
+            body.push(typ.code());
+        }
+        for instruction in &function.instructions {
+            instruction.encode(&mut body);
+        }
+        body.push(0x0B);
+        w::write_unsigned(&mut section, body.len() as u64);
+        section.append(&mut body);
+
+        let function_index = imports.len() + index;
+        write!(text, "  (func (;{};) (type {})", function_index, type_index).unwrap();
+        if !function.locals.is_empty() {
+            text.push_str(" (local");
+            for local in &function.locals {
+                write!(text, " {}", local).unwrap();
+            }
+            text.push(')');
+        }
+        text.push('\n');
+        let mut depth = 2;
+        for instruction in &function.instructions {
+            if let W::End | W::Else = instruction {
+                depth -= 1;
+            }
+            writeln!(text, "{:2$}{}", "", instruction, depth * 2).unwrap();
+            if let W::Block | W::Loop | W::If | W::Else = instruction {
+                depth += 1;
+            }
+        }
+        text.push_str("  )\n");
+    }
+    write_section(&mut binary, 10, functions.len(), section);
+
+    writeln!(text, "  (memory (;0;) {})", min_pages).unwrap();
+    writeln!(text, "  (global (;0;) i32 (i32.const {}))", heap_base).unwrap();
+    text.push_str("  (export \"memory\" (memory 0))\n");
+    text.push_str("  (export \"heap_base\" (global 0))\n");
+    for (index, (function, _)) in functions.iter().enumerate() {
+        writeln!(
+            text,
+            "  (export \"{}\" (func {}))",
+            function.export_name,
+            imports.len() + index
+        )
+        .unwrap();
+    }
+
+    if !data.is_empty() {
+        let mut section = vec![0x00];
+        W::I32Const(0).encode(&mut section);
+        section.push(0x0B);
+        w::write_unsigned(&mut section, data.len() as u64);
+        section.extend_from_slice(data);
+        write_section(&mut binary, 11, 1, section);
+        text.push_str("  (data (;0;) (i32.const 0) \"");
+        for byte in data {
+            write!(text, "\\{:02x}", byte).unwrap();
+        }
+        text.push_str("\")\n");
+    }
+    text.push_str(")\n");
+
+    (binary, text)
+}
+
+/// Converts the program to a WebAssembly module. The module imports:
+/// - `sin`, `cos`, `exp`, `exp2`, `log`, `log10`, `log2`, `pow`, and `fmod` from `math`, which
+///   operate on f32 values.
+/// - Every extern function from `env`, using the name it was added with. Arrays and outputs are
+///   passed as addresses in memory, like the pointers used by the LLVM version.
+///
+/// It exports:
+/// - `memory`, where the input, output, and static data are stored. Memory below `heap_base` is
+///   used by the module itself.
+/// - `static_init`, taking the address of the static data.
+/// - `main` and `entry_<name>` for each entry point, taking the addresses of the input, static,
+///   and output data.
+///
+/// Every function returns 0 on success or an error code. The data uses the same layout as the
+/// LLVM version.
+pub fn ingest(source: &i::Program) -> o::Program {
+    let mut struct_offsets = HashMap::new();
+    let input_size = layout_struct(source, i::StorageLocation::Input, true, &mut struct_offsets);
+    let output_size = layout_struct(
+        source,
+        i::StorageLocation::Output,
+        true,
+        &mut struct_offsets,
+    );
+    let static_size = layout_struct(
+        source,
+        i::StorageLocation::Static,
+        false,
+        &mut struct_offsets,
+    );
+    let mut entry_sizes = Vec::new();
+    for index in 0..source.borrow_entry_points().len() {
+        let input = i::StorageLocation::EntryInput(index);
+        let output = i::StorageLocation::EntryOutput(index);
+        entry_sizes.push((
+            layout_struct(source, input, true, &mut struct_offsets),
+            layout_struct(source, output, true, &mut struct_offsets),
+        ));
+    }
+
+    let (addressed, literal_addresses, data) = prepare(source);
+    let mut converter = Converter {
+        source,
+        struct_offsets,
+        addressed,
+        literal_addresses,
+        scratch_base: align(data.len() as u32, 8),
+        scratch_size: 0,
+
+        storage: HashMap::new(),
+        num_params: 0,
+        locals: Vec::new(),
+        instructions: Vec::new(),
+        state_local: 0,
+        temp_i32: 0,
+        temp_f32s: (0, 0),
+        label_segments: HashMap::new(),
+        num_segments: 0,
+        current_segment: 0,
+    };
+
+    let mut types = Vec::new();
+    let mut imports = Vec::new();
+    for (name, num_args) in MATH_IMPORTS {
+        let params = vec![ValueType::F32; *num_args];
+        let type_index = add_type(&mut types, params, vec![ValueType::F32]);
+        imports.push(("math", (*name).to_owned(), type_index));
+    }
+    for function in source.borrow_extern_functions() {
+        let mut params = Vec::new();
+        for typ in function.borrow_inputs() {
+            params.push(match typ {
+                i::DataType::Array(..) => ValueType::I32,
+                _ => value_type(typ),
+            });
+        }
+        params.extend(function.borrow_outputs().iter().map(|_| ValueType::I32));
+        let type_index = add_type(&mut types, params, Vec::new());
+        imports.push(("env", function.borrow_name().clone(), type_index));
+    }
+
+    let mut functions = vec![
+        converter.convert_body(
+            "static_init".to_owned(),
+            i::CodeBody::StaticInit,
+            source.borrow_static_init_instructions(),
+        ),
+        converter.convert_body(
+            "main".to_owned(),
+            i::CodeBody::Main,
+            source.borrow_instructions(),
+        ),
+    ];
+    for (index, entry_point) in source.borrow_entry_points().iter().enumerate() {
+        functions.push(converter.convert_body(
+            format!("entry_{}", entry_point.borrow_name()),
+            i::CodeBody::EntryPoint(index),
+            entry_point.borrow_instructions(),
+        ));
+    }
+    let functions: Vec<_> = functions
+        .into_iter()
+        .map(|function| {
+            let type_index = add_type(&mut types, function.params.clone(), vec![ValueType::I32]);
+            (function, type_index)
+        })
+        .collect();
+
+    let heap_base = align(converter.scratch_base + converter.scratch_size, 16);
+    // Leave at least one page above the heap base so small programs work without growing memory.
+    let min_pages = heap_base / 0x10000 + 1;
+    let (binary, text) = build_module(&types, &imports, &functions, min_pages, heap_base, &data);
+
+    let sizes = (
+        input_size as usize,
+        output_size as usize,
+        static_size as usize,
+    );
+    let mut program = o::Program::new(
+        binary,
+        text,
+        heap_base as usize,
+        sizes,
+        source.borrow_errors().clone(),
+    );
+    for (index, entry_point) in source.borrow_entry_points().iter().enumerate() {
+        let (input_size, output_size) = entry_sizes[index];
+        program.add_entry_point(
+            entry_point.borrow_name().clone(),
+            input_size as usize,
+            output_size as usize,
+        );
+    }
+    program
+}
@@ -0,0 +1,249 @@
+use std::fmt::{self, Display, Formatter};
+
+pub(super) fn write_unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub(super) fn write_signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(super) enum ValueType {
+    I32,
+    F32,
+}
+
+impl ValueType {
+    pub(super) fn code(&self) -> u8 {
+        match self {
+            Self::I32 => 0x7F,
+            Self::F32 => 0x7D,
+        }
+    }
+}
+
+impl Display for ValueType {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::I32 => write!(formatter, "i32"),
+            Self::F32 => write!(formatter, "f32"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(super) enum MemoryOp {
+    I32Load,
+    F32Load,
+    I32Load8U,
+    I32Store,
+    F32Store,
+    I32Store8,
+}
+
+impl MemoryOp {
+    fn opcode(&self) -> u8 {
+        match self {
+            Self::I32Load => 0x28,
+            Self::F32Load => 0x2A,
+            Self::I32Load8U => 0x2D,
+            Self::I32Store => 0x36,
+            Self::F32Store => 0x38,
+            Self::I32Store8 => 0x3A,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::I32Load => "i32.load",
+            Self::F32Load => "f32.load",
+            Self::I32Load8U => "i32.load8_u",
+            Self::I32Store => "i32.store",
+            Self::F32Store => "f32.store",
+            Self::I32Store8 => "i32.store8",
+        }
+    }
+
+    /// The log2 of the natural alignment of the operation, which is used as its alignment hint.
+    fn alignment(&self) -> u32 {
+        match self {
+            Self::I32Load8U | Self::I32Store8 => 0,
+            _ => 2,
+        }
+    }
+}
+
+/// An instruction along with its immediates. Instructions without immediates are represented by
+/// their name in the text format along with their opcode.
+#[derive(Clone, Debug)]
+pub(super) enum Instruction {
+    Simple(&'static str, &'static [u8]),
+    LocalGet(u32),
+    LocalSet(u32),
+    I32Const(i32),
+    F32Const(f32),
+    /// An operation on the memory at the address on the stack plus a constant offset.
+    Memory(MemoryOp, u32),
+    Call(u32),
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    BrTable(Vec<u32>, u32),
+}
+
+pub(super) const RETURN: Instruction = Instruction::Simple("return", &[0x0F]);
+pub(super) const SELECT: Instruction = Instruction::Simple("select", &[0x1B]);
+
+pub(super) const I32_EQZ: Instruction = Instruction::Simple("i32.eqz", &[0x45]);
+pub(super) const I32_EQ: Instruction = Instruction::Simple("i32.eq", &[0x46]);
+pub(super) const I32_NE: Instruction = Instruction::Simple("i32.ne", &[0x47]);
+pub(super) const I32_LT_S: Instruction = Instruction::Simple("i32.lt_s", &[0x48]);
+pub(super) const I32_GT_S: Instruction = Instruction::Simple("i32.gt_s", &[0x4A]);
+pub(super) const I32_LE_S: Instruction = Instruction::Simple("i32.le_s", &[0x4C]);
+pub(super) const I32_GE_S: Instruction = Instruction::Simple("i32.ge_s", &[0x4E]);
+pub(super) const F32_EQ: Instruction = Instruction::Simple("f32.eq", &[0x5B]);
+pub(super) const F32_LT: Instruction = Instruction::Simple("f32.lt", &[0x5D]);
+pub(super) const F32_GT: Instruction = Instruction::Simple("f32.gt", &[0x5E]);
+pub(super) const F32_LE: Instruction = Instruction::Simple("f32.le", &[0x5F]);
+pub(super) const F32_GE: Instruction = Instruction::Simple("f32.ge", &[0x60]);
+
+pub(super) const I32_ADD: Instruction = Instruction::Simple("i32.add", &[0x6A]);
+pub(super) const I32_SUB: Instruction = Instruction::Simple("i32.sub", &[0x6B]);
+pub(super) const I32_MUL: Instruction = Instruction::Simple("i32.mul", &[0x6C]);
+pub(super) const I32_DIV_S: Instruction = Instruction::Simple("i32.div_s", &[0x6D]);
+pub(super) const I32_REM_S: Instruction = Instruction::Simple("i32.rem_s", &[0x6F]);
+pub(super) const I32_AND: Instruction = Instruction::Simple("i32.and", &[0x71]);
+pub(super) const I32_OR: Instruction = Instruction::Simple("i32.or", &[0x72]);
+pub(super) const I32_XOR: Instruction = Instruction::Simple("i32.xor", &[0x73]);
+pub(super) const I32_SHL: Instruction = Instruction::Simple("i32.shl", &[0x74]);
+pub(super) const I32_SHR_U: Instruction = Instruction::Simple("i32.shr_u", &[0x76]);
+
+pub(super) const F32_ABS: Instruction = Instruction::Simple("f32.abs", &[0x8B]);
+pub(super) const F32_CEIL: Instruction = Instruction::Simple("f32.ceil", &[0x8D]);
+pub(super) const F32_FLOOR: Instruction = Instruction::Simple("f32.floor", &[0x8E]);
+pub(super) const F32_TRUNC: Instruction = Instruction::Simple("f32.trunc", &[0x8F]);
+pub(super) const F32_SQRT: Instruction = Instruction::Simple("f32.sqrt", &[0x91]);
+pub(super) const F32_ADD: Instruction = Instruction::Simple("f32.add", &[0x92]);
+pub(super) const F32_SUB: Instruction = Instruction::Simple("f32.sub", &[0x93]);
+pub(super) const F32_MUL: Instruction = Instruction::Simple("f32.mul", &[0x94]);
+pub(super) const F32_DIV: Instruction = Instruction::Simple("f32.div", &[0x95]);
+
+pub(super) const F32_CONVERT_I32_S: Instruction = Instruction::Simple("f32.convert_i32_s", &[0xB2]);
+// The saturating version is used because the LLVM version does not trap on overflow either.
+pub(super) const I32_TRUNC_SAT_F32_S: Instruction =
+    Instruction::Simple("i32.trunc_sat_f32_s", &[0xFC, 0x00]);
+
+impl Instruction {
+    pub(super) fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Simple(_, opcode) => out.extend_from_slice(opcode),
+            Self::LocalGet(index) => {
+                out.push(0x20);
+                write_unsigned(out, *index as u64);
+            }
+            Self::LocalSet(index) => {
+                out.push(0x21);
+                write_unsigned(out, *index as u64);
+            }
+            Self::I32Const(value) => {
+                out.push(0x41);
+                write_signed(out, *value as i64);
+            }
+            Self::F32Const(value) => {
+                out.push(0x43);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            Self::Memory(op, offset) => {
+                out.push(op.opcode());
+                write_unsigned(out, op.alignment() as u64);
+                write_unsigned(out, *offset as u64);
+            }
+            Self::Call(index) => {
+                out.push(0x10);
+                write_unsigned(out, *index as u64);
+            }
+            // 0x40 is the empty block type.
+            Self::Block => out.extend_from_slice(&[0x02, 0x40]),
+            Self::Loop => out.extend_from_slice(&[0x03, 0x40]),
+            Self::If => out.extend_from_slice(&[0x04, 0x40]),
+            Self::Else => out.push(0x05),
+            Self::End => out.push(0x0B),
+            Self::Br(depth) => {
+                out.push(0x0C);
+                write_unsigned(out, *depth as u64);
+            }
+            Self::BrTable(targets, default) => {
+                out.push(0x0E);
+                write_unsigned(out, targets.len() as u64);
+                for target in targets {
+                    write_unsigned(out, *target as u64);
+                }
+                write_unsigned(out, *default as u64);
+            }
+        }
+    }
+}
+
+fn float_text(value: f32) -> String {
+    if value.is_nan() {
+        "nan".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_owned()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Simple(name, _) => write!(formatter, "{}", name),
+            Self::LocalGet(index) => write!(formatter, "local.get {}", index),
+            Self::LocalSet(index) => write!(formatter, "local.set {}", index),
+            Self::I32Const(value) => write!(formatter, "i32.const {}", value),
+            Self::F32Const(value) => write!(formatter, "f32.const {}", float_text(*value)),
+            Self::Memory(op, offset) => {
+                write!(formatter, "{}", op.name())?;
+                if *offset > 0 {
+                    write!(formatter, " offset={}", offset)?;
+                }
+                write!(formatter, "")
+            }
+            Self::Call(index) => write!(formatter, "call {}", index),
+            Self::Block => write!(formatter, "block"),
+            Self::Loop => write!(formatter, "loop"),
+            Self::If => write!(formatter, "if"),
+            Self::Else => write!(formatter, "else"),
+            Self::End => write!(formatter, "end"),
+            Self::Br(depth) => write!(formatter, "br {}", depth),
+            Self::BrTable(targets, default) => {
+                write!(formatter, "br_table")?;
+                for target in targets {
+                    write!(formatter, " {}", target)?;
+                }
+                write!(formatter, " {}", default)
+            }
+        }
+    }
+}
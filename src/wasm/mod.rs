@@ -0,0 +1,26 @@
+mod ingest;
+mod instruction;
+pub mod structure;
+
+pub use ingest::ingest;
+
+use crate::high_level::backend::Backend;
+
+/// Produces a WebAssembly module, see `ingest` for what it imports and exports.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WasmBackend;
+
+impl Backend for WasmBackend {
+    type Output = structure::Program;
+
+    fn borrow_name(&self) -> &str {
+        "wasm"
+    }
+
+    fn compile(
+        &mut self,
+        source: &crate::trivial::structure::Program,
+    ) -> Result<Self::Output, String> {
+        Ok(ingest(source))
+    }
+}
@@ -0,0 +1,101 @@
+use crate::high_level::problem::RuntimeError;
+
+/// An additional function exported as `entry_<name>`, see the llvmir version of this struct.
+#[derive(Clone, Debug)]
+pub struct EntryPoint {
+    name: String,
+    input_size: usize,
+    output_size: usize,
+}
+
+impl EntryPoint {
+    pub fn borrow_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_input_size(&self) -> usize {
+        self.input_size
+    }
+
+    pub fn get_output_size(&self) -> usize {
+        self.output_size
+    }
+}
+
+/// A WebAssembly module containing the program. See `wasm::ingest` for the functions it imports
+/// and exports.
+pub struct Program {
+    binary: Vec<u8>,
+    text: String,
+    heap_base: usize,
+    input_size: usize,
+    output_size: usize,
+    static_size: usize,
+    entry_points: Vec<EntryPoint>,
+    errors: Vec<RuntimeError>,
+}
+
+impl Program {
+    pub(super) fn new(
+        binary: Vec<u8>,
+        text: String,
+        heap_base: usize,
+        (input_size, output_size, static_size): (usize, usize, usize),
+        errors: Vec<RuntimeError>,
+    ) -> Self {
+        Self {
+            binary,
+            text,
+            heap_base,
+            input_size,
+            output_size,
+            static_size,
+            entry_points: Vec::new(),
+            errors,
+        }
+    }
+
+    pub(super) fn add_entry_point(&mut self, name: String, input_size: usize, output_size: usize) {
+        self.entry_points.push(EntryPoint {
+            name,
+            input_size,
+            output_size,
+        });
+    }
+
+    /// The module in the binary format, as would be stored in a `.wasm` file.
+    pub fn borrow_binary(&self) -> &Vec<u8> {
+        &self.binary
+    }
+
+    /// The module in the text format, as would be stored in a `.wat` file.
+    pub fn borrow_text(&self) -> &String {
+        &self.text
+    }
+
+    /// Memory below this address is used by the module itself. The input, output, and static
+    /// data should be placed above it.
+    pub fn get_heap_base(&self) -> usize {
+        self.heap_base
+    }
+
+    pub fn get_input_size(&self) -> usize {
+        self.input_size
+    }
+
+    pub fn get_output_size(&self) -> usize {
+        self.output_size
+    }
+
+    pub fn get_static_size(&self) -> usize {
+        self.static_size
+    }
+
+    pub fn borrow_entry_points(&self) -> &Vec<EntryPoint> {
+        &self.entry_points
+    }
+
+    pub fn borrow_errors(&self) -> &Vec<RuntimeError> {
+        &self.errors
+    }
+}
@@ -34,6 +34,7 @@ fn arithmetic() {
     let mut outputs: Outputs = Default::default();
     unsafe {
         let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
         for (a, b) in &[
             (0.0, 1.0),
             (99.0, 32.0),
@@ -46,7 +47,7 @@ fn arithmetic() {
             inputs.a = a;
             inputs.b = b;
             program
-                .execute_data(&mut inputs, &mut outputs, &mut static_data)
+                .execute_data(&mut inputs, &mut outputs, &mut static_data, &mut scratch)
                 .unwrap();
             assert!(outputs.sum == a + b);
             assert!(outputs.difference == a - b);
@@ -64,6 +65,315 @@ fn arithmetic() {
     };
 }
 
+#[test]
+fn runtime_assert() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "runtime_assert.ns".to_owned(),
+        include_str!("runtime_assert.ns").to_owned(),
+    );
+    let program = compiler.compile("runtime_assert.ns").unwrap();
+    let (mut a, mut b) = (1i32, 0i32);
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        program
+            .execute_data(&mut a, &mut b, &mut static_data, &mut scratch)
+            .unwrap();
+        a = -1;
+        let error = program
+            .execute_data(&mut a, &mut b, &mut static_data, &mut scratch)
+            .unwrap_err();
+        assert_eq!(error.get_kind(), nodespeak::RuntimeErrorKind::Assert);
+        assert_eq!(error.get_code(), 1);
+        assert!(error
+            .borrow_message()
+            .starts_with("Assert failed at runtime_assert.ns:5:1"));
+        let formatted = compiler.format_runtime_error(&error);
+        assert!(formatted.contains("assert a > 0;"));
+    }
+}
+
+#[test]
+fn assert_modes() {
+    for mode in &[nodespeak::AssertMode::Elide, nodespeak::AssertMode::Assume] {
+        let mut compiler = nodespeak::Compiler::new();
+        compiler.set_assert_mode(*mode);
+        compiler.add_source(
+            "runtime_assert.ns".to_owned(),
+            include_str!("runtime_assert.ns").to_owned(),
+        );
+        let program = compiler.compile("runtime_assert.ns").unwrap();
+        assert!(program.borrow_errors().is_empty());
+        // Running an assumed assert with a false condition is undefined behavior.
+        let mut a = if *mode == nodespeak::AssertMode::Elide {
+            -1i32
+        } else {
+            1i32
+        };
+        let mut b = 0i32;
+        unsafe {
+            let mut static_data = program.create_static_data().unwrap();
+            let mut scratch = program.create_scratch();
+            program
+                .execute_data(&mut a, &mut b, &mut static_data, &mut scratch)
+                .unwrap();
+        }
+        assert_eq!(a, b);
+    }
+
+    // Asserts known to fail at compile time are errors regardless of the mode.
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.set_assert_mode(nodespeak::AssertMode::Elide);
+    compiler.add_source("guaranteed.ns".to_owned(), "assert 1 == 2;".to_owned());
+    assert!(compiler.compile("guaranteed.ns").is_err());
+}
+
+#[test]
+fn static_data_snapshot() {
+    use nodespeak::llvmir::structure::StaticData;
+
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "static_counter.ns".to_owned(),
+        include_str!("static_counter.ns").to_owned(),
+    );
+    compiler.add_source(
+        "arithmetic.ns".to_owned(),
+        include_str!("arithmetic.ns").to_owned(),
+    );
+    let program = compiler.compile("static_counter.ns").unwrap();
+    let other_program = compiler.compile("arithmetic.ns").unwrap();
+    let (mut increment, mut total) = (3i32, 0i32);
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        program
+            .execute_data(&mut increment, &mut total, &mut static_data, &mut scratch)
+            .unwrap();
+        assert_eq!(total, 3);
+
+        let bytes = static_data.to_bytes();
+        let mut cloned = static_data.clone();
+        program
+            .execute_data(&mut increment, &mut total, &mut static_data, &mut scratch)
+            .unwrap();
+        assert_eq!(total, 6);
+        program
+            .execute_data(&mut increment, &mut total, &mut cloned, &mut scratch)
+            .unwrap();
+        assert_eq!(total, 6);
+
+        let mut restored = StaticData::from_bytes(&program, &bytes).unwrap();
+        program
+            .execute_data(&mut increment, &mut total, &mut restored, &mut scratch)
+            .unwrap();
+        assert_eq!(total, 6);
+
+        assert!(StaticData::from_bytes(&other_program, &bytes).is_err());
+        assert!(StaticData::from_bytes(&program, &bytes[1..]).is_err());
+    }
+
+    // Programs whose static data has the same shape but different variables cannot share it.
+    compiler.add_source(
+        "shape_a.ns".to_owned(),
+        "static count, gain {\n    INT count = 1;\n    FLOAT gain = 0.5;\n}\n".to_owned(),
+    );
+    compiler.add_source(
+        "shape_b.ns".to_owned(),
+        "static voices, level {\n    INT voices = 1;\n    FLOAT level = 0.5;\n}\n".to_owned(),
+    );
+    let program = compiler.compile("shape_a.ns").unwrap();
+    let other_program = compiler.compile("shape_b.ns").unwrap();
+    assert!(format!("{:?}", program).contains("{ i32, float }*"));
+    assert!(format!("{:?}", other_program).contains("{ i32, float }*"));
+    unsafe {
+        let bytes = program.create_static_data().unwrap().to_bytes();
+        assert!(StaticData::from_bytes(&program, &bytes).is_ok());
+        assert!(StaticData::from_bytes(&other_program, &bytes).is_err());
+    }
+}
+
+#[test]
+fn static_variable_access() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "static_variables.ns".to_owned(),
+        include_str!("static_variables.ns").to_owned(),
+    );
+    let program = compiler.compile("static_variables.ns").unwrap();
+    let variables = program.borrow_static_variables();
+    assert_eq!(variables.len(), 3);
+    assert!(variables.contains_key("buffer"));
+    let (mut sample, mut delayed) = (1.0f32, 0.0f32);
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        assert_eq!(static_data.get::<bool>("enabled"), Ok(true));
+        program
+            .execute_data(&mut sample, &mut delayed, &mut static_data, &mut scratch)
+            .unwrap();
+        sample = 2.0;
+        program
+            .execute_data(&mut sample, &mut delayed, &mut static_data, &mut scratch)
+            .unwrap();
+        assert_eq!(static_data.get::<f32>("level"), Ok(3.0));
+        assert_eq!(
+            static_data.get_array::<f32>("buffer"),
+            Ok(vec![2.0, 1.0, 0.0, 0.0])
+        );
+
+        static_data.set_array("buffer", &[0.0f32; 4]).unwrap();
+        static_data.set("enabled", false).unwrap();
+        program
+            .execute_data(&mut sample, &mut delayed, &mut static_data, &mut scratch)
+            .unwrap();
+        assert_eq!(delayed, 0.0);
+        assert_eq!(static_data.get::<f32>("level"), Ok(3.0));
+        assert_eq!(
+            static_data.get_array::<f32>("buffer"),
+            Ok(vec![2.0, 0.0, 0.0, 0.0])
+        );
+
+        assert!(static_data.get::<i32>("level").is_err());
+        assert!(static_data.get::<f32>("buffer").is_err());
+        assert!(static_data.get::<f32>("missing").is_err());
+        assert!(static_data.set_array("buffer", &[0.0f32; 3]).is_err());
+    }
+}
+
+#[test]
+fn macro_static_variables() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "macro_static.ns".to_owned(),
+        include_str!("assert_ok/macro_static.ns").to_owned(),
+    );
+    let program = compiler.compile("macro_static.ns").unwrap();
+    let mut names: Vec<_> = program.borrow_static_variables().keys().cloned().collect();
+    names.sort();
+    assert_eq!(names, vec!["counter(0).total", "counter(10).total"]);
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        assert_eq!(static_data.get::<i32>("counter(10).total"), Ok(10));
+        program
+            .execute_raw(&mut [], &mut [], &mut static_data, &mut scratch)
+            .unwrap();
+        assert_eq!(static_data.get::<i32>("counter(0).total"), Ok(1));
+        assert_eq!(static_data.get::<i32>("counter(10).total"), Ok(11));
+    }
+}
+
+#[test]
+fn macro_static_migration() {
+    let counter = r#"
+        macro counter(start):(count) {
+            static total {
+                INT total = start;
+            }
+            total = total + 1;
+            INT count = total;
+        }
+        output INT result;
+    "#;
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "counters.ns".to_owned(),
+        format!("{}\nresult = counter(10);\n", counter),
+    );
+    let old_program = compiler.compile("counters.ns").unwrap();
+    // Adding a call before the existing one should not give the new call the state of the old
+    // one.
+    compiler.add_source(
+        "counters.ns".to_owned(),
+        format!(
+            "{}\nINT first = counter(0);\nresult = first + counter(10);\n",
+            counter
+        ),
+    );
+    let new_program = compiler.compile("counters.ns").unwrap();
+    let mut result = 0i32;
+    unsafe {
+        let mut old_data = old_program.create_static_data().unwrap();
+        let mut scratch = old_program.create_scratch();
+        old_program
+            .execute_data(&mut (), &mut result, &mut old_data, &mut scratch)
+            .unwrap();
+        assert_eq!(result, 11);
+        let (mut new_data, migration) = new_program
+            .migrate_static_data(&old_program, &old_data)
+            .unwrap();
+        assert_eq!(
+            migration.borrow_preserved(),
+            &vec!["counter(10).total".to_owned()]
+        );
+        assert_eq!(
+            migration.borrow_reinitialized(),
+            &vec!["counter(0).total".to_owned()]
+        );
+        let mut scratch = new_program.create_scratch();
+        new_program
+            .execute_data(&mut (), &mut result, &mut new_data, &mut scratch)
+            .unwrap();
+        assert_eq!(new_data.get::<i32>("counter(0).total"), Ok(1));
+        assert_eq!(new_data.get::<i32>("counter(10).total"), Ok(12));
+        assert_eq!(result, 13);
+    }
+}
+
+#[test]
+fn static_data_migration() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "static_variables.ns".to_owned(),
+        include_str!("static_variables.ns").to_owned(),
+    );
+    let old_program = compiler.compile("static_variables.ns").unwrap();
+    compiler.add_source(
+        "static_variables.ns".to_owned(),
+        r#"
+        input FLOAT sample;
+        output FLOAT delayed;
+        static buffer, level, gain {
+            [8]FLOAT buffer = 0.0;
+            FLOAT level = 0.0;
+            FLOAT gain = 0.5;
+        }
+        level = level + sample * gain;
+        delayed = level;
+        "#
+        .to_owned(),
+    );
+    let new_program = compiler.compile("static_variables.ns").unwrap();
+    let (mut sample, mut delayed) = (4.0f32, 0.0f32);
+    unsafe {
+        let mut old_data = old_program.create_static_data().unwrap();
+        let mut scratch = old_program.create_scratch();
+        old_program
+            .execute_data(&mut sample, &mut delayed, &mut old_data, &mut scratch)
+            .unwrap();
+        let (mut new_data, migration) = new_program
+            .migrate_static_data(&old_program, &old_data)
+            .unwrap();
+        assert_eq!(migration.borrow_preserved(), &vec!["level".to_owned()]);
+        assert_eq!(
+            migration.borrow_reinitialized(),
+            &vec!["buffer".to_owned(), "gain".to_owned()]
+        );
+        assert_eq!(
+            migration.borrow_dropped(),
+            &vec!["buffer".to_owned(), "enabled".to_owned()]
+        );
+        assert_eq!(new_data.get::<f32>("level"), Ok(4.0));
+        assert_eq!(new_data.get::<f32>("gain"), Ok(0.5));
+        new_program
+            .execute_data(&mut sample, &mut delayed, &mut new_data, &mut scratch)
+            .unwrap();
+        assert_eq!(delayed, 6.0);
+    }
+}
+
 #[test]
 fn compile_ok() {
     for entry in std::fs::read_dir("tests/compile_ok/").unwrap() {
@@ -98,11 +408,525 @@ fn assert_ok() {
         };
         unsafe {
             let mut static_data = program.create_static_data().unwrap();
+            let mut scratch = program.create_scratch();
             let mut in_dat = Vec::new();
             let mut out_dat = Vec::new();
-            program.execute_raw(&mut in_dat[..], &mut out_dat[..], &mut static_data).unwrap();
+            program.execute_raw(&mut in_dat[..], &mut out_dat[..], &mut static_data, &mut scratch).unwrap();
+        }
+    }
+}
+
+#[test]
+fn entry_points() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "entry_points.ns".to_owned(),
+        include_str!("entry_points.ns").to_owned(),
+    );
+    let program = compiler.compile("entry_points.ns").unwrap();
+    assert!(program.has_entry_point("reset"));
+    assert!(program.has_entry_point("add"));
+    assert!(!program.has_entry_point("main"));
+    let (mut increment, mut total) = (2i32, 0i32);
+    let (mut amount, mut previous) = (10i32, 0i32);
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        program
+            .execute_data(&mut increment, &mut total, &mut static_data, &mut scratch)
+            .unwrap();
+        assert_eq!(total, 2);
+        program
+            .execute_entry(
+                "add",
+                &mut amount,
+                &mut previous,
+                &mut static_data,
+                &mut scratch,
+            )
+            .unwrap();
+        assert_eq!(previous, 2);
+        program
+            .execute_data(&mut increment, &mut total, &mut static_data, &mut scratch)
+            .unwrap();
+        assert_eq!(total, 14);
+        program
+            .execute_entry("reset", &mut (), &mut (), &mut static_data, &mut scratch)
+            .unwrap();
+        program
+            .execute_data(&mut increment, &mut total, &mut static_data, &mut scratch)
+            .unwrap();
+        assert_eq!(total, 2);
+    }
+}
+
+#[test]
+fn every_block() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "every_block.ns".to_owned(),
+        include_str!("every_block.ns").to_owned(),
+    );
+    let program = compiler.compile("every_block.ns").unwrap();
+    assert!(program.borrow_static_variables().contains_key("gain"));
+    #[repr(C, packed)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Outputs(f32, i32);
+    let mut output = Outputs(0.0, 0);
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        for (index, sample) in [2.0f32, 3.0, 4.0, 5.0, 6.0, 7.0].iter().enumerate() {
+            program
+                .execute_data(
+                    &mut sample.clone(),
+                    &mut output,
+                    &mut static_data,
+                    &mut scratch,
+                )
+                .unwrap();
+            // The block runs on the first and fifth invocations.
+            let gain = if index < 4 { 2.0 } else { 6.0 };
+            let updates = if index < 4 { 1 } else { 2 };
+            assert_eq!(output, Outputs(sample * gain, updates));
+        }
+    }
+
+    // Inside a loop, the block still runs every 2 executions instead of every 2 iterations.
+    compiler.add_source(
+        "every_block_loop.ns".to_owned(),
+        include_str!("every_block_loop.ns").to_owned(),
+    );
+    let program = compiler.compile("every_block_loop.ns").unwrap();
+    let mut fired = 0i32;
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        for index in 0..6 {
+            program
+                .execute_data(&mut 3i32, &mut fired, &mut static_data, &mut scratch)
+                .unwrap();
+            assert_eq!(fired, if index % 2 == 0 { 3 } else { 0 });
+        }
+    }
+}
+
+#[test]
+fn std_oscillators() {
+    #[repr(C, packed)]
+    #[derive(Clone, Copy, Debug, Default)]
+    struct Outputs(f32, f32, f32, f32, f32);
+
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "oscillators.ns".to_owned(),
+        include_str!("oscillators.ns").to_owned(),
+    );
+    let program = compiler.compile("oscillators.ns").unwrap();
+    // A quarter of a cycle per sample, which is exact in floating point.
+    let mut frequency = 12000.0f32;
+    let mut output = Outputs::default();
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        for index in 0..9 {
+            program
+                .execute_data(&mut frequency, &mut output, &mut static_data, &mut scratch)
+                .unwrap();
+            let Outputs(phase, sine, saw, square, triangle) = output;
+            // The phase wraps back to the start after every four samples.
+            let expected_phase = (index % 4) as f32 * 0.25;
+            assert_eq!(phase, expected_phase);
+            let expected_sine = (expected_phase * std::f32::consts::TAU).sin();
+            assert!((sine - expected_sine).abs() < 1e-5);
+            assert_eq!(saw, expected_phase * 2.0 - 1.0);
+            assert_eq!(square, if expected_phase < 0.5 { 1.0 } else { -1.0 });
+            assert_eq!(triangle, [-1.0, 0.0, 1.0, 0.0][index % 4]);
+        }
+    }
+}
+
+extern "C" fn lookup(index: i32, scale: f32, value: *mut f32) {
+    let table = [0.5f32, 1.5, 2.5, 3.5];
+    unsafe { *value = table[index as usize] * scale };
+}
+
+extern "C" fn sum4(values: *const [f32; 4], total: *mut f32) {
+    unsafe { *total = (*values).iter().sum() };
+}
+
+extern "C" fn div_mod(a: i32, b: i32, quotient: *mut i32, remainder: *mut i32) {
+    unsafe {
+        *quotient = a / b;
+        *remainder = a % b;
+    }
+}
+
+#[test]
+fn extern_functions() {
+    use nodespeak::vague::structure::DataType;
+    let mut compiler = nodespeak::Compiler::new();
+    unsafe {
+        compiler.add_extern_function(
+            "Lookup",
+            vec![DataType::Int, DataType::Float],
+            vec![DataType::Float],
+            lookup as *const _,
+        );
+        compiler.add_extern_function(
+            "Sum4",
+            vec![DataType::Array(4, Box::new(DataType::Float))],
+            vec![DataType::Float],
+            sum4 as *const _,
+        );
+        compiler.add_extern_function(
+            "DivMod",
+            vec![DataType::Int, DataType::Int],
+            vec![DataType::Int, DataType::Int],
+            div_mod as *const _,
+        );
+    }
+    compiler.add_source(
+        "extern_functions.ns".to_owned(),
+        include_str!("extern_functions.ns").to_owned(),
+    );
+    let program = compiler.compile("extern_functions.ns").unwrap();
+    #[repr(C, packed)]
+    struct Inputs(i32, f32);
+    #[repr(C, packed)]
+    struct Outputs(f32, f32, i32, i32);
+    let mut inputs = Inputs(2, 2.0);
+    let mut outputs = Outputs(0.0, 0.0, 0, 0);
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        program
+            .execute_data(&mut inputs, &mut outputs, &mut static_data, &mut scratch)
+            .unwrap();
+    }
+    let Outputs(value, total, quotient, remainder) = outputs;
+    assert_eq!((value, total, quotient, remainder), (5.0, 8.0, 0, 2));
+
+    let mut compiler = nodespeak::Compiler::new();
+    unsafe {
+        compiler.add_extern_function(
+            "Lookup",
+            vec![DataType::Int, DataType::Float],
+            vec![DataType::Float],
+            lookup as *const _,
+        );
+    }
+    let code = "output FLOAT value; value = Lookup(1.0, 2.0);";
+    compiler.add_source("wrong_type.ns".to_owned(), code.to_owned());
+    assert!(compiler.compile("wrong_type.ns").is_err());
+}
+
+#[test]
+fn host_constants() {
+    use nodespeak::vague::structure::{DataType, KnownData, Program};
+    let mut compiler = nodespeak::Compiler::new();
+    compiler
+        .define_constant("BLOCK_SIZE", KnownData::Int(4))
+        .unwrap();
+    compiler
+        .define_constant_from_str("SAMPLE_RATE", "48000.0")
+        .unwrap();
+    compiler.define_type_alias("SAMPLE", DataType::Float);
+    assert!(compiler.define_constant_from_str("BAD", "[1, ").is_err());
+    assert!(compiler.define_constant_from_str("BAD", "[]").is_err());
+    assert!(compiler
+        .define_constant_from_str("BAD", "[1, 2.0]")
+        .is_err());
+    // Macros have to come from a prelude, so they are rejected here instead of when compiling.
+    let builtins = Program::new();
+    let select = builtins
+        .lookup_symbol(builtins.get_builtins_scope(), "Select")
+        .unwrap();
+    let mac = builtins[select].borrow_initial_value().clone().unwrap();
+    assert!(compiler.define_constant("MAC", mac.clone()).is_err());
+    let params = [("MAC", KnownData::Array(vec![mac]))];
+    assert!(compiler
+        .compile_with_params("host_constants.ns", &params)
+        .is_err());
+    compiler.add_prelude(
+        "host_prelude.ns".to_owned(),
+        include_str!("host_prelude.ns").to_owned(),
+    );
+    compiler.add_source(
+        "host_constants.ns".to_owned(),
+        include_str!("host_constants.ns").to_owned(),
+    );
+    let program = compiler.compile("host_constants.ns").unwrap();
+    let mut inputs = [1.0f32, 2.0, 3.0, 6.0];
+    let mut outputs = [0.0f32; 2];
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        program
+            .execute_data(&mut inputs, &mut outputs, &mut static_data, &mut scratch)
+            .unwrap();
+    }
+    assert_eq!(outputs, [3.0, 24000.0]);
+
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_prelude("bad_prelude.ns".to_owned(), "input INT x;".to_owned());
+    compiler.add_source("main.ns".to_owned(), "output INT y; y = 1;".to_owned());
+    assert!(compiler.compile("main.ns").is_err());
+}
+
+#[test]
+fn include_loaders() {
+    let run = |compiler: &mut nodespeak::Compiler, name: &str| {
+        let program = compiler.compile(name)?;
+        let mut outputs = [0i32];
+        unsafe {
+            let mut static_data = program.create_static_data().unwrap();
+            let mut scratch = program.create_scratch();
+            program
+                .execute_data(&mut (), &mut outputs, &mut static_data, &mut scratch)
+                .unwrap();
         }
+        Ok::<_, String>(outputs[0])
+    };
+
+    let mut compiler = nodespeak::Compiler::new();
+    let mut loader = nodespeak::FileSystemLoader::new();
+    loader.add_search_path("tests/includes/search");
+    compiler.set_source_loader(Box::new(loader));
+    compiler
+        .add_source_from_file("tests/includes/main.ns".to_owned())
+        .unwrap();
+    assert_eq!(run(&mut compiler, "tests/includes/main.ns"), Ok(42));
+
+    // Without the search path, shared.ns cannot be found.
+    let mut compiler = nodespeak::Compiler::new();
+    compiler
+        .add_source_from_file("tests/includes/main.ns".to_owned())
+        .unwrap();
+    let error = run(&mut compiler, "tests/includes/main.ns").unwrap_err();
+    assert!(error.contains("tests/includes/shared.ns"));
+
+    let mut compiler = nodespeak::Compiler::new();
+    let mut loader = nodespeak::MemoryLoader::new();
+    loader.add_file(
+        "project/lib/math.ns".to_owned(),
+        "include \"consts.ns\"; macro Scale(value):(result) { INT result = value * FACTOR; }"
+            .to_owned(),
+    );
+    loader.add_file(
+        "project/lib/consts.ns".to_owned(),
+        "INT FACTOR = 3;".to_owned(),
+    );
+    compiler.set_source_loader(Box::new(loader));
+    compiler.add_source(
+        "project/main.ns".to_owned(),
+        "include \"lib/math.ns\"; output INT result; result = Scale(5);".to_owned(),
+    );
+    assert_eq!(run(&mut compiler, "project/main.ns"), Ok(15));
+
+    // A file next to the including file wins over a source with the same name that was added to
+    // the compiler, and different spellings of the same path only include it once.
+    let mut compiler = nodespeak::Compiler::new();
+    let mut loader = nodespeak::MemoryLoader::new();
+    loader.add_file("project/lib/util.ns".to_owned(), "INT UTIL = 2;".to_owned());
+    loader.add_file(
+        "project/lib/math.ns".to_owned(),
+        "include \"util.ns\"; include \"./util.ns\"; INT MATH = UTIL * 10;".to_owned(),
+    );
+    compiler.set_source_loader(Box::new(loader));
+    compiler.add_source("util.ns".to_owned(), "INT UTIL = 1;".to_owned());
+    compiler.add_source(
+        "project/main.ns".to_owned(),
+        "include \"lib/math.ns\"; include \"lib/../lib/util.ns\"; output INT result; result = MATH + UTIL;"
+            .to_owned(),
+    );
+    assert_eq!(run(&mut compiler, "project/main.ns"), Ok(22));
+}
+
+#[test]
+fn compile_with_params() {
+    use nodespeak::vague::structure::KnownData;
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "specialization.ns".to_owned(),
+        include_str!("specialization.ns").to_owned(),
+    );
+    let stereo = compiler
+        .compile_with_params("specialization.ns", &[("CHANNELS", KnownData::Int(2))])
+        .unwrap();
+    let quad = compiler
+        .compile_with_params("specialization.ns", &[("CHANNELS", KnownData::Int(4))])
+        .unwrap();
+    let mut inputs = 0.5f32;
+    let mut stereo_outputs = [0.0f32; 2];
+    let mut quad_outputs = [0.0f32; 4];
+    unsafe {
+        let mut static_data = stereo.create_static_data().unwrap();
+        let mut scratch = stereo.create_scratch();
+        stereo
+            .execute_data(
+                &mut inputs,
+                &mut stereo_outputs,
+                &mut static_data,
+                &mut scratch,
+            )
+            .unwrap();
+        let mut static_data = quad.create_static_data().unwrap();
+        let mut scratch = quad.create_scratch();
+        quad.execute_data(
+            &mut inputs,
+            &mut quad_outputs,
+            &mut static_data,
+            &mut scratch,
+        )
+        .unwrap();
+    }
+    assert_eq!(stereo_outputs, [0.5, 1.0]);
+    assert_eq!(quad_outputs, [0.5, 1.0, 1.5, 2.0]);
+
+    let again = compiler
+        .compile_with_params("specialization.ns", &[("CHANNELS", KnownData::Int(2))])
+        .unwrap();
+    assert!(std::rc::Rc::ptr_eq(&stereo, &again));
+    // The order the parameters are given in does not matter.
+    let both = compiler
+        .compile_with_params(
+            "specialization.ns",
+            &[
+                ("CHANNELS", KnownData::Int(2)),
+                ("UNUSED", KnownData::Int(1)),
+            ],
+        )
+        .unwrap();
+    let swapped = compiler
+        .compile_with_params(
+            "specialization.ns",
+            &[
+                ("UNUSED", KnownData::Int(1)),
+                ("CHANNELS", KnownData::Int(2)),
+            ],
+        )
+        .unwrap();
+    assert!(std::rc::Rc::ptr_eq(&both, &swapped));
+    // Changing the source means the cached version is out of date.
+    compiler.add_source(
+        "specialization.ns".to_owned(),
+        include_str!("specialization.ns").to_owned(),
+    );
+    let recompiled = compiler
+        .compile_with_params("specialization.ns", &[("CHANNELS", KnownData::Int(2))])
+        .unwrap();
+    assert!(!std::rc::Rc::ptr_eq(&stereo, &recompiled));
+    assert!(compiler.compile("specialization.ns").is_err());
+}
+
+#[test]
+fn specializable_inputs() {
+    use nodespeak::vague::structure::KnownData;
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "specializable_inputs.ns".to_owned(),
+        include_str!("specializable_inputs.ns").to_owned(),
+    );
+    #[repr(C, packed)]
+    struct Inputs(i32, f32);
+    #[repr(C, packed)]
+    struct Outputs(f32, i32);
+
+    let doubled = compiler
+        .compile_specialized("specializable_inputs.ns", &[("mode", KnownData::Int(1))])
+        .unwrap();
+    // The value in the input data is ignored.
+    let mut inputs = Inputs(0, 3.0);
+    let mut outputs = Outputs(0.0, 0);
+    unsafe {
+        let mut static_data = doubled.create_static_data().unwrap();
+        let mut scratch = doubled.create_scratch();
+        doubled
+            .execute_data(&mut inputs, &mut outputs, &mut static_data, &mut scratch)
+            .unwrap();
     }
+    let Outputs(result, _) = outputs;
+    assert_eq!(result, 6.0);
+    assert!(compiler
+        .compile_specialized(
+            "specializable_inputs.ns",
+            &[("sample", KnownData::Float(1.0))]
+        )
+        .is_err());
+
+    let mut program = compiler
+        .compile_specializing("specializable_inputs.ns")
+        .unwrap();
+    let run = |program: &mut nodespeak::SpecializingProgram, mode, sample| {
+        let mut inputs = Inputs(mode, sample);
+        let mut outputs = Outputs(0.0, 0);
+        unsafe {
+            program.execute_data(&mut inputs, &mut outputs).unwrap();
+        }
+        let Outputs(result, calls) = outputs;
+        (result, calls)
+    };
+    assert_eq!(run(&mut program, 1, 2.0), (4.0, 1));
+    assert_eq!(run(&mut program, 2, 2.0), (-2.0, 2));
+    assert_eq!(program.get_num_pending(), 2);
+    assert_eq!(unsafe { program.compile_pending(&mut compiler) }, Ok(2));
+    assert_eq!(program.get_num_specializations(), 2);
+    // Static data is carried over when switching between versions.
+    assert_eq!(run(&mut program, 1, 2.0), (4.0, 3));
+    assert_eq!(run(&mut program, 2, 2.0), (-2.0, 4));
+    assert_eq!(run(&mut program, 0, 2.0), (2.0, 5));
+    assert_eq!(run(&mut program, 0, 2.0), (2.0, 6));
+    // Running again with values that are already waiting does not queue them a second time.
+    assert_eq!(program.get_num_pending(), 1);
+
+    // The size of the input data is checked before the specializable inputs are read from it.
+    let short_input = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe {
+        program.execute_raw(&mut [0u8; 2], &mut [0u8; 8])
+    }));
+    let message = short_input.unwrap_err();
+    assert_eq!(
+        message.downcast_ref::<String>().unwrap(),
+        "Expected 8, got 2."
+    );
+}
+
+#[test]
+fn specialized_static_variables() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "specialized_statics.ns".to_owned(),
+        include_str!("specialized_statics.ns").to_owned(),
+    );
+    #[repr(C, packed)]
+    struct Inputs(i32);
+    #[repr(C, packed)]
+    struct Outputs(i32, i32, i32);
+
+    let mut program = compiler
+        .compile_specializing("specialized_statics.ns")
+        .unwrap();
+    let run = |program: &mut nodespeak::SpecializingProgram, mode| {
+        let mut outputs = Outputs(0, 0, 0);
+        unsafe {
+            program
+                .execute_data(&mut Inputs(mode), &mut outputs)
+                .unwrap();
+        }
+        let Outputs(first, second, ticks) = outputs;
+        (first, second, ticks)
+    };
+    assert_eq!(run(&mut program, 0), (0, 101, 1));
+    assert_eq!(run(&mut program, 0), (0, 102, 0));
+    assert_eq!(unsafe { program.compile_pending(&mut compiler) }, Ok(1));
+    // The specialized version does not call the first counter, so the static variable of the
+    // second one has a different name in it. The counter of the every block has no name at all.
+    // Both should still keep their values.
+    assert_eq!(run(&mut program, 0), (0, 103, 0));
+    assert_eq!(run(&mut program, 0), (0, 104, 1));
+    assert_eq!(run(&mut program, 1), (1, 105, 0));
+    assert_eq!(run(&mut program, 0), (0, 106, 0));
+    assert_eq!(run(&mut program, 1), (2, 107, 1));
 }
 
 #[test]
@@ -122,3 +946,1222 @@ fn compile_err() {
         }
     }
 }
+
+/// Compiles the C version of a program along with a harness which runs the main body once for
+/// every input struct read from stdin and writes each output struct to stdout. Panics if there is
+/// no C compiler available, since the C backend cannot be checked without one.
+fn run_c_version(
+    compiler: &mut nodespeak::Compiler,
+    source_name: &str,
+    input_data: &[u8],
+) -> Result<Vec<u8>, String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    const HARNESS: &str = r#"
+#include <stdio.h>
+
+int main(void) {
+    ns_input_t in;
+    ns_output_t out;
+    ns_static_t st;
+    uint32_t code = ns_static_init(&st);
+    while (code == 0 && fread(&in, sizeof(in), 1, stdin) == 1) {
+        code = ns_main(&in, &st, &out);
+        fwrite(&out, sizeof(out), 1, stdout);
+    }
+    if (code != 0) {
+        fprintf(stderr, "%s", ns_describe_error(code));
+        return 1;
+    }
+    return 0;
+}
+"#;
+    let code = compiler.compile_to_c(source_name, "ns").unwrap() + HARNESS;
+    let name: String = source_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    let dir = std::env::temp_dir();
+    let base = dir.join(format!("nodespeak_{}_{}", std::process::id(), name));
+    let c_path = base.with_extension("c");
+    std::fs::write(&c_path, code).unwrap();
+    let status = Command::new("cc")
+        .arg("-std=c99")
+        .arg("-o")
+        .arg(&base)
+        .arg(&c_path)
+        .arg("-lm")
+        .status()
+        .expect("No C compiler was found. The tests of the C backend need `cc` to be installed.");
+    assert!(
+        status.success(),
+        "Failed to compile C version of {}.",
+        source_name
+    );
+    let mut child = Command::new(&base)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input_data).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_file(&c_path);
+    let _ = std::fs::remove_file(&base);
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}
+
+#[test]
+fn c_backend() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "c_backend.ns".to_owned(),
+        include_str!("c_backend.ns").to_owned(),
+    );
+    let program = compiler.compile("c_backend.ns").unwrap();
+    // sample, values, negate, index.
+    let inputs = [
+        (1.5f32, [1, 2, 3, 4], false, 0),
+        (-2.25, [-10, 0, 10, 20], true, 3),
+        (0.125, [7, 7, 7, 7], true, 6),
+        (99.0, [100000, -100000, 3, 2], false, 1),
+        (0.0, [0, 0, 0, 0], false, 2),
+    ];
+    let mut input_data = Vec::new();
+    let mut jit_output = Vec::new();
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        for (sample, values, negate, index) in &inputs {
+            let mut in_dat = Vec::new();
+            in_dat.extend_from_slice(&sample.to_ne_bytes());
+            for value in values {
+                in_dat.extend_from_slice(&(*value as i32).to_ne_bytes());
+            }
+            in_dat.push(*negate as u8);
+            in_dat.extend_from_slice(&(*index as i32).to_ne_bytes());
+            let mut out_dat = vec![0u8; 28];
+            program
+                .execute_raw(
+                    &mut in_dat[..],
+                    &mut out_dat[..],
+                    &mut static_data,
+                    &mut scratch,
+                )
+                .unwrap();
+            input_data.append(&mut in_dat);
+            jit_output.append(&mut out_dat);
+        }
+    }
+    let c_output = run_c_version(&mut compiler, "c_backend.ns", &input_data).unwrap();
+    assert_eq!(c_output, jit_output);
+
+    // Both versions should report the same error when an assert fails.
+    let mut bad_input = input_data[..25].to_owned();
+    bad_input[..4].copy_from_slice(&1000.0f32.to_ne_bytes());
+    let error = run_c_version(&mut compiler, "c_backend.ns", &bad_input).unwrap_err();
+    assert_eq!(
+        error,
+        format!(
+            "{:?}: {}",
+            program.borrow_errors()[0].get_kind(),
+            program.borrow_errors()[0].borrow_message()
+        )
+    );
+
+    // Programs without inputs have a single unused byte in their input struct.
+    for entry in std::fs::read_dir("tests/assert_ok/").unwrap() {
+        let name = entry.unwrap().path().to_str().unwrap().to_owned();
+        let mut compiler = nodespeak::Compiler::new();
+        compiler.add_source(name.clone(), std::fs::read_to_string(&name).unwrap());
+        if let Err(message) = run_c_version(&mut compiler, &name, &[0]) {
+            panic!("C version of {} failed: {}", name, message);
+        }
+    }
+}
+
+#[test]
+fn element_wise_fusion() {
+    use nodespeak::trivial::structure::StorageLocation;
+
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "element_wise.ns".to_owned(),
+        include_str!("element_wise.ns").to_owned(),
+    );
+    // Only the declared local and the result that has to be computed before being stored need to
+    // be full-size arrays, everything else is done one element at a time.
+    let trivial = compiler.compile_to_trivial("element_wise.ns").unwrap();
+    let local_arrays = trivial
+        .iterate_all_variables()
+        .filter(|id| {
+            let variable = &trivial[*id];
+            variable.get_location() == StorageLocation::MainBody
+                && variable.borrow_type().collect_dimensions().len() > 0
+        })
+        .count();
+    assert_eq!(local_arrays, 2);
+
+    let program = compiler.compile("element_wise.ns").unwrap();
+    let a: Vec<f32> = (0..8).map(|i| i as f32 * 0.5 - 1.0).collect();
+    let b: Vec<f32> = (0..8).map(|i| 3.0 - i as f32).collect();
+    let c: Vec<f32> = (0..8).map(|i| (i * i) as f32).collect();
+    let gain = 0.25f32;
+    let m: Vec<f32> = (0..16).map(|i| i as f32 - 4.0).collect();
+    let col = [2.0f32, -3.0];
+    let sq = [1i32, 2, 30, 40];
+    let mut in_dat = Vec::new();
+    for value in a
+        .iter()
+        .chain(&b)
+        .chain(&c)
+        .chain(&[gain])
+        .chain(&m)
+        .chain(&col)
+    {
+        in_dat.extend_from_slice(&value.to_ne_bytes());
+    }
+    for value in &sq {
+        in_dat.extend_from_slice(&value.to_ne_bytes());
+    }
+    let mut out_dat = vec![0u8; 36 * 4];
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        program
+            .execute_raw(
+                &mut in_dat[..],
+                &mut out_dat[..],
+                &mut static_data,
+                &mut scratch,
+            )
+            .unwrap();
+    }
+    let word = |index: usize| {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&out_dat[index * 4..index * 4 + 4]);
+        bytes
+    };
+    for i in 0..8 {
+        let mixed = f32::from_ne_bytes(word(i));
+        assert_eq!(mixed, a[i] * b[i] + c[i] * (gain * 2.0));
+        assert_eq!(f32::from_ne_bytes(word(8 + i)), a[i] * 2.0 + a[i]);
+        let first = m[i] * col[0] + gain;
+        let second = m[8 + i] * col[1] + gain;
+        assert_eq!(f32::from_ne_bytes(word(16 + i)), first);
+        assert_eq!(f32::from_ne_bytes(word(24 + i)), first - second);
+    }
+    let offset: Vec<i32> = (32..36)
+        .map(|index| i32::from_ne_bytes(word(index)))
+        .collect();
+    assert_eq!(offset, vec![1 + 1, 2 + 1, 30 + 2, 40 + 2]);
+
+    let c_output = run_c_version(&mut compiler, "element_wise.ns", &in_dat).unwrap();
+    assert_eq!(c_output, out_dat);
+}
+
+#[test]
+fn scratch_memory() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "element_wise.ns".to_owned(),
+        include_str!("element_wise.ns").to_owned(),
+    );
+    let mut in_dat = Vec::new();
+    for index in 0..43 {
+        in_dat.extend_from_slice(&(index as f32 * 0.75 - 3.0).to_ne_bytes());
+    }
+    for value in &[1i32, -2, 3, 4] {
+        in_dat.extend_from_slice(&value.to_ne_bytes());
+    }
+    let run = |program: &nodespeak::llvmir::structure::Program,
+               scratch: &mut nodespeak::Scratch| {
+        let mut in_dat = in_dat.clone();
+        let mut out_dat = vec![0u8; 36 * 4];
+        unsafe {
+            let mut static_data = program.create_static_data().unwrap();
+            program
+                .execute_raw(&mut in_dat[..], &mut out_dat[..], &mut static_data, scratch)
+                .unwrap();
+        }
+        out_dat
+    };
+
+    let on_stack = compiler.compile("element_wise.ns").unwrap();
+    assert_eq!(on_stack.scratch_size(), 0);
+    let expected = run(&on_stack, &mut nodespeak::Scratch::default());
+
+    // Both local arrays (work and the temporary holding work + work[0]) are bigger than a single
+    // value, so they should be moved to scratch.
+    compiler.set_scratch_threshold(Some(4));
+    let in_scratch = compiler.compile("element_wise.ns").unwrap();
+    assert_eq!(in_scratch.scratch_size(), 2 * (2 * 2 * 4));
+    let mut scratch = nodespeak::Scratch::default();
+    scratch.reserve(in_scratch.scratch_size());
+    assert_eq!(run(&in_scratch, &mut scratch), expected);
+    // Scratch contents are not preserved between runs, so running again should not change
+    // anything.
+    assert_eq!(run(&in_scratch, &mut scratch), expected);
+
+    let too_small = std::panic::catch_unwind(|| run(&in_scratch, &mut nodespeak::Scratch::new(4)));
+    assert!(too_small.is_err());
+}
+
+#[test]
+fn scratch_alignment() {
+    #[repr(C, packed)]
+    #[derive(Clone, Copy)]
+    struct Data([bool; 5], [f32; 4]);
+
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "scratch_alignment.ns".to_owned(),
+        include_str!("scratch_alignment.ns").to_owned(),
+    );
+    compiler.set_scratch_threshold(Some(4));
+    let program = compiler.compile("scratch_alignment.ns").unwrap();
+    // The floats start at a multiple of 4 instead of right after the bools.
+    assert_eq!(program.scratch_size(), 8 + 4 * 4);
+    let mut scratch = program.create_scratch();
+    assert_eq!(
+        scratch.as_mut_ptr() as usize % nodespeak::Scratch::ALIGNMENT,
+        0
+    );
+    let mut inputs = Data([true, false, true, true, false], [0.5, -1.0, 2.0, 3.5]);
+    let mut outputs = Data([false; 5], [0.0; 4]);
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        program
+            .execute_data(&mut inputs, &mut outputs, &mut static_data, &mut scratch)
+            .unwrap();
+    }
+    let Data(inverted, doubled) = outputs;
+    assert_eq!(inverted, [false, true, false, false, true]);
+    assert_eq!(doubled, [1.0, -2.0, 4.0, 7.0]);
+}
+
+#[test]
+fn multiple_instances() {
+    #[repr(C, packed)]
+    #[derive(Clone, Copy)]
+    struct Inputs(f32, bool);
+    #[repr(C, packed)]
+    #[derive(Clone, Copy, Default)]
+    struct Outputs(f32, [f32; 2]);
+
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "instances.ns".to_owned(),
+        include_str!("instances.ns").to_owned(),
+    );
+    let single = compiler.compile("instances.ns").unwrap();
+    assert_eq!(single.get_instance_count(), None);
+    compiler.set_instance_count(Some(4));
+    let program = compiler.compile("instances.ns").unwrap();
+    assert_eq!(program.get_instance_count(), Some(4));
+
+    let blocks = [
+        [
+            Inputs(0.5, true),
+            Inputs(-2.0, false),
+            Inputs(3.0, true),
+            Inputs(0.25, false),
+        ],
+        [
+            Inputs(1.5, false),
+            Inputs(4.0, true),
+            Inputs(-1.0, true),
+            Inputs(8.0, true),
+        ],
+        [
+            Inputs(2.0, true),
+            Inputs(0.0, false),
+            Inputs(6.0, false),
+            Inputs(-3.5, true),
+        ],
+    ];
+    let mut scratch = program.create_scratch();
+    unsafe {
+        // Running every voice at once should give the same results as running them one at a time.
+        let mut states = program.create_instance_states().unwrap();
+        assert_eq!(states.get_instance_count(), 4);
+        let mut voices: Vec<_> = (0..4)
+            .map(|_| single.create_static_data().unwrap())
+            .collect();
+        for block in &blocks {
+            let mut inputs = *block;
+            let mut outputs = [Outputs::default(); 4];
+            program
+                .execute_instances(&mut inputs, &mut outputs, &mut states, &mut scratch)
+                .unwrap();
+            for voice in 0..4 {
+                let mut expected = Outputs::default();
+                single
+                    .execute_data(
+                        &mut block[voice].clone(),
+                        &mut expected,
+                        &mut voices[voice],
+                        &mut scratch,
+                    )
+                    .unwrap();
+                let (Outputs(a, b), Outputs(c, d)) = (outputs[voice], expected);
+                assert_eq!((a, b), (c, d));
+            }
+        }
+        let static_data = states.borrow_static_data();
+        let levels: Vec<f32> = voices
+            .iter()
+            .map(|data| data.get("level").unwrap())
+            .collect();
+        assert_eq!(static_data.get_array::<f32>("level").unwrap(), levels);
+        assert_eq!(static_data.get_array::<i32>("count").unwrap(), vec![3; 4]);
+
+        // The inputs and outputs of every voice can also be passed with one array per variable.
+        let mut in_dat = Vec::new();
+        for sample in &[1.0f32, 2.0, 3.0, 4.0] {
+            in_dat.extend_from_slice(&sample.to_ne_bytes());
+        }
+        in_dat.extend_from_slice(&[1, 1, 1, 1]);
+        let mut out_dat = vec![0u8; 3 * 4 * 4];
+        program.reinit_instance_states(&mut states).unwrap();
+        program
+            .execute_instances_raw(&mut in_dat[..], &mut out_dat[..], &mut states, &mut scratch)
+            .unwrap();
+        let word = |index: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&out_dat[index * 4..index * 4 + 4]);
+            f32::from_ne_bytes(bytes)
+        };
+        let envelopes: Vec<_> = (0..4).map(word).collect();
+        assert_eq!(envelopes, vec![2.0, 3.0, 4.0, 5.0]);
+        let stereo: Vec<_> = (4..12).map(word).collect();
+        assert_eq!(stereo, vec![2.0, 1.0, 6.0, 1.0, 12.0, 1.0, 20.0, 1.0]);
+
+        // A voice failing should not stop the others from running.
+        let mut inputs = [
+            Inputs(1.0, true),
+            Inputs(1.0, true),
+            Inputs(1000.0, true),
+            Inputs(1.0, true),
+        ];
+        let mut outputs = [Outputs::default(); 4];
+        let (index, error) = program
+            .execute_instances(&mut inputs, &mut outputs, &mut states, &mut scratch)
+            .unwrap_err();
+        assert_eq!(index, 2);
+        assert_eq!(error.get_kind(), nodespeak::RuntimeErrorKind::Assert);
+        let envelope = outputs[3].0;
+        assert_eq!(envelope, 6.0);
+    }
+}
+
+#[test]
+fn vectorized_instances() {
+    #[repr(C, packed)]
+    #[derive(Clone, Copy)]
+    struct Inputs(f32, f32);
+
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "instances_simd.ns".to_owned(),
+        include_str!("instances_simd.ns").to_owned(),
+    );
+    compiler.set_instance_count(Some(8));
+    let program = compiler.compile("instances_simd.ns").unwrap();
+    // Neither the assert nor the if statement should stop the instances from being run with SIMD
+    // instructions.
+    let ir = format!("{:?}", program);
+    assert!(ir.contains(" x float>"), "{}", ir);
+
+    let mut inputs: Vec<_> = (0..8)
+        .map(|index| Inputs(index as f32 - 3.0, 2.0))
+        .collect();
+    inputs[5].1 = -1.0;
+    let mut outputs = [0.0f32; 8];
+    let mut scratch = program.create_scratch();
+    unsafe {
+        let mut states = program.create_instance_states().unwrap();
+        let (index, error) = program
+            .execute_instances(&mut inputs, &mut outputs, &mut states, &mut scratch)
+            .unwrap_err();
+        assert_eq!(index, 5);
+        assert_eq!(error.get_kind(), nodespeak::RuntimeErrorKind::Assert);
+    }
+    for (index, output) in outputs.iter().enumerate() {
+        // The outputs of the instance which failed are unspecified.
+        if index == 5 {
+            continue;
+        }
+        let sample = index as f32 - 3.0;
+        let expected = if sample > 0.0 {
+            sample * 2.0
+        } else {
+            sample + 2.0
+        };
+        assert_eq!(*output, expected);
+    }
+
+    // Every instance gets its own copy of the scratch data.
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "scratch_alignment.ns".to_owned(),
+        include_str!("scratch_alignment.ns").to_owned(),
+    );
+    compiler.set_scratch_threshold(Some(4));
+    compiler.set_instance_count(Some(4));
+    let program = compiler.compile("scratch_alignment.ns").unwrap();
+    assert_eq!(program.scratch_size(), 4 * (8 + 4 * 4));
+    #[repr(C, packed)]
+    #[derive(Clone, Copy)]
+    struct Data([bool; 5], [f32; 4]);
+    let mut inputs: Vec<_> = (0..4)
+        .map(|index| Data([index % 2 == 0; 5], [index as f32; 4]))
+        .collect();
+    let mut outputs = [Data([false; 5], [0.0; 4]); 4];
+    let mut scratch = program.create_scratch();
+    unsafe {
+        let mut states = program.create_instance_states().unwrap();
+        program
+            .execute_instances(&mut inputs, &mut outputs, &mut states, &mut scratch)
+            .unwrap();
+    }
+    for (index, Data(inverted, doubled)) in outputs.iter().copied().enumerate() {
+        assert_eq!(inverted, [index % 2 == 1; 5]);
+        assert_eq!(doubled, [index as f32 * 2.0; 4]);
+    }
+}
+
+#[test]
+fn instance_aborts_are_contained() {
+    #[repr(C, packed)]
+    #[derive(Clone, Copy)]
+    struct Inputs(i32, f32);
+
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "instances_bounds.ns".to_owned(),
+        include_str!("instances_bounds.ns").to_owned(),
+    );
+    compiler.set_instance_count(Some(4));
+    let program = compiler.compile("instances_bounds.ns").unwrap();
+    // The second instance fails the bounds check. Writing to index 4 of its history would
+    // overwrite the history of the third instance.
+    let mut inputs = [
+        Inputs(0, 1.0),
+        Inputs(4, 2.0),
+        Inputs(2, 3.0),
+        Inputs(3, 4.0),
+    ];
+    let mut outputs = [0.0f32; 4];
+    let mut scratch = program.create_scratch();
+    unsafe {
+        let mut states = program.create_instance_states().unwrap();
+        let (index, error) = program
+            .execute_instances(&mut inputs, &mut outputs, &mut states, &mut scratch)
+            .unwrap_err();
+        assert_eq!(index, 1);
+        assert_eq!(error.get_kind(), nodespeak::RuntimeErrorKind::Assert);
+        let static_data = states.borrow_static_data();
+        #[rustfmt::skip]
+        assert_eq!(
+            static_data.get_array::<f32>("history"),
+            Ok(vec![
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 0.0, 0.0, 0.0,
+                0.0, 0.0, 3.0, 0.0,
+                0.0, 0.0, 0.0, 4.0,
+            ])
+        );
+        // The static data of the instance which failed is left as it was.
+        assert_eq!(static_data.get_array::<i32>("runs"), Ok(vec![1, 0, 1, 1]));
+    }
+    assert_eq!([outputs[0], outputs[2], outputs[3]], [1.0, 3.0, 4.0]);
+}
+
+#[test]
+fn select_lowering() {
+    use nodespeak::trivial::passes::Pass;
+    use nodespeak::trivial::structure::{CodeBody, Instruction};
+
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source("select.ns".to_owned(), include_str!("select.ns").to_owned());
+    let trivial = compiler.compile_to_trivial("select.ns").unwrap();
+    let main = trivial.borrow_body(CodeBody::Main);
+    let count = |pred: fn(&Instruction) -> bool| main.iter().filter(|i| pred(i)).count();
+    // Only the loops over the elements of `gated` should still need to branch.
+    assert_eq!(count(|i| matches!(i, Instruction::Branch { .. })), 2);
+    assert_eq!(count(|i| matches!(i, Instruction::Jump { .. })), 0);
+    assert_eq!(count(|i| matches!(i, Instruction::Select { .. })), 5);
+
+    // sample, block, mode.
+    let inputs = [
+        (2.0f32, [0.25f32, 0.75, 1.0, -1.0], 1),
+        (-3.0, [0.5, 0.0, 2.0, 0.6], 0),
+        (0.5, [1.0; 4], 1),
+        (-0.25, [-2.0; 4], 7),
+    ];
+    // clipped, rectified, gated, picked.
+    let expected = [
+        (1.0f32, 2.0f32, [0.0f32, 0.75, 1.0, 0.0], 10),
+        (-1.0, 3.0, [0.0, 0.0, 2.0, 0.6], 20),
+        (0.5, 0.5, [1.0; 4], 10),
+        (-0.25, 0.25, [0.0; 4], 20),
+    ];
+    let mut input_data = Vec::new();
+    for (sample, block, mode) in &inputs {
+        input_data.extend_from_slice(&sample.to_ne_bytes());
+        for value in block {
+            input_data.extend_from_slice(&value.to_ne_bytes());
+        }
+        input_data.extend_from_slice(&(*mode as i32).to_ne_bytes());
+    }
+    let mut expected_output = Vec::new();
+    for (clipped, rectified, gated, picked) in &expected {
+        expected_output.extend_from_slice(&clipped.to_ne_bytes());
+        expected_output.extend_from_slice(&rectified.to_ne_bytes());
+        for value in gated {
+            expected_output.extend_from_slice(&value.to_ne_bytes());
+        }
+        expected_output.extend_from_slice(&(*picked as i32).to_ne_bytes());
+    }
+
+    // The results should be the same whether or not the branches were converted.
+    for convert in &[true, false] {
+        compiler.set_pass_enabled(Pass::IfConversion, *convert);
+        let program = compiler.compile("select.ns").unwrap();
+        let mut output = Vec::new();
+        unsafe {
+            let mut static_data = program.create_static_data().unwrap();
+            let mut scratch = program.create_scratch();
+            for in_dat in input_data.chunks(24) {
+                let mut in_dat = in_dat.to_owned();
+                let mut out_dat = vec![0u8; 28];
+                program
+                    .execute_raw(
+                        &mut in_dat[..],
+                        &mut out_dat[..],
+                        &mut static_data,
+                        &mut scratch,
+                    )
+                    .unwrap();
+                output.append(&mut out_dat);
+            }
+        }
+        assert_eq!(output, expected_output);
+    }
+
+    compiler.set_pass_enabled(Pass::IfConversion, true);
+    let c_output = run_c_version(&mut compiler, "select.ns", &input_data).unwrap();
+    assert_eq!(c_output, expected_output);
+}
+
+/// Runs the WebAssembly version of a program in an interpreter. The input, static, and output data
+/// are placed one after another starting at the heap base.
+struct WasmInstance {
+    program: nodespeak::wasm::structure::Program,
+    store: wasmi::Store<()>,
+    instance: wasmi::Instance,
+    memory: wasmi::Memory,
+}
+
+impl WasmInstance {
+    fn new(program: nodespeak::wasm::structure::Program) -> Self {
+        use wasmi::core::F32;
+
+        let engine = wasmi::Engine::default();
+        let module = wasmi::Module::new(&engine, &program.borrow_binary()[..]).unwrap();
+        let mut store = wasmi::Store::new(&engine, ());
+        let mut linker = <wasmi::Linker<()>>::new(&engine);
+        let unary: &[(&str, fn(f32) -> f32)] = &[
+            ("sin", f32::sin),
+            ("cos", f32::cos),
+            ("exp", f32::exp),
+            ("exp2", f32::exp2),
+            ("log", f32::ln),
+            ("log10", f32::log10),
+            ("log2", f32::log2),
+        ];
+        for (name, function) in unary {
+            let function = *function;
+            linker
+                .func_wrap("math", name, move |x: F32| {
+                    F32::from_float(function(x.to_float()))
+                })
+                .unwrap();
+        }
+        linker
+            .func_wrap("math", "pow", |x: F32, y: F32| {
+                F32::from_float(x.to_float().powf(y.to_float()))
+            })
+            .unwrap();
+        linker
+            .func_wrap("math", "fmod", |x: F32, y: F32| {
+                F32::from_float(x.to_float() % y.to_float())
+            })
+            .unwrap();
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let memory = instance.get_memory(&store, "memory").unwrap();
+        let mut result = Self {
+            program,
+            store,
+            instance,
+            memory,
+        };
+        let static_address = result.static_address();
+        result.call("static_init", &[static_address]).unwrap();
+        result
+    }
+
+    fn static_address(&self) -> i32 {
+        let input_size = self.program.get_input_size();
+        let max_entry_input_size = self
+            .program
+            .borrow_entry_points()
+            .iter()
+            .map(|entry| entry.get_input_size())
+            .fold(input_size, usize::max);
+        (self.program.get_heap_base() + max_entry_input_size) as i32
+    }
+
+    fn call(&mut self, name: &str, args: &[i32]) -> Result<(), String> {
+        let function = self.instance.get_func(&self.store, name).unwrap();
+        let args: Vec<_> = args.iter().map(|arg| wasmi::Value::I32(*arg)).collect();
+        let mut result = [wasmi::Value::I32(0)];
+        function
+            .call(&mut self.store, &args[..], &mut result[..])
+            .unwrap();
+        match result[0].i32().unwrap() {
+            0 => Ok(()),
+            code => {
+                let error = &self.program.borrow_errors()[code as usize - 1];
+                Err(format!(
+                    "{:?}: {}",
+                    error.get_kind(),
+                    error.borrow_message()
+                ))
+            }
+        }
+    }
+
+    /// Runs `main` or `entry_<name>` with the given input data, returning the output data.
+    fn execute(&mut self, function: &str, input_data: &[u8]) -> Result<Vec<u8>, String> {
+        let output_size = if function == "main" {
+            self.program.get_output_size()
+        } else {
+            let name = &function["entry_".len()..];
+            self.program
+                .borrow_entry_points()
+                .iter()
+                .find(|entry| entry.borrow_name() == name)
+                .unwrap()
+                .get_output_size()
+        };
+        let input_address = self.program.get_heap_base() as i32;
+        let static_address = self.static_address();
+        let output_address = static_address + self.program.get_static_size() as i32;
+        self.memory
+            .write(&mut self.store, input_address as usize, input_data)
+            .unwrap();
+        self.call(function, &[input_address, static_address, output_address])?;
+        let mut output_data = vec![0; output_size];
+        self.memory
+            .read(&self.store, output_address as usize, &mut output_data[..])
+            .unwrap();
+        Ok(output_data)
+    }
+}
+
+#[test]
+fn wasm_backend() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "c_backend.ns".to_owned(),
+        include_str!("c_backend.ns").to_owned(),
+    );
+    let program = compiler.compile("c_backend.ns").unwrap();
+    let wasm_program = compiler.compile_to_wasm("c_backend.ns").unwrap();
+    // The text version should describe exactly the same module.
+    assert_eq!(
+        &wat::parse_str(wasm_program.borrow_text()).unwrap(),
+        wasm_program.borrow_binary()
+    );
+    assert_eq!(wasm_program.get_input_size(), 25);
+    assert_eq!(wasm_program.get_output_size(), 28);
+    let mut instance = WasmInstance::new(wasm_program);
+    // sample, values, negate, index.
+    let inputs = [
+        (1.5f32, [1, 2, 3, 4], false, 0),
+        (-2.25, [-10, 0, 10, 20], true, 3),
+        (0.125, [7, 7, 7, 7], true, 6),
+        (99.0, [100000, -100000, 3, 2], false, 1),
+        (0.0, [0, 0, 0, 0], false, 2),
+    ];
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        for (sample, values, negate, index) in &inputs {
+            let mut in_dat = Vec::new();
+            in_dat.extend_from_slice(&sample.to_le_bytes());
+            for value in values {
+                in_dat.extend_from_slice(&(*value as i32).to_le_bytes());
+            }
+            in_dat.push(*negate as u8);
+            in_dat.extend_from_slice(&(*index as i32).to_le_bytes());
+            let mut out_dat = vec![0u8; 28];
+            program
+                .execute_raw(
+                    &mut in_dat[..],
+                    &mut out_dat[..],
+                    &mut static_data,
+                    &mut scratch,
+                )
+                .unwrap();
+            assert_eq!(instance.execute("main", &in_dat).unwrap(), out_dat);
+        }
+    }
+
+    // Both versions should report the same error when an assert fails.
+    let mut bad_input = vec![0u8; 25];
+    bad_input[..4].copy_from_slice(&1000.0f32.to_le_bytes());
+    assert_eq!(
+        instance.execute("main", &bad_input).unwrap_err(),
+        format!(
+            "{:?}: {}",
+            program.borrow_errors()[0].get_kind(),
+            program.borrow_errors()[0].borrow_message()
+        )
+    );
+
+    compiler.add_source(
+        "entry_points.ns".to_owned(),
+        include_str!("entry_points.ns").to_owned(),
+    );
+    let mut instance = WasmInstance::new(compiler.compile_to_wasm("entry_points.ns").unwrap());
+    let mut execute = |function: &str, input: &[u8]| {
+        let data = instance.execute(function, input).unwrap();
+        data.get(..4)
+            .map(|data| i32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+    };
+    assert_eq!(execute("main", &2i32.to_le_bytes()), Some(2));
+    assert_eq!(execute("entry_add", &10i32.to_le_bytes()), Some(2));
+    assert_eq!(execute("main", &2i32.to_le_bytes()), Some(14));
+    assert_eq!(execute("entry_reset", &[]), None);
+    assert_eq!(execute("main", &2i32.to_le_bytes()), Some(2));
+
+    for entry in std::fs::read_dir("tests/assert_ok/").unwrap() {
+        let name = entry.unwrap().path().to_str().unwrap().to_owned();
+        let mut compiler = nodespeak::Compiler::new();
+        compiler.add_source(name.clone(), std::fs::read_to_string(&name).unwrap());
+        let program = compiler.compile_to_wasm(&name).unwrap();
+        let input_data = vec![0; program.get_input_size()];
+        if let Err(message) = WasmInstance::new(program).execute("main", &input_data) {
+            panic!("WebAssembly version of {} failed: {}", name, message);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "cranelift")]
+fn cranelift_backend() {
+    use nodespeak::vague::structure::DataType;
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "c_backend.ns".to_owned(),
+        include_str!("c_backend.ns").to_owned(),
+    );
+    let program = compiler.compile("c_backend.ns").unwrap();
+    let fast_program = compiler.compile_with_cranelift("c_backend.ns").unwrap();
+    // sample, values, negate, index.
+    let inputs = [
+        (1.5f32, [1, 2, 3, 4], false, 0),
+        (-2.25, [-10, 0, 10, 20], true, 3),
+        (0.125, [7, 7, 7, 7], true, 6),
+        (99.0, [100000, -100000, 3, 2], false, 1),
+        (0.0, [0, 0, 0, 0], false, 2),
+    ];
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        let mut fast_static_data = fast_program.create_static_data().unwrap();
+        for (sample, values, negate, index) in &inputs {
+            let mut in_dat = Vec::new();
+            in_dat.extend_from_slice(&sample.to_ne_bytes());
+            for value in values {
+                in_dat.extend_from_slice(&(*value as i32).to_ne_bytes());
+            }
+            in_dat.push(*negate as u8);
+            in_dat.extend_from_slice(&(*index as i32).to_ne_bytes());
+            let mut out_dat = vec![0u8; 28];
+            let mut fast_out_dat = vec![0u8; 28];
+            program
+                .execute_raw(
+                    &mut in_dat[..],
+                    &mut out_dat[..],
+                    &mut static_data,
+                    &mut scratch,
+                )
+                .unwrap();
+            fast_program
+                .execute_raw(
+                    &mut in_dat[..],
+                    &mut fast_out_dat[..],
+                    &mut fast_static_data,
+                )
+                .unwrap();
+            assert_eq!(fast_out_dat, out_dat);
+        }
+        // Everything but the layout fingerprint at the start should be the same.
+        assert_eq!(
+            fast_static_data.to_bytes()[8..],
+            static_data.to_bytes()[8..]
+        );
+        let mut bad_input = vec![0u8; 25];
+        bad_input[..4].copy_from_slice(&1000.0f32.to_ne_bytes());
+        let error = fast_program
+            .execute_raw(&mut bad_input[..], &mut [0u8; 28], &mut fast_static_data)
+            .unwrap_err();
+        assert_eq!(error.get_code(), program.borrow_errors()[0].get_code());
+    }
+
+    compiler.add_source(
+        "entry_points.ns".to_owned(),
+        include_str!("entry_points.ns").to_owned(),
+    );
+    let program = compiler.compile_with_cranelift("entry_points.ns").unwrap();
+    assert!(program.has_entry_point("add"));
+    let (mut increment, mut total) = (2i32, 0i32);
+    let (mut amount, mut previous) = (10i32, 0i32);
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        program
+            .execute_data(&mut increment, &mut total, &mut static_data)
+            .unwrap();
+        program
+            .execute_entry("add", &mut amount, &mut previous, &mut static_data)
+            .unwrap();
+        assert_eq!(previous, 2);
+        program
+            .execute_data(&mut increment, &mut total, &mut static_data)
+            .unwrap();
+        assert_eq!(total, 14);
+        program
+            .execute_entry("reset", &mut (), &mut (), &mut static_data)
+            .unwrap();
+        assert_eq!(static_data.get::<i32>("count").unwrap(), 0);
+    }
+
+    let mut compiler = nodespeak::Compiler::new();
+    unsafe {
+        compiler.add_extern_function(
+            "Lookup",
+            vec![DataType::Int, DataType::Float],
+            vec![DataType::Float],
+            lookup as *const _,
+        );
+        compiler.add_extern_function(
+            "Sum4",
+            vec![DataType::Array(4, Box::new(DataType::Float))],
+            vec![DataType::Float],
+            sum4 as *const _,
+        );
+        compiler.add_extern_function(
+            "DivMod",
+            vec![DataType::Int, DataType::Int],
+            vec![DataType::Int, DataType::Int],
+            div_mod as *const _,
+        );
+    }
+    compiler.add_source(
+        "extern_functions.ns".to_owned(),
+        include_str!("extern_functions.ns").to_owned(),
+    );
+    let program = compiler
+        .compile_with_cranelift("extern_functions.ns")
+        .unwrap();
+    #[repr(C, packed)]
+    struct Inputs(i32, f32);
+    #[repr(C, packed)]
+    struct Outputs(f32, f32, i32, i32);
+    let mut inputs = Inputs(2, 2.0);
+    let mut outputs = Outputs(0.0, 0.0, 0, 0);
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        program
+            .execute_data(&mut inputs, &mut outputs, &mut static_data)
+            .unwrap();
+    }
+    let Outputs(value, total, quotient, remainder) = outputs;
+    assert_eq!((value, total, quotient, remainder), (5.0, 8.0, 0, 2));
+
+    for entry in std::fs::read_dir("tests/assert_ok/").unwrap() {
+        let name = entry.unwrap().path().to_str().unwrap().to_owned();
+        let mut compiler = nodespeak::Compiler::new();
+        compiler.add_source(name.clone(), std::fs::read_to_string(&name).unwrap());
+        let program = compiler.compile_with_cranelift(&name).unwrap();
+        unsafe {
+            let mut static_data = program.create_static_data().unwrap();
+            if let Err(err) = program.execute_data(&mut (), &mut (), &mut static_data) {
+                panic!("Cranelift version of {} failed: {:?}", name, err);
+            }
+        }
+    }
+}
+
+/// Wraps the LLVM backend, recording how many instructions each program it compiles contains.
+struct CountingBackend {
+    inner: nodespeak::llvmir::LlvmJitBackend,
+    instruction_counts: Vec<usize>,
+}
+
+impl nodespeak::Backend for CountingBackend {
+    type Output = nodespeak::llvmir::structure::Program;
+
+    fn borrow_name(&self) -> &str {
+        "counting"
+    }
+
+    fn compile(
+        &mut self,
+        source: &nodespeak::trivial::structure::Program,
+    ) -> Result<Self::Output, String> {
+        self.instruction_counts
+            .push(source.borrow_instructions().len());
+        self.inner.compile(source)
+    }
+}
+
+fn run_entry_points_program(program: &impl nodespeak::CompiledProgram) {
+    let (mut increment, mut total) = (2i32, 0i32);
+    let (mut amount, mut previous) = (10i32, 0i32);
+    assert!(program.has_entry_point("add"));
+    assert!(program.borrow_static_variables().contains_key("count"));
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        program
+            .execute_data(&mut increment, &mut total, &mut static_data, &mut scratch)
+            .unwrap();
+        program
+            .execute_entry(
+                "add",
+                &mut amount,
+                &mut previous,
+                &mut static_data,
+                &mut scratch,
+            )
+            .unwrap();
+        assert_eq!(previous, 2);
+        program
+            .execute_data(&mut increment, &mut total, &mut static_data, &mut scratch)
+            .unwrap();
+        assert_eq!(total, 14);
+    }
+}
+
+#[test]
+fn custom_backend() {
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(
+        "entry_points.ns".to_owned(),
+        include_str!("entry_points.ns").to_owned(),
+    );
+    let mut backend = CountingBackend {
+        inner: nodespeak::llvmir::LlvmJitBackend::new(),
+        instruction_counts: Vec::new(),
+    };
+    let program = compiler
+        .compile_with("entry_points.ns", &mut backend)
+        .unwrap();
+    assert_eq!(backend.instruction_counts.len(), 1);
+    assert!(backend.instruction_counts[0] > 0);
+    run_entry_points_program(&program);
+    run_entry_points_program(&compiler.compile("entry_points.ns").unwrap());
+    #[cfg(feature = "cranelift")]
+    run_entry_points_program(&compiler.compile_with_cranelift("entry_points.ns").unwrap());
+
+    let counters = format!("{}", compiler.borrow_performance_counters());
+    assert!(counters.contains("counting: "));
+    assert!(counters.contains("  llvmir: "));
+}
+
+#[test]
+fn trivial_text() {
+    use nodespeak::trivial::text;
+    use nodespeak::Backend;
+
+    let mut names = vec![
+        "tests/c_backend.ns".to_owned(),
+        "tests/entry_points.ns".to_owned(),
+    ];
+    for dir in &["tests/assert_ok/", "tests/compile_ok/"] {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            names.push(entry.unwrap().path().to_str().unwrap().to_owned());
+        }
+    }
+    for name in names {
+        let mut compiler = nodespeak::Compiler::new();
+        compiler.add_source(name.clone(), std::fs::read_to_string(&name).unwrap());
+        let original = text::print(&compiler.compile_to_trivial(&name).unwrap());
+        let parsed = match text::parse(&original) {
+            Ok(program) => program,
+            Err(err) => panic!("Failed to parse trivial IR of {}:\n{}", name, err),
+        };
+        assert_eq!(text::print(&parsed), original);
+        if name.starts_with("tests/assert_ok/") {
+            let program = nodespeak::llvmir::LlvmJitBackend::new()
+                .compile(&parsed)
+                .unwrap();
+            unsafe {
+                let mut static_data = program.create_static_data().unwrap();
+                let mut scratch = program.create_scratch();
+                if let Err(err) =
+                    program.execute_data(&mut (), &mut (), &mut static_data, &mut scratch)
+                {
+                    panic!("Parsed version of {} failed: {:?}", name, err);
+                }
+            }
+        }
+    }
+
+    let source = text::parse(include_str!("trivial/checked_divide.tir")).unwrap();
+    let program = nodespeak::llvmir::LlvmJitBackend::new()
+        .compile(&source)
+        .unwrap();
+    let mut quotient = 0i32;
+    unsafe {
+        let mut static_data = program.create_static_data().unwrap();
+        let mut scratch = program.create_scratch();
+        program
+            .execute_data(
+                &mut [7i32, 2],
+                &mut quotient,
+                &mut static_data,
+                &mut scratch,
+            )
+            .unwrap();
+        assert_eq!(quotient, 3);
+        let error = program
+            .execute_data(
+                &mut [7i32, 0],
+                &mut quotient,
+                &mut static_data,
+                &mut scratch,
+            )
+            .unwrap_err();
+        assert_eq!(error.get_code(), 1);
+        assert_eq!(static_data.get::<i32>("count").unwrap(), 1);
+        program
+            .execute_entry("reset", &mut (), &mut (), &mut static_data, &mut scratch)
+            .unwrap();
+        assert_eq!(static_data.get::<i32>("count").unwrap(), 0);
+    }
+
+    let error = text::parse("main {\n    move 1i32 -> tv0\n}\n")
+        .err()
+        .unwrap();
+    assert!(error.contains("tv0 was not declared"), "{}", error);
+    assert!(text::parse("var tv1: i32 main_body\n").is_err());
+}
+
+#[test]
+#[cfg(not(feature = "no-trivial"))]
+fn trivial_passes() {
+    use nodespeak::trivial::passes::Pass;
+    use nodespeak::trivial::text;
+
+    let cases = [
+        ("constant_propagation", Pass::ConstantPropagation),
+        ("copy_propagation", Pass::CopyPropagation),
+        ("dead_store_elimination", Pass::DeadStoreElimination),
+        ("dead_variable_elimination", Pass::DeadVariableElimination),
+        ("if_conversion", Pass::IfConversion),
+        ("jump_threading", Pass::JumpThreading),
+        ("unreachable_label_removal", Pass::UnreachableLabelRemoval),
+    ];
+    for (name, pass) in cases.iter() {
+        let input = format!("tests/trivial/passes/{}.in.tir", name);
+        let output = format!("tests/trivial/passes/{}.out.tir", name);
+        let mut program = text::parse(&std::fs::read_to_string(&input).unwrap()).unwrap();
+        assert!(
+            pass.run(&mut program),
+            "{:?} did nothing to {}",
+            pass,
+            input
+        );
+        assert_eq!(
+            text::print(&program),
+            std::fs::read_to_string(&output).unwrap()
+        );
+        // Running a pass again right away should not find anything else to do.
+        assert!(
+            !pass.run(&mut program),
+            "{:?} is not idempotent on {}",
+            pass,
+            input
+        );
+    }
+
+    // With every pass disabled, the compiler should leave the temporaries it generates alone.
+    let name = "tests/c_backend.ns";
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(name.to_owned(), std::fs::read_to_string(name).unwrap());
+    let optimized = compiler.compile_to_trivial(name).unwrap();
+    for pass in Pass::ALL {
+        compiler.set_pass_enabled(*pass, false);
+    }
+    let unoptimized = compiler.compile_to_trivial(name).unwrap();
+    assert!(
+        optimized.iterate_all_variables().count() < unoptimized.iterate_all_variables().count()
+    );
+    compiler.set_pass_enabled(Pass::DeadVariableElimination, true);
+    let partial = compiler.compile_to_trivial(name).unwrap();
+    assert!(partial.iterate_all_variables().count() < unoptimized.iterate_all_variables().count());
+
+    // Each backend decides which passes run unless they were set explicitly. LLVM does these
+    // optimizations itself, so it skips all of them by default.
+    use nodespeak::trivial::structure::Program;
+    use nodespeak::Backend;
+    struct CountVariables(&'static [Pass]);
+    impl Backend for CountVariables {
+        type Output = usize;
+        fn borrow_name(&self) -> &str {
+            "count_variables"
+        }
+        fn default_passes(&self) -> &'static [Pass] {
+            self.0
+        }
+        fn compile(&mut self, source: &Program) -> Result<usize, String> {
+            Ok(source.iterate_all_variables().count())
+        }
+    }
+    assert!(nodespeak::llvmir::LlvmJitBackend::new()
+        .default_passes()
+        .is_empty());
+    let mut compiler = nodespeak::Compiler::new();
+    compiler.add_source(name.to_owned(), std::fs::read_to_string(name).unwrap());
+    let count = |compiler: &mut nodespeak::Compiler, passes| {
+        compiler
+            .compile_with(name, &mut CountVariables(passes))
+            .unwrap()
+    };
+    let all = count(&mut compiler, Pass::ALL);
+    let none = count(&mut compiler, &[]);
+    assert_eq!(all, optimized.iterate_all_variables().count());
+    assert_eq!(none, unoptimized.iterate_all_variables().count());
+    compiler.set_pass_enabled(Pass::DeadVariableElimination, true);
+    assert_eq!(
+        count(&mut compiler, &[]),
+        partial.iterate_all_variables().count()
+    );
+    compiler.set_pass_enabled(Pass::DeadVariableElimination, false);
+    assert!(count(&mut compiler, Pass::ALL) > all);
+}